@@ -1,54 +1,381 @@
+use super::auth::CredentialSource;
+use super::bulk::BulkWriter;
+use super::errors::{Error, Result};
+use super::fieldpath::{escape_key, FieldPath};
 use super::firestore;
+use super::firestore::documents::CommitResponse;
+use super::firestore::query::StructuredAggregationQuery;
+use super::firestore::types::Operation;
+use super::firestore::write::{
+    FieldTransform as RestFieldTransform, Operation as WriteOperation, Precondition, ServerValue,
+    TransformType, Write,
+};
+use super::index::Index;
+use super::operation::OperationPoller;
+use super::pool::CommitPool;
+use super::proxy;
+use super::ratelimit::RateLimiter;
+use super::serializer;
+use super::token::TokenCache;
+use super::type_style::TypeStyle;
+use chrono::DateTime;
 use chrono::Utc;
-use chrono::{Date, DateTime};
-use goauth::auth::JwtClaims;
-use goauth::scopes::Scope;
-use goauth::scopes::Scope::Firebase;
-use serde::Deserializer;
-use smpl_jwt::Jwt;
-use std::collections::HashMap;
-use std::convert::TryFrom;
-use std::path::{Path, PathBuf};
-
-const FIRESTORE_BASE_URL: &'static str = "https://firestore.googleapis.com/v1";
-const FIRESTORE_BETA_BASE_URL: &'static str = " https://firestore.googleapis.com/v1beta1";
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_DATABASE_NAME: &'static str = "(default)";
+
+/// Longest a request may take unless set with `with_timeout`
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most writes Firestore accepts in a single commit
+pub const MAX_WRITES_PER_COMMIT: usize = 500;
+
+/// How many times `DatabaseContext::run_transaction` attempts a contended transaction
+pub const MAX_TRANSACTION_ATTEMPTS: usize = 5;
+
+/// Length of the IDs Firestore generates for documents
+const AUTO_ID_LENGTH: usize = 20;
+
+/// Random picks `DatabaseContext::sample` makes per document before settling for fewer
+const SAMPLE_ATTEMPTS: usize = 3;
+
+/// How requests failing with a transient error (see `Error::is_transient`) are retried.
+/// Reads are always safe to send again, writes are only retried when `retry_writes` is set
+/// since a write whose response was lost may already have been applied.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts made in total, including the first one
+    pub max_attempts: u32,
+    /// Wait before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound on the wait between two attempts
+    pub max_backoff: Duration,
+    /// Factor the wait grows by after every retry
+    pub multiplier: f64,
+    /// Wait a random duration between zero and the backoff instead of the backoff itself,
+    /// so concurrent clients do not retry in lockstep
+    pub jitter: bool,
+    pub retry_writes: bool,
+}
+
+/// Receives the writes a `DatabaseContext` would commit, in place of Firestore.
+/// Set with `DatabaseContext::with_write_sink` for dry runs.
+pub trait WriteSink: fmt::Debug + Send + Sync {
+    /// Called with the writes of every commit, in the order they would be applied
+    fn write(&self, writes: &[Write]) -> Result<()>;
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: true,
+            retry_writes: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that sends every request once
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// How long to wait after the `attempt`th attempt failed, counting from 1
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let max = self.max_backoff.as_millis() as f64;
+        let millis =
+            self.initial_backoff.as_millis() as f64 * self.multiplier.powi(attempt as i32 - 1);
+        let millis = if millis.is_finite() && millis < max {
+            millis
+        } else {
+            max
+        };
+        let millis = if self.jitter && millis >= 1.0 {
+            rand::thread_rng().gen_range(0.0, millis)
+        } else {
+            millis
+        };
+        Duration::from_millis(millis as u64)
+    }
+}
 
 //// the `fields` attribute for Firestore Documents
-#[derive(Debug, Deserialize)]
-pub struct FirestoreFields(HashMap<String, FirestoreType>);
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FirestoreFields(pub HashMap<String, FirestoreType>);
 
-#[derive(Debug, Deserialize)]
-struct Map {
-    fields: FirestoreFields,
+impl FirestoreFields {
+    /// Converts a JSON object into Firestore fields,
+    /// failing if `value` is anything other than an object
+    pub fn from_json(value: serde_json::Value) -> Result<FirestoreFields> {
+        match value {
+            serde_json::Value::Object(object) => Ok(FirestoreFields(
+                object
+                    .into_iter()
+                    .map(|(key, value)| (key, FirestoreType::from(value)))
+                    .collect(),
+            )),
+            _ => Err(Error::InvalidDocument {
+                reason: String::from("document payload must be a JSON object"),
+            }),
+        }
+    }
+
+    /// Converts these fields into a plain JSON object
+    pub fn to_json(&self) -> serde_json::Value {
+        self.to_json_with(&TypeStyle::default())
+    }
+
+    /// Converts these fields into a plain JSON object, rendering values in said style
+    pub fn to_json_with(&self, style: &TypeStyle) -> serde_json::Value {
+        self.to_json_embedding(style, &|_| None)
+    }
+
+    /// Converts these fields into a plain JSON object as `to_json_with` does, see
+    /// `FirestoreType::to_json_embedding`
+    pub fn to_json_embedding<F>(&self, style: &TypeStyle, embed: &F) -> serde_json::Value
+    where
+        F: Fn(&str) -> Option<serde_json::Value>,
+    {
+        serde_json::Value::Object(
+            self.0
+                .iter()
+                .map(|(key, value)| (key.clone(), value.to_json_embedding(style, embed)))
+                .collect(),
+        )
+    }
+
+    /// Converts a serializable map or struct into Firestore fields,
+    /// see `serializer::to_fields` for how values are converted
+    pub fn from_serializable<T>(value: &T) -> Result<FirestoreFields>
+    where
+        T: Serialize + ?Sized,
+    {
+        serializer::to_fields(value)
+    }
+
+    /// Deserializes these fields into `T`, with values converted as `to_json` does
+    pub fn deserialize<T>(&self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_value(self.to_json()).map_err(Error::from)
+    }
+
+    /// Resolves the references given as document paths, see `FirestoreType::Reference`
+    fn resolve_references(self, documents_path: &str) -> FirestoreFields {
+        FirestoreFields(
+            self.0
+                .into_iter()
+                .map(|(key, value)| (key, value.resolve_references(documents_path)))
+                .collect(),
+        )
+    }
+
+    /// The value at a field path such as `address.city` or `items[2].price`,
+    /// see `FieldPath`
+    pub fn get(&self, field_path: &str) -> Option<&FirestoreType> {
+        FieldPath::parse(field_path).ok()?.get(self)
+    }
+
+    /// Dotted paths to every leaf value, descending into non-empty maps, with their keys
+    /// quoted as Firestore requires
+    pub fn field_paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        for (key, value) in &self.0 {
+            match value {
+                FirestoreType::Map(map) if !map.fields.0.is_empty() => paths.extend(
+                    map.fields
+                        .field_paths()
+                        .into_iter()
+                        .map(|path| format!("{}.{}", escape_key(key), path)),
+                ),
+                _ => paths.push(escape_key(key)),
+            }
+        }
+        paths
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct Array {
-    values: Vec<FirestoreType>,
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Map {
+    #[serde(default)]
+    pub fields: FirestoreFields,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Array {
+    #[serde(default)]
+    pub values: Vec<FirestoreType>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DatabaseContext {
     pub project_id: String,
-    auth_token: goauth::auth::Token,
+    /// ID of the database within the project, `(default)` unless set with `with_database`
+    database_id: String,
+    /// `None` when talking to the emulator, which does not check credentials.
+    /// Shared by every clone of the context, so a refreshed token serves them all.
+    tokens: Option<Arc<TokenCache>>,
+    pub(crate) base_url: String,
     client: reqwest::Client,
+    /// Longest a request may take, `None` to wait forever
+    timeout: Option<Duration>,
+    retry_policy: RetryPolicy,
+    /// Takes the writes instead of Firestore when set, reads still hit the database
+    write_sink: Option<Arc<dyn WriteSink>>,
+    /// Most commits bulk operations have in flight at once
+    concurrency: usize,
+    /// Paces the commits of bulk operations, shared by every clone of the context
+    write_limiter: Arc<RateLimiter>,
+    /// Reads see the database as it was at this time when set, see `read_at`
+    read_time: Option<DateTime<Utc>>,
+}
+
+/// Settings of a `DatabaseContext` to be built, see `DatabaseContext::builder`
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseContextBuilder {
+    project_id: Option<String>,
+    /// Application Default Credentials when `None`
+    credentials: Option<CredentialSource>,
+    database_id: Option<String>,
+    emulator_host: Option<String>,
+    /// Left at `DEFAULT_TIMEOUT` when `None`
+    timeout: Option<Option<Duration>>,
+    retry_policy: Option<RetryPolicy>,
+}
+
+impl DatabaseContextBuilder {
+    /// Project the database belongs to, the only setting without a default
+    pub fn project<S: Into<String>>(mut self, project_id: S) -> DatabaseContextBuilder {
+        self.project_id = Some(project_id.into());
+        self
+    }
+
+    /// Authenticates with a service account key or another credentials file
+    pub fn credentials_file<P: Into<PathBuf>>(mut self, path: P) -> DatabaseContextBuilder {
+        self.credentials = Some(CredentialSource::File(path.into()));
+        self
+    }
+
+    /// Authenticates with the contents of a credentials file
+    pub fn credentials_json<S: Into<String>>(mut self, json: S) -> DatabaseContextBuilder {
+        self.credentials = Some(CredentialSource::Json(json.into()));
+        self
+    }
+
+    /// Authenticates with credentials found the way Application Default Credentials
+    /// are, see `CredentialSource::resolve`. The default.
+    pub fn adc(mut self) -> DatabaseContextBuilder {
+        self.credentials = None;
+        self
+    }
+
+    /// Authenticates with credentials from `source`, such as impersonated ones
+    pub fn credentials(mut self, source: CredentialSource) -> DatabaseContextBuilder {
+        self.credentials = Some(source);
+        self
+    }
+
+    /// Database of the project, `(default)` by default
+    pub fn database<S: Into<String>>(mut self, database_id: S) -> DatabaseContextBuilder {
+        self.database_id = Some(database_id.into());
+        self
+    }
+
+    /// Talks to the emulator listening on `host`, such as `localhost:8080`, which
+    /// takes no credentials
+    pub fn emulator<S: Into<String>>(mut self, host: S) -> DatabaseContextBuilder {
+        self.emulator_host = Some(host.into());
+        self
+    }
+
+    /// Longest a request may take, `None` to wait forever. Thirty seconds by default.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> DatabaseContextBuilder {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How requests failing with a transient error are retried
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> DatabaseContextBuilder {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Connects, minting the first token right away so that unusable credentials are
+    /// reported here
+    pub fn build(self) -> Result<DatabaseContext> {
+        let project_id = self.project_id.ok_or_else(|| Error::InvalidArgument {
+            reason: String::from("a DatabaseContext needs a project"),
+        })?;
+        let context = match self.emulator_host {
+            Some(host) => DatabaseContext::emulator(project_id, host),
+            None => {
+                let source = self
+                    .credentials
+                    .unwrap_or_else(|| CredentialSource::resolve(None));
+                DatabaseContext::with_credentials(project_id, source)?
+            }
+        };
+        let context = match self.database_id {
+            Some(database_id) => context.with_database(database_id),
+            None => context,
+        };
+        let context = match self.timeout {
+            Some(timeout) => context.with_timeout(timeout),
+            None => context,
+        };
+        Ok(match self.retry_policy {
+            Some(retry_policy) => context.with_retry_policy(retry_policy),
+            None => context,
+        })
+    }
+}
+
+/// Builds the HTTP client of a context, whose requests fail after `timeout` and go
+/// through the proxy `proxy::proxy_for` picks
+fn http_client(timeout: Option<Duration>) -> reqwest::Client {
+    reqwest::Client::builder()
+        .proxy(proxy::reqwest_proxy())
+        .timeout(timeout)
+        .build()
+        .expect("the TLS backend initializes like it does for reqwest::Client::new")
 }
 
 // Firestore GeoPoint type
-#[derive(Debug, Deserialize, Clone, Copy)]
-struct GeoPoint {
-    latitude: i32,
-    longitude: i32,
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
 }
 
 use serde_aux::field_attributes::deserialize_number_from_string;
 
 // Represents a mapping between Firestore data types and Rust types
-#[derive(Debug, Deserialize)]
-enum FirestoreType {
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FirestoreType {
     #[serde(rename = "integerValue")]
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    Integer(i32),
+    Integer(i64),
+    #[serde(rename = "doubleValue")]
+    Double(f64),
     #[serde(rename = "booleanValue")]
     Boolean(bool),
     #[serde(rename = "stringValue")]
@@ -62,12 +389,129 @@ enum FirestoreType {
     #[serde(rename = "timestampValue")]
     Timestamp(DateTime<Utc>),
     #[serde(rename = "nullValue")]
-    Null,
+    Null(()),
+    /// Resource name of another document, e.g.
+    /// projects/{project_id}/databases/{database_id}/documents/{document_path}.
+    /// Written references may also be the slash-delimited path of a document of the
+    /// database written to, such as users/bob.
+    #[serde(rename = "referenceValue")]
+    Reference(String),
+    /// Base64 encoded bytes
+    #[serde(rename = "bytesValue")]
+    Bytes(String),
+}
+
+impl FirestoreType {
+    /// Converts this value into plain JSON, dropping the Firestore type tags.
+    /// Timestamps become RFC 3339 strings and geo points become objects.
+    pub fn to_json(&self) -> serde_json::Value {
+        self.to_json_with(&TypeStyle::default())
+    }
+
+    /// Converts this value into plain JSON, rendering references, geo points, bytes
+    /// and timestamps in said style
+    pub fn to_json_with(&self, style: &TypeStyle) -> serde_json::Value {
+        self.to_json_embedding(style, &|_| None)
+    }
+
+    /// Converts this value into plain JSON as `to_json_with` does, except that
+    /// references for which `embed` returns a value, such as the referenced document,
+    /// are replaced by that value
+    pub fn to_json_embedding<F>(&self, style: &TypeStyle, embed: &F) -> serde_json::Value
+    where
+        F: Fn(&str) -> Option<serde_json::Value>,
+    {
+        use serde_json::Value;
+        match self {
+            FirestoreType::Integer(i) => Value::from(*i),
+            FirestoreType::Double(d) => Value::from(*d),
+            FirestoreType::Boolean(b) => Value::Bool(*b),
+            FirestoreType::String(s) => Value::String(s.clone()),
+            FirestoreType::GeoLocation(point) => style.geo_point(point),
+            FirestoreType::Array(array) => Value::Array(
+                array
+                    .values
+                    .iter()
+                    .map(|value| value.to_json_embedding(style, embed))
+                    .collect(),
+            ),
+            FirestoreType::Map(map) => map.fields.to_json_embedding(style, embed),
+            FirestoreType::Timestamp(time) => style.timestamp(time),
+            FirestoreType::Null(_) => Value::Null,
+            FirestoreType::Reference(name) => embed(name).unwrap_or_else(|| style.reference(name)),
+            FirestoreType::Bytes(bytes) => style.bytes(bytes),
+        }
+    }
+
+    /// Turns references given as document paths into the resource names of those
+    /// documents below `documents_path`, inside of maps and arrays as well
+    fn resolve_references(self, documents_path: &str) -> FirestoreType {
+        match self {
+            FirestoreType::Reference(path) if !path.starts_with("projects/") => {
+                FirestoreType::Reference(format!(
+                    "{}/{}",
+                    documents_path,
+                    path.trim_start_matches('/')
+                ))
+            }
+            FirestoreType::Array(array) => FirestoreType::Array(Array {
+                values: array
+                    .values
+                    .into_iter()
+                    .map(|value| value.resolve_references(documents_path))
+                    .collect(),
+            }),
+            FirestoreType::Map(map) => FirestoreType::Map(Map {
+                fields: map.fields.resolve_references(documents_path),
+            }),
+            value => value,
+        }
+    }
+}
+
+impl From<serde_json::Value> for FirestoreType {
+    fn from(value: serde_json::Value) -> Self {
+        use serde_json::Value;
+        match value {
+            Value::Null => FirestoreType::Null(()),
+            Value::Bool(b) => FirestoreType::Boolean(b),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => FirestoreType::Integer(i),
+                None => FirestoreType::Double(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) => FirestoreType::String(s),
+            Value::Array(values) => FirestoreType::Array(Array {
+                values: values.into_iter().map(FirestoreType::from).collect(),
+            }),
+            Value::Object(object) => FirestoreType::Map(Map {
+                fields: FirestoreFields(
+                    object
+                        .into_iter()
+                        .map(|(key, value)| (key, FirestoreType::from(value)))
+                        .collect(),
+                ),
+            }),
+        }
+    }
+}
+
+/// A new document ID of random letters and digits, like those Firestore generates
+pub fn auto_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(AUTO_ID_LENGTH)
+        .collect()
+}
+
+/// The path of a document within its database, given its resource name
+pub fn relative_path(name: &str) -> &str {
+    name.splitn(6, '/').nth(5).unwrap_or(name)
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Document {
     name: String,
+    #[serde(default)]
     fields: FirestoreFields,
     #[serde(rename = "createTime")]
     create_time: DateTime<Utc>,
@@ -75,10 +519,67 @@ pub struct Document {
     update_time: DateTime<Utc>,
 }
 
-#[derive(Serialize)]
+impl Document {
+    /// The document's ID, the last segment of its resource name
+    pub fn id(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or(&*self.name)
+    }
+
+    /// The full resource name of the document
+    pub fn name(&self) -> &str {
+        &*self.name
+    }
+
+    /// The slash-delimited path of the document within its database,
+    /// its resource name without the `projects/{p}/databases/{d}/documents/` prefix
+    pub fn path(&self) -> &str {
+        relative_path(&self.name)
+    }
+
+    pub fn fields(&self) -> &FirestoreFields {
+        &self.fields
+    }
+
+    pub fn create_time(&self) -> DateTime<Utc> {
+        self.create_time
+    }
+
+    pub fn update_time(&self) -> DateTime<Utc> {
+        self.update_time
+    }
+
+    /// Deserializes the fields of the document into `T`. Timestamps are given as
+    /// RFC 3339 strings, which `chrono::DateTime` accepts, references as resource names
+    /// and geo points as structs with `latitude` and `longitude`.
+    pub fn deserialize<T>(&self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.fields.deserialize()
+    }
+
+    /// Converts the document into plain JSON along with its ID and metadata
+    pub fn to_json(&self) -> serde_json::Value {
+        self.to_json_with(&TypeStyle::default())
+    }
+
+    /// Converts the document into plain JSON along with its ID and metadata, rendering
+    /// its fields and times in said style
+    pub fn to_json_with(&self, style: &TypeStyle) -> serde_json::Value {
+        json!({
+            "id": self.id(),
+            "name": self.name,
+            "createTime": style.timestamp(&self.create_time),
+            "updateTime": style.timestamp(&self.update_time),
+            "fields": self.fields.to_json_with(style),
+        })
+    }
+}
+
+#[derive(Clone, Serialize)]
 pub struct DocumentMask {
     #[serde(rename = "fieldPaths")]
-    field_paths: Vec<String>,
+    pub field_paths: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -104,238 +605,2824 @@ pub mod list_documents {
 
     #[derive(Debug, Deserialize)]
     pub struct Response {
-        documents: Vec<super::Document>,
+        #[serde(default)]
+        pub documents: Vec<super::Document>,
         #[serde(rename = "nextPageToken")]
-        next_page_token: String,
+        pub next_page_token: Option<String>,
+    }
+
+    /// Iterator over every document of a collection, requesting a page at a time
+    /// as the previous one is consumed
+    pub struct Iter<'a> {
+        pub(super) ctx: &'a super::DatabaseContext,
+        pub(super) collection_id: String,
+        pub(super) page_size: Option<i32>,
+        pub(super) order_by: Option<String>,
+        pub(super) mask: Option<Vec<String>>,
+        pub(super) page: std::vec::IntoIter<super::Document>,
+        /// `None` once the last page has been requested
+        pub(super) next_page_token: Option<String>,
+    }
+
+    impl<'a> Iter<'a> {
+        /// Sorts the documents by `field`, after the fields given before.
+        /// Documents that do not have the field are left out.
+        pub fn order_by<S>(mut self, field: S, direction: super::query::Direction) -> Iter<'a>
+        where
+            S: Into<String>,
+        {
+            let mut order = field.into();
+            if direction == super::query::Direction::Descending {
+                order.push_str(" desc");
+            }
+            self.order_by = Some(match self.order_by {
+                Some(order_by) => format!("{},{}", order_by, order),
+                None => order,
+            });
+            self
+        }
+
+        /// Only fetches the fields named by `field_paths` of each document
+        pub fn select(mut self, field_paths: Vec<String>) -> Iter<'a> {
+            self.mask = Some(field_paths);
+            self
+        }
+
+        /// Token to continue the listing from with `resume_from`, only given between
+        /// pages: once every document of the current page has been yielded and before
+        /// the next page is requested. `None` at any other time and after the last page.
+        pub fn resume_token(&self) -> Option<&str> {
+            if self.page.len() > 0 {
+                return None;
+            }
+            self.next_page_token
+                .as_ref()
+                .map(String::as_str)
+                .filter(|token| !token.is_empty())
+        }
+
+        /// Continues a listing from a token given by `resume_token`, such as one
+        /// saved by an earlier run. The sorting and mask must be the same as that run's.
+        pub fn resume_from<S>(mut self, page_token: S) -> Iter<'a>
+        where
+            S: Into<String>,
+        {
+            self.next_page_token = Some(page_token.into());
+            self
+        }
+    }
+
+    impl<'a> Iterator for Iter<'a> {
+        type Item = crate::errors::Result<super::Document>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            loop {
+                if let Some(document) = self.page.next() {
+                    return Some(Ok(document));
+                }
+                // an empty token marks the first page
+                let page_token = self.next_page_token.take()?;
+                let page_token = if page_token.is_empty() {
+                    None
+                } else {
+                    Some(page_token)
+                };
+                match self.ctx.list_documents_request(
+                    self.collection_id.clone(),
+                    self.page_size,
+                    page_token,
+                    self.order_by.clone(),
+                    self.mask.clone(),
+                ) {
+                    Ok(response) => {
+                        self.next_page_token =
+                            response.next_page_token.filter(|token| !token.is_empty());
+                        self.page = response.documents.into_iter();
+                    }
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        }
     }
 }
 
-pub mod batch_get {
-    #[derive(Serialize)]
-    pub struct Request {
-        documents: Vec<String>,
+/// Reads the next entry of a JSON array that is still being written,
+/// `None` once the array is closed or the stream ends
+fn next_entry<R, T>(reader: &mut R) -> Result<Option<T>>
+where
+    R: BufRead,
+    T: DeserializeOwned,
+{
+    loop {
+        let (skipped, next) = {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                return Ok(None);
+            }
+            let skipped = buffer
+                .iter()
+                .take_while(|b| b" \t\r\n,[".contains(b))
+                .count();
+            (skipped, buffer.get(skipped).cloned())
+        };
+        reader.consume(skipped);
+        match next {
+            // the rest of the separators are in the next read
+            None => continue,
+            Some(b']') => return Ok(None),
+            Some(_) => {
+                // an object is complete at its closing brace, so nothing past it is read
+                let mut deserializer = serde_json::Deserializer::from_reader(&mut *reader);
+                return Ok(Some(T::deserialize(&mut deserializer)?));
+            }
+        }
     }
+}
 
-    #[derive(Deserialize)]
-    pub struct Response {
-        transaction: String,
-        read_time: String,
-        found: Option<super::Document>,
-        missing: Option<String>,
+/// Iterator over the documents matching a query, yielded as Firestore streams them
+/// rather than once all of them are read. See `DatabaseContext::stream_query`.
+pub struct QueryStream {
+    reader: BufReader<reqwest::Response>,
+    done: bool,
+}
+
+impl Iterator for QueryStream {
+    type Item = Result<Document>;
+
+    fn next(&mut self) -> Option<Result<Document>> {
+        while !self.done {
+            match next_entry::<_, firestore::documents::RunQueryResponse>(&mut self.reader) {
+                Ok(Some(response)) => {
+                    if let Some(status) = response.error {
+                        self.done = true;
+                        return Some(Err(Error::from_code(status.code, status.message)));
+                    }
+                    if let Some(document) = response.document {
+                        return Some(Ok(document));
+                    }
+                    // entries without a document only report progress
+                }
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
     }
 }
 
-impl DatabaseContext {
-    /// Creates a header map with proper authorization
-    fn auth_header_map(&self) -> Result<reqwest::header::HeaderMap, String> {
-        let mut map = reqwest::header::HeaderMap::new();
-        let str = &*self.auth_token.access_token();
-        map.insert(
-            reqwest::header::AUTHORIZATION,
-            str.parse().map_err(|_| "Invalid Header Value")?,
-        );
-        Ok(map)
+pub mod watch {
+    use crate::errors::Result;
+    use chrono::{DateTime, Utc};
+    use std::collections::{HashMap, VecDeque};
+    use std::thread;
+    use std::time::Duration;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ChangeKind {
+        /// The document started matching the query, or already did when watching began
+        Added,
+        /// The update time of the document changed since the last run of the query
+        Modified,
+        /// The document was deleted or stopped matching the query
+        Removed,
+    }
+
+    impl ChangeKind {
+        pub fn name(self) -> &'static str {
+            match self {
+                ChangeKind::Added => "added",
+                ChangeKind::Modified => "modified",
+                ChangeKind::Removed => "removed",
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct Change {
+        pub kind: ChangeKind,
+        /// Resource name of the document
+        pub name: String,
+        /// The document as it is now, `None` once removed
+        pub document: Option<super::Document>,
+        /// Whether the document was part of the results when watching began
+        pub initial: bool,
+    }
+
+    impl Change {
+        /// The slash-delimited path of the document within its database
+        pub fn path(&self) -> &str {
+            super::relative_path(&self.name)
+        }
+    }
+
+    /// Iterator over the changes to the results of a query, found by running the query
+    /// again every `interval` and comparing the update times of the documents with those
+    /// of the previous run. It never ends on its own. Changes made and undone between two
+    /// runs are not seen, and every run reads all of the matching documents.
+    pub struct Changes<'a> {
+        pub(super) ctx: &'a super::DatabaseContext,
+        pub(super) query: super::query::Query,
+        pub(super) interval: Duration,
+        /// Update times of the documents matching the query on the last run, `None`
+        /// before the first one
+        pub(super) seen: Option<HashMap<String, DateTime<Utc>>>,
+        /// Changes found by the last run which are yet to be yielded
+        pub(super) pending: VecDeque<Change>,
+        pub(super) done: bool,
+    }
+
+    impl<'a> Changes<'a> {
+        /// Runs the query, queuing the differences with the previous run
+        fn poll(&mut self) -> Result<()> {
+            let initial = self.seen.is_none();
+            if !initial {
+                thread::sleep(self.interval);
+            }
+            let mut seen = self.seen.take().unwrap_or_default();
+            let mut current = HashMap::new();
+            for document in self.ctx.stream_query(self.query.clone())? {
+                let document = document?;
+                let kind = match seen.remove(&document.name) {
+                    None => Some(ChangeKind::Added),
+                    Some(time) if time != document.update_time => Some(ChangeKind::Modified),
+                    Some(_) => None,
+                };
+                current.insert(document.name.clone(), document.update_time);
+                if let Some(kind) = kind {
+                    self.pending.push_back(Change {
+                        kind,
+                        name: document.name.clone(),
+                        document: Some(document),
+                        initial,
+                    });
+                }
+            }
+            // whatever is left of the last run no longer matches
+            let mut removed = seen.into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+            removed.sort();
+            self.pending.extend(removed.into_iter().map(|name| Change {
+                kind: ChangeKind::Removed,
+                name,
+                document: None,
+                initial: false,
+            }));
+            self.seen = Some(current);
+            Ok(())
+        }
+    }
+
+    impl<'a> Iterator for Changes<'a> {
+        type Item = Result<Change>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            while !self.done {
+                if let Some(change) = self.pending.pop_front() {
+                    return Some(Ok(change));
+                }
+                if let Err(e) = self.poll() {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+            None
+        }
+    }
+}
+
+pub mod query {
+    use super::FirestoreType;
+    use crate::errors::{Error, Result};
+    pub use crate::firestore::query::Direction;
+    pub use crate::firestore::query::FieldOperator as Operator;
+    use crate::firestore::query::{
+        Aggregation as RestAggregation, AggregationOperator, CollectionSelector, CompositeFilter,
+        CompositeOperator, Count, Cursor, FieldAggregation, FieldFilter, FieldReference,
+        Filter as RestFilter, Order, Projection, StructuredQuery, UnaryFilter, UnaryOperator,
+    };
+    use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+    /// Operators recognized in filter expressions, longest spelling first
+    /// so that `<=` is never mistaken for `<`
+    const OPERATORS: &[(&str, Operator)] = &[
+        (" array-contains-any ", Operator::ArrayContainsAny),
+        (" array-contains ", Operator::ArrayContains),
+        (" not-in ", Operator::NotIn),
+        (" in ", Operator::In),
+        ("<=", Operator::LessThanOrEqual),
+        (">=", Operator::GreaterThanOrEqual),
+        ("==", Operator::Equal),
+        ("!=", Operator::NotEqual),
+        ("<", Operator::LessThan),
+        (">", Operator::GreaterThan),
+    ];
+
+    /// A comparison between a document field and a value
+    #[derive(Clone)]
+    pub struct Filter {
+        pub field: String,
+        pub op: Operator,
+        pub value: FirestoreType,
+    }
+
+    /// Interprets the right hand side of a filter expression.
+    /// JSON literals keep their type, RFC 3339 times and dates such as 2022-01-01 are
+    /// timestamps, at midnight UTC for dates, NaN is a double and anything else is taken
+    /// as a string.
+    pub fn parse_literal(literal: &str) -> FirestoreType {
+        if literal == "NaN" {
+            return FirestoreType::Double(std::f64::NAN);
+        }
+        if literal.len() >= 2 && literal.starts_with('\'') && literal.ends_with('\'') {
+            return FirestoreType::String(literal[1..literal.len() - 1].to_string());
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(literal) {
+            return FirestoreType::Timestamp(time.with_timezone(&Utc));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(literal, "%Y-%m-%d") {
+            return FirestoreType::Timestamp(
+                Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()),
+            );
+        }
+        match serde_json::from_str::<serde_json::Value>(literal) {
+            Ok(value) => FirestoreType::from(value),
+            Err(_) => FirestoreType::String(literal.to_string()),
+        }
+    }
+
+    impl Filter {
+        pub fn new<S>(field: S, op: Operator, value: FirestoreType) -> Filter
+        where
+            S: Into<String>,
+        {
+            Filter {
+                field: field.into(),
+                op,
+                value,
+            }
+        }
+
+        /// Parses an expression such as `age > 21` or `city == Austin`
+        pub fn parse(expression: &str) -> Result<Filter> {
+            let (position, token, op) = OPERATORS
+                .iter()
+                .filter_map(|(token, op)| expression.find(token).map(|i| (i, *token, *op)))
+                .min_by_key(|(i, token, _)| (*i, std::cmp::Reverse(token.len())))
+                .ok_or_else(|| Error::InvalidArgument {
+                    reason: format!("{} does not contain a known operator", expression),
+                })?;
+            let field = expression[..position].trim();
+            let literal = expression[position + token.len()..].trim();
+            if field.is_empty() || literal.is_empty() {
+                return Err(Error::InvalidArgument {
+                    reason: format!("{} is not of the form <field> <op> <value>", expression),
+                });
+            }
+            Ok(Filter::new(field, op, parse_literal(literal)))
+        }
+
+        fn into_rest(self) -> RestFilter {
+            let field = FieldReference {
+                field_path: self.field,
+            };
+            // comparisons against null and NaN must be sent as unary filters
+            let unary = match (&self.value, self.op) {
+                (FirestoreType::Null(_), Operator::Equal) => Some(UnaryOperator::IsNull),
+                (FirestoreType::Null(_), Operator::NotEqual) => Some(UnaryOperator::IsNotNull),
+                (FirestoreType::Double(d), Operator::Equal) if d.is_nan() => {
+                    Some(UnaryOperator::IsNan)
+                }
+                (FirestoreType::Double(d), Operator::NotEqual) if d.is_nan() => {
+                    Some(UnaryOperator::IsNotNan)
+                }
+                _ => None,
+            };
+            match unary {
+                Some(op) => RestFilter::Unary(UnaryFilter { op, field }),
+                None => RestFilter::Field(FieldFilter {
+                    field,
+                    op: self.op,
+                    value: self.value,
+                }),
+            }
+        }
+    }
+
+    /// Parses an ordering of the form `field[:asc|:desc]`, ascending by default
+    pub fn parse_order(expression: &str) -> Result<(String, Direction)> {
+        let (field, direction) = match expression.rfind(':') {
+            Some(colon) => (&expression[..colon], &expression[colon + 1..]),
+            None => (expression, "asc"),
+        };
+        let direction = match &*direction.to_lowercase() {
+            "asc" | "ascending" => Direction::Ascending,
+            "desc" | "descending" => Direction::Descending,
+            _ => {
+                return Err(Error::InvalidArgument {
+                    reason: format!("{} is not a valid direction, use asc or desc", direction),
+                })
+            }
+        };
+        if field.is_empty() {
+            return Err(Error::InvalidArgument {
+                reason: format!("{} does not name a field to order by", expression),
+            });
+        }
+        Ok((field.to_string(), direction))
+    }
+
+    /// Builder for a structured query over a single collection or a collection group
+    #[derive(Clone)]
+    pub struct Query {
+        /// Path of the document the collection belongs to, empty for root collections
+        parent: String,
+        collection_id: String,
+        all_descendants: bool,
+        select: Option<Vec<String>>,
+        filters: Vec<Filter>,
+        order_by: Vec<(String, Direction)>,
+        start_at: Option<Cursor>,
+        end_at: Option<Cursor>,
+        offset: Option<i32>,
+        limit: Option<i32>,
+    }
+
+    impl Query {
+        /// Queries the collection at `collection_path`, either a collection ID or a
+        /// slash-delimited path such as `users/alice/orders` for nested collections
+        pub fn new<S>(collection_path: S) -> Query
+        where
+            S: Into<String>,
+        {
+            let collection_path = collection_path.into();
+            let (parent, collection_id) = match collection_path.rfind('/') {
+                Some(slash) => (
+                    collection_path[..slash].to_string(),
+                    collection_path[slash + 1..].to_string(),
+                ),
+                None => (String::new(), collection_path),
+            };
+            Query {
+                parent,
+                collection_id,
+                all_descendants: false,
+                select: None,
+                filters: Vec::new(),
+                order_by: Vec::new(),
+                start_at: None,
+                end_at: None,
+                offset: None,
+                limit: None,
+            }
+        }
+
+        /// Queries every collection named `collection_id`, wherever it is nested in the database.
+        /// A slash-delimited path restricts the query to collections below its parent document.
+        pub fn collection_group<S>(collection_id: S) -> Query
+        where
+            S: Into<String>,
+        {
+            Query {
+                all_descendants: true,
+                ..Query::new(collection_id)
+            }
+        }
+
+        /// Queries every document nested below the document at the slash-delimited
+        /// `document_path`, in all of its subcollections at any depth
+        pub fn descendants_of<S>(document_path: S) -> Query
+        where
+            S: Into<String>,
+        {
+            Query {
+                parent: document_path.into(),
+                collection_id: String::new(),
+                all_descendants: true,
+                ..Query::new("")
+            }
+        }
+
+        /// Sorts results by `field`, after any previous orderings
+        pub fn order_by<S>(mut self, field: S, direction: Direction) -> Query
+        where
+            S: Into<String>,
+        {
+            self.order_by.push((field.into(), direction));
+            self
+        }
+
+        /// Only returns the fields named by `field_paths` of each result
+        pub fn select(mut self, field_paths: Vec<String>) -> Query {
+            self.select = Some(field_paths);
+            self
+        }
+
+        /// Only returns the names of the results, without any of their fields
+        pub fn keys_only(self) -> Query {
+            self.select(vec![String::from("__name__")])
+        }
+
+        /// Starts the results at the document with `values` for the orderings of the query,
+        /// in the order they were added. A value past the orderings is a document
+        /// reference, as documents are ordered by name last.
+        pub fn start_at(mut self, values: Vec<FirestoreType>) -> Query {
+            self.start_at = Some(Cursor {
+                values,
+                before: true,
+            });
+            self
+        }
+
+        /// Starts the results right after the document with `values`, see `start_at`
+        pub fn start_after(mut self, values: Vec<FirestoreType>) -> Query {
+            self.start_at = Some(Cursor {
+                values,
+                before: false,
+            });
+            self
+        }
+
+        /// Ends the results at the document with `values`, see `start_at`
+        pub fn end_at(mut self, values: Vec<FirestoreType>) -> Query {
+            self.end_at = Some(Cursor {
+                values,
+                before: false,
+            });
+            self
+        }
+
+        /// Ends the results right before the document with `values`, see `start_at`
+        pub fn end_before(mut self, values: Vec<FirestoreType>) -> Query {
+            self.end_at = Some(Cursor {
+                values,
+                before: true,
+            });
+            self
+        }
+
+        /// Skips the first `offset` results
+        pub fn offset(mut self, offset: i32) -> Query {
+            self.offset = Some(offset);
+            self
+        }
+
+        /// Returns at most `limit` results
+        pub fn limit(mut self, limit: i32) -> Query {
+            self.limit = Some(limit);
+            self
+        }
+
+        /// Restricts results to documents matching `filter`, in addition to any previous filters
+        pub fn filter(mut self, filter: Filter) -> Query {
+            self.filters.push(filter);
+            self
+        }
+
+        /// Path of the document the queried collection belongs to, empty for root collections
+        pub(crate) fn parent(&self) -> &str {
+            &self.parent
+        }
+
+        pub fn into_structured_query(self) -> StructuredQuery {
+            let mut filters = self
+                .filters
+                .into_iter()
+                .map(Filter::into_rest)
+                .collect::<Vec<RestFilter>>();
+            let filter = if filters.len() > 1 {
+                Some(RestFilter::Composite(CompositeFilter {
+                    op: CompositeOperator::And,
+                    filters,
+                }))
+            } else {
+                filters.pop()
+            };
+            StructuredQuery {
+                select: self.select.map(|field_paths| Projection {
+                    fields: field_paths
+                        .into_iter()
+                        .map(|field_path| FieldReference { field_path })
+                        .collect(),
+                }),
+                from: vec![CollectionSelector {
+                    collection_id: self.collection_id,
+                    all_descendants: self.all_descendants,
+                }],
+                filter,
+                order_by: self
+                    .order_by
+                    .into_iter()
+                    .map(|(field_path, direction)| Order {
+                        field: FieldReference { field_path },
+                        direction,
+                    })
+                    .collect(),
+                start_at: self.start_at,
+                end_at: self.end_at,
+                offset: self.offset,
+                limit: self.limit,
+            }
+        }
+    }
+
+    /// A value computed over every document matching a query
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Aggregation {
+        /// The number of matching documents, stopping at `up_to` when given
+        Count { up_to: Option<i64> },
+        /// The sum of the numeric values of `field`, other values are ignored.
+        /// An integer unless a double was summed or the sum overflowed.
+        Sum { field: String },
+        /// The mean of the numeric values of `field` as a double,
+        /// null when no document has a numeric value there
+        Average { field: String },
+    }
+
+    impl Aggregation {
+        pub(crate) fn into_rest(self, alias: String) -> RestAggregation {
+            let operator = match self {
+                Aggregation::Count { up_to } => AggregationOperator::Count(Count { up_to }),
+                Aggregation::Sum { field } => AggregationOperator::Sum(FieldAggregation {
+                    field: FieldReference { field_path: field },
+                }),
+                Aggregation::Average { field } => AggregationOperator::Average(FieldAggregation {
+                    field: FieldReference { field_path: field },
+                }),
+            };
+            RestAggregation { alias, operator }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::api::Array;
+
+        fn parsed(expression: &str) -> (String, Operator, FirestoreType) {
+            let filter = Filter::parse(expression).unwrap();
+            (filter.field, filter.op, filter.value)
+        }
+
+        #[test]
+        fn picks_the_first_and_longest_operator() {
+            assert_eq!(
+                parsed("age <= 21"),
+                (
+                    String::from("age"),
+                    Operator::LessThanOrEqual,
+                    FirestoreType::Integer(21)
+                )
+            );
+            assert_eq!(parsed("age < 21").1, Operator::LessThan);
+            assert_eq!(parsed("age >= 21").1, Operator::GreaterThanOrEqual);
+            assert_eq!(
+                parsed("title == Lost in Space"),
+                (
+                    String::from("title"),
+                    Operator::Equal,
+                    FirestoreType::String(String::from("Lost in Space"))
+                )
+            );
+            assert_eq!(
+                parsed("tags array-contains-any [1]").1,
+                Operator::ArrayContainsAny
+            );
+        }
+
+        #[test]
+        fn parses_lists_of_values() {
+            let strings = |values: &[&str]| {
+                FirestoreType::Array(Array {
+                    values: values
+                        .iter()
+                        .map(|value| FirestoreType::String(value.to_string()))
+                        .collect(),
+                })
+            };
+            assert_eq!(
+                parsed(r#"x in ["a","b"]"#),
+                (String::from("x"), Operator::In, strings(&["a", "b"]))
+            );
+            assert_eq!(parsed(r#"x not-in ["a"]"#).1, Operator::NotIn);
+        }
+
+        #[test]
+        fn rejects_expressions_missing_a_part() {
+            for expression in &["== 1", "age ==", "  <  ", "age 21"] {
+                assert!(
+                    Filter::parse(expression).is_err(),
+                    "{} was accepted",
+                    expression
+                );
+            }
+        }
+
+        #[test]
+        fn sends_null_and_nan_comparisons_as_unary_filters() {
+            let rest = |expression: &str| {
+                serde_json::to_value(Filter::parse(expression).unwrap().into_rest()).unwrap()
+            };
+            assert_eq!(
+                rest("deleted == null"),
+                json!({ "unaryFilter": { "op": "IS_NULL", "field": { "fieldPath": "deleted" } } })
+            );
+            assert_eq!(
+                rest("deleted != null"),
+                json!({ "unaryFilter": { "op": "IS_NOT_NULL", "field": { "fieldPath": "deleted" } } })
+            );
+            assert_eq!(
+                rest("score == NaN"),
+                json!({ "unaryFilter": { "op": "IS_NAN", "field": { "fieldPath": "score" } } })
+            );
+            assert_eq!(
+                rest("score != NaN"),
+                json!({ "unaryFilter": { "op": "IS_NOT_NAN", "field": { "fieldPath": "score" } } })
+            );
+            assert_eq!(rest("score != 1")["fieldFilter"]["op"], "NOT_EQUAL");
+        }
+
+        #[test]
+        fn types_literals() {
+            let string = |value: &str| FirestoreType::String(value.to_string());
+            assert_eq!(parse_literal("'42'"), string("42"));
+            assert_eq!(parse_literal("''"), string(""));
+            assert_eq!(
+                parse_literal("2022-01-01T12:00:00+02:00"),
+                FirestoreType::Timestamp(Utc.with_ymd_and_hms(2022, 1, 1, 10, 0, 0).unwrap())
+            );
+            assert_eq!(
+                parse_literal("2022-01-01"),
+                FirestoreType::Timestamp(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap())
+            );
+            assert_eq!(parse_literal("42"), FirestoreType::Integer(42));
+            assert_eq!(parse_literal("1.5"), FirestoreType::Double(1.5));
+            assert_eq!(parse_literal("true"), FirestoreType::Boolean(true));
+            assert_eq!(parse_literal("null"), FirestoreType::Null(()));
+            assert_eq!(parse_literal(r#""2022-01-01""#), string("2022-01-01"));
+            assert_eq!(parse_literal("Austin"), string("Austin"));
+            assert_eq!(parse_literal("2022-13-01"), string("2022-13-01"));
+        }
+    }
+}
+
+/// A change computed by the server for a single field, applied after
+/// the other fields of an update have been written
+#[derive(Debug, Clone)]
+pub enum FieldTransform {
+    /// Sets the field to the time at which the server processed the write
+    ServerTimestamp,
+    /// Adds to an integer or double field, a missing field counts as zero
+    Increment(FirestoreType),
+    /// Keeps the greater of the field and the value
+    Maximum(FirestoreType),
+    /// Keeps the lesser of the field and the value
+    Minimum(FirestoreType),
+    /// Appends the elements not yet present in the array field
+    ArrayUnion(Vec<FirestoreType>),
+    /// Removes every occurrence of the elements from the array field
+    ArrayRemove(Vec<FirestoreType>),
+}
+
+impl FieldTransform {
+    /// Resolves the references given as document paths, see `FirestoreType::Reference`
+    fn resolve_references(self, documents_path: &str) -> FieldTransform {
+        let resolve = |values: Vec<FirestoreType>| {
+            values
+                .into_iter()
+                .map(|value| value.resolve_references(documents_path))
+                .collect()
+        };
+        match self {
+            FieldTransform::Maximum(value) => {
+                FieldTransform::Maximum(value.resolve_references(documents_path))
+            }
+            FieldTransform::Minimum(value) => {
+                FieldTransform::Minimum(value.resolve_references(documents_path))
+            }
+            FieldTransform::ArrayUnion(values) => FieldTransform::ArrayUnion(resolve(values)),
+            FieldTransform::ArrayRemove(values) => FieldTransform::ArrayRemove(resolve(values)),
+            transform => transform,
+        }
     }
 
-    /// Create a new instance that uses project_id as anchoring context
-    pub fn new<S>(project_id: S, service_account_path: S) -> Result<DatabaseContext, String>
+    fn into_rest(self, field_path: String) -> RestFieldTransform {
+        let transform = match self {
+            FieldTransform::ServerTimestamp => {
+                TransformType::SetToServerValue(ServerValue::RequestTime)
+            }
+            FieldTransform::Increment(value) => TransformType::Increment(value),
+            FieldTransform::Maximum(value) => TransformType::Maximum(value),
+            FieldTransform::Minimum(value) => TransformType::Minimum(value),
+            FieldTransform::ArrayUnion(values) => {
+                TransformType::AppendMissingElements(Array { values })
+            }
+            FieldTransform::ArrayRemove(values) => {
+                TransformType::RemoveAllFromArray(Array { values })
+            }
+        };
+        RestFieldTransform {
+            field_path,
+            transform,
+        }
+    }
+}
+
+/// Splits the slash-delimited path of a document into the path of its collection and its ID
+pub fn split_document_path(path: &str) -> (&str, &str) {
+    let slash = path
+        .rfind('/')
+        .expect("document paths have at least two segments");
+    (&path[..slash], &path[slash + 1..])
+}
+
+/// The precondition of an update, which requires the document to exist unless
+/// `precondition` requires more, such as it having been updated last at a given time
+pub fn update_precondition(precondition: Precondition) -> Precondition {
+    if precondition.exists.is_none() && precondition.update_time.is_none() {
+        return Precondition {
+            exists: Some(true),
+            ..Precondition::default()
+        };
+    }
+    precondition
+}
+
+/// Whether `error` is Firestore aborting a transaction because of contention
+fn is_aborted(error: &Error) -> bool {
+    match error {
+        Error::Aborted { .. } => true,
+        _ => false,
+    }
+}
+
+/// A read-write transaction started by `DatabaseContext::run_transaction`.
+/// Reads see a consistent snapshot and writes are buffered until the transaction commits.
+pub struct Transaction<'a> {
+    id: String,
+    batch: WriteBatch<'a>,
+}
+
+impl<'a> Transaction<'a> {
+    /// GETs a document from said collection as part of the transaction
+    pub fn get_document<S>(&self, collection_name: S, document_id: S) -> Result<Document>
     where
         S: Into<String>,
     {
-        // ensure String types
-        let project_id = project_id.into();
-        let service_account_path = service_account_path.into();
+        let ctx = self.batch.ctx;
+        let name = ctx.document_path(&*collection_name.into(), &*document_id.into());
+        ctx.get_document_by_name(name, Some(self.id.clone()), None)
+    }
 
-        // get jwt & credentials from file
-        let credentials = goauth::credentials::Credentials::from_file(&*service_account_path)
-            .map_err(|_| "Failed to load credentials from file")?;
-        let claims = JwtClaims::new(
-            credentials.iss(),
-            &Scope::DataStore,
-            credentials.token_uri(),
-            None,
-            None,
-        );
-        let jwt = Jwt::new(
-            claims,
-            credentials
-                .rsa_key()
-                .map_err(|_| "Failed to get RSA private key from credentials")?,
-            None,
+    /// Runs a structured query as part of the transaction
+    pub fn run_query(&self, query: query::Query) -> Result<Vec<Document>> {
+        self.batch.ctx.run_query_in(query, Some(self.id.clone()))
+    }
+
+    /// The writes buffered so far, to be applied when the transaction commits
+    pub fn writes(&mut self) -> &mut WriteBatch<'a> {
+        &mut self.batch
+    }
+
+    /// Buffers a write as `WriteBatch::set` does
+    pub fn set<S>(
+        &mut self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        merge: bool,
+    ) -> &mut Transaction<'a>
+    where
+        S: Into<String>,
+    {
+        self.batch.set(collection_name, document_id, fields, merge);
+        self
+    }
+
+    /// Buffers a write as `WriteBatch::update` does
+    pub fn update<S>(
+        &mut self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        field_paths: Vec<String>,
+    ) -> &mut Transaction<'a>
+    where
+        S: Into<String>,
+    {
+        self.batch
+            .update(collection_name, document_id, fields, field_paths);
+        self
+    }
+
+    /// Buffers a write as `WriteBatch::update_with_transforms` does
+    pub fn update_with_transforms<S>(
+        &mut self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        field_paths: Vec<String>,
+        transforms: Vec<(String, FieldTransform)>,
+    ) -> &mut Transaction<'a>
+    where
+        S: Into<String>,
+    {
+        self.batch.update_with_transforms(
+            collection_name,
+            document_id,
+            fields,
+            field_paths,
+            transforms,
         );
-        // cool, we have a token
-        let auth_token = goauth::get_token_with_creds(&jwt, &credentials)
-            .map_err(|_| "Failed to authenticate")?;
-        let client = reqwest::Client::new();
-        // return success
-        Ok(DatabaseContext {
-            client,
-            project_id,
-            auth_token,
-        })
+        self
+    }
+
+    /// Buffers a delete as `WriteBatch::delete` does
+    pub fn delete<S>(&mut self, collection_name: S, document_id: S) -> &mut Transaction<'a>
+    where
+        S: Into<String>,
+    {
+        self.batch.delete(collection_name, document_id);
+        self
+    }
+}
+
+/// Writes collected to be applied atomically by a single commit,
+/// at most `MAX_WRITES_PER_COMMIT` of them
+pub struct WriteBatch<'a> {
+    ctx: &'a DatabaseContext,
+    writes: Vec<Write>,
+}
+
+impl<'a> WriteBatch<'a> {
+    /// Writes `fields` to a document as `DatabaseContext::set_document` does
+    pub fn set<S>(
+        &mut self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        merge: bool,
+    ) -> &mut WriteBatch<'a>
+    where
+        S: Into<String>,
+    {
+        let update_mask = if merge {
+            Some(DocumentMask {
+                field_paths: fields.field_paths(),
+            })
+        } else {
+            None
+        };
+        let name = self
+            .ctx
+            .document_path(&*collection_name.into(), &*document_id.into());
+        let fields = fields.resolve_references(&self.ctx.documents_path());
+        self.writes.push(Write {
+            operation: WriteOperation::Update(firestore::write::Document { name, fields }),
+            update_mask,
+            update_transforms: Vec::new(),
+            current_document: None,
+        });
+        self
+    }
+
+    /// Creates a document as `DatabaseContext::create_document` does
+    pub fn create<S>(
+        &mut self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+    ) -> &mut WriteBatch<'a>
+    where
+        S: Into<String>,
+    {
+        let name = self
+            .ctx
+            .document_path(&*collection_name.into(), &*document_id.into());
+        let fields = fields.resolve_references(&self.ctx.documents_path());
+        self.writes.push(Write {
+            operation: WriteOperation::Update(firestore::write::Document { name, fields }),
+            update_mask: None,
+            update_transforms: Vec::new(),
+            current_document: Some(Precondition {
+                exists: Some(false),
+                ..Precondition::default()
+            }),
+        });
+        self
+    }
+
+    /// Updates the fields of an existing document as `DatabaseContext::update_document` does
+    pub fn update<S>(
+        &mut self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        field_paths: Vec<String>,
+    ) -> &mut WriteBatch<'a>
+    where
+        S: Into<String>,
+    {
+        self.update_with_transforms(
+            collection_name,
+            document_id,
+            fields,
+            field_paths,
+            Vec::new(),
+        )
+    }
+
+    /// Updates the fields of an existing document, then applies `transforms`,
+    /// given as field paths along with their transform, in the same write
+    pub fn update_with_transforms<S>(
+        &mut self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        field_paths: Vec<String>,
+        transforms: Vec<(String, FieldTransform)>,
+    ) -> &mut WriteBatch<'a>
+    where
+        S: Into<String>,
+    {
+        let name = self
+            .ctx
+            .document_path(&*collection_name.into(), &*document_id.into());
+        let documents_path = self.ctx.documents_path();
+        let fields = fields.resolve_references(&documents_path);
+        self.writes.push(Write {
+            operation: WriteOperation::Update(firestore::write::Document { name, fields }),
+            update_mask: Some(DocumentMask { field_paths }),
+            update_transforms: transforms
+                .into_iter()
+                .map(|(field_path, transform)| {
+                    transform
+                        .resolve_references(&documents_path)
+                        .into_rest(field_path)
+                })
+                .collect(),
+            current_document: Some(Precondition {
+                exists: Some(true),
+                ..Precondition::default()
+            }),
+        });
+        self
+    }
+
+    /// Deletes a document, succeeding even if it does not exist
+    pub fn delete<S>(&mut self, collection_name: S, document_id: S) -> &mut WriteBatch<'a>
+    where
+        S: Into<String>,
+    {
+        let name = self
+            .ctx
+            .document_path(&*collection_name.into(), &*document_id.into());
+        self.writes.push(Write {
+            operation: WriteOperation::Delete(name),
+            update_mask: None,
+            update_transforms: Vec::new(),
+            current_document: None,
+        });
+        self
+    }
+
+    /// Makes the write buffered last conditional on `precondition`, in place of the
+    /// precondition it had, which an empty precondition removes
+    pub fn with_precondition(&mut self, precondition: Precondition) -> &mut WriteBatch<'a> {
+        if let Some(write) = self.writes.last_mut() {
+            write.current_document =
+                if precondition.exists.is_none() && precondition.update_time.is_none() {
+                    None
+                } else {
+                    Some(precondition)
+                };
+        }
+        self
+    }
+
+    /// Number of writes in the batch
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Takes the buffered writes, to be committed elsewhere
+    pub(crate) fn into_writes(self) -> Vec<Write> {
+        self.writes
+    }
+
+    /// Applies every write atomically, failing without writing anything
+    /// if the batch holds more than `MAX_WRITES_PER_COMMIT` writes
+    pub fn commit(self) -> Result<CommitResponse> {
+        if self.writes.len() > MAX_WRITES_PER_COMMIT {
+            return Err(Error::InvalidArgument {
+                reason: format!(
+                    "a batch holds at most {} writes, got {}",
+                    MAX_WRITES_PER_COMMIT,
+                    self.writes.len()
+                ),
+            });
+        }
+        self.ctx.commit(self.writes)
+    }
+}
+
+pub mod batch_get {
+    #[derive(Serialize)]
+    pub struct Request {
+        documents: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Response {
+        transaction: String,
+        read_time: String,
+        found: Option<super::Document>,
+        missing: Option<String>,
+    }
+}
+
+/// A field whose timestamp expires the documents of a collection group
+#[derive(Debug, Clone, PartialEq)]
+pub struct TtlPolicy {
+    /// ID of the collections whose documents expire
+    pub collection_group: String,
+    pub field_path: String,
+    /// Such as `CREATING` or `ACTIVE`
+    pub state: Option<String>,
+}
+
+impl DatabaseContext {
+    /// Creates a header map with proper authorization
+    pub(crate) fn auth_header_map(&self) -> Result<reqwest::header::HeaderMap> {
+        let mut map = reqwest::header::HeaderMap::new();
+        let str = &*self.get_authorization_key()?;
+        map.insert(
+            reqwest::header::AUTHORIZATION,
+            str.parse().map_err(|_| Error::InvalidHeader {
+                name: reqwest::header::AUTHORIZATION.to_string(),
+            })?,
+        );
+        Ok(map)
+    }
+
+    /// Settings of a context to connect with, such as
+    /// `DatabaseContext::builder().project("my-project").credentials_file("key.json").build()`
+    pub fn builder() -> DatabaseContextBuilder {
+        DatabaseContextBuilder::default()
+    }
+
+    /// Create a new instance that uses project_id as anchoring context,
+    /// the same as building one with `credentials_file`
+    pub fn new<S>(project_id: S, service_account_path: S) -> Result<DatabaseContext>
+    where
+        S: Into<String>,
+    {
+        DatabaseContext::builder()
+            .project(project_id)
+            .credentials_file(service_account_path.into())
+            .build()
+    }
+
+    /// Create a new instance, finding credentials the way Application Default Credentials do:
+    /// `service_account_path`, then `GOOGLE_APPLICATION_CREDENTIALS`, then the gcloud
+    /// ADC file and finally the metadata server
+    pub fn from_environment<S>(
+        project_id: S,
+        service_account_path: Option<String>,
+    ) -> Result<DatabaseContext>
+    where
+        S: Into<String>,
+    {
+        let source = CredentialSource::resolve(service_account_path);
+        DatabaseContext::with_credentials(project_id, source)
+    }
+
+    /// Create a new instance authenticated by `source`
+    pub fn with_credentials<S>(project_id: S, source: CredentialSource) -> Result<DatabaseContext>
+    where
+        S: Into<String>,
+    {
+        DatabaseContext::with_token_cache(project_id, TokenCache::new(source))
+    }
+
+    /// Create a new instance authenticated by the tokens of `tokens`. The first token
+    /// is minted right away, so that unusable credentials are reported here.
+    pub fn with_token_cache<S>(project_id: S, tokens: TokenCache) -> Result<DatabaseContext>
+    where
+        S: Into<String>,
+    {
+        let client = http_client(Some(DEFAULT_TIMEOUT));
+        tokens.access_token(&client)?;
+        Ok(DatabaseContext {
+            client,
+            timeout: Some(DEFAULT_TIMEOUT),
+            project_id: project_id.into(),
+            database_id: String::from(DEFAULT_DATABASE_NAME),
+            tokens: Some(Arc::new(tokens)),
+            base_url: String::from(firestore::FIRESTORE_BASE_1),
+            retry_policy: RetryPolicy::default(),
+            write_sink: None,
+            concurrency: 1,
+            write_limiter: Arc::new(RateLimiter::new(None)),
+            read_time: None,
+        })
+    }
+
+    /// Create a new instance that talks to the Firestore emulator listening on `host`
+    /// (e.g. `localhost:8080`), no credentials are required
+    pub fn emulator<S>(project_id: S, host: S) -> DatabaseContext
+    where
+        S: Into<String>,
+    {
+        DatabaseContext {
+            client: http_client(Some(DEFAULT_TIMEOUT)),
+            timeout: Some(DEFAULT_TIMEOUT),
+            project_id: project_id.into(),
+            database_id: String::from(DEFAULT_DATABASE_NAME),
+            tokens: None,
+            base_url: format!("http://{}/v1", host.into()),
+            retry_policy: RetryPolicy::default(),
+            write_sink: None,
+            concurrency: 1,
+            write_limiter: Arc::new(RateLimiter::new(None)),
+            read_time: None,
+        }
+    }
+
+    /// Uses the database `database_id` of the project instead of the default one
+    pub fn with_database<S>(mut self, database_id: S) -> DatabaseContext
+    where
+        S: Into<String>,
+    {
+        self.database_id = database_id.into();
+        self
+    }
+
+    /// Replaces the policy transient failures are retried with
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> DatabaseContext {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Hands every write to `sink` instead of committing it. Writes that would
+    /// return the written document return one holding only the written fields.
+    pub fn with_write_sink(mut self, sink: Box<dyn WriteSink>) -> DatabaseContext {
+        self.write_sink = Some(Arc::from(sink));
+        self
+    }
+
+    /// Lets bulk operations such as `delete_collection` and `copy_document` have up to
+    /// `concurrency` commits in flight at once, each on its own thread. One by default.
+    pub fn with_concurrency(mut self, concurrency: usize) -> DatabaseContext {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Fails requests that take longer than `timeout` instead of waiting on a hung
+    /// connection, or lets them wait forever when `None`. Thirty seconds by default.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> DatabaseContext {
+        self.client = http_client(timeout);
+        self.timeout = timeout;
+        self
+    }
+
+    /// Longest a request may take, `None` when requests wait forever
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Makes reads see the database as it was at `time` rather than its latest state.
+    /// Firestore keeps an hour of versions, or seven days with point-in-time recovery
+    /// enabled, and takes whole minutes past the last hour. Writes are left unchanged.
+    pub fn read_at(mut self, time: DateTime<Utc>) -> DatabaseContext {
+        self.read_time = Some(time);
+        self
+    }
+
+    /// Time reads see the database at, `None` for its latest state
+    pub fn read_time(&self) -> Option<DateTime<Utc>> {
+        self.read_time
+    }
+
+    /// The read time of a read, reads in a transaction see the time of the transaction
+    fn read_time_outside(&self, transaction: &Option<String>) -> Option<DateTime<Utc>> {
+        match transaction {
+            Some(_) => None,
+            None => self.read_time,
+        }
+    }
+
+    /// Most commits bulk operations have in flight at once
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    /// Keeps bulk operations under `rate` writes per second, across every thread.
+    /// Without a limit they go as fast as Firestore lets them, slowing down once
+    /// it answers RESOURCE_EXHAUSTED either way.
+    pub fn with_max_writes_per_second(mut self, rate: f64) -> DatabaseContext {
+        self.write_limiter = Arc::new(RateLimiter::new(Some(rate)));
+        self
+    }
+
+    /// ID of the database within the project
+    pub fn database_id(&self) -> &str {
+        &*self.database_id
+    }
+
+    /// Whether writes go to a sink rather than to Firestore
+    pub fn is_dry_run(&self) -> bool {
+        self.write_sink.is_some()
+    }
+
+    /// Stands in for the document returned by a write that went to the sink
+    fn unwritten_document(&self, name: String, fields: FirestoreFields) -> Document {
+        let now = Utc::now();
+        Document {
+            name,
+            fields,
+            create_time: now,
+            update_time: now,
+        }
+    }
+
+    /// Calls `request` until it succeeds, fails with an error that is not transient
+    /// or runs out of attempts, sleeping between attempts as the retry policy says.
+    /// Unless `idempotent` is set the request is only retried if the policy allows
+    /// retrying writes.
+    fn retrying<F, T>(&self, idempotent: bool, mut request: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let policy = &self.retry_policy;
+        let max_attempts = if idempotent || policy.retry_writes {
+            policy.max_attempts
+        } else {
+            1
+        };
+        let mut attempt = 1;
+        loop {
+            match request() {
+                Err(ref e) if e.is_transient() && attempt < max_attempts => {
+                    let backoff = policy.backoff(attempt);
+                    debug!(
+                        "attempt {} failed with {}, retrying in {:?}",
+                        attempt, e, backoff
+                    );
+                    thread::sleep(backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Resource name of the database this context operates on
+    pub(crate) fn database_path(&self) -> String {
+        format!(
+            "projects/{}/databases/{}",
+            self.project_id, self.database_id
+        )
+    }
+
+    /// Resource name of the root of the document tree
+    pub(crate) fn documents_path(&self) -> String {
+        format!("{}/documents", self.database_path())
+    }
+
+    /// Resource name of the document at the slash-delimited `path`,
+    /// the root of the document tree when `path` is empty
+    pub(crate) fn parent_path(&self, path: &str) -> String {
+        if path.is_empty() {
+            self.documents_path()
+        } else {
+            format!("{}/{}", self.documents_path(), path)
+        }
+    }
+
+    /// A reference to the document at the slash-delimited `document_path` of this
+    /// database, as stored in reference fields and used in query cursors
+    pub fn reference<S: AsRef<str>>(&self, document_path: S) -> FirestoreType {
+        FirestoreType::Reference(format!(
+            "{}/{}",
+            self.documents_path(),
+            document_path.as_ref()
+        ))
+    }
+
+    /// Resource name of a document inside of a collection
+    pub(crate) fn document_path(&self, collection_name: &str, document_id: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            self.documents_path(),
+            collection_name,
+            document_id
+        )
+    }
+
+    /// GETs a document from said collection
+    pub fn get_document<S>(&self, collection_name: S, document_id: S) -> Result<Document>
+    where
+        S: Into<String>,
+    {
+        let name = self.document_path(&*collection_name.into(), &*document_id.into());
+        self.get_document_by_name(name, None, None)
+    }
+
+    /// GETs only the fields named by `field_paths` of a document from said collection
+    pub fn get_document_fields<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        field_paths: Vec<String>,
+    ) -> Result<Document>
+    where
+        S: Into<String>,
+    {
+        let name = self.document_path(&*collection_name.into(), &*document_id.into());
+        self.get_document_by_name(name, None, Some(field_paths))
+    }
+
+    /// GETs a document from said collection and deserializes its fields into `T`
+    pub fn get_as<T, S>(&self, collection_name: S, document_id: S) -> Result<T>
+    where
+        T: DeserializeOwned,
+        S: Into<String>,
+    {
+        self.get_document(collection_name, document_id)?
+            .deserialize()
+    }
+
+    /// GETs a document by its resource name, optionally as part of `transaction`
+    /// and only with the fields of `mask`
+    fn get_document_by_name(
+        &self,
+        name: String,
+        transaction: Option<String>,
+        mask: Option<Vec<String>>,
+    ) -> Result<Document> {
+        self.retrying(true, || {
+            firestore::documents::get(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::GetDocumentQuery {
+                    name: name.clone(),
+                    transaction: transaction.clone(),
+                    read_time: self.read_time_outside(&transaction),
+                    mask: mask.clone(),
+                },
+            )
+        })
+    }
+
+    /// Whether said document exists, without reading any of its fields
+    pub fn document_exists<S>(&self, collection_name: S, document_id: S) -> Result<bool>
+    where
+        S: Into<String>,
+    {
+        let name = self.document_path(&*collection_name.into(), &*document_id.into());
+        // masking every field but the name keeps the response small
+        let mask = Some(vec![String::from("__name__")]);
+        match self.get_document_by_name(name, None, mask) {
+            Ok(_) => Ok(true),
+            Err(Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Deletes a document from said collection
+    pub fn delete_document<S>(&self, collection_name: S, document_id: S) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        self.delete_document_if(collection_name, document_id, Precondition::default())
+    }
+
+    /// Deletes a document as `delete_document` does, failing unless it meets
+    /// `precondition`, such as having been updated last at a given time
+    pub fn delete_document_if<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        precondition: Precondition,
+    ) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        let (collection_name, document_id) = (collection_name.into(), document_id.into());
+        if self.is_dry_run() {
+            let mut batch = self.batch();
+            batch
+                .delete(collection_name, document_id)
+                .with_precondition(precondition);
+            return batch.commit().map(|_| ());
+        }
+        let name = self.document_path(&*collection_name, &*document_id);
+        self.retrying(false, || {
+            firestore::documents::delete(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::DeleteDocumentQuery {
+                    name: name.clone(),
+                    current_document: precondition.clone(),
+                },
+            )
+        })
+    }
+
+    /// Lists the IDs of every subcollection of said document
+    pub fn list_collection_ids<S>(&self, collection_name: S, document_id: S) -> Result<Vec<String>>
+    where
+        S: Into<String>,
+    {
+        let parent = self.document_path(&*collection_name.into(), &*document_id.into());
+        self.list_collection_ids_of(parent)
+    }
+
+    /// Lists the IDs of every collection at the root of the database
+    pub fn list_root_collection_ids(&self) -> Result<Vec<String>> {
+        self.list_collection_ids_of(self.documents_path())
+    }
+
+    /// Lists the IDs of every collection directly below the resource named `parent`
+    fn list_collection_ids_of(&self, parent: String) -> Result<Vec<String>> {
+        let mut collection_ids = Vec::new();
+        let mut page_token = None;
+        loop {
+            let response = self.retrying(true, || {
+                firestore::documents::list_collection_ids(
+                    self.client.clone(),
+                    &*self.base_url,
+                    self.auth_header_map()?,
+                    firestore::documents::ListCollectionIdsQuery {
+                        parent: parent.clone(),
+                        page_size: None,
+                        page_token: page_token.clone(),
+                        read_time: self.read_time,
+                    },
+                )
+            })?;
+            collection_ids.extend(response.collection_ids);
+            match response.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => return Ok(collection_ids),
+            }
+        }
+    }
+
+    /// Deletes every document of said collection in batched commits, calling `progress`
+    /// with the running total of deleted documents after each commit.
+    /// When `recursive` is set the subcollections of every document are deleted as well,
+    /// documents that only exist as the parent of a subcollection are not visited.
+    /// Up to `concurrency` commits are in flight at once.
+    pub fn delete_collection<S, F>(
+        &self,
+        collection_name: S,
+        recursive: bool,
+        progress: F,
+    ) -> Result<usize>
+    where
+        S: Into<String>,
+        F: FnMut(usize),
+    {
+        let mut pool = CommitPool::new(self, progress);
+        self.delete_collection_tree(&*collection_name.into(), recursive, &mut pool)?;
+        pool.finish()
+    }
+
+    /// Deletes the subcollections of said document and then the document itself,
+    /// reporting progress as `delete_collection` does
+    pub fn delete_document_recursive<S, F>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        progress: F,
+    ) -> Result<usize>
+    where
+        S: Into<String>,
+        F: FnMut(usize),
+    {
+        let collection_name = collection_name.into();
+        let document_id = document_id.into();
+        let mut pool = CommitPool::new(self, progress);
+        self.delete_subcollections(&*collection_name, &*document_id, &mut pool)?;
+        let mut batch = self.batch();
+        batch.delete(collection_name, document_id);
+        pool.submit(batch)?;
+        pool.finish()
+    }
+
+    fn delete_subcollections(
+        &self,
+        collection_name: &str,
+        document_id: &str,
+        pool: &mut CommitPool,
+    ) -> Result<()> {
+        for collection_id in self.list_collection_ids(collection_name, document_id)? {
+            let path = format!("{}/{}/{}", collection_name, document_id, collection_id);
+            self.delete_collection_tree(&*path, true, pool)?;
+        }
+        Ok(())
+    }
+
+    fn delete_collection_tree(
+        &self,
+        collection_name: &str,
+        recursive: bool,
+        pool: &mut CommitPool,
+    ) -> Result<()> {
+        // page tokens carry on after the last document listed, so deleting
+        // the documents of earlier pages does not disturb the listing
+        let mut batch = self.batch();
+        for document in self.iter_documents(collection_name, Some(MAX_WRITES_PER_COMMIT as i32)) {
+            let document = document?;
+            if recursive {
+                self.delete_subcollections(collection_name, document.id(), pool)?;
+            }
+            batch.delete(collection_name, document.id());
+            if batch.len() == MAX_WRITES_PER_COMMIT {
+                pool.submit(mem::replace(&mut batch, self.batch()))?;
+            }
+        }
+        pool.submit(batch)
+    }
+
+    /// Copies said document to the document `target_id` of `target_collection`,
+    /// overwriting it, and calls `progress` with the running total of copied
+    /// documents after each commit. When `recursive` is set the subcollections
+    /// are copied as well, documents that only exist as the parent of a
+    /// subcollection are not visited. Up to `concurrency` commits are in flight at once.
+    pub fn copy_document<S, F>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        target_collection: S,
+        target_id: S,
+        recursive: bool,
+        progress: F,
+    ) -> Result<usize>
+    where
+        S: Into<String>,
+        F: FnMut(usize),
+    {
+        self.copy_document_to(
+            self,
+            collection_name,
+            document_id,
+            target_collection,
+            target_id,
+            recursive,
+            progress,
+        )
+    }
+
+    /// Copies said document as `copy_document` does, writing the copy to the
+    /// database of `destination`, which may belong to another project.
+    /// The concurrency of `destination` applies.
+    pub fn copy_document_to<S, F>(
+        &self,
+        destination: &DatabaseContext,
+        collection_name: S,
+        document_id: S,
+        target_collection: S,
+        target_id: S,
+        recursive: bool,
+        progress: F,
+    ) -> Result<usize>
+    where
+        S: Into<String>,
+        F: FnMut(usize),
+    {
+        let (collection_name, document_id) = (collection_name.into(), document_id.into());
+        let source = format!("{}/{}", collection_name, document_id);
+        let target = format!("{}/{}", target_collection.into(), target_id.into());
+        if recursive
+            && destination.database_path() == self.database_path()
+            && target.starts_with(&format!("{}/", source))
+        {
+            return Err(Error::InvalidArgument {
+                reason: format!("cannot copy {} into its own subcollection", source),
+            });
+        }
+        let mut pool = CommitPool::new(destination, progress);
+        let mut batch = destination.batch();
+        self.copy_document_tree(
+            self.get_document(&*collection_name, &*document_id)?,
+            &source,
+            &target,
+            recursive,
+            destination,
+            &mut batch,
+            &mut pool,
+        )?;
+        pool.submit(batch)?;
+        pool.finish()
+    }
+
+    /// Moves said document to the document `target_id` of `target_collection` by
+    /// copying it and deleting the original inside of a single transaction,
+    /// returning the number of documents moved. When `recursive` is set its
+    /// subcollections are moved along, the whole tree has to fit in one commit.
+    pub fn move_document<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        target_collection: S,
+        target_id: S,
+        recursive: bool,
+    ) -> Result<usize>
+    where
+        S: Into<String>,
+    {
+        let source = format!("{}/{}", collection_name.into(), document_id.into());
+        let target = format!("{}/{}", target_collection.into(), target_id.into());
+        if source == target || target.starts_with(&format!("{}/", source)) {
+            return Err(Error::InvalidArgument {
+                reason: format!("cannot move {} onto itself or below it", source),
+            });
+        }
+        let documents_prefix = format!("{}/", self.documents_path());
+        self.run_transaction(|transaction| {
+            let (collection_name, document_id) = split_document_path(&source);
+            let mut documents = vec![transaction.get_document(collection_name, document_id)?];
+            if recursive {
+                documents.extend(transaction.run_query(query::Query::descendants_of(&*source))?);
+            }
+            let moved = documents.len();
+            for document in documents {
+                let path = document.name[documents_prefix.len()..].to_string();
+                let target_path = format!("{}{}", target, &path[source.len()..]);
+                let (target_collection, target_id) = split_document_path(&target_path);
+                transaction.set(target_collection, target_id, document.fields, false);
+                let (collection_name, document_id) = split_document_path(&path);
+                transaction.delete(collection_name, document_id);
+            }
+            Ok(moved)
+        })
+    }
+
+    /// Buffers a copy of `document`, found at `source`, at `target` of `destination` and,
+    /// when `recursive` is set, of its subcollections, submitting `batch` to `pool`
+    /// whenever it fills up
+    fn copy_document_tree<'a>(
+        &self,
+        document: Document,
+        source: &str,
+        target: &str,
+        recursive: bool,
+        destination: &'a DatabaseContext,
+        batch: &mut WriteBatch<'a>,
+        pool: &mut CommitPool,
+    ) -> Result<()> {
+        if batch.len() == MAX_WRITES_PER_COMMIT {
+            pool.submit(mem::replace(batch, destination.batch()))?;
+        }
+        let (target_collection, target_id) = split_document_path(target);
+        batch.set(target_collection, target_id, document.fields, false);
+        if !recursive {
+            return Ok(());
+        }
+        for collection_id in self.list_collection_ids_of(document.name)? {
+            let source_collection = format!("{}/{}", source, collection_id);
+            let target_collection = format!("{}/{}", target, collection_id);
+            for child in
+                self.iter_documents(&*source_collection, Some(MAX_WRITES_PER_COMMIT as i32))
+            {
+                let child = child?;
+                let id = child.id().to_string();
+                self.copy_document_tree(
+                    child,
+                    &format!("{}/{}", source_collection, id),
+                    &format!("{}/{}", target_collection, id),
+                    true,
+                    destination,
+                    batch,
+                    pool,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists the documents of said collection
+    pub fn list_documents<S>(&self, collection_name: S) -> Result<list_documents::Response>
+    where
+        S: Into<String>,
+    {
+        self.list_documents_page(collection_name, None, None)
+    }
+
+    /// Lists a single page of said collection, continuing from `page_token`
+    pub fn list_documents_page<S>(
+        &self,
+        collection_name: S,
+        page_size: Option<i32>,
+        page_token: Option<String>,
+    ) -> Result<list_documents::Response>
+    where
+        S: Into<String>,
+    {
+        self.list_documents_request(collection_name.into(), page_size, page_token, None, None)
+    }
+
+    /// Lists a single page of a collection, sorted by `order_by` and
+    /// with only the fields of `mask` when given
+    fn list_documents_request(
+        &self,
+        collection_id: String,
+        page_size: Option<i32>,
+        page_token: Option<String>,
+        order_by: Option<String>,
+        mask: Option<Vec<String>>,
+    ) -> Result<list_documents::Response> {
+        self.retrying(true, || {
+            firestore::documents::list(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::ListDocumentsQuery {
+                    parent: self.documents_path(),
+                    collection_id: collection_id.clone(),
+                    page_size,
+                    page_token: page_token.clone(),
+                    order_by: order_by.clone(),
+                    mask: mask.clone(),
+                    read_time: self.read_time,
+                },
+            )
+        })
+    }
+
+    /// Iterates over every document of said collection, following page tokens
+    /// until the collection is exhausted
+    pub fn iter_documents<S>(
+        &self,
+        collection_name: S,
+        page_size: Option<i32>,
+    ) -> list_documents::Iter<'_>
+    where
+        S: Into<String>,
+    {
+        list_documents::Iter {
+            ctx: self,
+            collection_id: collection_name.into(),
+            page_size,
+            order_by: None,
+            mask: None,
+            page: Vec::new().into_iter(),
+            next_page_token: Some(String::new()),
+        }
+    }
+
+    /// Runs a structured query, returning every matching document
+    pub fn run_query(&self, query: query::Query) -> Result<Vec<Document>> {
+        self.run_query_in(query, None)
+    }
+
+    /// Asks Firestore how it runs a query, such as the indexes it uses. When `analyze` is
+    /// set the query is run as well, returning the matching documents along with the
+    /// statistics of running it, otherwise no document is read.
+    pub fn explain_query(
+        &self,
+        query: query::Query,
+        analyze: bool,
+    ) -> Result<(Vec<Document>, firestore::documents::ExplainMetrics)> {
+        let parent = self.parent_path(query.parent());
+        let structured_query = query.into_structured_query();
+        let responses = self.retrying(true, || {
+            firestore::documents::run_query(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::RunQueryQuery {
+                    parent: parent.clone(),
+                    structured_query: structured_query.clone(),
+                    transaction: None,
+                    read_time: self.read_time,
+                    explain_options: Some(firestore::documents::ExplainOptions { analyze }),
+                },
+            )
+        })?;
+        let mut documents = Vec::new();
+        let mut metrics = None;
+        for response in responses {
+            documents.extend(response.document);
+            if response.explain_metrics.is_some() {
+                metrics = response.explain_metrics;
+            }
+        }
+        match metrics {
+            Some(metrics) => Ok((documents, metrics)),
+            None => Err(Error::Status {
+                status: String::from("UNIMPLEMENTED"),
+                message: String::from(
+                    "no query plan was returned, the emulator does not explain queries",
+                ),
+            }),
+        }
+    }
+
+    /// Runs a structured query, yielding the matching documents as they arrive so that
+    /// the first ones are at hand right away and memory stays flat however many match.
+    /// Only opening the stream is retried, an error midway ends it.
+    pub fn stream_query(&self, query: query::Query) -> Result<QueryStream> {
+        let parent = self.parent_path(query.parent());
+        let structured_query = query.into_structured_query();
+        let response = self.retrying(true, || {
+            firestore::documents::run_query_stream(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::RunQueryQuery {
+                    parent: parent.clone(),
+                    structured_query: structured_query.clone(),
+                    transaction: None,
+                    read_time: self.read_time,
+                    explain_options: None,
+                },
+            )
+        })?;
+        Ok(QueryStream {
+            reader: BufReader::new(response),
+            done: false,
+        })
+    }
+
+    /// Follows the results of a query by running it every `interval`, yielding every
+    /// matching document as added and then the changes found by each run. Firestore only
+    /// serves its Listen stream over gRPC, so changes are polled for over REST instead.
+    pub fn watch(&self, query: query::Query, interval: Duration) -> watch::Changes<'_> {
+        watch::Changes {
+            ctx: self,
+            query,
+            interval,
+            seen: None,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Picks up to `count` documents of a collection at random, one query each.
+    /// Each pick is the first document named after a random auto ID, so the sample
+    /// is only uniform when the collection uses auto IDs.
+    pub fn sample<S>(&self, collection_name: S, count: usize) -> Result<Vec<Document>>
+    where
+        S: Into<String>,
+    {
+        let collection_name = collection_name.into();
+        let mut names = HashSet::new();
+        let mut documents = Vec::with_capacity(count);
+        // picks repeat in small collections, so give up after a few misses per document
+        let mut attempts = count * SAMPLE_ATTEMPTS;
+        while documents.len() < count && attempts > 0 {
+            attempts -= 1;
+            let id = auto_id();
+            let after = query::Query::new(&*collection_name)
+                .order_by("__name__", query::Direction::Ascending)
+                .start_at(vec![self.reference(format!("{}/{}", collection_name, id))])
+                .limit(1);
+            let mut picked = self.run_query(after)?;
+            if picked.is_empty() {
+                // wrap around to the start of the collection
+                picked = self.run_query(query::Query::new(&*collection_name).limit(1))?;
+            }
+            match picked.pop() {
+                None => break,
+                Some(document) => {
+                    if names.insert(document.name.clone()) {
+                        documents.push(document);
+                    }
+                }
+            }
+        }
+        Ok(documents)
+    }
+
+    /// Runs a structured query, optionally as part of `transaction`
+    fn run_query_in(
+        &self,
+        query: query::Query,
+        transaction: Option<String>,
+    ) -> Result<Vec<Document>> {
+        let parent = self.parent_path(query.parent());
+        let structured_query = query.into_structured_query();
+        let responses = self.retrying(true, || {
+            firestore::documents::run_query(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::RunQueryQuery {
+                    parent: parent.clone(),
+                    structured_query: structured_query.clone(),
+                    transaction: transaction.clone(),
+                    read_time: self.read_time_outside(&transaction),
+                    explain_options: None,
+                },
+            )
+        })?;
+        Ok(responses
+            .into_iter()
+            .filter_map(|response| response.document)
+            .collect())
+    }
+
+    /// Computes every `(alias, aggregation)` over the documents matching `query`,
+    /// returning the results keyed by alias
+    pub fn run_aggregation(
+        &self,
+        query: query::Query,
+        aggregations: Vec<(String, query::Aggregation)>,
+    ) -> Result<FirestoreFields> {
+        let parent = self.parent_path(query.parent());
+        let structured_aggregation_query = StructuredAggregationQuery {
+            structured_query: query.into_structured_query(),
+            aggregations: aggregations
+                .into_iter()
+                .map(|(alias, aggregation)| aggregation.into_rest(alias))
+                .collect(),
+        };
+        let responses = self.retrying(true, || {
+            firestore::documents::run_aggregation_query(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::RunAggregationQueryQuery {
+                    parent: parent.clone(),
+                    structured_aggregation_query: structured_aggregation_query.clone(),
+                    transaction: None,
+                    read_time: self.read_time,
+                },
+            )
+        })?;
+        responses
+            .into_iter()
+            .filter_map(|response| response.result)
+            .map(|result| result.aggregate_fields)
+            .next()
+            .ok_or_else(|| Error::InvalidDocument {
+                reason: String::from("the aggregation query returned no result"),
+            })
+    }
+
+    /// Counts the documents matching `query` without reading them
+    pub fn count(&self, query: query::Query) -> Result<i64> {
+        let alias = String::from("count");
+        let aggregation = query::Aggregation::Count { up_to: None };
+        let mut results = self.run_aggregation(query, vec![(alias.clone(), aggregation)])?;
+        match results.0.remove(&alias) {
+            Some(FirestoreType::Integer(count)) => Ok(count),
+            _ => Err(Error::InvalidDocument {
+                reason: String::from("the count aggregation did not return an integer"),
+            }),
+        }
+    }
+
+    /// Writes `fields` to a document, creating it if it does not exist.
+    /// When `merge` is set, fields of the existing document that are not
+    /// present in `fields` are preserved, otherwise the document is replaced.
+    pub fn set_document<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        merge: bool,
+    ) -> Result<Document>
+    where
+        S: Into<String>,
+    {
+        self.set_document_if(
+            collection_name,
+            document_id,
+            fields,
+            merge,
+            Precondition::default(),
+        )
+    }
+
+    /// Writes a document as `set_document` does, failing unless it meets
+    /// `precondition`, such as existing or having been updated last at a given time
+    pub fn set_document_if<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        merge: bool,
+        precondition: Precondition,
+    ) -> Result<Document>
+    where
+        S: Into<String>,
+    {
+        let (collection_name, document_id) = (collection_name.into(), document_id.into());
+        let name = self.document_path(&*collection_name, &*document_id);
+        let fields = fields.resolve_references(&self.documents_path());
+        if self.is_dry_run() {
+            let mut batch = self.batch();
+            batch
+                .set(collection_name, document_id, fields.clone(), merge)
+                .with_precondition(precondition);
+            batch.commit()?;
+            return Ok(self.unwritten_document(name, fields));
+        }
+        let update_mask = if merge {
+            Some(fields.field_paths())
+        } else {
+            None
+        };
+        self.retrying(false, || {
+            firestore::documents::patch(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::PatchDocumentQuery {
+                    name: name.clone(),
+                    fields: fields.clone(),
+                    update_mask: update_mask.clone(),
+                    current_document: precondition.clone(),
+                },
+            )
+        })
+    }
+
+    /// Creates a document with said fields, failing with ALREADY_EXISTS if it exists
+    pub fn create_document<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+    ) -> Result<Document>
+    where
+        S: Into<String>,
+    {
+        let (collection_name, document_id) = (collection_name.into(), document_id.into());
+        let name = self.document_path(&*collection_name, &*document_id);
+        let fields = fields.resolve_references(&self.documents_path());
+        if self.is_dry_run() {
+            let mut batch = self.batch();
+            batch.create(collection_name, document_id, fields.clone());
+            batch.commit()?;
+            return Ok(self.unwritten_document(name, fields));
+        }
+        self.retrying(false, || {
+            firestore::documents::patch(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::PatchDocumentQuery {
+                    name: name.clone(),
+                    fields: fields.clone(),
+                    update_mask: None,
+                    current_document: Precondition {
+                        exists: Some(false),
+                        ..Precondition::default()
+                    },
+                },
+            )
+        })
+    }
+
+    /// Creates a document with an ID chosen by Firestore, as `add` does in the SDKs.
+    /// In a dry run nothing is sent, so the ID is made up with `auto_id` instead.
+    pub fn add_document<S>(&self, collection_name: S, fields: FirestoreFields) -> Result<Document>
+    where
+        S: Into<String>,
+    {
+        let collection_name = collection_name.into();
+        if self.is_dry_run() {
+            return self.create_document(collection_name, auto_id(), fields);
+        }
+        let fields = fields.resolve_references(&self.documents_path());
+        let (parent, collection_id) = match collection_name.rfind('/') {
+            Some(slash) => (&collection_name[..slash], &collection_name[slash + 1..]),
+            None => ("", &*collection_name),
+        };
+        // retrying could add the document twice
+        self.retrying(false, || {
+            firestore::documents::create(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::CreateDocumentQuery {
+                    parent: self.parent_path(parent),
+                    collection_id: collection_id.to_string(),
+                    document_id: None,
+                    fields: fields.clone(),
+                },
+            )
+        })
+    }
+
+    /// Writes a serializable map or struct to a document as `set_document` does
+    pub fn set_from<T, S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        value: &T,
+        merge: bool,
+    ) -> Result<Document>
+    where
+        T: Serialize + ?Sized,
+        S: Into<String>,
+    {
+        let fields = FirestoreFields::from_serializable(value)?;
+        self.set_document(collection_name, document_id, fields, merge)
+    }
+
+    /// Updates only the fields named by `field_paths`, taking their values
+    /// from `fields`. Paths missing from `fields` are removed from the document.
+    /// Fails if the document does not exist.
+    pub fn update_document<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        field_paths: Vec<String>,
+    ) -> Result<Document>
+    where
+        S: Into<String>,
+    {
+        self.update_document_if(
+            collection_name,
+            document_id,
+            fields,
+            field_paths,
+            Precondition::default(),
+        )
+    }
+
+    /// Updates a document as `update_document` does, failing unless it meets
+    /// `precondition` as well, see `update_precondition`
+    pub fn update_document_if<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        field_paths: Vec<String>,
+        precondition: Precondition,
+    ) -> Result<Document>
+    where
+        S: Into<String>,
+    {
+        let (collection_name, document_id) = (collection_name.into(), document_id.into());
+        let name = self.document_path(&*collection_name, &*document_id);
+        let fields = fields.resolve_references(&self.documents_path());
+        let precondition = update_precondition(precondition);
+        if self.is_dry_run() {
+            let mut batch = self.batch();
+            batch
+                .update(collection_name, document_id, fields.clone(), field_paths)
+                .with_precondition(precondition);
+            batch.commit()?;
+            return Ok(self.unwritten_document(name, fields));
+        }
+        self.retrying(false, || {
+            firestore::documents::patch(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::PatchDocumentQuery {
+                    name: name.clone(),
+                    fields: fields.clone(),
+                    update_mask: Some(field_paths.clone()),
+                    current_document: precondition.clone(),
+                },
+            )
+        })
+    }
+
+    /// Updates a document as `update_document` does, then applies `transforms`,
+    /// given as field paths along with their transform, on the server.
+    /// The document is read back once the write has been committed.
+    pub fn update_document_with_transforms<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        field_paths: Vec<String>,
+        transforms: Vec<(String, FieldTransform)>,
+    ) -> Result<Document>
+    where
+        S: Into<String>,
+    {
+        self.update_document_with_transforms_if(
+            collection_name,
+            document_id,
+            fields,
+            field_paths,
+            transforms,
+            Precondition::default(),
+        )
+    }
+
+    /// Updates a document as `update_document_with_transforms` does, failing unless it
+    /// meets `precondition` as well, see `update_precondition`
+    pub fn update_document_with_transforms_if<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        field_paths: Vec<String>,
+        transforms: Vec<(String, FieldTransform)>,
+        precondition: Precondition,
+    ) -> Result<Document>
+    where
+        S: Into<String>,
+    {
+        let (collection_name, document_id) = (collection_name.into(), document_id.into());
+        if transforms.is_empty() {
+            return self.update_document_if(
+                collection_name,
+                document_id,
+                fields,
+                field_paths,
+                precondition,
+            );
+        }
+        let mut batch = self.batch();
+        batch
+            .update_with_transforms(
+                &*collection_name,
+                &*document_id,
+                fields.clone(),
+                field_paths,
+                transforms,
+            )
+            .with_precondition(update_precondition(precondition));
+        batch.commit()?;
+        if self.is_dry_run() {
+            let name = self.document_path(&*collection_name, &*document_id);
+            return Ok(self.unwritten_document(name, fields));
+        }
+        self.get_document(collection_name, document_id)
+    }
+
+    /// Starts an empty batch of writes against this database
+    pub fn batch(&self) -> WriteBatch<'_> {
+        WriteBatch {
+            ctx: self,
+            writes: Vec::new(),
+        }
+    }
+
+    /// Starts buffering independent writes, committed in batches as they fill up.
+    /// Unlike a `WriteBatch` the writes are not applied atomically, a failed write is
+    /// reported to the callbacks of the writer while the others are still committed.
+    pub fn bulk_writer(&self) -> BulkWriter<'_> {
+        BulkWriter::new(self)
+    }
+
+    /// Applies `writes` atomically, at most `MAX_WRITES_PER_COMMIT` may be given
+    pub fn commit(&self, writes: Vec<Write>) -> Result<CommitResponse> {
+        self.commit_in(writes, None)
+    }
+
+    /// Commits the writes of a bulk operation once the rate limiter allows it. A commit
+    /// rejected with RESOURCE_EXHAUSTED was not applied, so it is sent again at a slower
+    /// rate as many times as the retry policy allows, even when writes are not retried.
+    pub(crate) fn commit_throttled(&self, writes: Vec<Write>) -> Result<CommitResponse> {
+        if self.is_dry_run() {
+            return self.commit(writes);
+        }
+        let mut attempt = 1;
+        loop {
+            self.write_limiter.acquire(writes.len());
+            match self.commit(writes.clone()) {
+                Err(Error::ResourceExhausted { .. })
+                    if attempt < self.retry_policy.max_attempts =>
+                {
+                    self.write_limiter.slow_down();
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Applies `writes` atomically, committing `transaction` along with them.
+    /// A failed transaction commit is not retried here, `run_transaction` starts over instead.
+    fn commit_in(&self, writes: Vec<Write>, transaction: Option<String>) -> Result<CommitResponse> {
+        if let Some(sink) = &self.write_sink {
+            sink.write(&writes)?;
+            // nothing was written, so the transaction is abandoned
+            if let Some(transaction) = transaction {
+                self.rollback(transaction)?;
+            }
+            return Ok(CommitResponse {
+                write_results: Vec::new(),
+                commit_time: Utc::now(),
+            });
+        }
+        let commit = || {
+            firestore::documents::commit(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::CommitQuery {
+                    database: self.database_path(),
+                    writes: writes.clone(),
+                    transaction: transaction.clone(),
+                },
+            )
+        };
+        if transaction.is_some() {
+            return commit();
+        }
+        self.retrying(false, commit)
+    }
+
+    /// Runs `f` inside of a read-write transaction and commits the writes it buffers.
+    /// When the commit is aborted because of contention with another transaction,
+    /// `f` is run again in a fresh transaction, up to `MAX_TRANSACTION_ATTEMPTS` times.
+    /// If `f` fails the transaction is rolled back and its error returned.
+    pub fn run_transaction<F, T>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut(&mut Transaction) -> Result<T>,
+    {
+        let mut retry_transaction = None;
+        let mut attempt = 1;
+        loop {
+            let id = firestore::documents::begin_transaction(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::documents::BeginTransactionQuery {
+                    database: self.database_path(),
+                    retry_transaction: retry_transaction.take(),
+                },
+            )?
+            .transaction;
+            let mut transaction = Transaction {
+                id: id.clone(),
+                batch: self.batch(),
+            };
+            let result = match f(&mut transaction) {
+                Ok(value) => {
+                    let writes = transaction.batch.writes;
+                    self.commit_in(writes, Some(id.clone())).map(|_| value)
+                }
+                Err(e) => {
+                    // an aborted transaction is already rolled back
+                    if !is_aborted(&e) {
+                        self.rollback(id.clone())?;
+                    }
+                    Err(e)
+                }
+            };
+            match result {
+                Err(ref e) if is_aborted(e) && attempt < MAX_TRANSACTION_ATTEMPTS => {
+                    retry_transaction = Some(id);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Abandons `transaction` without applying any of its writes
+    fn rollback(&self, transaction: String) -> Result<()> {
+        firestore::documents::rollback(
+            self.client.clone(),
+            &*self.base_url,
+            self.auth_header_map()?,
+            firestore::documents::RollbackQuery {
+                database: self.database_path(),
+                transaction,
+            },
+        )
+    }
+
+    /// Writes every `(document_id, fields)` pair to said collection in a single commit,
+    /// merging with existing documents when `merge` is set, as `set_document` does
+    pub fn set_documents<S>(
+        &self,
+        collection_name: S,
+        documents: Vec<(String, FirestoreFields)>,
+        merge: bool,
+    ) -> Result<CommitResponse>
+    where
+        S: Into<String>,
+    {
+        let collection_name = collection_name.into();
+        let mut batch = self.batch();
+        for (document_id, fields) in documents {
+            batch.set(&*collection_name, &*document_id, fields, merge);
+        }
+        batch.commit()
+    }
+
+    /// Lists the composite indexes of every collection group of the database
+    pub fn list_indexes(&self) -> Result<Vec<Index>> {
+        let parent = format!("{}/collectionGroups/-", self.database_path());
+        let mut indexes = Vec::new();
+        let mut page_token = None;
+        loop {
+            let response = self.retrying(true, || {
+                firestore::indexes::list(
+                    self.client.clone(),
+                    &*self.base_url,
+                    self.auth_header_map()?,
+                    firestore::indexes::ListIndexesQuery {
+                        parent: parent.clone(),
+                        page_token: page_token.clone(),
+                    },
+                )
+            })?;
+            indexes.extend(response.indexes.into_iter().filter_map(Index::from_rest));
+            match response.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => return Ok(indexes),
+            }
+        }
+    }
+
+    /// Starts building `index`, returning the name of the operation building it.
+    /// Queries can use the index once the operation is done, see `wait_for_operation`.
+    pub fn create_index(&self, index: &Index) -> Result<String> {
+        let operation = self.retrying(false, || {
+            firestore::indexes::create(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::indexes::CreateIndexQuery {
+                    parent: format!(
+                        "{}/collectionGroups/{}",
+                        self.database_path(),
+                        index.collection_group
+                    ),
+                    index: index.to_rest(),
+                },
+            )
+        })?;
+        Ok(operation.name)
+    }
+
+    /// Deletes the composite index with said ID, whatever its collection group
+    pub fn delete_index(&self, index_id: &str) -> Result<()> {
+        let name = self
+            .list_indexes()?
+            .into_iter()
+            .filter(|index| index.id() == Some(index_id))
+            .filter_map(|index| index.name)
+            .next()
+            .ok_or_else(|| Error::NotFound {
+                message: format!("no index has the ID {}", index_id),
+            })?;
+        self.retrying(false, || {
+            firestore::indexes::delete(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                &name,
+            )
+        })
+    }
+
+    /// Lists the fields documents expire by, across every collection group of the database
+    pub fn list_ttl_policies(&self) -> Result<Vec<TtlPolicy>> {
+        let parent = format!("{}/collectionGroups/-", self.database_path());
+        let mut policies = Vec::new();
+        let mut page_token = None;
+        loop {
+            let response = self.retrying(true, || {
+                firestore::fields::list(
+                    self.client.clone(),
+                    &*self.base_url,
+                    self.auth_header_map()?,
+                    firestore::fields::ListFieldsQuery {
+                        parent: parent.clone(),
+                        filter: String::from("ttlConfig:*"),
+                        page_token: page_token.clone(),
+                    },
+                )
+            })?;
+            for field in response.fields {
+                let ttl_config = match field.ttl_config {
+                    Some(ttl_config) => ttl_config,
+                    None => continue,
+                };
+                // projects/{project}/databases/{database}/collectionGroups/{id}/fields/{path}
+                let mut segments = field.name.rsplitn(4, '/');
+                let (field_path, collection_group) = match (segments.next(), segments.nth(1)) {
+                    (Some(field_path), Some(collection_group)) => (field_path, collection_group),
+                    _ => continue,
+                };
+                policies.push(TtlPolicy {
+                    collection_group: collection_group.to_string(),
+                    field_path: field_path.to_string(),
+                    state: ttl_config.state,
+                });
+            }
+            match response.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => return Ok(policies),
+            }
+        }
+    }
+
+    /// Makes documents of the collections with said ID expire once the timestamp in
+    /// `field_path` is past, or stop expiring by it, returning the name of the operation
+    /// changing the policy. See `wait_for_operation`.
+    pub fn set_ttl_policy(
+        &self,
+        collection_group: &str,
+        field_path: &str,
+        enabled: bool,
+    ) -> Result<String> {
+        let field = firestore::fields::Field {
+            name: format!(
+                "{}/collectionGroups/{}/fields/{}",
+                self.database_path(),
+                collection_group,
+                field_path
+            ),
+            ttl_config: if enabled {
+                Some(firestore::fields::TtlConfig::default())
+            } else {
+                None
+            },
+        };
+        let operation = self.retrying(false, || {
+            firestore::fields::patch(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                field.clone(),
+                "ttlConfig",
+            )
+        })?;
+        Ok(operation.name)
+    }
+
+    /// The resource name of an operation of the database given its name or ID
+    fn operation_name(&self, name: &str) -> String {
+        if name.contains('/') {
+            name.to_string()
+        } else {
+            format!("{}/operations/{}", self.database_path(), name)
+        }
+    }
+
+    /// Lists the recent long-running operations of the database, running or done
+    pub fn list_operations(&self) -> Result<Vec<Operation>> {
+        let mut operations = Vec::new();
+        let mut page_token = None;
+        loop {
+            let response = self.retrying(true, || {
+                firestore::operations::list(
+                    self.client.clone(),
+                    &*self.base_url,
+                    self.auth_header_map()?,
+                    firestore::operations::ListOperationsQuery {
+                        database_name: self.database_path(),
+                        page_token: page_token.clone(),
+                    },
+                )
+            })?;
+            operations.extend(response.operations);
+            match response.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => return Ok(operations),
+            }
+        }
+    }
+
+    /// Gets the long-running operation with said name, or with said ID in the database
+    pub fn get_operation(&self, name: &str) -> Result<Operation> {
+        let name = self.operation_name(name);
+        self.retrying(true, || {
+            firestore::operations::get(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                &name,
+            )
+        })
+    }
+
+    /// Checks on long-running operations until they are done, with a backoff
+    pub fn operation_poller(&self) -> OperationPoller<'_> {
+        OperationPoller::new(self)
+    }
+
+    /// Waits for the long-running operation with said name to be done with the default
+    /// backoff, see `OperationPoller::wait`
+    pub fn wait_for_operation<F>(&self, name: &str, on_progress: F) -> Result<Operation>
+    where
+        F: FnMut(&Operation),
+    {
+        self.operation_poller().wait(name, on_progress)
+    }
+
+    /// Lists the databases of the project, whichever database the context uses
+    pub fn list_databases(&self) -> Result<Vec<firestore::databases::Database>> {
+        let response = self.retrying(true, || {
+            firestore::databases::list_databases(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                &self.project_id,
+            )
+        })?;
+        Ok(response.databases)
+    }
+
+    /// Starts creating a database with said ID in the project, returning the name of the
+    /// operation creating it. See `wait_for_operation`.
+    pub fn create_database(
+        &self,
+        database_id: &str,
+        database: firestore::databases::Database,
+    ) -> Result<String> {
+        let operation = self.retrying(false, || {
+            firestore::databases::create_database(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::databases::CreateDatabaseQuery {
+                    project_id: self.project_id.clone(),
+                    database_id: database_id.to_string(),
+                    database: database.clone(),
+                },
+            )
+        })?;
+        Ok(operation.name)
+    }
+
+    /// Starts deleting the database with said ID and every document in it, returning the
+    /// name of the operation. Fails for databases with delete protection.
+    pub fn delete_database(&self, database_id: &str) -> Result<String> {
+        let name = format!("projects/{}/databases/{}", self.project_id, database_id);
+        let operation = self.retrying(false, || {
+            firestore::databases::delete_database(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                &name,
+            )
+        })?;
+        Ok(operation.name)
+    }
+
+    /// Starts a managed export of the collections with said IDs, every collection when none
+    /// are given, to a Cloud Storage location such as gs://bucket/path. Returns the name of
+    /// the operation exporting them, see `wait_for_operation`.
+    pub fn export_documents(
+        &self,
+        output_uri_prefix: &str,
+        collection_ids: Vec<String>,
+    ) -> Result<String> {
+        let operation = self.retrying(false, || {
+            firestore::databases::export_documents(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::databases::ExportDocumentQuery {
+                    database_name: self.database_path(),
+                    collection_ids: collection_ids.clone(),
+                    output_uri_prefix: output_uri_prefix.to_string(),
+                },
+            )
+        })?;
+        Ok(operation.name)
     }
 
-    //    fn make_api_base(&self) -> String {
-    //        format!("{}/projects/{}", FIRESTORE_BASE_URL, self.project_id)
-    //    }
-    //
-    //    // Creates a proper URL for the Firestore REST api
-    //    fn make_document_url(&self, collection_name: String, document_id: String) -> String {
-    //        format!(
-    //            "{}/databases/(default)/documents/{}{}",
-    //            self.make_api_base(),
-    //            collection_name,
-    //            document_id
-    //        )
-    //    }
-    //
-    //    fn make_batch_get_url(&self, database_name: String) -> String {
-    //        format!(
-    //            "{}/{{database={}}}/documents:batchGet",
-    //            FIRESTORE_BETA_BASE_URL,
-    //            format!("projects/{}/databases/{}", self.project_id, database_name)
-    //        )
-    //    }
-    //
-    //    // Deletes a document from said collection
-    //
-    //    pub fn delete_document<S>(&self, collection_name: S, document_id: S) -> Result<Document, String>
-    //    where
-    //        S: Into<String>,
-    //    {
-    //        // ensure String types
-    //        let collection_name = collection_name.into();
-    //        let document_id = document_id.into();
-    //
-    //        let document_ref_url = self.make_document_url(collection_name, document_id);
-    //        self.delete_document_at_path(&*document_ref_url)
-    //    }
-    //
-    //    // TODO(hazebooth): support document masks
-    //    // GETs a document from said collection
-    //    // https://firebase.google.com/docs/firestore/reference/rest/v1beta1/projects.databases.documents/get
-    //    pub fn get_document<S>(&self, collection_name: S, document_id: S) -> Result<Document, String>
-    //    where
-    //        S: Into<String>,
-    //    {
-    //        // ensure String types
-    //        let collection_name = collection_name.into();
-    //        let document_id = document_id.into();
-    //
-    //        let document_ref_url = self.make_document_url(collection_name, document_id);
-    //        self.retrieve_document(&*document_ref_url)
-    //    }
-    //
-    //    // Inner implementation of `delete_document`
-    //    fn delete_document_at_path(&self, path: &str) -> Result<Document, String> {
-    //        let mut response = self
-    //            .client
-    //            .delete(path)
-    //            .headers(self.auth_header_map()?)
-    //            .send()
-    //            .map_err(errors::http_error)?;
-    //        let document = response
-    //            .json::<Document>()
-    //            .map_err(errors::json_decode_error)?;
-    //        Ok(document)
-    //    }
-    //
-    //    // Inner implementation of `get_document`.
-    //    fn retrieve_document(&self, path: &str) -> Result<Document, String> {
-    //        let mut response = self
-    //            .client
-    //            .get(path)
-    //            .headers(self.auth_header_map()?)
-    //            .send()
-    //            .map_err(errors::http_error)?;
-    //        let document = response
-    //            .json::<Document>()
-    //            .map_err(errors::json_decode_error)?;
-    //        Ok(document)
-    //    }
-    //
-    //    // Internal for batch_get_documents
-    //    // https://firebase.google.com/docs/firestore/reference/rest/v1beta1/projects.databases.documents/batchGet#google.firestore.v1beta1.Firestore.BatchGetDocuments
-    //    fn batch_get(&self, documents: Vec<String>, path: &str) -> Result<batch_get::Response, String> {
-    //        let mut response = self
-    //            .client
-    //            .post(path)
-    //            .headers(self.auth_header_map()?)
-    //            .send()
-    //            .map_err(errors::http_error)?;
-    //        response
-    //            .json::<batch_get::Response>()
-    //            .map_err(errors::json_decode_error)
-    //    }
-    //
-    //    pub fn batch_get_documents<S>(
-    //        &self,
-    //        documents: Vec<S>,
-    //        database_name: S,
-    //    ) -> Result<batch_get::Response, String>
-    //    where
-    //        S: Into<String>,
-    //    {
-    //        let documents = documents
-    //            .into_iter()
-    //            .map(|s| s.into())
-    //            .collect::<Vec<String>>();
-    //        let database_name: String = database_name.into();
-    //        self.batch_get(documents, &*self.make_batch_get_url(database_name))
-    //    }
-    //
-    //    // want https://firestore.googleapis.com/v1beta1/{parent=projects/*/databases/*/documents/*/**}/{collectionId}
-    //    // ours https://firestore.googleapis.com/v1beta1/{parent=projects/hazes-test-project/databases/default/documents/*/**}/cars
-    //    fn make_list_documents_url(&self, database_name: &str, collection_name: &str) -> String {
-    //        let parent = format!("projects/{}/databases/{}", self.project_id, database_name);
-    //        format!(
-    //            "{}/{{parent={}}}/{}",
-    //            FIRESTORE_BETA_BASE_URL, parent, collection_name
-    //        )
-    //    }
-    //
-    //    pub fn list_documents(
-    //        &self,
-    //        page_size: i32,
-    //        order_by: String,
-    //        mask: Option<DocumentMask>,
-    //        show_missing: bool,
-    //        consistency_selector: ConsistencySelector,
-    //        database_name: &str,
-    //        collection_name: &str,
-    //    ) -> Result<list_documents::Response, String> {
-    //        let request = list_documents::Request {
-    //            page_size,
-    //            order_by,
-    //            mask,
-    //            show_missing,
-    //            consistency_selector,
-    //        };
-    //        let request_json = serde_json::to_string(&request).map_err(errors::json_encode_error)?;
-    //        println!(
-    //            "{}",
-    //            &*self.make_list_documents_url(database_name, collection_name)
-    //        );
-    //        let mut response = self
-    //            .client
-    //            .get(&*self.make_list_documents_url(database_name, collection_name))
-    //            .headers(self.auth_header_map()?)
-    //            .body(request_json)
-    //            .send()
-    //            .map_err(errors::http_error)?;
-    //        response
-    //            .json::<list_documents::Response>()
-    //            .map_err(errors::json_decode_error)
-    //    }
-
-    pub fn export_database(&self, query: firestore::databases::ExportDocumentQuery) {
-        firestore::databases::export_documents(self.client, self.auth_header_map())
+    /// Starts importing the collections with said IDs from a completed managed export at
+    /// `input_uri_prefix`, every collection of the export when none are given. Documents
+    /// of the export replace those with the same name. Returns the name of the operation.
+    pub fn import_documents(
+        &self,
+        input_uri_prefix: &str,
+        collection_ids: Vec<String>,
+    ) -> Result<String> {
+        let operation = self.retrying(false, || {
+            firestore::databases::import_documents(
+                self.client.clone(),
+                &*self.base_url,
+                self.auth_header_map()?,
+                firestore::databases::ImportDocumentQuery {
+                    database_name: self.database_path(),
+                    collection_ids: collection_ids.clone(),
+                    input_uri_prefix: input_uri_prefix.to_string(),
+                },
+            )
+        })?;
+        Ok(operation.name)
     }
 
     // Used to give us the key for our Authorization Header
     // Authorization: Bearer <token>
     // ------------------^
-    fn get_authorization_key(&self) -> String {
-        format!("Bearer {}", self.auth_token.access_token())
+    // The emulator treats the `owner` token as an admin that bypasses security rules
+    fn get_authorization_key(&self) -> Result<String> {
+        match &self.tokens {
+            Some(tokens) => Ok(format!("Bearer {}", tokens.access_token(&self.client)?)),
+            None => Ok(String::from("Bearer owner")),
+        }
     }
 }
@@ -0,0 +1,392 @@
+// Resolution of Google credentials, mirroring Application Default Credentials:
+// explicit path -> GOOGLE_APPLICATION_CREDENTIALS_JSON -> GOOGLE_APPLICATION_CREDENTIALS
+// -> gcloud ADC file -> metadata server. The JSON variable holds the credentials
+// themselves, as CI secrets usually do, rather than the path of a file.
+// Any of them may be used to impersonate a service account through the IAM Credentials
+// API, which takes the roles/iam.serviceAccountTokenCreator role on that account.
+// The project of the active gcloud configuration is read from the same config directory.
+// Credentials files of external accounts, for workload identity federation, are handled
+// by `external_account`.
+
+use super::credential_store;
+use super::errors::{Error, Result};
+use super::external_account;
+use super::firestore::{CheckStatus, Logged};
+use chrono::{DateTime, Utc};
+use goauth::auth::{JwtClaims, Token};
+use goauth::scopes::Scope;
+use smpl_jwt::Jwt;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+
+const GOOGLE_APPLICATION_CREDENTIALS_KEY: &'static str = "GOOGLE_APPLICATION_CREDENTIALS";
+pub const GOOGLE_APPLICATION_CREDENTIALS_JSON_KEY: &'static str =
+    "GOOGLE_APPLICATION_CREDENTIALS_JSON";
+const CLOUDSDK_CONFIG_KEY: &'static str = "CLOUDSDK_CONFIG";
+const ADC_FILE_NAME: &'static str = "application_default_credentials.json";
+const CLOUDSDK_CORE_PROJECT_KEY: &'static str = "CLOUDSDK_CORE_PROJECT";
+const CLOUDSDK_ACTIVE_CONFIG_NAME_KEY: &'static str = "CLOUDSDK_ACTIVE_CONFIG_NAME";
+const ACTIVE_CONFIG_FILE_NAME: &'static str = "active_config";
+const DEFAULT_CONFIG_NAME: &'static str = "default";
+/// Prefix of an explicit credentials path naming credentials stored in the keychain
+pub const KEYCHAIN_PREFIX: &'static str = "keychain:";
+
+const OAUTH_TOKEN_URI: &'static str = "https://oauth2.googleapis.com/token";
+const JWT_BEARER_GRANT_TYPE: &'static str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+const METADATA_TOKEN_URI: &'static str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const IAM_CREDENTIALS_URI: &'static str =
+    "https://iamcredentials.googleapis.com/v1/projects/-/serviceAccounts";
+/// Lifetime asked for impersonated tokens, the longest allowed by default
+const IMPERSONATED_TOKEN_LIFETIME: &'static str = "3600s";
+
+/// Where the credentials of a `DatabaseContext` come from
+#[derive(Debug, Clone, PartialEq)]
+pub enum CredentialSource {
+    /// A service account key or `gcloud auth application-default login` file
+    File(PathBuf),
+    /// Contents of a credentials file kept in the keychain under this account,
+    /// see `credential_store`
+    Stored(String),
+    /// Contents of a credentials file, such as a key kept in a secret
+    Json(String),
+    /// The GCE / Cloud Run metadata server
+    MetadataServer,
+    /// Short-lived tokens of `service_account`, obtained with the credentials of `source`
+    Impersonated {
+        source: Box<CredentialSource>,
+        service_account: String,
+    },
+}
+
+/// The `type` discriminator shared by every credentials file
+#[derive(Deserialize)]
+struct CredentialsKind {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// Response of `generateAccessToken`
+#[derive(Deserialize)]
+struct GeneratedToken {
+    #[serde(rename = "accessToken")]
+    access_token: String,
+    #[serde(rename = "expireTime")]
+    expire_time: DateTime<Utc>,
+}
+
+/// Credentials written by `gcloud auth application-default login`
+#[derive(Deserialize)]
+struct AuthorizedUser {
+    client_id: String,
+    client_secret: String,
+    refresh_token: String,
+}
+
+pub(crate) fn auth_error(reason: String) -> Error {
+    Error::Authentication { reason }
+}
+
+/// The config directory of gcloud
+fn gcloud_config_dir() -> Option<PathBuf> {
+    match env::var(CLOUDSDK_CONFIG_KEY) {
+        Ok(dir) => Some(PathBuf::from(dir)),
+        Err(_) if cfg!(windows) => Some(PathBuf::from(env::var("APPDATA").ok()?).join("gcloud")),
+        Err(_) => Some(
+            PathBuf::from(env::var("HOME").ok()?)
+                .join(".config")
+                .join("gcloud"),
+        ),
+    }
+}
+
+/// Location of the file written by `gcloud auth application-default login`
+pub fn gcloud_adc_path() -> Option<PathBuf> {
+    Some(gcloud_config_dir()?.join(ADC_FILE_NAME))
+}
+
+/// The value of `key` in `[section]` of an INI file, as gcloud writes its configurations
+fn ini_value(contents: &str, section: &str, key: &str) -> Option<String> {
+    let mut in_section = false;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            in_section = line[1..line.len() - 1].trim() == section;
+            continue;
+        }
+        if !in_section || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        if parts.next().map(str::trim) == Some(key) {
+            return parts
+                .next()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty());
+        }
+    }
+    None
+}
+
+/// The project `gcloud config get-value project` prints: the one of
+/// `CLOUDSDK_CORE_PROJECT`, or else of the active gcloud configuration
+pub fn gcloud_project() -> Option<String> {
+    if let Ok(project) = env::var(CLOUDSDK_CORE_PROJECT_KEY) {
+        return Some(project);
+    }
+    let config_dir = gcloud_config_dir()?;
+    let name = match env::var(CLOUDSDK_ACTIVE_CONFIG_NAME_KEY) {
+        Ok(name) => name,
+        Err(_) => fs::read_to_string(config_dir.join(ACTIVE_CONFIG_FILE_NAME))
+            .map(|name| name.trim().to_string())
+            .unwrap_or_else(|_| String::from(DEFAULT_CONFIG_NAME)),
+    };
+    let path = config_dir
+        .join("configurations")
+        .join(format!("config_{}", name));
+    ini_value(&*fs::read_to_string(path).ok()?, "core", "project")
+}
+
+impl CredentialSource {
+    /// Walks the resolution chain, preferring `explicit_path` when given.
+    /// An explicit `keychain:NAME` refers to credentials stored in the keychain.
+    /// Falls back to the metadata server when no credentials file is found.
+    pub fn resolve(explicit_path: Option<String>) -> CredentialSource {
+        if let Some(path) = explicit_path {
+            if path.starts_with(KEYCHAIN_PREFIX) {
+                return CredentialSource::Stored(path[KEYCHAIN_PREFIX.len()..].to_string());
+            }
+            return CredentialSource::File(PathBuf::from(path));
+        }
+        if let Ok(json) = env::var(GOOGLE_APPLICATION_CREDENTIALS_JSON_KEY) {
+            return CredentialSource::Json(json);
+        }
+        if let Ok(path) = env::var(GOOGLE_APPLICATION_CREDENTIALS_KEY) {
+            return CredentialSource::File(PathBuf::from(path));
+        }
+        match gcloud_adc_path() {
+            Some(path) if path.is_file() => CredentialSource::File(path),
+            _ => CredentialSource::MetadataServer,
+        }
+    }
+
+    /// Uses these credentials to act as `service_account` instead
+    pub fn impersonate<S: Into<String>>(self, service_account: S) -> CredentialSource {
+        CredentialSource::Impersonated {
+            source: Box::new(self),
+            service_account: service_account.into(),
+        }
+    }
+
+    /// The `type` of the credentials, such as `service_account` or `authorized_user`,
+    /// read without exchanging them for a token. The metadata server has none.
+    pub fn kind(&self) -> Result<Option<String>> {
+        let contents = match self {
+            CredentialSource::File(path) => fs::read_to_string(path)?,
+            CredentialSource::Stored(account) => {
+                credential_store::get(account)?.ok_or_else(|| {
+                    auth_error(format!("no credentials named {} in the keychain", account))
+                })?
+            }
+            CredentialSource::Json(contents) => contents.clone(),
+            CredentialSource::MetadataServer => return Ok(None),
+            CredentialSource::Impersonated { source, .. } => return source.kind(),
+        };
+        let kind: CredentialsKind = serde_json::from_str(&*contents)?;
+        Ok(Some(kind.kind))
+    }
+
+    /// Exchanges these credentials for an access token
+    pub fn token(&self, client: &reqwest::Client) -> Result<Token> {
+        self.token_for(client, &Scope::DataStore)
+    }
+
+    /// Exchanges these credentials for an access token of `scope`. Only service account
+    /// keys choose their scope, the others get the scopes they were granted.
+    fn token_for(&self, client: &reqwest::Client, scope: &Scope) -> Result<Token> {
+        match self {
+            CredentialSource::File(path) => {
+                let mut contents = String::new();
+                File::open(path)?.read_to_string(&mut contents)?;
+                json_token(client, &*contents, scope, &path.display().to_string())
+            }
+            CredentialSource::Stored(account) => {
+                let contents = credential_store::get(account)?.ok_or_else(|| {
+                    auth_error(format!("no credentials named {} in the keychain", account))
+                })?;
+                json_token(
+                    client,
+                    &*contents,
+                    scope,
+                    &format!("{}{}", KEYCHAIN_PREFIX, account),
+                )
+            }
+            CredentialSource::Json(contents) => {
+                json_token(client, &*contents, scope, &self.to_string())
+            }
+            CredentialSource::MetadataServer => metadata_token(client),
+            CredentialSource::Impersonated {
+                source,
+                service_account,
+            } => impersonated_token(client, source, service_account),
+        }
+    }
+}
+
+impl fmt::Display for CredentialSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CredentialSource::File(path) => write!(f, "{}", path.display()),
+            CredentialSource::Stored(account) => write!(f, "{}{}", KEYCHAIN_PREFIX, account),
+            // the contents are secret
+            CredentialSource::Json(_) => write!(f, "the credentials JSON"),
+            CredentialSource::MetadataServer => write!(f, "the metadata server"),
+            CredentialSource::Impersonated {
+                source,
+                service_account,
+            } => write!(f, "{} impersonated with {}", service_account, source),
+        }
+    }
+}
+
+/// Gets a token from the contents of a credentials file, dispatching on its `type`.
+/// `origin` names where the contents came from in errors.
+fn json_token(
+    client: &reqwest::Client,
+    contents: &str,
+    scope: &Scope,
+    origin: &str,
+) -> Result<Token> {
+    let kind: CredentialsKind = serde_json::from_str(contents)?;
+    match &*kind.kind {
+        "service_account" => service_account_token(client, contents, scope, origin),
+        "external_account" => external_account::token(client, contents, scope, origin),
+        "authorized_user" => {
+            let user: AuthorizedUser = serde_json::from_str(contents)?;
+            let mut response = client
+                .post(OAUTH_TOKEN_URI)
+                .form(&[
+                    ("grant_type", "refresh_token"),
+                    ("client_id", &*user.client_id),
+                    ("client_secret", &*user.client_secret),
+                    ("refresh_token", &*user.refresh_token),
+                ])
+                .send_logged()?
+                .check_status()?;
+            response.json::<Token>().map_err(Error::from)
+        }
+        other => Err(auth_error(format!(
+            "{} has unsupported credentials type {}",
+            origin, other
+        ))),
+    }
+}
+
+/// Signs a JWT with a service account key and trades it for a token, through `client`
+/// rather than goauth so that requests go through the same proxy
+fn service_account_token(
+    client: &reqwest::Client,
+    contents: &str,
+    scope: &Scope,
+    origin: &str,
+) -> Result<Token> {
+    let credentials = contents
+        .parse::<goauth::credentials::Credentials>()
+        .map_err(|e| auth_error(format!("failed to load credentials from {}: {}", origin, e)))?;
+    let claims = JwtClaims::new(
+        credentials.iss(),
+        scope,
+        credentials.token_uri(),
+        None,
+        None,
+    );
+    let jwt = Jwt::new(
+        claims,
+        credentials.rsa_key().map_err(|_| {
+            auth_error(String::from(
+                "failed to get RSA private key from credentials",
+            ))
+        })?,
+        None,
+    );
+    let assertion = jwt
+        .finalize()
+        .map_err(|e| auth_error(format!("failed to sign a JWT: {}", e)))?;
+    client
+        .post(&*credentials.token_uri())
+        .form(&[
+            ("grant_type", JWT_BEARER_GRANT_TYPE),
+            ("assertion", &*assertion),
+        ])
+        .send_logged()
+        .and_then(CheckStatus::check_status)
+        .and_then(|mut response| response.json::<Token>().map_err(Error::from))
+        .map_err(|e| auth_error(format!("failed to authenticate: {}", e)))
+}
+
+/// Asks the metadata server for a token of the instance's default service account
+fn metadata_token(client: &reqwest::Client) -> Result<Token> {
+    let mut response = client
+        .get(METADATA_TOKEN_URI)
+        .header("Metadata-Flavor", "Google")
+        .send_logged()
+        .map_err(|e| {
+            auth_error(format!(
+                "no credentials file found and the metadata server is unreachable: {}",
+                e
+            ))
+        })?
+        .check_status()?;
+    response.json::<Token>().map_err(Error::from)
+}
+
+/// Asks the IAM Credentials API for a token of `service_account`, authenticating
+/// with a cloud-platform token of `source`
+fn impersonated_token(
+    client: &reqwest::Client,
+    source: &CredentialSource,
+    service_account: &str,
+) -> Result<Token> {
+    let source_token = source.token_for(client, &Scope::CloudPlatform)?;
+    let url = format!(
+        "{}/{}:generateAccessToken",
+        IAM_CREDENTIALS_URI, service_account
+    );
+    generate_access_token(
+        client,
+        &*url,
+        source_token.access_token(),
+        &Scope::DataStore,
+    )
+    .map_err(|e| auth_error(format!("failed to impersonate {}: {}", service_account, e)))
+}
+
+/// Calls `generateAccessToken` of the IAM Credentials API at `url` for a token of
+/// `scope`, authenticating with `bearer`
+pub(crate) fn generate_access_token(
+    client: &reqwest::Client,
+    url: &str,
+    bearer: &str,
+    scope: &Scope,
+) -> Result<Token> {
+    let body = json!({
+        "scope": [scope.url()],
+        "lifetime": IMPERSONATED_TOKEN_LIFETIME,
+    });
+    let generated = client
+        .post(url)
+        .bearer_auth(bearer)
+        .json_logged(&body)
+        .send_logged()?
+        .check_status()?
+        .json::<GeneratedToken>()?;
+    // goauth tokens only come out of JSON
+    let token = json!({
+        "access_token": generated.access_token,
+        "token_type": "Bearer",
+        "expires_in": (generated.expire_time - Utc::now()).num_seconds().max(0),
+    });
+    serde_json::from_value(token).map_err(Error::from)
+}
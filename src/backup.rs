@@ -0,0 +1,430 @@
+// Backups of every collection of a database into a directory, laid out as
+//   manifest.json
+//   users/00000.ndjson
+//   users/00001.ndjson
+//   orders/00000.ndjson
+// with a directory of chunks per root collection. Each line of a chunk is a document of
+// that collection or of one of its subcollections, with its path relative to the root of
+// the database and its fields in Firestore's typed encoding so that restoring is lossless.
+// The manifest is rewritten after every chunk along with the page of the root collection
+// to continue from, so that `backup --resume` carries on from the last complete chunk.
+// Chunks end on page boundaries, so they hold at least `chunk_size` documents
+// rather than exactly that many.
+
+use crate::checkpoint;
+use crate::progress::Bar;
+use chrono::{DateTime, Utc};
+use libfiresale::api::query::Direction;
+use libfiresale::api::{DatabaseContext, Document, FirestoreFields, FirestoreType};
+use libfiresale::errors::{Error, Result};
+use libfiresale::pool::CommitPool;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+const MANIFEST_FILE_NAME: &'static str = "manifest.json";
+/// Chunks already written by an interrupted restore, one file name per line
+const RESTORED_FILE_NAME: &'static str = "restored";
+const MANIFEST_VERSION: u32 = 1;
+/// Number of documents of each root collection whose field types are recorded
+const SCHEMA_SAMPLE_SIZE: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub project_id: String,
+    pub database: String,
+    pub started_at: DateTime<Utc>,
+    /// `None` until every collection is backed up
+    pub finished_at: Option<DateTime<Utc>>,
+    pub chunk_size: usize,
+    pub collections: Vec<CollectionEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionEntry {
+    /// ID of the root collection
+    pub id: String,
+    /// Documents written so far, subcollections included
+    pub documents: usize,
+    pub chunks: Vec<Chunk>,
+    pub complete: bool,
+    /// Page of the collection to continue from once the last chunk is written,
+    /// `None` when the collection is complete or no chunk was written yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_token: Option<String>,
+    /// Types seen for each top level field among the first documents of the collection
+    pub schema: BTreeMap<String, BTreeSet<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Path of the file relative to the backup directory
+    pub file: String,
+    pub documents: usize,
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidArgument { reason }
+}
+
+/// Name of the kind of `value`, as recorded in the schema sample
+fn type_name(value: &FirestoreType) -> &'static str {
+    match value {
+        FirestoreType::Integer(_) => "integer",
+        FirestoreType::Double(_) => "double",
+        FirestoreType::Boolean(_) => "boolean",
+        FirestoreType::String(_) => "string",
+        FirestoreType::GeoLocation(_) => "geopoint",
+        FirestoreType::Array(_) => "array",
+        FirestoreType::Map(_) => "map",
+        FirestoreType::Timestamp(_) => "timestamp",
+        FirestoreType::Null(_) => "null",
+        FirestoreType::Reference(_) => "reference",
+        FirestoreType::Bytes(_) => "bytes",
+    }
+}
+
+/// Path of a document relative to the root of its database, e.g. `users/alice/orders/1`
+fn relative_path(document: &Document) -> &str {
+    let name = document.name();
+    // database IDs cannot contain slashes, so the first match is the root of the tree
+    name.splitn(2, "/documents/").nth(1).unwrap_or(name)
+}
+
+impl Manifest {
+    fn load(dir: &Path) -> Result<Option<Manifest>> {
+        match File::open(dir.join(MANIFEST_FILE_NAME)) {
+            Ok(file) => Ok(Some(serde_json::from_reader(BufReader::new(file))?)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+
+    fn save(&self, dir: &Path) -> Result<()> {
+        checkpoint::write_json(&dir.join(MANIFEST_FILE_NAME), self)
+    }
+}
+
+/// Writes the documents of one root collection into numbered chunk files
+struct ChunkWriter<'a> {
+    dir: &'a Path,
+    manifest: Manifest,
+    /// Index of the collection being written in `manifest.collections`
+    index: usize,
+    out: Option<BufWriter<File>>,
+    /// Documents written to the open chunk
+    written: usize,
+    bar: &'a Bar,
+}
+
+impl<'a> ChunkWriter<'a> {
+    fn entry(&mut self) -> &mut CollectionEntry {
+        &mut self.manifest.collections[self.index]
+    }
+
+    fn chunk_file(&self) -> String {
+        let entry = &self.manifest.collections[self.index];
+        format!("{}/{:05}.ndjson", entry.id, entry.chunks.len())
+    }
+
+    fn write(&mut self, document: &Document) -> Result<()> {
+        if self.out.is_none() {
+            let path = self.dir.join(self.chunk_file());
+            self.out = Some(BufWriter::new(File::create(path)?));
+        }
+        let record = json!({
+            "path": relative_path(document),
+            "createTime": document.create_time().to_rfc3339(),
+            "updateTime": document.update_time().to_rfc3339(),
+            "fields": document.fields(),
+        });
+        writeln!(self.out.as_mut().unwrap(), "{}", record)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Closes the open chunk, if any, and records it in the manifest along with
+    /// the page of the root collection that follows it
+    fn finish_chunk(&mut self, page_token: Option<&str>) -> Result<()> {
+        let mut out = match self.out.take() {
+            Some(out) => out,
+            None => return Ok(()),
+        };
+        out.flush()?;
+        let chunk = Chunk {
+            file: self.chunk_file(),
+            documents: self.written,
+        };
+        let written = self.written;
+        self.written = 0;
+        let entry = self.entry();
+        entry.documents += written;
+        entry.chunks.push(chunk);
+        entry.page_token = page_token.map(String::from);
+        self.manifest.save(self.dir)?;
+        let documents = self
+            .manifest
+            .collections
+            .iter()
+            .map(|entry| entry.documents);
+        self.bar.set(documents.sum());
+        Ok(())
+    }
+
+    fn sample(&mut self, fields: &FirestoreFields, position: usize) {
+        if position >= SCHEMA_SAMPLE_SIZE {
+            return;
+        }
+        let schema = &mut self.entry().schema;
+        for (name, value) in &fields.0 {
+            schema
+                .entry(name.clone())
+                .or_insert_with(BTreeSet::new)
+                .insert(type_name(value).to_string());
+        }
+    }
+}
+
+/// Writes `document` followed by every document of its subcollections, parents first
+fn write_tree(
+    ctx: &DatabaseContext,
+    collection_name: &str,
+    document: &Document,
+    page_size: Option<i32>,
+    writer: &mut ChunkWriter,
+) -> Result<()> {
+    writer.write(document)?;
+    let mut collection_ids = ctx.list_collection_ids(collection_name, document.id())?;
+    collection_ids.sort();
+    for collection_id in collection_ids {
+        let path = format!("{}/{}/{}", collection_name, document.id(), collection_id);
+        for child in ctx
+            .iter_documents(&*path, page_size)
+            .order_by("__name__", Direction::Ascending)
+        {
+            write_tree(ctx, &*path, &child?, page_size, writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the root collection of `writer`'s current entry, from the page its
+/// manifest entry stopped at. Chunks are closed between pages of the root collection
+/// once they hold `chunk_size` documents.
+fn write_collection(
+    ctx: &DatabaseContext,
+    page_size: Option<i32>,
+    writer: &mut ChunkWriter,
+) -> Result<()> {
+    let collection_id = writer.entry().id.clone();
+    let mut documents = ctx
+        .iter_documents(&*collection_id, page_size)
+        .order_by("__name__", Direction::Ascending);
+    // the schema is sampled from the first documents, which a resumed backup already saw
+    let mut position = 0;
+    if let Some(page_token) = writer.entry().page_token.clone() {
+        documents = documents.resume_from(page_token);
+        position = SCHEMA_SAMPLE_SIZE;
+    }
+    while let Some(document) = documents.next() {
+        let document = document?;
+        writer.sample(document.fields(), position);
+        position += 1;
+        write_tree(ctx, &*collection_id, &document, page_size, writer)?;
+        if writer.written >= writer.manifest.chunk_size {
+            if let Some(page_token) = documents.resume_token() {
+                writer.finish_chunk(Some(page_token))?;
+            }
+        }
+    }
+    writer.finish_chunk(None)
+}
+
+/// Backs up every root collection into `dir`. With `resume` set the unfinished backup
+/// of the same database in `dir` is continued, otherwise `dir` must not hold a backup.
+/// Returns the manifest of the finished backup.
+pub fn backup(
+    ctx: &DatabaseContext,
+    dir: &Path,
+    chunk_size: usize,
+    page_size: Option<i32>,
+    resume: bool,
+    bar: &Bar,
+) -> Result<Manifest> {
+    let manifest = match Manifest::load(dir)? {
+        Some(_) if !resume => {
+            return Err(invalid(format!(
+                "{} already holds a backup, pass --resume to continue an unfinished one",
+                dir.display()
+            )))
+        }
+        None if resume => {
+            return Err(invalid(format!(
+                "{} holds no backup to resume",
+                dir.display()
+            )))
+        }
+        Some(manifest) => {
+            if manifest.project_id != ctx.project_id || manifest.database != ctx.database_id() {
+                return Err(invalid(format!(
+                    "{} holds a backup of {}/{}",
+                    dir.display(),
+                    manifest.project_id,
+                    manifest.database
+                )));
+            }
+            if manifest.finished_at.is_some() {
+                return Err(invalid(format!(
+                    "{} already holds a finished backup",
+                    dir.display()
+                )));
+            }
+            manifest
+        }
+        None => Manifest {
+            version: MANIFEST_VERSION,
+            project_id: ctx.project_id.clone(),
+            database: ctx.database_id().to_string(),
+            started_at: Utc::now(),
+            finished_at: None,
+            chunk_size,
+            collections: Vec::new(),
+        },
+    };
+    fs::create_dir_all(dir)?;
+    let mut writer = ChunkWriter {
+        dir,
+        manifest,
+        index: 0,
+        out: None,
+        written: 0,
+        bar,
+    };
+    let mut collection_ids = ctx.list_root_collection_ids()?;
+    collection_ids.sort();
+    for collection_id in collection_ids {
+        let existing = writer
+            .manifest
+            .collections
+            .iter()
+            .position(|entry| entry.id == collection_id);
+        writer.index = match existing {
+            Some(index) if writer.manifest.collections[index].complete => continue,
+            Some(index) => index,
+            None => {
+                writer.manifest.collections.push(CollectionEntry {
+                    id: collection_id.clone(),
+                    documents: 0,
+                    chunks: Vec::new(),
+                    complete: false,
+                    page_token: None,
+                    schema: BTreeMap::new(),
+                });
+                writer.manifest.collections.len() - 1
+            }
+        };
+        fs::create_dir_all(dir.join(&*collection_id))?;
+        write_collection(ctx, page_size, &mut writer)?;
+        writer.entry().complete = true;
+        writer.manifest.save(dir)?;
+    }
+    writer.manifest.finished_at = Some(Utc::now());
+    writer.manifest.save(dir)?;
+    status!();
+    Ok(writer.manifest)
+}
+
+/// Splits a restored record into the collection and ID of its document and its fields
+fn parse_record(line: &str) -> Result<(String, String, FirestoreFields)> {
+    let mut record: serde_json::Value = serde_json::from_str(line)?;
+    let path = match record["path"].as_str() {
+        Some(path) if path.split('/').count() % 2 == 0 => path.to_string(),
+        _ => {
+            return Err(Error::InvalidDocument {
+                reason: String::from("backup record is missing the path of its document"),
+            })
+        }
+    };
+    let slash = path.rfind('/').unwrap();
+    let fields = serde_json::from_value(record["fields"].take())?;
+    Ok((
+        path[..slash].to_string(),
+        path[slash + 1..].to_string(),
+        fields,
+    ))
+}
+
+/// Writes every document of the backup in `dir` back into the database, overwriting
+/// documents with the same path. Chunks are committed in batches of `batch_size` writes,
+/// with the concurrency and write rate of `ctx`,
+/// and recorded once written, so an interrupted restore skips them when run again.
+/// Returns the number of documents written.
+pub fn restore(ctx: &DatabaseContext, dir: &Path, batch_size: usize, bar: &Bar) -> Result<usize> {
+    let manifest = Manifest::load(dir)?
+        .ok_or_else(|| invalid(format!("{} holds no backup manifest", dir.display())))?;
+    if manifest.version != MANIFEST_VERSION {
+        return Err(invalid(format!(
+            "backup version {} is not supported",
+            manifest.version
+        )));
+    }
+    if manifest.finished_at.is_none() {
+        status!("Warning: the backup in {} is incomplete", dir.display());
+    }
+    let progress_path: PathBuf = dir.join(RESTORED_FILE_NAME);
+    let restored = match fs::read_to_string(&progress_path) {
+        Ok(text) => text.lines().map(String::from).collect::<HashSet<String>>(),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => HashSet::new(),
+        Err(e) => return Err(Error::from(e)),
+    };
+    // a dry run writes nothing, so it does not record anything either
+    let mut progress = if ctx.is_dry_run() {
+        None
+    } else {
+        Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&progress_path)?,
+        )
+    };
+    let chunks = manifest
+        .collections
+        .iter()
+        .flat_map(|entry| &entry.chunks)
+        .filter(|chunk| !restored.contains(&chunk.file))
+        .collect::<Vec<_>>();
+    bar.set_total(chunks.iter().map(|chunk| chunk.documents).sum());
+    let mut count = 0;
+    for chunk in chunks {
+        // the chunk is only recorded once every commit of it went through
+        let before = count;
+        let mut pool = CommitPool::new(ctx, move |written| bar.set(before + written));
+        let mut batch = ctx.batch();
+        for line in BufReader::new(File::open(dir.join(&chunk.file))?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (collection_name, document_id, fields) = parse_record(&*line)?;
+            batch.set(collection_name, document_id, fields, false);
+            if batch.len() == batch_size {
+                pool.submit(mem::replace(&mut batch, ctx.batch()))?;
+            }
+        }
+        pool.submit(batch)?;
+        count += pool.finish()?;
+        if let Some(progress) = progress.as_mut() {
+            writeln!(progress, "{}", chunk.file)?;
+        }
+    }
+    if progress.take().is_some() {
+        fs::remove_file(progress_path)?;
+    }
+    Ok(count)
+}
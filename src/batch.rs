@@ -0,0 +1,147 @@
+// Parsing of batch scripts, one write per line:
+//   set <collection> <document> <json>
+//   merge <collection> <document> <json>
+//   update <collection> <document> <path[:type]=value>...
+//   delete <collection> <document>
+// Blank lines and lines starting with `#` are ignored.
+
+use crate::fields::{self, FieldAssignment};
+use libfiresale::api::FirestoreFields;
+use libfiresale::errors::{Error, Result};
+
+/// A single write of a batch script
+pub enum Operation {
+    Set {
+        collection_name: String,
+        document_name: String,
+        fields: FirestoreFields,
+        merge: bool,
+    },
+    Update {
+        collection_name: String,
+        document_name: String,
+        assignments: Vec<FieldAssignment>,
+    },
+    Delete {
+        collection_name: String,
+        document_name: String,
+    },
+}
+
+fn invalid(line_number: usize, reason: String) -> Error {
+    Error::InvalidArgument {
+        reason: format!("line {}: {}", line_number, reason),
+    }
+}
+
+/// Splits the first whitespace separated word off of `input`
+fn next_word(input: &str) -> (&str, &str) {
+    let input = input.trim_start();
+    match input.find(char::is_whitespace) {
+        Some(end) => (&input[..end], &input[end..]),
+        None => (input, ""),
+    }
+}
+
+/// Splits `input` on whitespace, keeping double quoted sections
+/// together and dropping the quotes
+fn split_words(input: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    for c in input.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::replace(&mut word, String::new()));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quoted {
+        return Err(Error::InvalidArgument {
+            reason: format!("{} has an unterminated quote", input),
+        });
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Parses a single non-empty line of a batch script
+fn parse_line(line_number: usize, line: &str) -> Result<Operation> {
+    let (verb, rest) = next_word(line);
+    let (collection_name, rest) = next_word(rest);
+    let (document_name, rest) = next_word(rest);
+    if document_name.is_empty() {
+        return Err(invalid(
+            line_number,
+            format!("expected {} <collection> <document>", verb),
+        ));
+    }
+    let collection_name = collection_name.to_string();
+    let document_name = document_name.to_string();
+    let rest = rest.trim();
+    match verb {
+        "set" | "merge" => Ok(Operation::Set {
+            collection_name,
+            document_name,
+            fields: FirestoreFields::from_json(serde_json::from_str(rest)?)?,
+            merge: verb == "merge",
+        }),
+        "update" => {
+            let assignments = split_words(rest)?
+                .iter()
+                .map(|assignment| fields::parse_assignment(assignment))
+                .collect::<Result<Vec<_>>>()?;
+            if assignments.is_empty() {
+                return Err(invalid(line_number, String::from("update has no fields")));
+            }
+            if let Some(element) = assignments.iter().find(|a| a.path.has_index()) {
+                return Err(invalid(
+                    line_number,
+                    format!(
+                        "{} is an element of an array, which batches cannot write",
+                        element.path
+                    ),
+                ));
+            }
+            Ok(Operation::Update {
+                collection_name,
+                document_name,
+                assignments,
+            })
+        }
+        "delete" if rest.is_empty() => Ok(Operation::Delete {
+            collection_name,
+            document_name,
+        }),
+        "delete" => Err(invalid(
+            line_number,
+            format!("unexpected {} after delete", rest),
+        )),
+        _ => Err(invalid(line_number, format!("unknown operation {}", verb))),
+    }
+}
+
+/// Parses every operation of a batch script
+pub fn parse_script(script: &str) -> Result<Vec<Operation>> {
+    script
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| parse_line(line_number, line))
+        .collect()
+}
@@ -0,0 +1,194 @@
+// Writes of bulk operations where every write stands on its own, buffered and committed
+// in batches of up to `MAX_WRITES_PER_COMMIT` on the calling thread. A batch turned away
+// with RESOURCE_EXHAUSTED is sent again once writes slow down, and one failing with
+// another transient error only when `RetryPolicy::retry_writes` is set, as it may have
+// been applied already. A batch rejected because of one of its writes is split in halves and committed again, so a bad write only fails
+// itself. The outcome of every write is reported to the callbacks, and whatever is still
+// buffered is committed when the writer is dropped.
+
+use crate::api::{
+    DatabaseContext, FieldTransform, FirestoreFields, WriteBatch, MAX_WRITES_PER_COMMIT,
+};
+use crate::errors::Error;
+use crate::firestore::write::{Write, WriteResult};
+use std::mem;
+
+/// Number of writes a `BulkWriter` committed and gave up on so far
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BulkSummary {
+    pub written: usize,
+    pub failed: usize,
+}
+
+/// Buffers writes and commits them in batches, see `DatabaseContext::bulk_writer`
+pub struct BulkWriter<'a> {
+    ctx: &'a DatabaseContext,
+    batch: WriteBatch<'a>,
+    summary: BulkSummary,
+    on_success: Box<dyn FnMut(&Write, &WriteResult) + 'a>,
+    on_failure: Box<dyn FnMut(&Write, &Error) + 'a>,
+}
+
+/// Whether a commit failing with `error` may have been rejected because of a single
+/// one of its writes rather than because of the request as a whole
+fn rejected_write(error: &Error) -> bool {
+    match error {
+        Error::InvalidArgument { .. }
+        | Error::NotFound { .. }
+        | Error::AlreadyExists { .. }
+        | Error::FailedPrecondition { .. }
+        | Error::Status { .. } => true,
+        _ => false,
+    }
+}
+
+impl<'a> BulkWriter<'a> {
+    pub(crate) fn new(ctx: &'a DatabaseContext) -> BulkWriter<'a> {
+        BulkWriter {
+            ctx,
+            batch: ctx.batch(),
+            summary: BulkSummary::default(),
+            on_success: Box::new(|_, _| {}),
+            on_failure: Box::new(|write, error| {
+                warn!("{} was not written: {}", write.document_name(), error)
+            }),
+        }
+    }
+
+    /// Calls `f` with every write once it is committed, along with its result.
+    /// The result has no update time during dry runs.
+    pub fn on_success<F>(mut self, f: F) -> BulkWriter<'a>
+    where
+        F: FnMut(&Write, &WriteResult) + 'a,
+    {
+        self.on_success = Box::new(f);
+        self
+    }
+
+    /// Calls `f` with every write that could not be committed and the reason why,
+    /// instead of logging it as a warning
+    pub fn on_failure<F>(mut self, f: F) -> BulkWriter<'a>
+    where
+        F: FnMut(&Write, &Error) + 'a,
+    {
+        self.on_failure = Box::new(f);
+        self
+    }
+
+    /// Buffers a write of `fields` to a document as `WriteBatch::set` does
+    pub fn set<S>(
+        &mut self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        merge: bool,
+    ) -> &mut BulkWriter<'a>
+    where
+        S: Into<String>,
+    {
+        self.batch.set(collection_name, document_id, fields, merge);
+        self.flush_full()
+    }
+
+    /// Buffers an update of an existing document as `WriteBatch::update` does
+    pub fn update<S>(
+        &mut self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        field_paths: Vec<String>,
+    ) -> &mut BulkWriter<'a>
+    where
+        S: Into<String>,
+    {
+        self.batch
+            .update(collection_name, document_id, fields, field_paths);
+        self.flush_full()
+    }
+
+    /// Buffers an update with transforms as `WriteBatch::update_with_transforms` does
+    pub fn update_with_transforms<S>(
+        &mut self,
+        collection_name: S,
+        document_id: S,
+        fields: FirestoreFields,
+        field_paths: Vec<String>,
+        transforms: Vec<(String, FieldTransform)>,
+    ) -> &mut BulkWriter<'a>
+    where
+        S: Into<String>,
+    {
+        self.batch.update_with_transforms(
+            collection_name,
+            document_id,
+            fields,
+            field_paths,
+            transforms,
+        );
+        self.flush_full()
+    }
+
+    /// Buffers a delete as `WriteBatch::delete` does
+    pub fn delete<S>(&mut self, collection_name: S, document_id: S) -> &mut BulkWriter<'a>
+    where
+        S: Into<String>,
+    {
+        self.batch.delete(collection_name, document_id);
+        self.flush_full()
+    }
+
+    /// Commits the buffered writes once there are as many as a commit holds
+    fn flush_full(&mut self) -> &mut BulkWriter<'a> {
+        if self.batch.len() >= MAX_WRITES_PER_COMMIT {
+            self.flush();
+        }
+        self
+    }
+
+    /// Commits every buffered write, reporting each of them to the callbacks
+    pub fn flush(&mut self) {
+        let batch = mem::replace(&mut self.batch, self.ctx.batch());
+        if !batch.is_empty() {
+            self.commit(batch.into_writes());
+        }
+    }
+
+    /// Commits `writes`, which `commit_throttled` sends again on errors the retry policy
+    /// allows for writes
+    fn commit(&mut self, mut writes: Vec<Write>) {
+        match self.ctx.commit_throttled(writes.clone()) {
+            Ok(response) => {
+                self.summary.written += writes.len();
+                let unknown = WriteResult { update_time: None };
+                for (i, write) in writes.iter().enumerate() {
+                    let result = response.write_results.get(i).unwrap_or(&unknown);
+                    (self.on_success)(write, result);
+                }
+            }
+            Err(ref e) if writes.len() > 1 && rejected_write(e) => {
+                // nothing was applied, so each half is committed again on its own
+                let second_half = writes.split_off(writes.len() / 2);
+                self.commit(writes);
+                self.commit(second_half);
+            }
+            Err(e) => {
+                self.summary.failed += writes.len();
+                for write in &writes {
+                    (self.on_failure)(write, &e);
+                }
+            }
+        }
+    }
+
+    /// Commits the buffered writes and tells how many writes were committed and failed
+    pub fn close(mut self) -> BulkSummary {
+        self.flush();
+        self.summary
+    }
+}
+
+impl<'a> Drop for BulkWriter<'a> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
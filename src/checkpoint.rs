@@ -0,0 +1,87 @@
+// Progress of a long running export, saved next to its output as `<out>.checkpoint`
+// after every page so that `export --resume` carries on from the last complete page
+// instead of starting over. The output file is cut back to the length it had when the
+// checkpoint was taken, dropping documents written after it.
+// Backups keep the same kind of progress in their manifest.
+
+use libfiresale::errors::{Error, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Length of the output file when the checkpoint was taken
+    pub offset: u64,
+    pub collections: Vec<Progress>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Progress {
+    /// Path of the collection
+    pub id: String,
+    /// Page to continue listing from, `None` to start from the first page
+    pub page_token: Option<String>,
+    /// Documents written so far
+    pub documents: usize,
+    pub complete: bool,
+}
+
+/// Writes `value` to `path` as JSON, replacing the previous file only once the new one
+/// is complete so that an interruption never leaves a truncated file behind
+pub fn write_json<T: serde::Serialize>(path: &Path, value: &T) -> Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let mut out = BufWriter::new(File::create(&partial)?);
+    serde_json::to_writer_pretty(&mut out, value)?;
+    writeln!(out)?;
+    out.flush()?;
+    fs::rename(partial, path)?;
+    Ok(())
+}
+
+impl Checkpoint {
+    /// Where the checkpoint of an export to `out` is kept
+    pub fn path_for(out: &str) -> PathBuf {
+        PathBuf::from(format!("{}.checkpoint", out))
+    }
+
+    pub fn load(path: &Path) -> Result<Checkpoint> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Err(Error::InvalidArgument {
+                reason: format!(
+                    "there is no checkpoint at {} to resume from",
+                    path.display()
+                ),
+            }),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_json(path, self)
+    }
+
+    /// Progress of the collection `id`, starting it if the checkpoint has none
+    pub fn progress(&mut self, id: &str) -> &mut Progress {
+        match self
+            .collections
+            .iter()
+            .position(|progress| progress.id == id)
+        {
+            Some(index) => &mut self.collections[index],
+            None => {
+                self.collections.push(Progress {
+                    id: id.to_string(),
+                    page_token: None,
+                    documents: 0,
+                    complete: false,
+                });
+                self.collections.last_mut().unwrap()
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Shell completion scripts. The scripts generated by clap are extended, for the shells
+// that allow it, with a hook completing collection names by running `firesale collections`
+// with the arguments given before the subcommand, which only succeeds when a project and
+// credentials are available.
+
+use clap::{App, Shell};
+use libfiresale::errors::Result;
+use std::io::Write;
+
+/// Subcommands whose first argument is a collection
+const COLLECTION_SUB_COMMANDS: &str =
+    "get exists diff delete set update query count aggregate copy move import export";
+
+fn bash_hook() -> String {
+    format!(
+        r#"
+# Completes the collection argument of {subcommands} with the
+# collections of the project, falling back to the generated completion
+_firesale_collections() {{
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case " {subcommands} " in
+        *" ${{prev}} "*)
+            local options=("${{COMP_WORDS[@]:1:COMP_CWORD-2}}")
+            local collections
+            collections="$(firesale "${{options[@]}}" --format table collections 2>/dev/null)"
+            if [ -n "${{collections}}" ]; then
+                COMPREPLY=($(compgen -W "${{collections}}" -- "${{COMP_WORDS[COMP_CWORD]}}"))
+                return 0
+            fi
+            ;;
+    esac
+    _firesale "$@"
+}}
+complete -F _firesale_collections -o bashdefault -o default firesale
+"#,
+        subcommands = COLLECTION_SUB_COMMANDS
+    )
+}
+
+fn fish_hook() -> String {
+    format!(
+        r#"
+# Completes the collection argument of {subcommands} with the collections of the project
+function __firesale_collections
+    set -l words (commandline -opc)
+    set -l options
+    if test (count $words) -gt 2
+        set options $words[2..-2]
+    end
+    firesale $options --format table collections 2>/dev/null
+end
+complete -c firesale -n "__fish_seen_subcommand_from {subcommands}" -f -a "(__firesale_collections)"
+"#,
+        subcommands = COLLECTION_SUB_COMMANDS
+    )
+}
+
+/// Writes the completion script of `app` for `shell` to `out`
+pub fn write<W: Write>(mut app: App, shell: Shell, out: &mut W) -> Result<()> {
+    app.gen_completions_to(crate::APP_NAME, shell, out);
+    match shell {
+        Shell::Bash => write!(out, "{}", bash_hook())?,
+        Shell::Fish => write!(out, "{}", fish_hook())?,
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+// Named profiles read from `~/.config/firesale/config.toml`:
+//   default_profile = "staging"
+//
+//   [profiles.staging]
+//   project_id = "my-project-staging"
+//   credentials = "/path/to/service-account.json"   # or "keychain:NAME"
+//   emulator_host = "localhost:8080"
+//   format = "yaml"
+//   impersonate_service_account = "deployer@my-project-staging.iam.gserviceaccount.com"
+// Every key is optional. Command line arguments and environment variables
+// take precedence over the values of a profile.
+
+use libfiresale::auth::KEYCHAIN_PREFIX;
+use libfiresale::credential_store;
+use libfiresale::errors::{Error, Result};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+const XDG_CONFIG_HOME_KEY: &'static str = "XDG_CONFIG_HOME";
+const XDG_CACHE_HOME_KEY: &'static str = "XDG_CACHE_HOME";
+const CONFIG_FILE_NAME: &'static str = "config.toml";
+const CREDENTIALS_FILE_NAME: &'static str = "credentials.json";
+const GOOGLE_APPLICATION_CREDENTIALS_KEY: &'static str = "GOOGLE_APPLICATION_CREDENTIALS";
+/// Keychain account the credentials of `auth login` are stored under
+pub const LOGIN_ACCOUNT: &'static str = "login";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Profile {
+    pub project_id: Option<String>,
+    pub credentials: Option<String>,
+    pub emulator_host: Option<String>,
+    pub format: Option<String>,
+    pub impersonate_service_account: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Profile used when `--profile` is not given
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// Directory of the config file, honoring `XDG_CONFIG_HOME`
+fn config_dir() -> Option<PathBuf> {
+    let config_dir = match env::var(XDG_CONFIG_HOME_KEY) {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) if cfg!(windows) => PathBuf::from(env::var("APPDATA").ok()?),
+        Err(_) => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+    };
+    Some(config_dir.join("firesale"))
+}
+
+/// Where the config file is read from, whether or not it exists
+pub fn config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join(CONFIG_FILE_NAME))
+}
+
+/// Where `auth login` stores the credentials of the signed in user
+pub fn credentials_path() -> Option<PathBuf> {
+    Some(config_dir()?.join(CREDENTIALS_FILE_NAME))
+}
+
+/// The credentials stored by `auth login`, which take the place of the gcloud
+/// ADC file: they are only used when `GOOGLE_APPLICATION_CREDENTIALS` is not set.
+/// The keychain is looked at before the config directory.
+pub fn login_credentials() -> Option<String> {
+    if env::var_os(GOOGLE_APPLICATION_CREDENTIALS_KEY).is_some() {
+        return None;
+    }
+    if credential_store::is_available() {
+        if let Ok(Some(_)) = credential_store::get(LOGIN_ACCOUNT) {
+            return Some(format!("{}{}", KEYCHAIN_PREFIX, LOGIN_ACCOUNT));
+        }
+    }
+    let path = credentials_path()?;
+    if path.is_file() {
+        Some(path.to_string_lossy().into_owned())
+    } else {
+        None
+    }
+}
+
+/// Directory access tokens are kept in with `--cache-token`, honoring `XDG_CACHE_HOME`
+pub fn token_cache_dir() -> Option<PathBuf> {
+    let cache_dir = match env::var(XDG_CACHE_HOME_KEY) {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) if cfg!(windows) => PathBuf::from(env::var("LOCALAPPDATA").ok()?),
+        Err(_) => PathBuf::from(env::var("HOME").ok()?).join(".cache"),
+    };
+    Some(cache_dir.join("firesale").join("tokens"))
+}
+
+impl Config {
+    /// Reads the config file, a missing file is treated as an empty config
+    pub fn load() -> Result<Config> {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(Error::from(e)),
+        };
+        toml::from_str(&text).map_err(|e| Error::InvalidArgument {
+            reason: format!("{}: {}", path.display(), e),
+        })
+    }
+
+    /// The name of the profile `into_profile` takes: `name`, or else the default profile
+    pub fn profile_name(&self, name: Option<&str>) -> Option<String> {
+        name.map(String::from)
+            .or_else(|| self.default_profile.clone())
+    }
+
+    /// Takes the profile called `name`, or the default profile when no name is given.
+    /// Without either an empty profile is returned.
+    pub fn into_profile(mut self, name: Option<&str>) -> Result<Profile> {
+        let name = match self.profile_name(name) {
+            Some(name) => name,
+            None => return Ok(Profile::default()),
+        };
+        self.profiles
+            .remove(&name)
+            .ok_or_else(|| Error::InvalidArgument {
+                reason: format!("no profile named {} in the config file", name),
+            })
+    }
+}
@@ -0,0 +1,56 @@
+// Secrets kept in the keychain of the operating system: the macOS Keychain, the Secret
+// Service on Linux and the Windows Credential Manager. Secrets are stored under the
+// service `firesale`, one per account name. The keychain is only used when firesale is
+// built with the `keychain` feature, since the Secret Service client needs libdbus.
+
+use super::errors::{Error, Result};
+
+#[cfg(feature = "keychain")]
+const SERVICE: &'static str = "firesale";
+
+/// Whether this build can use the keychain
+pub fn is_available() -> bool {
+    cfg!(feature = "keychain")
+}
+
+#[cfg(feature = "keychain")]
+fn store_error(error: keyring::KeyringError) -> Error {
+    Error::Authentication {
+        reason: format!("keychain: {}", error),
+    }
+}
+
+#[cfg(not(feature = "keychain"))]
+fn unavailable() -> Error {
+    Error::Authentication {
+        reason: String::from("keychain: firesale was built without the keychain feature"),
+    }
+}
+
+/// The secret stored for `account`, if any
+#[cfg(feature = "keychain")]
+pub fn get(account: &str) -> Result<Option<String>> {
+    match keyring::Keyring::new(SERVICE, account).get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::KeyringError::NoPasswordFound) => Ok(None),
+        Err(e) => Err(store_error(e)),
+    }
+}
+
+#[cfg(not(feature = "keychain"))]
+pub fn get(_account: &str) -> Result<Option<String>> {
+    Err(unavailable())
+}
+
+/// Stores `secret` for `account`, replacing the previous one
+#[cfg(feature = "keychain")]
+pub fn set(account: &str, secret: &str) -> Result<()> {
+    keyring::Keyring::new(SERVICE, account)
+        .set_password(secret)
+        .map_err(store_error)
+}
+
+#[cfg(not(feature = "keychain"))]
+pub fn set(_account: &str, _secret: &str) -> Result<()> {
+    Err(unavailable())
+}
@@ -0,0 +1,89 @@
+// Duplicate detection for `dedupe`: documents are grouped by the values of their key
+// fields, and every group of more than one document keeps either its oldest or its
+// newest document by creation time. Documents missing a key field are never duplicates.
+
+use chrono::{DateTime, Utc};
+use libfiresale::api::FirestoreFields;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Which document of a group of duplicates is kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keep {
+    Oldest,
+    Newest,
+}
+
+/// Documents sharing the values of the key fields
+#[derive(Debug)]
+pub struct DuplicateGroup {
+    /// Value of the key field, or array of the values when there are several
+    pub key: Value,
+    pub kept: String,
+    /// Paths of the other documents of the group, oldest first
+    pub duplicates: Vec<String>,
+}
+
+/// The documents observed so far, by their key
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    documents: usize,
+    /// Paths and creation times by the JSON of the key, for a stable order
+    groups: BTreeMap<String, (Value, Vec<(DateTime<Utc>, String)>)>,
+}
+
+impl Duplicates {
+    /// Adds the document at `path`, unless it misses one of the `keys`
+    pub fn observe(
+        &mut self,
+        path: &str,
+        create_time: DateTime<Utc>,
+        fields: &FirestoreFields,
+        keys: &[String],
+    ) {
+        self.documents += 1;
+        let mut values = Vec::new();
+        for key in keys {
+            match fields.get(key) {
+                Some(value) => values.push(value.to_json()),
+                None => return,
+            }
+        }
+        let key = if values.len() == 1 {
+            values.pop().unwrap()
+        } else {
+            Value::Array(values)
+        };
+        self.groups
+            .entry(key.to_string())
+            .or_insert_with(|| (key, Vec::new()))
+            .1
+            .push((create_time, path.to_string()));
+    }
+
+    /// Number of documents observed
+    pub fn documents(&self) -> usize {
+        self.documents
+    }
+
+    /// The groups of more than one document, in the order of their keys
+    pub fn groups(self, keep: Keep) -> Vec<DuplicateGroup> {
+        let mut groups = Vec::new();
+        for (_, (key, mut documents)) in self.groups {
+            if documents.len() < 2 {
+                continue;
+            }
+            documents.sort();
+            let kept = match keep {
+                Keep::Oldest => documents.remove(0),
+                Keep::Newest => documents.pop().unwrap(),
+            };
+            groups.push(DuplicateGroup {
+                key,
+                kept: kept.1,
+                duplicates: documents.into_iter().map(|(_, path)| path).collect(),
+            });
+        }
+        groups
+    }
+}
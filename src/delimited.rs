@@ -0,0 +1,232 @@
+// CSV output of documents and CSV input for `import --format csv`.
+// Nested maps are flattened into dotted headers such as `address.city` when writing
+// and rebuilt from them when reading, keys holding dots being quoted in backticks as in
+// field paths. Arrays are written as JSON.
+
+use crate::fields::{self, FieldAssignment};
+use crate::output::META_COLUMNS;
+use libfiresale::api::FirestoreFields;
+use libfiresale::errors::{Error, Result};
+use libfiresale::fieldpath::{self, FieldPath};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{Read, Write};
+
+fn output_error(source: csv::Error) -> Error {
+    Error::Output {
+        reason: source.to_string(),
+    }
+}
+
+fn input_error(source: csv::Error) -> Error {
+    Error::InvalidDocument {
+        reason: source.to_string(),
+    }
+}
+
+/// Renders a value as a CSV cell, strings without quotes and null as an empty cell
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// A key as part of a header, quoted when it would not be read back as a single key.
+/// Other keys are left as they are, so `first name` stays a readable header.
+fn header_key(key: &str) -> String {
+    if key.contains(|c| c == '.' || c == '[' || c == '`') {
+        fieldpath::escape_key(key)
+    } else {
+        key.to_string()
+    }
+}
+
+/// Collects the cells of `value` keyed by their dotted path below `prefix`
+fn flatten(prefix: &str, value: &Value, cells: &mut BTreeMap<String, String>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let path = if prefix.is_empty() {
+                    header_key(key)
+                } else {
+                    format!("{}.{}", prefix, header_key(key))
+                };
+                flatten(&path, value, cells);
+            }
+        }
+        value => {
+            cells.insert(prefix.to_string(), cell(value));
+        }
+    }
+}
+
+/// Writes documents, as given by `Document::to_json`, with an ID column followed by
+/// a column for each of `fields`, or for every flattened field when none are given.
+/// With `meta`, the name, createTime and updateTime of documents come after their ID.
+pub fn write_documents<W: Write>(
+    out: W,
+    documents: &[Value],
+    fields: Option<&[String]>,
+    meta: bool,
+) -> Result<()> {
+    let meta_columns = if meta { META_COLUMNS } else { &[] };
+    let rows = documents
+        .iter()
+        .map(|document| {
+            let mut cells = BTreeMap::new();
+            flatten("", &document["fields"], &mut cells);
+            cells
+        })
+        .collect::<Vec<_>>();
+    let columns = match fields {
+        Some(fields) => fields.to_vec(),
+        None => rows
+            .iter()
+            .flat_map(|cells| cells.keys().cloned())
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect(),
+    };
+    let mut writer = csv::Writer::from_writer(out);
+    writer
+        .write_record(
+            std::iter::once("id")
+                .chain(meta_columns.iter().cloned())
+                .chain(columns.iter().map(|c| &**c)),
+        )
+        .map_err(output_error)?;
+    for (document, cells) in documents.iter().zip(&rows) {
+        let mut record = vec![cell(&document["id"])];
+        record.extend(meta_columns.iter().map(|column| cell(&document[*column])));
+        for column in &columns {
+            let value = match cells.get(column) {
+                Some(value) => value.clone(),
+                // a map chosen with --fields is written whole
+                None => FieldPath::parse(column)
+                    .ok()
+                    .and_then(|path| path.get_json(&document["fields"]).map(Value::to_string))
+                    .unwrap_or_default(),
+            };
+            record.push(value);
+        }
+        writer.write_record(&record).map_err(output_error)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a CSV file with a header row into document IDs and fields. The ID is taken
+/// from `id_column`, other columns are strings unless `types` names their type.
+/// Empty cells are left out of the document. Without `id_column` every column is a
+/// field and the IDs are left empty, for the caller to choose.
+pub fn read_documents<R: Read>(
+    input: R,
+    id_column: Option<&str>,
+    types: &HashMap<String, String>,
+) -> Result<Vec<(String, FirestoreFields)>> {
+    let mut reader = csv::Reader::from_reader(input);
+    let headers = reader.headers().map_err(input_error)?.clone();
+    let id_column = match id_column {
+        Some(id_column) => id_column,
+        None => {
+            return reader
+                .records()
+                .map(|record| {
+                    let record = record.map_err(input_error)?;
+                    let assignments = row_assignments(&headers, &record, None, types)?;
+                    Ok((String::new(), fields::into_fields(assignments).0))
+                })
+                .collect();
+        }
+    };
+    if !headers.iter().any(|header| header == id_column) {
+        return Err(Error::InvalidDocument {
+            reason: format!("the header row has no {} column", id_column),
+        });
+    }
+    let mut documents = Vec::new();
+    for (row, record) in reader.records().enumerate() {
+        let record = record.map_err(input_error)?;
+        let id = headers
+            .iter()
+            .zip(record.iter())
+            .find(|(header, _)| *header == id_column)
+            .map_or("", |(_, value)| value)
+            .to_string();
+        let assignments = row_assignments(&headers, &record, Some(id_column), types)?;
+        if id.is_empty() {
+            return Err(Error::InvalidDocument {
+                // the header is line 1
+                reason: format!("line {} has an empty {} column", row + 2, id_column),
+            });
+        }
+        documents.push((id, fields::into_fields(assignments).0));
+    }
+    Ok(documents)
+}
+
+/// The fields of a row, every non-empty cell outside of `id_column`
+fn row_assignments(
+    headers: &csv::StringRecord,
+    record: &csv::StringRecord,
+    id_column: Option<&str>,
+    types: &HashMap<String, String>,
+) -> Result<Vec<FieldAssignment>> {
+    let mut assignments = Vec::new();
+    for (header, value) in headers.iter().zip(record.iter()) {
+        if Some(header) != id_column && !value.is_empty() {
+            let kind = types.get(header).map_or("string", |kind| &**kind);
+            let path = FieldPath::parse(header)?;
+            if path.has_index() {
+                return Err(Error::InvalidDocument {
+                    reason: format!("the {} column writes an element of an array", header),
+                });
+            }
+            assignments.push(FieldAssignment {
+                path,
+                value: fields::parse_value(kind, value)?,
+            });
+        }
+    }
+    Ok(assignments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let fields = json!({
+            "a.b": "1",
+            "c": { "d": "2", "e.f": "3" },
+            "tick`": "4",
+            "first name": "5",
+        });
+        let document = json!({ "id": "x", "fields": fields.clone() });
+        let mut csv = Vec::new();
+        write_documents(&mut csv, &[document], None, false).unwrap();
+        let header = String::from_utf8(csv.clone()).unwrap();
+        assert_eq!(
+            header.lines().next(),
+            Some(r#"id,`a.b`,`tick\``,c.`e.f`,c.d,first name"#)
+        );
+        let documents = read_documents(&*csv, Some("id"), &HashMap::new()).unwrap();
+        assert_eq!(
+            documents,
+            vec![(
+                String::from("x"),
+                FirestoreFields::from_json(fields).unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_columns_of_array_elements() {
+        let csv = "id,items[0]\nx,1\n";
+        assert!(read_documents(csv.as_bytes(), Some("id"), &HashMap::new()).is_err());
+    }
+}
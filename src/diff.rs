@@ -0,0 +1,149 @@
+// Field level comparison of documents. Maps are compared field by field,
+// any other value, arrays included, is compared as a whole.
+// Collections are compared document by document, matching documents by ID.
+
+use crate::api::{Document, FirestoreFields, FirestoreType};
+use crate::errors::Result;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::iter::Peekable;
+
+/// How a single field differs between two documents
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Only the second document has the field
+    Added(FirestoreType),
+    /// Only the first document has the field
+    Removed(FirestoreType),
+    /// Both documents have the field, with these different values
+    Changed(FirestoreType, FirestoreType),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+    /// Segments of the path to the field, outermost first
+    pub path: Vec<String>,
+    pub change: Change,
+}
+
+impl FieldDiff {
+    /// The dotted form of `path`, as used in document masks
+    pub fn field_path(&self) -> String {
+        self.path.join(".")
+    }
+}
+
+/// Lists the fields that differ between `old` and `new`, sorted by path
+pub fn diff_fields(old: &FirestoreFields, new: &FirestoreFields) -> Vec<FieldDiff> {
+    let mut diffs = Vec::new();
+    diff_into(&mut Vec::new(), old, new, &mut diffs);
+    diffs
+}
+
+fn diff_into(
+    path: &mut Vec<String>,
+    old: &FirestoreFields,
+    new: &FirestoreFields,
+    diffs: &mut Vec<FieldDiff>,
+) {
+    let keys = old.0.keys().chain(new.0.keys()).collect::<BTreeSet<_>>();
+    for key in keys {
+        path.push(key.clone());
+        let change = match (old.0.get(key), new.0.get(key)) {
+            (Some(FirestoreType::Map(old)), Some(FirestoreType::Map(new))) => {
+                diff_into(path, &old.fields, &new.fields, diffs);
+                None
+            }
+            (Some(old), Some(new)) if old != new => Some(Change::Changed(old.clone(), new.clone())),
+            (Some(old), None) => Some(Change::Removed(old.clone())),
+            (None, Some(new)) => Some(Change::Added(new.clone())),
+            _ => None,
+        };
+        if let Some(change) = change {
+            diffs.push(FieldDiff {
+                path: path.clone(),
+                change,
+            });
+        }
+        path.pop();
+    }
+}
+
+/// How a document differs between two collections
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocumentDiff {
+    /// Only the first collection has a document with this ID
+    Missing(String),
+    /// Only the second collection has a document with this ID
+    Extra(String),
+    /// Both collections have a document with this ID, with these differing fields
+    Changed(String, Vec<FieldDiff>),
+}
+
+/// Compares the documents of two collections by ID, see `diff_collections`
+pub struct CollectionDiff<L, R>
+where
+    L: Iterator<Item = Result<Document>>,
+    R: Iterator<Item = Result<Document>>,
+{
+    left: Peekable<L>,
+    right: Peekable<R>,
+    compare_fields: bool,
+}
+
+/// Walks two streams of documents ordered by ID side by side, yielding the documents
+/// only one of them has and, when `compare_fields` is set, those whose fields differ.
+/// Neither stream is read further than needed, so collections of any size can be compared.
+pub fn diff_collections<L, R>(left: L, right: R, compare_fields: bool) -> CollectionDiff<L, R>
+where
+    L: Iterator<Item = Result<Document>>,
+    R: Iterator<Item = Result<Document>>,
+{
+    CollectionDiff {
+        left: left.peekable(),
+        right: right.peekable(),
+        compare_fields,
+    }
+}
+
+impl<L, R> Iterator for CollectionDiff<L, R>
+where
+    L: Iterator<Item = Result<Document>>,
+    R: Iterator<Item = Result<Document>>,
+{
+    type Item = Result<DocumentDiff>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.left.peek(), self.right.peek()) {
+                (Some(Err(_)), _) => return Some(Err(self.left.next()?.err()?)),
+                (_, Some(Err(_))) => return Some(Err(self.right.next()?.err()?)),
+                (None, None) => return None,
+                (Some(Ok(_)), None) => Ordering::Less,
+                (None, Some(Ok(_))) => Ordering::Greater,
+                (Some(Ok(left)), Some(Ok(right))) => left.id().cmp(right.id()),
+            };
+            match order {
+                Ordering::Less => {
+                    let left = self.left.next()?.ok()?;
+                    return Some(Ok(DocumentDiff::Missing(left.id().to_string())));
+                }
+                Ordering::Greater => {
+                    let right = self.right.next()?.ok()?;
+                    return Some(Ok(DocumentDiff::Extra(right.id().to_string())));
+                }
+                Ordering::Equal => {
+                    let left = self.left.next()?.ok()?;
+                    let right = self.right.next()?.ok()?;
+                    if !self.compare_fields {
+                        continue;
+                    }
+                    let diffs = diff_fields(left.fields(), right.fields());
+                    if !diffs.is_empty() {
+                        return Some(Ok(DocumentDiff::Changed(left.id().to_string(), diffs)));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,334 @@
+// `doctor`: lists every place the project, emulator, credentials, service account to
+// impersonate and proxy were found in, marking the one that is used, then goes through
+// connecting step by step: reading the config file and the credentials, minting a token,
+// reaching Firestore or the emulator, or the proxy in between, and finding the database.
+// A step is skipped when one it depends on failed, and the command fails when any step did.
+
+use crate::config::{self, Config, Profile};
+use crate::{Environment, Options};
+use libfiresale::api::DatabaseContext;
+use libfiresale::auth;
+use libfiresale::errors::{Error, Result};
+use libfiresale::proxy;
+use reqwest::Url;
+use std::env;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+const FIRESTORE_ADDRESS: &'static str = "firestore.googleapis.com:443";
+const GOOGLE_APPLICATION_CREDENTIALS_KEY: &'static str = "GOOGLE_APPLICATION_CREDENTIALS";
+/// Longest the network check waits for a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A place a setting may come from, along with its value there
+struct Source {
+    origin: String,
+    value: Option<String>,
+}
+
+fn source<S: Into<String>>(origin: S, value: Option<String>) -> Source {
+    Source {
+        origin: origin.into(),
+        value,
+    }
+}
+
+enum Outcome {
+    Passed(String),
+    Failed(String),
+    Skipped(String),
+}
+
+/// Counts the checks run, printing each as it ends
+#[derive(Default)]
+struct Report {
+    checks: usize,
+    failed: usize,
+}
+
+impl Report {
+    /// Prints the outcome of `check`, returning whether it passed
+    fn print(&mut self, check: &str, outcome: Outcome) -> bool {
+        self.checks += 1;
+        let (label, detail) = match &outcome {
+            Outcome::Passed(detail) => ("ok", detail),
+            Outcome::Failed(detail) => {
+                self.failed += 1;
+                ("failed", detail)
+            }
+            Outcome::Skipped(detail) => ("skipped", detail),
+        };
+        println!("  {:<9}{:<13}{}", label, check, detail);
+        match outcome {
+            Outcome::Passed(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Prints the sources of a setting that have a value, the first of which is used,
+/// and returns that value
+fn print_setting(name: &str, sources: Vec<Source>) -> Option<String> {
+    let mut found = sources.into_iter().filter(|source| source.value.is_some());
+    let used = match found.next() {
+        Some(used) => used,
+        None => {
+            println!("  {:<13}none", name);
+            return None;
+        }
+    };
+    let value = used.value.unwrap();
+    println!("  {:<13}{} (from {})", name, value, used.origin);
+    for overridden in found {
+        println!(
+            "  {:<13}{} (from {}, overridden)",
+            "",
+            overridden.value.unwrap(),
+            overridden.origin
+        );
+    }
+    Some(value)
+}
+
+/// Reads the config file and the chosen profile, an empty profile when either fails
+fn load_profile(options: &Options) -> (Option<String>, Profile, Outcome) {
+    let path = match config::config_path() {
+        Some(path) => path,
+        None => {
+            let outcome = Outcome::Skipped(String::from("no home directory to look in"));
+            return (None, Profile::default(), outcome);
+        }
+    };
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => return (None, Profile::default(), Outcome::Failed(e.to_string())),
+    };
+    let name = config.profile_name(options.profile.as_ref().map(|p| &**p));
+    let read = if path.is_file() {
+        format!("read {}", path.display())
+    } else {
+        format!("no config file at {}", path.display())
+    };
+    match config.into_profile(options.profile.as_ref().map(|p| &**p)) {
+        Ok(profile) => (name, profile, Outcome::Passed(read)),
+        Err(e) => (None, Profile::default(), Outcome::Failed(e.to_string())),
+    }
+}
+
+/// Opens a TCP connection to `address`, such as `localhost:8080`
+fn reach(address: &str) -> Outcome {
+    let resolved = match address.to_socket_addrs() {
+        Ok(mut addresses) => addresses.next(),
+        Err(e) => return Outcome::Failed(format!("cannot resolve {}: {}", address, e)),
+    };
+    let resolved = match resolved {
+        Some(resolved) => resolved,
+        None => return Outcome::Failed(format!("{} has no addresses", address)),
+    };
+    let started = Instant::now();
+    match TcpStream::connect_timeout(&resolved, CONNECT_TIMEOUT) {
+        Ok(_) => Outcome::Passed(format!(
+            "reached {} in {} ms",
+            address,
+            started.elapsed().as_millis()
+        )),
+        Err(e) => Outcome::Failed(format!("cannot reach {}: {}", address, e)),
+    }
+}
+
+pub fn run(options: &Options, environment: &Environment) -> Result<()> {
+    let (profile_name, profile, config_outcome) = load_profile(options);
+    let in_profile = |key: &str| match &profile_name {
+        Some(name) => format!("profile {} {}", name, key),
+        None => format!("profile {}", key),
+    };
+    println!("Settings");
+    println!(
+        "  {:<13}{}",
+        "profile",
+        profile_name.as_ref().map_or("none", |name| &**name)
+    );
+    let project_id = print_setting(
+        "project",
+        vec![
+            source("--project", options.environment.project_id.clone()),
+            source("PROJECT_ID", environment.project_id.clone()),
+            source(in_profile("project_id"), profile.project_id.clone()),
+            source("gcloud", auth::gcloud_project()),
+        ],
+    );
+    println!("  {:<13}{}", "database", options.database_name);
+    let emulator_host = print_setting(
+        "emulator",
+        vec![
+            source("--emulator", options.environment.emulator_host.clone()),
+            source("FIRESTORE_EMULATOR_HOST", environment.emulator_host.clone()),
+            source(in_profile("emulator_host"), profile.emulator_host.clone()),
+        ],
+    );
+    let explicit = options
+        .environment
+        .service_account_path
+        .clone()
+        .or(profile.credentials.clone())
+        .or_else(config::login_credentials);
+    print_setting(
+        "credentials",
+        vec![
+            source(
+                "--credentials",
+                options.environment.service_account_path.clone(),
+            ),
+            source(in_profile("credentials"), profile.credentials.clone()),
+            source("auth login", config::login_credentials()),
+            source(
+                auth::GOOGLE_APPLICATION_CREDENTIALS_JSON_KEY,
+                env::var_os(auth::GOOGLE_APPLICATION_CREDENTIALS_JSON_KEY)
+                    .map(|_| String::from("the credentials JSON")),
+            ),
+            source(
+                GOOGLE_APPLICATION_CREDENTIALS_KEY,
+                env::var(GOOGLE_APPLICATION_CREDENTIALS_KEY).ok(),
+            ),
+            source(
+                "gcloud",
+                auth::gcloud_adc_path()
+                    .filter(|path| path.is_file())
+                    .map(|path| path.display().to_string()),
+            ),
+            source("fallback", Some(String::from("the metadata server"))),
+        ],
+    );
+    let impersonate = print_setting(
+        "impersonate",
+        vec![
+            source(
+                "--impersonate-service-account",
+                options.impersonate_service_account.clone(),
+            ),
+            source(
+                in_profile("impersonate_service_account"),
+                profile.impersonate_service_account.clone(),
+            ),
+        ],
+    );
+
+    let mut proxy_sources = vec![source(
+        "--proxy",
+        options.proxy.as_ref().map(|p| proxy::display(p)),
+    )];
+    proxy_sources.extend(proxy::HTTPS_PROXY_KEYS.iter().map(|key| {
+        let value = env::var(key).ok().filter(|value| !value.trim().is_empty());
+        source(*key, value.map(|p| proxy::display(&*p)))
+    }));
+    print_setting("proxy", proxy_sources);
+
+    println!();
+    println!("Checks");
+    let mut report = Report::default();
+    report.print("config", config_outcome);
+    // read once, since credentials given on stdin cannot be read again
+    let credentials = match emulator_host {
+        Some(_) => None,
+        None => Some(
+            crate::credential_source(explicit).map(|credentials| match &impersonate {
+                Some(service_account) => credentials.impersonate(&**service_account),
+                None => credentials,
+            }),
+        ),
+    };
+    let authenticated = match &credentials {
+        None => {
+            let skipped = || Outcome::Skipped(String::from("the emulator takes no credentials"));
+            report.print("credentials", skipped());
+            report.print("token", skipped());
+            true
+        }
+        Some(Err(e)) => {
+            report.print("credentials", Outcome::Failed(e.to_string()));
+            report.print("token", Outcome::Skipped(String::from("no credentials")))
+        }
+        Some(Ok(credentials)) => {
+            let read = match credentials.kind() {
+                Ok(Some(kind)) => {
+                    Outcome::Passed(format!("{} credentials in {}", kind, credentials))
+                }
+                Ok(None) => Outcome::Passed(format!("tokens come from {}", credentials)),
+                Err(e) => Outcome::Failed(format!("cannot read {}: {}", credentials, e)),
+            };
+            if report.print("credentials", read) {
+                let client = reqwest::Client::builder()
+                    .timeout(options.timeout)
+                    .proxy(proxy::reqwest_proxy())
+                    .build()
+                    .map_err(Error::from)?;
+                let minted = match credentials.token(&client) {
+                    Ok(_) => {
+                        Outcome::Passed(format!("minted an access token with {}", credentials))
+                    }
+                    Err(e) => Outcome::Failed(e.to_string()),
+                };
+                report.print("token", minted)
+            } else {
+                report.print("token", Outcome::Skipped(String::from("no credentials")))
+            }
+        }
+    };
+    let (scheme, address) = match &emulator_host {
+        Some(host) => ("http", host.clone()),
+        None => ("https", String::from(FIRESTORE_ADDRESS)),
+    };
+    let url = Url::parse(&*format!("{}://{}", scheme, address)).ok();
+    let network = match url.and_then(|url| proxy::proxy_for(&url)) {
+        // Firestore itself is only reached through the proxy by the database check
+        Some(proxy) => match reach(&*format!(
+            "{}:{}",
+            proxy.host_str().unwrap_or_default(),
+            proxy.port_or_known_default().unwrap_or_default()
+        )) {
+            Outcome::Passed(detail) => Outcome::Passed(format!("{}, the proxy", detail)),
+            outcome => outcome,
+        },
+        None => reach(&address),
+    };
+    let reached = report.print("network", network);
+    let found = match project_id {
+        None => Outcome::Failed(String::from(
+            "no project given with --project, PROJECT_ID, the profile or gcloud",
+        )),
+        Some(_) if !authenticated || !reached => {
+            Outcome::Skipped(String::from("Firestore cannot be reached"))
+        }
+        Some(project_id) => {
+            let connected = match (emulator_host, credentials) {
+                (Some(host), _) => Ok(DatabaseContext::emulator(project_id.clone(), host)),
+                (None, Some(Ok(credentials))) => {
+                    crate::authenticate(project_id.clone(), credentials, false)
+                }
+                (None, _) => unreachable!("authenticated without credentials"),
+            };
+            let listed = connected.and_then(|context| {
+                context
+                    .with_timeout(options.timeout)
+                    .with_database(&*options.database_name)
+                    .list_root_collection_ids()
+            });
+            match listed {
+                Ok(collection_ids) => Outcome::Passed(format!(
+                    "found database {} of {} with {} root collections",
+                    options.database_name,
+                    project_id,
+                    collection_ids.len()
+                )),
+                Err(e) => Outcome::Failed(e.to_string()),
+            }
+        }
+    };
+    report.print("database", found);
+    if report.failed > 0 {
+        return Err(Error::FailedPrecondition {
+            message: format!("{} of {} checks failed", report.failed, report.checks),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// Dry runs print the writes a command would commit instead of committing them.
+// Reads still go to Firestore, so the printed writes are the ones a real run would issue:
+//   update users/bob
+//     age = 31
+//     nickname (deleted)
+//     views += 1
+
+use libfiresale::api::WriteSink;
+use libfiresale::errors::Result;
+use libfiresale::fieldpath::FieldPath;
+use libfiresale::firestore::write::{Operation, TransformType, Write};
+
+/// Prints every write to stdout, the path of the document followed by the fields it changes
+#[derive(Debug)]
+pub struct PrintSink;
+
+/// The path of a document relative to the root of the document tree
+fn relative_path(name: &str) -> &str {
+    const DOCUMENTS: &str = "/documents/";
+    name.find(DOCUMENTS)
+        .map_or(name, |i| &name[i + DOCUMENTS.len()..])
+}
+
+/// Finds the value at a field path of a JSON object
+fn lookup<'a>(value: &'a serde_json::Value, field_path: &str) -> Option<&'a serde_json::Value> {
+    FieldPath::parse(field_path).ok()?.get_json(value)
+}
+
+fn print_write(write: &Write) {
+    let document = match &write.operation {
+        Operation::Delete(name) => {
+            println!("delete {}", relative_path(name));
+            return;
+        }
+        Operation::Update(document) => document,
+    };
+    let exists = write
+        .current_document
+        .as_ref()
+        .and_then(|precondition| precondition.exists);
+    let verb = match (&write.update_mask, exists) {
+        (_, Some(true)) => "update",
+        (_, Some(false)) => "create",
+        (Some(_), None) => "merge",
+        (None, None) => "set",
+    };
+    println!("{} {}", verb, relative_path(&document.name));
+    let fields = document.fields.to_json();
+    match &write.update_mask {
+        Some(mask) => {
+            for field_path in &mask.field_paths {
+                match lookup(&fields, field_path) {
+                    Some(value) => println!("  {} = {}", field_path, value),
+                    None => println!("  {} (deleted)", field_path),
+                }
+            }
+        }
+        None => {
+            if let serde_json::Value::Object(fields) = &fields {
+                for (field, value) in fields {
+                    println!("  {} = {}", field, value);
+                }
+            }
+        }
+    }
+    for transform in &write.update_transforms {
+        let path = &transform.field_path;
+        match &transform.transform {
+            TransformType::SetToServerValue(_) => println!("  {} = server timestamp", path),
+            TransformType::Increment(value) => println!("  {} += {}", path, value.to_json()),
+            TransformType::Maximum(value) => println!("  {} max= {}", path, value.to_json()),
+            TransformType::Minimum(value) => println!("  {} min= {}", path, value.to_json()),
+            TransformType::AppendMissingElements(array) => {
+                for value in &array.values {
+                    println!("  {} array-union {}", path, value.to_json());
+                }
+            }
+            TransformType::RemoveAllFromArray(array) => {
+                for value in &array.values {
+                    println!("  {} array-remove {}", path, value.to_json());
+                }
+            }
+        }
+    }
+}
+
+impl WriteSink for PrintSink {
+    fn write(&self, writes: &[Write]) -> Result<()> {
+        // the lock is reentrant, holding it keeps concurrent commits from interleaving
+        let stdout = std::io::stdout();
+        let _lock = stdout.lock();
+        for write in writes {
+            print_write(write);
+        }
+        Ok(())
+    }
+}
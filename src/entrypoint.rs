@@ -1,13 +1,1936 @@
-use libfiresale::errors::Result;
-use libfiresale::firestore;
+use crate::backup;
+use crate::batch::{self, Operation};
+use crate::checkpoint::Checkpoint;
+use crate::dedupe::{Duplicates, Keep};
+use crate::delimited;
+use crate::fields::{self, FieldAssignment};
+use crate::hook::Hook;
+use crate::jsonschema::Validator;
+use crate::migrate::{self, Migration};
+use crate::output::{self, Format, Printer};
+use crate::progress::Bar;
+use crate::readahead;
+use crate::refs::Resolver;
+use crate::schema::Schema;
+use crate::seed::SeedTemplate;
+use crate::stats::{self, Stats};
+use crate::terminal;
+use chrono::TimeZone;
+use libfiresale::api::query::{self, Aggregation, Filter, Query};
+use libfiresale::api::{
+    relative_path, split_document_path, update_precondition, Document, FieldTransform,
+    FirestoreFields, FirestoreType, WriteBatch, MAX_WRITES_PER_COMMIT,
+};
+use libfiresale::diff;
+use libfiresale::errors::{Error, Result};
+use libfiresale::fieldpath::FieldPath;
+use libfiresale::firestore::types::Operation as AdminOperation;
+use libfiresale::firestore::write::Precondition;
+use libfiresale::operation;
+use libfiresale::pool::CommitPool;
+use libfiresale::size;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::Path;
 
-pub fn handle_database_export(
-    query: crate::ExportCollectionQuery,
+pub fn handle_document_get(
+    query: crate::DocumentQuery,
+    ctx: crate::DatabaseContext,
+    printer: Printer,
+) -> Result<()> {
+    if let Some(field) = query.field {
+        // the mask reaches no further than the array of an element
+        let mask = FieldPath::parse(&field)?.array_path().to_string();
+        let document =
+            ctx.get_document_fields(query.collection_name, query.document_name, vec![mask])?;
+        return output::print_field(&printer, &document, &*field, query.raw);
+    }
+    let document = match query.fields {
+        Some(fields) => {
+            ctx.get_document_fields(query.collection_name, query.document_name, fields)?
+        }
+        None => ctx.get_document(query.collection_name, query.document_name)?,
+    };
+    if query.resolve_refs > 0 {
+        let value = Resolver::new(&ctx, printer.types).resolve(&document, query.resolve_refs)?;
+        return output::print_document_json(&printer, value);
+    }
+    output::print_document(&printer, &document)
+}
+
+pub fn handle_document_exists(
+    query: crate::ExistsQuery,
+    ctx: crate::DatabaseContext,
+) -> Result<()> {
+    if !ctx.document_exists(query.collection_name, query.document_name)? {
+        // a missing document is an answer rather than an error, so nothing is printed
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+pub fn handle_document_diff(query: crate::DiffQuery, ctx: crate::DatabaseContext) -> Result<()> {
+    let ((left_collection, left_id), (right_collection, right_id)) = (query.left, query.right);
+    let left = ctx.get_document(left_collection, left_id)?;
+    let right = ctx.get_document(right_collection, right_id)?;
+    output::print_diff(&diff::diff_fields(left.fields(), right.fields()))
+}
+
+/// Reads the document at points in time, from `--from` ago up to its latest version, and
+/// prints a revision each time it was seen to change. A revision is dated by the update
+/// time of the document, so changes made between two reads show up as a single one.
+pub fn handle_document_history(
+    query: crate::HistoryQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+) -> Result<()> {
+    let too_long = || Error::InvalidArgument {
+        reason: String::from("--from and --every must be at most 7d"),
+    };
+    let span = chrono::Duration::from_std(query.from).map_err(|_| too_long())?;
+    let every = match query.every {
+        Some(every) => chrono::Duration::from_std(every).map_err(|_| too_long())?,
+        None if span <= chrono::Duration::hours(1) => chrono::Duration::minutes(5),
+        None if span <= chrono::Duration::days(2) => chrono::Duration::hours(1),
+        None => chrono::Duration::days(1),
+    };
+    let now = chrono::Utc::now();
+    let start = now.checked_sub_signed(span).ok_or_else(too_long)?;
+    // versions older than an hour can only be read at whole minutes
+    let start = chrono::Utc
+        .timestamp_opt((start.timestamp() / 60 + 1) * 60, 0)
+        .unwrap();
+    let mut times = Vec::new();
+    let mut time = start;
+    while time < now {
+        times.push(Some(time));
+        time = time + every;
+    }
+    // the latest version is read last, as of no particular time
+    times.push(None);
+    let (collection_name, document_name) = (query.collection_name, query.document_name);
+    let read = |time: Option<chrono::DateTime<chrono::Utc>>| {
+        let ctx = match time {
+            Some(time) => ctx.clone().read_at(time),
+            None => ctx.clone(),
+        };
+        match ctx.get_document(collection_name.clone(), document_name.clone()) {
+            Ok(document) => Ok(Some(document)),
+            Err(Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    };
+    let empty = FirestoreFields::default();
+    let mut previous = read(times[0])?;
+    match &previous {
+        Some(document) => output::print_revision(
+            format,
+            document.update_time(),
+            "exists",
+            &diff::diff_fields(&empty, document.fields()),
+        )?,
+        None => output::print_revision(format, start, "missing", &[])?,
+    }
+    for &time in &times[1..] {
+        let current = read(time)?;
+        match (&previous, &current) {
+            (None, Some(new)) => output::print_revision(
+                format,
+                new.create_time(),
+                "created",
+                &diff::diff_fields(&empty, new.fields()),
+            )?,
+            (Some(_), None) => {
+                // a deleted document leaves no time behind, only the read that missed it
+                output::print_revision(format, time.unwrap_or(now), "deleted", &[])?
+            }
+            (Some(old), Some(new)) if old.update_time() != new.update_time() => {
+                let change = if old.create_time() == new.create_time() {
+                    "updated"
+                } else {
+                    "recreated"
+                };
+                output::print_revision(
+                    format,
+                    new.update_time(),
+                    change,
+                    &diff::diff_fields(old.fields(), new.fields()),
+                )?
+            }
+            _ => {}
+        }
+        previous = current;
+    }
+    Ok(())
+}
+
+pub fn handle_collection_diff(
+    query: crate::CollectionDiffQuery,
+    ctx: crate::DatabaseContext,
+) -> Result<()> {
+    // both sides are listed in ID order so they can be walked side by side a page at a time
+    let list = |collection: String| {
+        let documents = ctx
+            .iter_documents(collection, None)
+            .order_by("__name__", query::Direction::Ascending);
+        if query.keys_only {
+            documents.select(vec![String::from("__name__")])
+        } else {
+            documents
+        }
+    };
+    let left = list(query.left.clone());
+    let right = list(query.right.clone());
+    output::print_collection_diff(diff::diff_collections(left, right, !query.keys_only))
+}
+
+/// Values of a cursor given on the command line: literals for the orderings of the query,
+/// then optionally a document, by its ID in `collection_name` or by its path
+fn cursor_values(
+    values: &[String],
+    orderings: usize,
+    collection_name: &str,
+    ctx: &crate::DatabaseContext,
+) -> Result<Vec<FirestoreType>> {
+    if values.len() > orderings + 1 {
+        return Err(Error::InvalidArgument {
+            reason: format!(
+                "a cursor takes a value for each of the {} --order-by fields and a document ID, {} were given",
+                orderings,
+                values.len()
+            ),
+        });
+    }
+    Ok(values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            if i < orderings {
+                query::parse_literal(value)
+            } else if value.contains('/') {
+                ctx.reference(value)
+            } else {
+                ctx.reference(format!("{}/{}", collection_name, value))
+            }
+        })
+        .collect())
+}
+
+/// Applies field masks, ordering and limits from the command line to a query
+fn apply_options(
+    mut query: Query,
+    options: &crate::QueryOptions,
+    collection_name: &str,
+    ctx: &crate::DatabaseContext,
+) -> Result<Query> {
+    if let Some(fields) = &options.fields {
+        query = query.select(fields.clone());
+    }
+    for order in &options.order_by {
+        let (field, direction) = query::parse_order(order)?;
+        query = query.order_by(field, direction);
+    }
+    let orderings = options.order_by.len();
+    let cursor = |values: &[String]| cursor_values(values, orderings, collection_name, ctx);
+    if !options.start_at.is_empty() {
+        query = query.start_at(cursor(&options.start_at)?);
+    }
+    if !options.start_after.is_empty() {
+        query = query.start_after(cursor(&options.start_after)?);
+    }
+    if !options.end_at.is_empty() {
+        query = query.end_at(cursor(&options.end_at)?);
+    }
+    if !options.end_before.is_empty() {
+        query = query.end_before(cursor(&options.end_before)?);
+    }
+    if let Some(offset) = options.offset {
+        query = query.offset(offset);
+    }
+    if let Some(limit) = options.limit {
+        query = query.limit(limit);
+    }
+    Ok(query)
+}
+
+pub fn handle_document_view(
+    query: crate::CollectionQuery,
+    ctx: crate::DatabaseContext,
+    printer: Printer,
+) -> Result<()> {
+    if query.options.offset.is_some() || query.options.has_cursors() {
+        // listing has no offset or cursors, skipped documents are left to the query
+        let structured = apply_options(
+            Query::new(&*query.collection_name),
+            &query.options,
+            &query.collection_name,
+            &ctx,
+        )?;
+        return output::print_document_stream(
+            &printer,
+            ctx.stream_query(structured)?,
+            query.options.fields.as_ref().map(Vec::as_slice),
+        );
+    }
+    let mut documents = ctx.iter_documents(query.collection_name, query.page_size);
+    for order in &query.options.order_by {
+        let (field, direction) = query::parse_order(order)?;
+        documents = documents.order_by(field, direction);
+    }
+    let fields = query.options.fields;
+    if let Some(fields) = &fields {
+        documents = documents.select(fields.clone());
+    }
+    match query.options.limit {
+        Some(limit) => output::print_document_stream(
+            &printer,
+            documents.take(limit as usize),
+            fields.as_ref().map(Vec::as_slice),
+        ),
+        None => {
+            output::print_document_stream(&printer, documents, fields.as_ref().map(Vec::as_slice))
+        }
+    }
+}
+
+/// Asks on stderr whether to go ahead, anything but y or yes declines. Without a
+/// terminal to ask on, the command fails instead of reading an answer from its input.
+fn confirm(question: &str) -> Result<bool> {
+    if !terminal::can_prompt() {
+        return Err(Error::InvalidArgument {
+            reason: format!(
+                "cannot ask \"{}\" without a terminal, pass --yes to go ahead",
+                question
+            ),
+        });
+    }
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        // end of input, keep the next message off the prompt line
+        eprintln!();
+    }
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(true),
+        _ => Ok(false),
+    }
+}
+
+pub fn handle_document_delete(
+    query: crate::DeleteDocumentQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let bar = Bar::new(quiet);
+    // nothing is deleted in a dry run, so there is nothing to confirm
+    if !query.yes && !ctx.is_dry_run() {
+        let path = format!("{}/{}", query.collection_name, query.document_name);
+        let question = if query.recursive {
+            let nested = ctx.count(Query::descendants_of(&*path))?;
+            bar.set_total(nested as usize + 1);
+            format!(
+                "Delete {} and the {} documents nested below it?",
+                path, nested
+            )
+        } else {
+            format!("Delete {}?", path)
+        };
+        if !confirm(&question)? {
+            status!("Nothing was deleted");
+            return Ok(());
+        }
+    }
+    if query.recursive {
+        let deleted =
+            ctx.delete_document_recursive(query.collection_name, query.document_name, |deleted| {
+                bar.set(deleted)
+            })?;
+        bar.finish();
+        status!("Deleted {} documents", deleted);
+        Ok(())
+    } else {
+        ctx.delete_document_if(
+            query.collection_name,
+            query.document_name,
+            query.precondition,
+        )
+    }
+}
+
+pub fn handle_document_copy(
+    query: crate::CopyDocumentQuery,
+    ctx: crate::DatabaseContext,
+    destination: Option<crate::DatabaseContext>,
+    quiet: bool,
+) -> Result<()> {
+    let ((collection_name, document_name), (target_collection, target_document)) =
+        (query.source, query.target);
+    let bar = Bar::new(quiet);
+    let copied = ctx.copy_document_to(
+        destination.as_ref().unwrap_or(&ctx),
+        collection_name,
+        document_name,
+        target_collection,
+        target_document,
+        query.recursive,
+        |copied| bar.set(copied),
+    )?;
+    bar.finish();
+    status!("Copied {} documents", copied);
+    Ok(())
+}
+
+pub fn handle_document_move(
+    query: crate::CopyDocumentQuery,
+    ctx: crate::DatabaseContext,
+) -> Result<()> {
+    let ((collection_name, document_name), (target_collection, target_document)) =
+        (query.source, query.target);
+    let moved = ctx.move_document(
+        collection_name,
+        document_name,
+        target_collection,
+        target_document,
+        query.recursive,
+    )?;
+    status!("Moved {} documents", moved);
+    Ok(())
+}
+
+pub fn handle_collection_delete(
+    query: crate::DeleteCollectionQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let bar = Bar::new(quiet);
+    if !query.yes && !ctx.is_dry_run() {
+        let count = ctx.count(Query::new(&*query.collection_name))?;
+        // subcollections are not counted, so a recursive delete only gets a spinner
+        if !query.recursive {
+            bar.set_total(count as usize);
+        }
+        let question = if query.recursive {
+            format!(
+                "Delete the {} documents of {} and their subcollections?",
+                count, query.collection_name
+            )
+        } else {
+            format!(
+                "Delete the {} documents of {}?",
+                count, query.collection_name
+            )
+        };
+        if !confirm(&question)? {
+            status!("Nothing was deleted");
+            return Ok(());
+        }
+    }
+    let deleted = ctx.delete_collection(query.collection_name, query.recursive, |deleted| {
+        bar.set(deleted)
+    })?;
+    bar.finish();
+    status!("Deleted {} documents", deleted);
+    Ok(())
+}
+
+/// Reads and parses the JSON body of a document write. Files and stdin are parsed
+/// as they are read rather than read into a string first.
+fn read_payload(payload: crate::DocumentPayload) -> Result<serde_json::Value> {
+    let stdin = io::stdin();
+    let value = match payload {
+        crate::DocumentPayload::Inline(json) => serde_json::from_str(&*json)?,
+        crate::DocumentPayload::File(path) => {
+            serde_json::from_reader(BufReader::new(File::open(path)?))?
+        }
+        crate::DocumentPayload::Stdin => serde_json::from_reader(stdin.lock())?,
+    };
+    Ok(value)
+}
+
+pub fn handle_document_set(
+    query: crate::SetDocumentQuery,
+    ctx: crate::DatabaseContext,
+    printer: Printer,
+) -> Result<()> {
+    let fields = FirestoreFields::from_json(read_payload(query.payload)?)?;
+    let document = ctx.set_document_if(
+        query.collection_name,
+        query.document_name,
+        fields,
+        query.merge,
+        query.precondition,
+    )?;
+    if ctx.is_dry_run() {
+        return Ok(());
+    }
+    output::print_document(&printer, &document)
+}
+
+pub fn handle_document_add(
+    query: crate::AddDocumentQuery,
     ctx: crate::DatabaseContext,
+    printer: Printer,
 ) -> Result<()> {
-    ctx.export_database(firestore::databases::ExportDocumentQuery {
-        database_name: "".to_string(),
-        collection_ids: None,
-        output_uri_prefix: "".to_string(),
+    let fields = FirestoreFields::from_json(read_payload(query.payload)?)?;
+    let document = ctx.add_document(query.collection_name, fields)?;
+    if ctx.is_dry_run() {
+        return Ok(());
+    }
+    status!("Added {}", document.path());
+    output::print_document(&printer, &document)
+}
+
+/// The writes `update` and `update-many` make to a document
+struct Updates {
+    fields: FirestoreFields,
+    field_paths: Vec<String>,
+    transforms: Vec<(String, FieldTransform)>,
+    /// Assignments to elements of arrays, which are written by rewriting their arrays
+    elements: Vec<FieldAssignment>,
+}
+
+/// The fields, mask and transforms of an update making said changes
+fn field_updates(updates: crate::FieldUpdates) -> Result<Updates> {
+    let mut assignments = Vec::new();
+    let mut elements = Vec::new();
+    let mut transforms = Vec::new();
+    for assignment in &updates.assignments {
+        match fields::parse_increment(assignment)? {
+            Some(increment) => transforms.push((
+                increment.field_path(),
+                FieldTransform::Increment(increment.value),
+            )),
+            None => {
+                let assignment = fields::parse_assignment(assignment)?;
+                if assignment.path.has_index() {
+                    elements.push(assignment);
+                } else {
+                    assignments.push(assignment);
+                }
+            }
+        }
+    }
+    for (field_path, values) in fields::group_elements(&updates.array_union)? {
+        transforms.push((field_path, FieldTransform::ArrayUnion(values)));
+    }
+    for (field_path, values) in fields::group_elements(&updates.array_remove)? {
+        transforms.push((field_path, FieldTransform::ArrayRemove(values)));
+    }
+    for field_path in updates.server_timestamps {
+        let field_path = fields::parse_field_path(&field_path)?.to_string();
+        transforms.push((field_path, FieldTransform::ServerTimestamp));
+    }
+    let (fields, mut field_paths) = fields::into_fields(assignments);
+    // a path in the mask without a value in the fields is deleted
+    for deletion in &updates.delete_fields {
+        let field_path = fields::parse_deletion(deletion)?;
+        if field_paths.contains(&field_path) {
+            return Err(Error::InvalidArgument {
+                reason: format!("{} is both written and deleted", field_path),
+            });
+        }
+        field_paths.push(field_path);
+    }
+    Ok(Updates {
+        fields,
+        field_paths,
+        transforms,
+        elements,
     })
 }
+
+pub fn handle_document_update(
+    query: crate::UpdateDocumentQuery,
+    ctx: crate::DatabaseContext,
+    printer: Printer,
+) -> Result<()> {
+    let updates = field_updates(query.updates)?;
+    if !updates.elements.is_empty() {
+        return update_elements(
+            query.collection_name,
+            query.document_name,
+            updates,
+            query.precondition,
+            ctx,
+            printer,
+        );
+    }
+    let document = ctx.update_document_with_transforms_if(
+        query.collection_name,
+        query.document_name,
+        updates.fields,
+        updates.field_paths,
+        updates.transforms,
+        query.precondition,
+    )?;
+    if ctx.is_dry_run() {
+        return Ok(());
+    }
+    output::print_document(&printer, &document)
+}
+
+pub fn handle_document_touch(
+    query: crate::TouchDocumentQuery,
+    ctx: crate::DatabaseContext,
+    printer: Printer,
+) -> Result<()> {
+    let mut assignments = Vec::new();
+    for assignment in &query.assignments {
+        let assignment = fields::parse_assignment(assignment)?;
+        if assignment.path.has_index() {
+            return Err(Error::InvalidArgument {
+                reason: format!(
+                    "{} is an element of an array, which a new document has none of",
+                    assignment.path
+                ),
+            });
+        }
+        assignments.push(assignment);
+    }
+    let (fields, _) = fields::into_fields(assignments);
+    let path = format!("{}/{}", query.collection_name, query.document_name);
+    match ctx.create_document(query.collection_name, query.document_name, fields) {
+        Ok(_) if ctx.is_dry_run() => Ok(()),
+        Ok(document) => output::print_document(&printer, &document),
+        Err(Error::AlreadyExists { .. }) => {
+            status!("{} already exists, it was left as it is", path);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Updates a document along with elements of its arrays, reading the arrays and writing
+/// them back whole within a transaction
+fn update_elements(
+    collection_name: String,
+    document_name: String,
+    updates: Updates,
+    precondition: Precondition,
+    ctx: crate::DatabaseContext,
+    printer: Printer,
+) -> Result<()> {
+    ctx.run_transaction(|transaction| {
+        let document = transaction.get_document(&*collection_name, &*document_name)?;
+        let (mut fields, mut field_paths) = (updates.fields.clone(), updates.field_paths.clone());
+        fields::assign_elements(
+            document.fields(),
+            &updates.elements,
+            &mut fields,
+            &mut field_paths,
+        )?;
+        transaction.update_with_transforms(
+            &*collection_name,
+            &*document_name,
+            fields,
+            field_paths,
+            updates.transforms.clone(),
+        );
+        transaction
+            .writes()
+            .with_precondition(update_precondition(precondition.clone()));
+        Ok(())
+    })?;
+    if ctx.is_dry_run() {
+        return Ok(());
+    }
+    let document = ctx.get_document(collection_name, document_name)?;
+    output::print_document(&printer, &document)
+}
+
+/// Builds the structured query described by the filters and options of `query`
+fn filter_query(query: crate::FilterQuery, ctx: &crate::DatabaseContext) -> Result<Query> {
+    let structured = if query.collection_group {
+        Query::collection_group(&*query.collection_name)
+    } else {
+        Query::new(&*query.collection_name)
+    };
+    let mut structured = apply_options(structured, &query.options, &query.collection_name, ctx)?;
+    for filter in &query.filters {
+        structured = structured.filter(Filter::parse(filter)?);
+    }
+    Ok(structured)
+}
+
+pub fn handle_collection_query(
+    query: crate::FilterQuery,
+    ctx: crate::DatabaseContext,
+    printer: Printer,
+) -> Result<()> {
+    let fields = query.options.fields.clone();
+    let documents = ctx.stream_query(filter_query(query, &ctx)?)?;
+    output::print_document_stream(&printer, documents, fields.as_ref().map(Vec::as_slice))
+}
+
+pub fn handle_query_explain(
+    query: crate::ExplainQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+) -> Result<()> {
+    let (_, metrics) = ctx.explain_query(filter_query(query.query, &ctx)?, query.analyze)?;
+    output::print_explain(format, &metrics)
+}
+
+pub fn handle_collection_list(
+    query: crate::FilterQuery,
+    ctx: crate::DatabaseContext,
+) -> Result<()> {
+    let documents = ctx.stream_query(filter_query(query, &ctx)?.keys_only())?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for document in documents {
+        writeln!(out, "{}", document?.path())?;
+    }
+    Ok(())
+}
+
+pub fn handle_collection_purge(
+    query: crate::PurgeQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let collection_name = query.query.collection_name.clone();
+    let filters = query.query.filters.join(" and ");
+    let structured = filter_query(query.query, &ctx)?;
+    if ctx.is_dry_run() {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let mut count = 0;
+        for document in ctx.stream_query(structured.keys_only())? {
+            writeln!(out, "{}", document?.path())?;
+            count += 1;
+        }
+        status!("Would delete {} documents", count);
+        return Ok(());
+    }
+    let bar = Bar::new(quiet);
+    let count = ctx.count(structured.clone())?;
+    bar.set_total(count as usize);
+    if !query.yes {
+        let question = format!(
+            "Delete the {} documents of {} where {}?",
+            count, collection_name, filters
+        );
+        if !confirm(&question)? {
+            status!("Nothing was deleted");
+            return Ok(());
+        }
+    }
+    let mut pool = CommitPool::new(&ctx, |deleted| bar.set(deleted));
+    let mut batch = ctx.batch();
+    let mut size = 0;
+    for document in ctx.stream_query(structured.keys_only())? {
+        let document = document?;
+        // collection group matches may be in any collection
+        let (collection_name, document_id) = split_document_path(document.path());
+        batch.delete(collection_name, document_id);
+        size += 1;
+        if size == MAX_WRITES_PER_COMMIT {
+            pool.submit(mem::replace(&mut batch, ctx.batch()))?;
+            size = 0;
+        }
+    }
+    if size > 0 {
+        pool.submit(batch)?;
+    }
+    let deleted = pool.finish()?;
+    bar.finish();
+    status!("Deleted {} documents", deleted);
+    Ok(())
+}
+
+pub fn handle_collection_dedupe(
+    query: crate::DedupeQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+    quiet: bool,
+) -> Result<()> {
+    let bar = Bar::new(quiet);
+    let mut duplicates = Duplicates::default();
+    let structured = Query::new(&*query.collection_name).select(query.keys.clone());
+    for document in ctx.stream_query(structured)? {
+        let document = document?;
+        duplicates.observe(
+            document.path(),
+            document.create_time(),
+            document.fields(),
+            &query.keys,
+        );
+        bar.set(duplicates.documents());
+    }
+    bar.finish();
+    let documents = duplicates.documents();
+    let groups = duplicates.groups(query.keep);
+    output::print_duplicates(format, &groups)?;
+    let count = groups
+        .iter()
+        .map(|group| group.duplicates.len())
+        .sum::<usize>();
+    status!(
+        "{} duplicates of {} documents among {} documents",
+        count,
+        groups.len(),
+        documents
+    );
+    if !query.delete || count == 0 {
+        return Ok(());
+    }
+    let kept = match query.keep {
+        Keep::Oldest => "oldest",
+        Keep::Newest => "newest",
+    };
+    if !query.yes && !ctx.is_dry_run() {
+        let question = format!(
+            "Delete the {} duplicates, keeping the {} document of each group?",
+            count, kept
+        );
+        if !confirm(&question)? {
+            status!("Nothing was deleted");
+            return Ok(());
+        }
+    }
+    let bar = Bar::with_total(count, quiet);
+    let deleted = Cell::new(0);
+    let mut writer = ctx
+        .bulk_writer()
+        .on_success(|_, _| {
+            deleted.set(deleted.get() + 1);
+            bar.set(deleted.get());
+        })
+        .on_failure(|write, error| {
+            eprintln!(
+                "{} was not deleted: {}",
+                relative_path(write.document_name()),
+                error
+            )
+        });
+    for path in groups.iter().flat_map(|group| &group.duplicates) {
+        let (collection_name, document_id) = split_document_path(path);
+        writer.delete(collection_name, document_id);
+    }
+    let summary = writer.close();
+    bar.finish();
+    if ctx.is_dry_run() {
+        return Ok(());
+    }
+    status!("Deleted {} duplicates", summary.written);
+    if summary.failed > 0 {
+        return Err(Error::Aborted {
+            message: format!("{} duplicates could not be deleted", summary.failed),
+        });
+    }
+    Ok(())
+}
+
+pub fn handle_update_many(
+    query: crate::UpdateManyQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let Updates {
+        fields,
+        field_paths,
+        transforms,
+        elements,
+    } = field_updates(query.updates)?;
+    if let Some(element) = elements.first() {
+        return Err(Error::InvalidArgument {
+            reason: format!(
+                "{} is an element of an array, which only update can write",
+                element.path
+            ),
+        });
+    }
+    let collection_name = query.query.collection_name.clone();
+    let filters = query.query.filters.join(" and ");
+    let structured = filter_query(query.query, &ctx)?;
+    if ctx.is_dry_run() {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let mut count = 0;
+        for document in ctx.stream_query(structured.keys_only())? {
+            writeln!(out, "{}", document?.path())?;
+            count += 1;
+        }
+        status!("Would update {} documents", count);
+        return Ok(());
+    }
+    let bar = Bar::new(quiet);
+    let count = ctx.count(structured.clone())?;
+    bar.set_total(count as usize);
+    if !query.yes {
+        let question = if filters.is_empty() {
+            format!("Update the {} documents of {}?", count, collection_name)
+        } else {
+            format!(
+                "Update the {} documents of {} where {}?",
+                count, collection_name, filters
+            )
+        };
+        if !confirm(&question)? {
+            status!("Nothing was updated");
+            return Ok(());
+        }
+    }
+    let processed = Cell::new(0);
+    let mut writer = ctx
+        .bulk_writer()
+        .on_success(|_, _| {
+            processed.set(processed.get() + 1);
+            bar.set(processed.get());
+        })
+        .on_failure(|write, error| {
+            processed.set(processed.get() + 1);
+            bar.set(processed.get());
+            eprintln!(
+                "{} was not updated: {}",
+                relative_path(write.document_name()),
+                error
+            );
+        });
+    for document in ctx.stream_query(structured.keys_only())? {
+        let document = document?;
+        // collection group matches may be in any collection
+        let (collection_name, document_id) = split_document_path(document.path());
+        writer.update_with_transforms(
+            collection_name,
+            document_id,
+            fields.clone(),
+            field_paths.clone(),
+            transforms.clone(),
+        );
+    }
+    let summary = writer.close();
+    bar.finish();
+    status!("Updated {} documents", summary.written);
+    if summary.failed > 0 {
+        return Err(Error::Aborted {
+            message: format!("{} documents could not be updated", summary.failed),
+        });
+    }
+    Ok(())
+}
+
+/// Applies `migrate` to every document matching `query`, returning how many it changed
+/// and how many it skipped
+fn run_migration<F>(
+    query: crate::FilterQuery,
+    ctx: &crate::DatabaseContext,
+    quiet: bool,
+    migrate: F,
+) -> Result<(usize, usize)>
+where
+    F: Fn(&FirestoreFields) -> Migration,
+{
+    let structured = filter_query(query, ctx)?;
+    let bar = Bar::new(quiet);
+    bar.set_total(ctx.count(structured.clone())? as usize);
+    let (mut processed, mut migrated, mut skipped, mut failed) = (0, 0, 0, 0);
+    for document in ctx.stream_query(structured.keys_only())? {
+        let document = document?;
+        match migrate::migrate_document(ctx, document.path(), &migrate) {
+            Ok(Migration::Update(..)) => migrated += 1,
+            Ok(Migration::Unchanged) => {}
+            Ok(Migration::Skipped(reason)) => {
+                skipped += 1;
+                status!("Skipped {}: {}", document.path(), reason);
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("{} was not migrated: {}", document.path(), e);
+            }
+        }
+        processed += 1;
+        bar.set(processed);
+    }
+    bar.finish();
+    if failed > 0 {
+        return Err(Error::Aborted {
+            message: format!("{} documents could not be migrated", failed),
+        });
+    }
+    Ok((migrated, skipped))
+}
+
+pub fn handle_migrate_rename(
+    query: crate::RenameFieldQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let (old_path, new_path) = (query.old_path, query.new_path);
+    let (migrated, _) = run_migration(query.query, &ctx, quiet, |fields| {
+        migrate::rename_field(fields, &old_path, &new_path)
+    })?;
+    if ctx.is_dry_run() {
+        status!(
+            "Would move {} to {} in {} documents",
+            old_path,
+            new_path,
+            migrated
+        );
+    } else {
+        status!(
+            "Moved {} to {} in {} documents",
+            old_path,
+            new_path,
+            migrated
+        );
+    }
+    Ok(())
+}
+
+pub fn handle_migrate_cast(
+    query: crate::CastFieldQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let (field_path, to) = (query.field_path, query.to);
+    let (converted, skipped) = run_migration(query.query, &ctx, quiet, |fields| {
+        migrate::cast_field(fields, &field_path, &to)
+    })?;
+    let verb = if ctx.is_dry_run() {
+        "Would convert"
+    } else {
+        "Converted"
+    };
+    status!(
+        "{} {} to {} in {} documents, {} could not be converted",
+        verb,
+        field_path,
+        to,
+        converted,
+        skipped
+    );
+    if skipped > 0 {
+        // like `validate`, values left unconverted are an answer rather than an error
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+pub fn handle_collection_sample(
+    query: crate::SampleQuery,
+    ctx: crate::DatabaseContext,
+    printer: Printer,
+) -> Result<()> {
+    let documents = ctx.sample(query.collection_name, query.count)?;
+    output::print_documents(&printer, &documents, None)
+}
+
+pub fn handle_collection_schema(
+    query: crate::SchemaQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+) -> Result<()> {
+    let documents = ctx.iter_documents(query.collection_name, None);
+    let limit = if query.sample == 0 {
+        usize::max_value()
+    } else {
+        query.sample
+    };
+    let mut schema = Schema::default();
+    for document in documents.take(limit) {
+        schema.observe(document?.fields());
+    }
+    output::print_schema(format, &schema)
+}
+
+/// Documents of the sample that `stats` looks below for subcollections, as every one
+/// costs a request per level
+const NESTING_SAMPLE: usize = 100;
+
+pub fn handle_collection_stats(
+    query: crate::StatsQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+    quiet: bool,
+) -> Result<()> {
+    let mut stats = Stats::default();
+    stats.documents = ctx.count(Query::new(&*query.collection_name))?;
+    let bar = Bar::with_total(query.sample.min(stats.documents as usize), quiet);
+    let mut paths = Vec::new();
+    for document in ctx
+        .iter_documents(&*query.collection_name, None)
+        .take(query.sample)
+    {
+        let document = document?;
+        stats.observe(&document);
+        if paths.len() < NESTING_SAMPLE {
+            paths.push(document.path().to_string());
+        }
+        bar.set(stats.sampled());
+    }
+    bar.finish();
+    for path in &paths {
+        let (depth, collection) = stats::nesting_depth(&ctx, path)?;
+        if stats
+            .nesting
+            .as_ref()
+            .map_or(true, |(deepest, _)| depth > *deepest)
+        {
+            stats.nesting = Some((depth, collection));
+        }
+    }
+    output::print_stats(format, &stats)
+}
+
+pub fn handle_size(
+    query: crate::SizeQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+    quiet: bool,
+) -> Result<()> {
+    if let Some(document_name) = query.document_name {
+        let document = ctx.get_document(query.collection_name, document_name)?;
+        return output::print_document_size(format, document.path(), &document);
+    }
+    let bar = Bar::new(quiet);
+    // the smallest of the largest documents so far is on top
+    let mut largest = BinaryHeap::new();
+    let (mut documents, mut total) = (0, 0);
+    for document in ctx.iter_documents(&*query.collection_name, None) {
+        let document = document?;
+        let size = size::document_size(&document);
+        documents += 1;
+        total += size;
+        largest.push(Reverse((size, document.path().to_string())));
+        if largest.len() > query.top {
+            largest.pop();
+        }
+        bar.set(documents);
+    }
+    bar.finish();
+    let sizes = largest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((size, path))| (path, size))
+        .collect::<Vec<(String, usize)>>();
+    output::print_sizes(format, &sizes)?;
+    status!("{} documents of {} bytes in total", documents, total);
+    Ok(())
+}
+
+pub fn handle_collection_validate(
+    query: crate::ValidateQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+) -> Result<()> {
+    let validator = Validator::new(read_payload(query.schema)?)?;
+    let (mut checked, mut failed) = (0, 0);
+    for document in ctx.iter_documents(query.collection_name, query.page_size) {
+        let document = document?;
+        let violations = validator.validate(&document.fields().to_json())?;
+        checked += 1;
+        if !violations.is_empty() {
+            failed += 1;
+            output::print_violations(format, document.path(), &violations)?;
+        }
+    }
+    status!(
+        "{} of {} documents do not match the schema",
+        failed,
+        checked
+    );
+    if failed > 0 {
+        // failing documents are an answer rather than an error, like a missing one for `exists`
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+pub fn handle_collection_watch(
+    query: crate::WatchQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+) -> Result<()> {
+    let hook = query.exec.map(Hook::new);
+    let structured = filter_query(query.query, &ctx)?;
+    for change in ctx.watch(structured, query.every) {
+        let change = change?;
+        if change.initial && query.changes_only {
+            continue;
+        }
+        let document = change
+            .document
+            .as_ref()
+            .map(|document| document.to_json())
+            .unwrap_or(serde_json::Value::Null);
+        match &hook {
+            Some(hook) => {
+                let status = hook.run(change.kind.name(), change.path(), &document)?;
+                if !status.success() {
+                    eprintln!("Command for {} failed with {}", change.path(), status);
+                }
+            }
+            None => output::print_change(format, change.kind.name(), change.path(), &document)?,
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_collection_tail(
+    query: crate::TailQuery,
+    ctx: crate::DatabaseContext,
+    printer: Printer,
+) -> Result<()> {
+    let field = query.field;
+    let filtered = filter_query(query.query, &ctx)?;
+    let mut cutoff = None;
+    let documents = match query.since {
+        Some(since) => {
+            let since = chrono::Duration::from_std(since).map_err(|_| Error::InvalidArgument {
+                reason: String::from("--since is too long"),
+            })?;
+            let start = chrono::Utc::now()
+                .checked_sub_signed(since)
+                .ok_or_else(|| Error::InvalidArgument {
+                    reason: String::from("--since is too long"),
+                })?;
+            let start = FirestoreType::Timestamp(start);
+            cutoff = Some(start.clone());
+            let recent = filtered
+                .clone()
+                .filter(Filter::new(
+                    &*field,
+                    query::Operator::GreaterThanOrEqual,
+                    start,
+                ))
+                .order_by(&*field, query::Direction::Ascending);
+            ctx.run_query(recent)?
+        }
+        None => {
+            let latest = filtered
+                .clone()
+                .order_by(&*field, query::Direction::Descending)
+                .limit(query.lines);
+            let mut documents = ctx.run_query(latest)?;
+            documents.reverse();
+            documents
+        }
+    };
+    // documents printed whose field holds the cutoff, which the next query returns again
+    let mut at_cutoff = HashSet::new();
+    for document in &documents {
+        output::print_document(&printer, document)?;
+        advance_cutoff(&mut cutoff, &mut at_cutoff, &*field, document);
+    }
+    // the query runs again for the documents from the last one printed on, so later
+    // updates to printed documents are not seen
+    loop {
+        std::thread::sleep(query.every);
+        let following = match &cutoff {
+            Some(cutoff) => filtered.clone().filter(Filter::new(
+                &*field,
+                query::Operator::GreaterThanOrEqual,
+                cutoff.clone(),
+            )),
+            None => filtered.clone(),
+        };
+        let following = following.order_by(&*field, query::Direction::Ascending);
+        for document in ctx.run_query(following)? {
+            if !at_cutoff.contains(document.name()) {
+                output::print_document(&printer, &document)?;
+            }
+            advance_cutoff(&mut cutoff, &mut at_cutoff, &*field, &document);
+        }
+    }
+}
+
+/// Moves the cutoff of `tail` to the value of `field` in a printed `document`, forgetting
+/// the documents printed at the previous cutoff when it moves forward
+fn advance_cutoff(
+    cutoff: &mut Option<FirestoreType>,
+    at_cutoff: &mut HashSet<String>,
+    field: &str,
+    document: &Document,
+) {
+    if let Some(value) = document.fields().get(field) {
+        if cutoff.as_ref() != Some(value) {
+            *cutoff = Some(value.clone());
+            at_cutoff.clear();
+        }
+        at_cutoff.insert(document.name().to_string());
+    }
+}
+
+pub fn handle_collection_count(
+    query: crate::FilterQuery,
+    ctx: crate::DatabaseContext,
+) -> Result<()> {
+    println!("{}", ctx.count(filter_query(query, &ctx)?)?);
+    Ok(())
+}
+
+pub fn handle_collection_aggregate(
+    query: crate::AggregateQuery,
+    ctx: crate::DatabaseContext,
+) -> Result<()> {
+    let mut aggregations = Vec::new();
+    if query.count {
+        aggregations.push((String::from("count"), Aggregation::Count { up_to: None }));
+    }
+    for field in query.sums {
+        aggregations.push((format!("sum({})", field), Aggregation::Sum { field }));
+    }
+    for field in query.averages {
+        aggregations.push((format!("avg({})", field), Aggregation::Average { field }));
+    }
+    // aliases must be valid field names, so results are keyed by position
+    let (labels, aggregations): (Vec<String>, Vec<(String, Aggregation)>) = aggregations
+        .into_iter()
+        .enumerate()
+        .map(|(i, (label, aggregation))| (label, (format!("aggregate_{}", i), aggregation)))
+        .unzip();
+    let mut results = ctx.run_aggregation(filter_query(query.query, &ctx)?, aggregations)?;
+    let rows = labels
+        .into_iter()
+        .enumerate()
+        .map(|(i, label)| {
+            let value = results
+                .0
+                .remove(&format!("aggregate_{}", i))
+                .map(|value| value.to_json())
+                .unwrap_or(serde_json::Value::Null);
+            (label, value)
+        })
+        .collect::<Vec<_>>();
+    output::print_aggregations(&rows)
+}
+
+/// What a managed export or import goes through, for messages
+fn describe_collections(collection_ids: &[String]) -> String {
+    if collection_ids.is_empty() {
+        String::from("every collection")
+    } else {
+        collection_ids.join(", ")
+    }
+}
+
+/// Waits for an operation to be done, drawing how many documents it went through for
+/// those reporting it such as exports and imports. Returns how many that was, as last
+/// reported, along with the ended operation.
+fn wait_for_transfer(
+    ctx: &crate::DatabaseContext,
+    operation: &str,
+    quiet: bool,
+) -> Result<(usize, AdminOperation)> {
+    let bar = Bar::new(quiet);
+    let mut completed = 0;
+    let operation = ctx.wait_for_operation(operation, |operation| {
+        if let Some(progress) = operation::document_progress(operation) {
+            if let Some(estimated) = progress.estimated {
+                bar.set_total(estimated as usize);
+            }
+            completed = progress.completed as usize;
+            bar.set(completed);
+        }
+    })?;
+    bar.finish();
+    Ok((completed, operation))
+}
+
+pub fn handle_operation_list(ctx: crate::DatabaseContext, format: Format) -> Result<()> {
+    output::print_operations(format, &ctx.list_operations()?)
+}
+
+pub fn handle_operation_describe(
+    query: crate::OperationQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+) -> Result<()> {
+    output::print_operation(format, &ctx.get_operation(&query.name)?)
+}
+
+pub fn handle_operation_wait(
+    query: crate::OperationQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let (_, operation) = wait_for_transfer(&ctx, &query.name, quiet)?;
+    status!("{} is done", operation.name);
+    Ok(())
+}
+
+pub fn handle_database_export(
+    query: crate::ManagedTransferQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let collections = describe_collections(&query.collection_ids);
+    if ctx.is_dry_run() {
+        status!("Would export {} to {}", collections, query.uri);
+        return Ok(());
+    }
+    let operation = ctx.export_documents(&query.uri, query.collection_ids)?;
+    if !query.wait {
+        status!(
+            "Exporting {} to {} in {}",
+            collections,
+            query.uri,
+            operation
+        );
+        return Ok(());
+    }
+    let (exported, operation) = wait_for_transfer(&ctx, &operation, quiet)?;
+    // exports to a bucket go to a new folder, which is what an import needs
+    let uri = operation
+        .response
+        .as_ref()
+        .and_then(|response| response["outputUriPrefix"].as_str())
+        .unwrap_or(&query.uri);
+    status!("Exported {} documents to {}", exported, uri);
+    Ok(())
+}
+
+pub fn handle_database_import(
+    query: crate::ManagedTransferQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let collections = describe_collections(&query.collection_ids);
+    if ctx.is_dry_run() {
+        status!("Would import {} from {}", collections, query.uri);
+        return Ok(());
+    }
+    let operation = ctx.import_documents(&query.uri, query.collection_ids)?;
+    if !query.wait {
+        status!(
+            "Importing {} from {} in {}",
+            collections,
+            query.uri,
+            operation
+        );
+        return Ok(());
+    }
+    let (imported, _) = wait_for_transfer(&ctx, &operation, quiet)?;
+    status!("Imported {} documents from {}", imported, query.uri);
+    Ok(())
+}
+
+/// Counts the documents of `collections` so that `bar` can tell how long is left.
+/// Counting is skipped when nothing is drawn, and a failed count only costs the estimate.
+fn count_documents(ctx: &crate::DatabaseContext, collections: &[String], bar: &Bar) {
+    if bar.is_hidden() {
+        return;
+    }
+    let mut total = 0;
+    for collection in collections {
+        match ctx.count(Query::new(&**collection)) {
+            Ok(count) => total += count as usize,
+            Err(_) => return,
+        }
+    }
+    bar.set_total(total);
+}
+
+pub fn handle_collection_export(
+    query: crate::ExportDocumentsQuery,
+    ctx: crate::DatabaseContext,
+    destination: Option<crate::DatabaseContext>,
+    quiet: bool,
+) -> Result<()> {
+    let bar = Bar::new(quiet);
+    count_documents(&ctx, &query.collections, &bar);
+    if let Some(destination) = destination {
+        return copy_collections(query, ctx, destination, bar);
+    }
+    if let Some(path) = &query.out {
+        return export_to_file(&query, &ctx, path, bar);
+    }
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut count = 0;
+    for collection in &query.collections {
+        for document in ctx.stream_query(Query::new(&**collection))? {
+            writeln!(out, "{}", document?.to_json())?;
+            count += 1;
+            bar.set(count);
+        }
+    }
+    out.flush()?;
+    bar.finish();
+    status!("Exported {} documents", count);
+    Ok(())
+}
+
+/// Writes the exported collections to the NDJSON file at `path`, saving a checkpoint
+/// after every page so that an interrupted export can be resumed
+fn export_to_file(
+    query: &crate::ExportDocumentsQuery,
+    ctx: &crate::DatabaseContext,
+    path: &str,
+    bar: Bar,
+) -> Result<()> {
+    let checkpoint_path = Checkpoint::path_for(path);
+    let mut checkpoint = if query.resume {
+        Checkpoint::load(&checkpoint_path)?
+    } else {
+        Checkpoint::default()
+    };
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    // documents written after the checkpoint are on pages that are read again
+    file.set_len(checkpoint.offset)?;
+    let mut out = BufWriter::new(file);
+    let mut collections = Vec::new();
+    for collection in &query.collections {
+        let progress = checkpoint.progress(collection);
+        if !progress.complete {
+            collections.push((collection.clone(), progress.page_token.clone()));
+        }
+    }
+    let readers = readahead::read_collections(ctx, collections.clone(), query.page_size);
+    for ((collection, _), pages) in collections.iter().zip(readers) {
+        for page in pages {
+            let page = page?;
+            for document in &page.documents {
+                writeln!(out, "{}", document.to_json())?;
+            }
+            checkpoint.progress(collection).documents += page.documents.len();
+            bar.set(exported(&checkpoint));
+            if let Some(page_token) = page.resume_token {
+                out.flush()?;
+                checkpoint.offset = out.get_ref().metadata()?.len();
+                checkpoint.progress(collection).page_token = Some(page_token);
+                checkpoint.save(&checkpoint_path)?;
+            }
+        }
+        out.flush()?;
+        checkpoint.offset = out.get_ref().metadata()?.len();
+        let progress = checkpoint.progress(collection);
+        progress.page_token = None;
+        progress.complete = true;
+        checkpoint.save(&checkpoint_path)?;
+    }
+    fs::remove_file(&checkpoint_path)?;
+    bar.finish();
+    status!("Exported {} documents", exported(&checkpoint));
+    Ok(())
+}
+
+/// Documents an export has written so far, including those of earlier runs
+fn exported(checkpoint: &Checkpoint) -> usize {
+    checkpoint
+        .collections
+        .iter()
+        .map(|progress| progress.documents)
+        .sum()
+}
+
+/// Writes every document of the exported collections into the same
+/// collections of `destination`, in place of an NDJSON file
+fn copy_collections(
+    query: crate::ExportDocumentsQuery,
+    ctx: crate::DatabaseContext,
+    destination: crate::DatabaseContext,
+    bar: Bar,
+) -> Result<()> {
+    let mut pool = CommitPool::new(&destination, |copied| bar.set(copied));
+    let mut batch = destination.batch();
+    let collections = query
+        .collections
+        .iter()
+        .map(|c| (c.clone(), None))
+        .collect();
+    let readers = readahead::read_collections(&ctx, collections, query.page_size);
+    for (collection, pages) in query.collections.iter().zip(readers) {
+        for page in pages {
+            for document in page?.documents {
+                batch.set(
+                    &**collection,
+                    document.id(),
+                    document.fields().clone(),
+                    false,
+                );
+                if batch.len() == MAX_WRITES_PER_COMMIT {
+                    pool.submit(mem::replace(&mut batch, destination.batch()))?;
+                }
+            }
+        }
+    }
+    pool.submit(batch)?;
+    let copied = pool.finish()?;
+    bar.finish();
+    status!("Copied {} documents", copied);
+    Ok(())
+}
+
+/// Reads the whole of `payload` as text
+fn read_text(payload: crate::DocumentPayload) -> Result<String> {
+    let text = match payload {
+        crate::DocumentPayload::Inline(text) => text,
+        crate::DocumentPayload::File(path) => {
+            let mut text = String::new();
+            File::open(path)?.read_to_string(&mut text)?;
+            text
+        }
+        crate::DocumentPayload::Stdin => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+    Ok(text)
+}
+
+/// Reads every record of a JSON or NDJSON source,
+/// the elements of top level arrays are treated as separate records
+fn read_records(payload: crate::DocumentPayload) -> Result<Vec<serde_json::Value>> {
+    let text = read_text(payload)?;
+    let mut records = Vec::new();
+    for value in serde_json::Deserializer::from_str(&*text).into_iter::<serde_json::Value>() {
+        match value? {
+            serde_json::Value::Array(values) => records.extend(values),
+            value => records.push(value),
+        }
+    }
+    Ok(records)
+}
+
+/// Splits a record into its document ID and fields. Records written by `export`
+/// keep their ID next to their fields, anything else takes it from `id_field`.
+/// Without `id_field` the ID is left empty, for an `IdStrategy` to choose.
+fn into_document(
+    record: serde_json::Value,
+    id_field: Option<&str>,
+) -> Result<(String, FirestoreFields)> {
+    let exported = record
+        .get("name")
+        .map_or(false, serde_json::Value::is_string)
+        && record
+            .get("fields")
+            .map_or(false, serde_json::Value::is_object);
+    let fields = |record: serde_json::Value| {
+        if exported {
+            FirestoreFields::from_json(record["fields"].clone())
+        } else {
+            FirestoreFields::from_json(record)
+        }
+    };
+    let id_field = match id_field {
+        Some(id_field) => id_field,
+        None => return Ok((String::new(), fields(record)?)),
+    };
+    let id = if exported {
+        record.get("id")
+    } else {
+        record.get(id_field)
+    };
+    let id = match id {
+        Some(serde_json::Value::String(id)) => id.clone(),
+        Some(serde_json::Value::Number(id)) => id.to_string(),
+        _ => {
+            return Err(Error::InvalidDocument {
+                reason: format!("record is missing a string or number {} field", id_field),
+            })
+        }
+    };
+    Ok((id, fields(record)?))
+}
+
+pub fn handle_collection_import(
+    query: crate::ImportDocumentsQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let id_field = match query.ids {
+        Some(_) => None,
+        None => Some(&*query.id_field),
+    };
+    let mut documents = if query.csv {
+        let types = query.types.into_iter().collect::<HashMap<_, _>>();
+        delimited::read_documents(read_text(query.payload)?.as_bytes(), id_field, &types)?
+    } else {
+        read_records(query.payload)?
+            .into_iter()
+            .map(|record| into_document(record, id_field))
+            .collect::<Result<Vec<_>>>()?
+    };
+    if let Some(ids) = &query.ids {
+        for (document_id, fields) in &mut documents {
+            *document_id = ids.id_for(fields)?;
+        }
+    }
+    let bar = Bar::with_total(documents.len(), quiet);
+    let mut pool = CommitPool::new(&ctx, |imported| bar.set(imported));
+    for chunk in documents.chunks(MAX_WRITES_PER_COMMIT) {
+        let mut batch = ctx.batch();
+        for (document_id, fields) in chunk {
+            batch.set(
+                &*query.collection_name,
+                &**document_id,
+                fields.clone(),
+                query.merge,
+            );
+        }
+        pool.submit(batch)?;
+    }
+    let imported = pool.finish()?;
+    bar.finish();
+    status!("Imported {} documents", imported);
+    Ok(())
+}
+
+pub fn handle_collection_seed(
+    query: crate::SeedQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let template = SeedTemplate::parse(read_payload(query.template)?)?;
+    let mut rng = rand::thread_rng();
+    let bar = Bar::with_total(query.count, quiet);
+    let mut pool = CommitPool::new(&ctx, |seeded| bar.set(seeded));
+    let mut index = 0;
+    while index < query.count {
+        let mut batch = ctx.batch();
+        for _ in 0..MAX_WRITES_PER_COMMIT.min(query.count - index) {
+            index += 1;
+            let fields = template.generate(index, &mut rng);
+            let document_id = query.ids.id_for(&fields)?;
+            batch.set(&*query.collection_name, &*document_id, fields, false);
+        }
+        pool.submit(batch)?;
+    }
+    let seeded = pool.finish()?;
+    bar.finish();
+    status!("Wrote {} documents to {}", seeded, query.collection_name);
+    Ok(())
+}
+
+pub fn handle_backup(
+    query: crate::BackupQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let bar = Bar::new(quiet);
+    let manifest = backup::backup(
+        &ctx,
+        Path::new(&*query.out),
+        query.chunk_size,
+        query.page_size,
+        query.resume,
+        &bar,
+    )?;
+    bar.finish();
+    let documents = manifest
+        .collections
+        .iter()
+        .map(|entry| entry.documents)
+        .sum::<usize>();
+    status!(
+        "Backed up {} documents of {} collections to {}",
+        documents,
+        manifest.collections.len(),
+        query.out
+    );
+    Ok(())
+}
+
+pub fn handle_restore(
+    query: crate::RestoreQuery,
+    ctx: crate::DatabaseContext,
+    quiet: bool,
+) -> Result<()> {
+    let bar = Bar::new(quiet);
+    let count = backup::restore(&ctx, Path::new(&*query.dir), MAX_WRITES_PER_COMMIT, &bar)?;
+    bar.finish();
+    status!("Restored {} documents", count);
+    Ok(())
+}
+
+/// Adds the writes of a parsed batch script to `batch`
+fn apply_operations(batch: &mut WriteBatch, operations: Vec<Operation>) {
+    for operation in operations {
+        match operation {
+            Operation::Set {
+                collection_name,
+                document_name,
+                fields,
+                merge,
+            } => batch.set(collection_name, document_name, fields, merge),
+            Operation::Update {
+                collection_name,
+                document_name,
+                assignments,
+            } => {
+                let (fields, field_paths) = fields::into_fields(assignments);
+                batch.update(collection_name, document_name, fields, field_paths)
+            }
+            Operation::Delete {
+                collection_name,
+                document_name,
+            } => batch.delete(collection_name, document_name),
+        };
+    }
+}
+
+pub fn handle_batch(query: crate::BatchQuery, ctx: crate::DatabaseContext) -> Result<()> {
+    let operations = batch::parse_script(&*read_text(query.script)?)?;
+    let mut batch = ctx.batch();
+    apply_operations(&mut batch, operations);
+    let writes = batch.len();
+    batch.commit()?;
+    status!("Committed {} writes", writes);
+    Ok(())
+}
+
+pub fn handle_transact(query: crate::BatchQuery, ctx: crate::DatabaseContext) -> Result<()> {
+    let script = read_text(query.script)?;
+    let writes = ctx.run_transaction(|transaction| {
+        apply_operations(transaction.writes(), batch::parse_script(&*script)?);
+        Ok(transaction.writes().len())
+    })?;
+    status!("Committed {} writes in a transaction", writes);
+    Ok(())
+}
+
+pub fn handle_index_list(
+    query: crate::IndexListQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+) -> Result<()> {
+    let mut indexes = ctx.list_indexes()?;
+    if let Some(collection_group) = &query.collection_group {
+        indexes.retain(|index| &index.collection_group == collection_group);
+    }
+    output::print_indexes(format, &indexes)
+}
+
+pub fn handle_index_create(
+    query: crate::IndexCreateQuery,
+    ctx: crate::DatabaseContext,
+) -> Result<()> {
+    // the write sink only sees documents, so a dry run stops here
+    if ctx.is_dry_run() {
+        status!("Would create an index on {}", query.index);
+        return Ok(());
+    }
+    let operation = ctx.create_index(&query.index)?;
+    if !query.wait {
+        status!("Building an index on {} in {}", query.index, operation);
+        return Ok(());
+    }
+    status!(
+        "Building an index on {}, this can take a few minutes",
+        query.index
+    );
+    ctx.wait_for_operation(&operation, |_| {})?;
+    status!("The index is ready");
+    Ok(())
+}
+
+pub fn handle_index_delete(
+    query: crate::IndexDeleteQuery,
+    ctx: crate::DatabaseContext,
+) -> Result<()> {
+    if ctx.is_dry_run() {
+        status!("Would delete the index {}", query.index_id);
+        return Ok(());
+    }
+    if !query.yes && !confirm(&format!("Delete the index {}?", query.index_id))? {
+        status!("Nothing was deleted");
+        return Ok(());
+    }
+    ctx.delete_index(&query.index_id)
+}
+
+pub fn handle_database_list(ctx: crate::DatabaseContext, format: Format) -> Result<()> {
+    output::print_databases(format, &ctx.list_databases()?)
+}
+
+pub fn handle_database_create(
+    query: crate::DatabaseCreateQuery,
+    ctx: crate::DatabaseContext,
+) -> Result<()> {
+    if ctx.is_dry_run() {
+        status!("Would create the database {}", query.database_id);
+        return Ok(());
+    }
+    let operation = ctx.create_database(&query.database_id, query.database)?;
+    if !query.wait {
+        status!(
+            "Creating the database {} in {}",
+            query.database_id,
+            operation
+        );
+        return Ok(());
+    }
+    ctx.wait_for_operation(&operation, |_| {})?;
+    status!("Created the database {}", query.database_id);
+    Ok(())
+}
+
+pub fn handle_database_delete(
+    query: crate::DatabaseDeleteQuery,
+    ctx: crate::DatabaseContext,
+) -> Result<()> {
+    if ctx.is_dry_run() {
+        status!("Would delete the database {}", query.database_id);
+        return Ok(());
+    }
+    let question = format!(
+        "Delete the database {} and every document in it?",
+        query.database_id
+    );
+    if !query.yes && !confirm(&question)? {
+        status!("Nothing was deleted");
+        return Ok(());
+    }
+    let operation = ctx.delete_database(&query.database_id)?;
+    if !query.wait {
+        status!(
+            "Deleting the database {} in {}",
+            query.database_id,
+            operation
+        );
+        return Ok(());
+    }
+    ctx.wait_for_operation(&operation, |_| {})?;
+    status!("Deleted the database {}", query.database_id);
+    Ok(())
+}
+
+pub fn handle_ttl_list(ctx: crate::DatabaseContext, format: Format) -> Result<()> {
+    output::print_ttl_policies(format, &ctx.list_ttl_policies()?)
+}
+
+pub fn handle_ttl_set(query: crate::TtlQuery, ctx: crate::DatabaseContext) -> Result<()> {
+    let field = format!("{}.{}", query.collection_group, query.field);
+    let action = if query.enable {
+        "Expiring documents by"
+    } else {
+        "No longer expiring documents by"
+    };
+    if ctx.is_dry_run() {
+        let verb = if query.enable { "enable" } else { "disable" };
+        status!("Would {} the TTL policy on {}", verb, field);
+        return Ok(());
+    }
+    let operation = ctx.set_ttl_policy(&query.collection_group, &query.field, query.enable)?;
+    if !query.wait {
+        status!("{} {} once {} is done", action, field, operation);
+        return Ok(());
+    }
+    ctx.wait_for_operation(&operation, |_| {})?;
+    status!("{} {}", action, field);
+    Ok(())
+}
+
+pub fn handle_collections_list(
+    query: crate::CollectionsQuery,
+    ctx: crate::DatabaseContext,
+    format: Format,
+) -> Result<()> {
+    let mut collection_ids = match query.document {
+        Some((collection_name, document_name)) => {
+            ctx.list_collection_ids(collection_name, document_name)?
+        }
+        None => ctx.list_root_collection_ids()?,
+    };
+    collection_ids.sort();
+    output::print_names(format, &collection_ids)
+}
@@ -1,5 +1,19 @@
 use reqwest::Error as ReqwestError;
 use serde_json::Error as SerdeError;
+use serde_json::Value;
+use std::io::Error as IoError;
+
+// Exit codes of the command line, one per kind of failure so scripts can branch on them.
+// They are kept as they are once released; 1 stands for failures of no other kind and for
+// commands such as `exists` that answer through their exit code.
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_INVALID_ARGUMENT: i32 = 2;
+pub const EXIT_AUTHENTICATION: i32 = 3;
+pub const EXIT_NOT_FOUND: i32 = 4;
+pub const EXIT_PERMISSION_DENIED: i32 = 5;
+pub const EXIT_NETWORK: i32 = 6;
+pub const EXIT_CONFLICT: i32 = 7;
+pub const EXIT_IO: i32 = 8;
 
 /// General purpose error describing multiple fault points
 /// in either firestore or processing of firestore responses
@@ -13,11 +27,67 @@ pub enum Error {
 
     #[snafu(display("Unknown Error from reqwest: {}", source))]
     UnknownReqwest { source: ReqwestError },
+
+    #[snafu(display("JSON Parse Error: {}", source))]
+    JSONParse { source: SerdeError },
+
+    #[snafu(display("IO Error: {}", source))]
+    Io { source: IoError },
+
+    #[snafu(display("Invalid value for header {}", name))]
+    InvalidHeader { name: String },
+
+    #[snafu(display("Invalid Document: {}", reason))]
+    InvalidDocument { reason: String },
+
+    #[snafu(display("Invalid Argument: {}", reason))]
+    InvalidArgument { reason: String },
+
+    #[snafu(display("Authentication Error: {}", reason))]
+    Authentication { reason: String },
+
+    #[snafu(display("Output Error: {}", reason))]
+    Output { reason: String },
+
+    #[snafu(display("Unauthenticated: {}", message))]
+    Unauthenticated { message: String },
+
+    #[snafu(display("Permission Denied: {}", message))]
+    PermissionDenied { message: String },
+
+    #[snafu(display("Not Found: {}", message))]
+    NotFound { message: String },
+
+    #[snafu(display("Already Exists: {}", message))]
+    AlreadyExists { message: String },
+
+    #[snafu(display("Failed Precondition: {}", message))]
+    FailedPrecondition { message: String },
+
+    #[snafu(display("Aborted: {}", message))]
+    Aborted { message: String },
+
+    #[snafu(display("Resource Exhausted: {}", message))]
+    ResourceExhausted { message: String },
+
+    #[snafu(display("Unavailable: {}", message))]
+    Unavailable { message: String },
+
+    #[snafu(display("Deadline Exceeded: {}", message))]
+    DeadlineExceeded { message: String },
+
+    /// Any other status returned by Firestore, `status` is the gRPC code name
+    #[snafu(display("Firestore Error ({}): {}", status, message))]
+    Status { status: String, message: String },
 }
 
 impl From<ReqwestError> for Error {
     fn from(source: ReqwestError) -> Self {
-        if source.is_serialization() {
+        if source.is_timeout() {
+            return Error::DeadlineExceeded {
+                message: source.to_string(),
+            };
+        } else if source.is_serialization() {
             return Error::JSON { source };
         } else if source.is_server_error()
             || source.is_client_error()
@@ -30,4 +100,165 @@ impl From<ReqwestError> for Error {
     }
 }
 
+impl Error {
+    /// Maps a canonical gRPC status name, as found in Firestore error bodies, onto an error
+    pub fn from_status(status: &str, message: String) -> Error {
+        match status {
+            "INVALID_ARGUMENT" | "OUT_OF_RANGE" => Error::InvalidArgument { reason: message },
+            "UNAUTHENTICATED" => Error::Unauthenticated { message },
+            "PERMISSION_DENIED" => Error::PermissionDenied { message },
+            "NOT_FOUND" => Error::NotFound { message },
+            "ALREADY_EXISTS" => Error::AlreadyExists { message },
+            "FAILED_PRECONDITION" => Error::FailedPrecondition { message },
+            "ABORTED" => Error::Aborted { message },
+            "RESOURCE_EXHAUSTED" => Error::ResourceExhausted { message },
+            "UNAVAILABLE" => Error::Unavailable { message },
+            "DEADLINE_EXCEEDED" => Error::DeadlineExceeded { message },
+            _ => Error::Status {
+                status: status.to_string(),
+                message,
+            },
+        }
+    }
+
+    /// Whether the request that failed with this error may succeed when sent again,
+    /// because the service was briefly unavailable, overloaded or out of time
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Unavailable { .. }
+            | Error::DeadlineExceeded { .. }
+            | Error::ResourceExhausted { .. }
+            | Error::Network { .. }
+            | Error::UnknownReqwest { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Kind of failure as named in machine-readable errors, one per exit code
+    pub fn kind(&self) -> &'static str {
+        match self.exit_code() {
+            EXIT_INVALID_ARGUMENT => "invalid_argument",
+            EXIT_AUTHENTICATION => "authentication",
+            EXIT_NOT_FOUND => "not_found",
+            EXIT_PERMISSION_DENIED => "permission_denied",
+            EXIT_NETWORK => "network",
+            EXIT_CONFLICT => "conflict",
+            EXIT_IO => "io",
+            _ => "failure",
+        }
+    }
+
+    /// Process exit code for this error, see `EXIT_FAILURE` and the codes after it
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidArgument { .. } | Error::InvalidDocument { .. } => EXIT_INVALID_ARGUMENT,
+            Error::Authentication { .. } | Error::Unauthenticated { .. } => EXIT_AUTHENTICATION,
+            Error::NotFound { .. } => EXIT_NOT_FOUND,
+            Error::PermissionDenied { .. } => EXIT_PERMISSION_DENIED,
+            Error::Network { .. }
+            | Error::UnknownReqwest { .. }
+            | Error::Unavailable { .. }
+            | Error::DeadlineExceeded { .. }
+            | Error::ResourceExhausted { .. } => EXIT_NETWORK,
+            Error::AlreadyExists { .. }
+            | Error::FailedPrecondition { .. }
+            | Error::Aborted { .. } => EXIT_CONFLICT,
+            Error::Io { .. } | Error::Output { .. } => EXIT_IO,
+            _ => EXIT_FAILURE,
+        }
+    }
+
+    /// The gRPC status name of errors Firestore answered with
+    pub fn status(&self) -> Option<&str> {
+        let status = match self {
+            Error::Unauthenticated { .. } => "UNAUTHENTICATED",
+            Error::PermissionDenied { .. } => "PERMISSION_DENIED",
+            Error::NotFound { .. } => "NOT_FOUND",
+            Error::AlreadyExists { .. } => "ALREADY_EXISTS",
+            Error::FailedPrecondition { .. } => "FAILED_PRECONDITION",
+            Error::Aborted { .. } => "ABORTED",
+            Error::ResourceExhausted { .. } => "RESOURCE_EXHAUSTED",
+            Error::Unavailable { .. } => "UNAVAILABLE",
+            Error::DeadlineExceeded { .. } => "DEADLINE_EXCEEDED",
+            Error::Status { status, .. } => status,
+            _ => return None,
+        };
+        Some(status)
+    }
+
+    /// The error as written by `--errors json`, such as
+    /// `{"error": {"kind": "not_found", "code": 4, "status": "NOT_FOUND", "message": ...}}`
+    pub fn to_json(&self) -> Value {
+        let mut error = json!({
+            "kind": self.kind(),
+            "code": self.exit_code(),
+            "message": self.to_string(),
+        });
+        if let Some(status) = self.status() {
+            error["status"] = Value::from(status);
+        }
+        json!({ "error": error })
+    }
+
+    /// Maps an HTTP status code onto an error, for responses without a Firestore error body
+    pub fn from_http_status(code: u16, message: String) -> Error {
+        let status = match code {
+            400 => "INVALID_ARGUMENT",
+            401 => "UNAUTHENTICATED",
+            403 => "PERMISSION_DENIED",
+            404 => "NOT_FOUND",
+            409 => "ABORTED",
+            412 => "FAILED_PRECONDITION",
+            429 => "RESOURCE_EXHAUSTED",
+            499 => "CANCELLED",
+            501 => "UNIMPLEMENTED",
+            503 => "UNAVAILABLE",
+            504 => "DEADLINE_EXCEEDED",
+            _ => "INTERNAL",
+        };
+        Error::from_status(status, message)
+    }
+
+    /// Maps a numeric gRPC status code, as found in `google.rpc.Status`, onto an error
+    pub fn from_code(code: i32, message: String) -> Error {
+        let status = match code {
+            1 => "CANCELLED",
+            3 => "INVALID_ARGUMENT",
+            4 => "DEADLINE_EXCEEDED",
+            5 => "NOT_FOUND",
+            6 => "ALREADY_EXISTS",
+            7 => "PERMISSION_DENIED",
+            8 => "RESOURCE_EXHAUSTED",
+            9 => "FAILED_PRECONDITION",
+            10 => "ABORTED",
+            11 => "OUT_OF_RANGE",
+            12 => "UNIMPLEMENTED",
+            14 => "UNAVAILABLE",
+            16 => "UNAUTHENTICATED",
+            _ => "INTERNAL",
+        };
+        Error::from_status(status, message)
+    }
+}
+
+impl From<SerdeError> for Error {
+    fn from(source: SerdeError) -> Self {
+        Error::JSONParse { source }
+    }
+}
+
+impl From<IoError> for Error {
+    fn from(source: IoError) -> Self {
+        Error::Io { source }
+    }
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::InvalidDocument {
+            reason: msg.to_string(),
+        }
+    }
+}
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,364 @@
+// Workload identity federation: credentials files of type `external_account`, which hold
+// no key but say where to find a token of another identity provider, such as the OIDC
+// token of a GitHub Actions job or the role of an AWS instance. That subject token is
+// exchanged for a Google access token at the STS endpoint, which may in turn be used to
+// impersonate a service account. Subject tokens are read from a file, fetched from a URL
+// or, for AWS, made of a GetCallerIdentity request signed with the AWS credentials of
+// the environment. Executable sources are not supported.
+
+use crate::auth::{auth_error, generate_access_token};
+use crate::errors::Result;
+use crate::firestore::{CheckStatus, Logged};
+use chrono::Utc;
+use goauth::auth::Token;
+use goauth::scopes::Scope;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+
+const DEFAULT_TOKEN_URL: &'static str = "https://sts.googleapis.com/v1/token";
+const TOKEN_EXCHANGE_GRANT_TYPE: &'static str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &'static str = "urn:ietf:params:oauth:token-type:access_token";
+
+const AWS_ENVIRONMENT_PREFIX: &'static str = "aws";
+const AWS_SIGNING_ALGORITHM: &'static str = "AWS4-HMAC-SHA256";
+const AWS_IMDSV2_TTL_HEADER: &'static str = "X-aws-ec2-metadata-token-ttl-seconds";
+const AWS_IMDSV2_TOKEN_HEADER: &'static str = "X-aws-ec2-metadata-token";
+/// Seconds the IMDSv2 session token asked for lasts, only a few requests are made with it
+const AWS_IMDSV2_TTL: &'static str = "300";
+/// Header naming the workload identity pool provider in signed AWS requests
+const TARGET_RESOURCE_HEADER: &'static str = "x-goog-cloud-target-resource";
+
+/// An `external_account` credentials file
+#[derive(Deserialize)]
+struct ExternalAccount {
+    /// Full resource name of the workload identity pool provider
+    audience: String,
+    subject_token_type: String,
+    token_url: Option<String>,
+    service_account_impersonation_url: Option<String>,
+    /// Project billed for workforce pools
+    workforce_pool_user_project: Option<String>,
+    credential_source: SubjectSource,
+}
+
+/// Where the subject token comes from, only some of the fields are set for each source
+#[derive(Deserialize)]
+struct SubjectSource {
+    file: Option<String>,
+    /// URL of the token, or of the role credentials for AWS
+    url: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    format: Option<SubjectFormat>,
+    /// Such as `aws1`
+    environment_id: Option<String>,
+    region_url: Option<String>,
+    regional_cred_verification_url: Option<String>,
+    imdsv2_session_token_url: Option<String>,
+    executable: Option<Value>,
+}
+
+/// How a token read from a file or URL is written, as text unless said otherwise
+#[derive(Deserialize)]
+struct SubjectFormat {
+    #[serde(rename = "type")]
+    kind: String,
+    /// Field of a JSON token holding the token itself
+    subject_token_field_name: Option<String>,
+}
+
+/// Credentials of the role of an AWS instance
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: Option<String>,
+}
+
+/// Exchanges the subject token of the `external_account` credentials in `contents` for
+/// an access token of `scope`
+pub(crate) fn token(
+    client: &reqwest::Client,
+    contents: &str,
+    scope: &Scope,
+    origin: &str,
+) -> Result<Token> {
+    let account: ExternalAccount = serde_json::from_str(contents)
+        .map_err(|e| auth_error(format!("failed to load credentials from {}: {}", origin, e)))?;
+    let subject_token = subject_token(client, &account)?;
+    // the federated token only needs to impersonate, the service account gets the scope
+    let exchanged_scope = match &account.service_account_impersonation_url {
+        Some(_) => Scope::CloudPlatform.url(),
+        None => scope.url(),
+    };
+    let mut form = vec![
+        ("grant_type", String::from(TOKEN_EXCHANGE_GRANT_TYPE)),
+        ("audience", account.audience.clone()),
+        ("scope", exchanged_scope),
+        ("requested_token_type", String::from(ACCESS_TOKEN_TYPE)),
+        ("subject_token", subject_token),
+        ("subject_token_type", account.subject_token_type.clone()),
+    ];
+    if let Some(project) = &account.workforce_pool_user_project {
+        form.push(("options", json!({ "userProject": project }).to_string()));
+    }
+    let token_url = account
+        .token_url
+        .as_ref()
+        .map_or(DEFAULT_TOKEN_URL, |url| &**url);
+    let federated = client
+        .post(token_url)
+        .form(&form)
+        .send_logged()
+        .and_then(CheckStatus::check_status)
+        .and_then(|mut response| response.json::<Token>().map_err(Into::into))
+        .map_err(|e| auth_error(format!("failed to exchange the token of {}: {}", origin, e)))?;
+    match &account.service_account_impersonation_url {
+        Some(url) => generate_access_token(client, url, federated.access_token(), scope)
+            .map_err(|e| auth_error(format!("failed to impersonate with {}: {}", origin, e))),
+        None => Ok(federated),
+    }
+}
+
+/// The token of the other identity provider
+fn subject_token(client: &reqwest::Client, account: &ExternalAccount) -> Result<String> {
+    let source = &account.credential_source;
+    if let Some(environment) = &source.environment_id {
+        if environment.starts_with(AWS_ENVIRONMENT_PREFIX) {
+            return aws_subject_token(client, account);
+        }
+        return Err(auth_error(format!(
+            "external accounts of environment {} are not supported",
+            environment
+        )));
+    }
+    let token = match (&source.file, &source.url) {
+        (Some(path), _) => fs::read_to_string(path)
+            .map_err(|e| auth_error(format!("cannot read the subject token {}: {}", path, e)))?,
+        (None, Some(url)) => {
+            let mut request = client.get(&**url);
+            for (name, value) in &source.headers {
+                request = request.header(&**name, &**value);
+            }
+            request.send_logged()?.check_status()?.text()?
+        }
+        (None, None) if source.executable.is_some() => {
+            return Err(auth_error(String::from(
+                "external accounts with executable sources are not supported",
+            )))
+        }
+        (None, None) => {
+            return Err(auth_error(String::from(
+                "the external account has no file or url to read the subject token from",
+            )))
+        }
+    };
+    match &source.format {
+        Some(format) if format.kind == "json" => {
+            let field = format
+                .subject_token_field_name
+                .as_ref()
+                .map_or("", |field| &**field);
+            let value: Value = serde_json::from_str(&*token)?;
+            value[field]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| auth_error(format!("the subject token has no {} field", field)))
+        }
+        _ => Ok(token.trim().to_string()),
+    }
+}
+
+/// A GetCallerIdentity request signed with the AWS credentials of the environment, which
+/// STS sends to AWS to learn the role it was signed by
+fn aws_subject_token(client: &reqwest::Client, account: &ExternalAccount) -> Result<String> {
+    let source = &account.credential_source;
+    let mut metadata_headers = Vec::new();
+    if let Some(url) = &source.imdsv2_session_token_url {
+        let session_token = client
+            .put(&**url)
+            .header(AWS_IMDSV2_TTL_HEADER, AWS_IMDSV2_TTL)
+            .send_logged()?
+            .check_status()?
+            .text()?;
+        metadata_headers.push((AWS_IMDSV2_TOKEN_HEADER, session_token));
+    }
+    let metadata = |url: &str| -> Result<String> {
+        let mut request = client.get(url);
+        for (name, value) in &metadata_headers {
+            request = request.header(*name, &**value);
+        }
+        Ok(request.send_logged()?.check_status()?.text()?)
+    };
+    let region = match env::var("AWS_REGION").or_else(|_| env::var("AWS_DEFAULT_REGION")) {
+        Ok(region) => region,
+        Err(_) => {
+            let url = source.region_url.as_ref().ok_or_else(|| {
+                auth_error(String::from(
+                    "AWS_REGION is not set and there is no region_url",
+                ))
+            })?;
+            // the availability zone, such as us-east-1b, ends in a letter past the region
+            let mut zone = metadata(url)?;
+            zone.pop();
+            zone
+        }
+    };
+    let credentials = match (
+        env::var("AWS_ACCESS_KEY_ID"),
+        env::var("AWS_SECRET_ACCESS_KEY"),
+    ) {
+        (Ok(access_key_id), Ok(secret_access_key)) => AwsCredentials {
+            access_key_id,
+            secret_access_key,
+            token: env::var("AWS_SESSION_TOKEN").ok(),
+        },
+        _ => {
+            let url = source.url.as_ref().ok_or_else(|| {
+                auth_error(String::from(
+                    "AWS_ACCESS_KEY_ID is not set and there is no url to get credentials from",
+                ))
+            })?;
+            let role = metadata(url)?;
+            serde_json::from_str(&*metadata(&format!("{}/{}", url, role.trim()))?)?
+        }
+    };
+    let url = source
+        .regional_cred_verification_url
+        .as_ref()
+        .ok_or_else(|| auth_error(String::from("the AWS source has no verification url")))?
+        .replace("{region}", &*region);
+    let headers = sign_aws_request(
+        "POST",
+        &*url,
+        &*region,
+        &credentials,
+        &[(TARGET_RESOURCE_HEADER, &*account.audience)],
+    )?;
+    let request = json!({
+        "url": url,
+        "method": "POST",
+        "headers": headers
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": value }))
+            .collect::<Vec<Value>>(),
+    });
+    Ok(quote(&*request.to_string()))
+}
+
+/// The headers of a body-less request to STS signed with AWS Signature Version 4, by key.
+/// `extra` headers, named in lowercase, are signed along with those of the signature.
+fn sign_aws_request(
+    method: &str,
+    url: &str,
+    region: &str,
+    credentials: &AwsCredentials,
+    extra: &[(&'static str, &str)],
+) -> Result<BTreeMap<String, String>> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| auth_error(format!("{} is not a valid URL: {}", url, e)))?;
+    let host = parsed.host_str().unwrap_or_default().to_string();
+    let path = match parsed.path() {
+        "" => "/",
+        path => path,
+    };
+    let mut query = parsed
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .collect::<Vec<&str>>();
+    query.retain(|pair| !pair.is_empty());
+    query.sort();
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    // canonical headers are sorted by name, as a BTreeMap keeps them
+    let mut signed = BTreeMap::new();
+    signed.insert("host", host.clone());
+    signed.insert("x-amz-date", amz_date.clone());
+    if let Some(token) = &credentials.token {
+        signed.insert("x-amz-security-token", token.clone());
+    }
+    for (name, value) in extra {
+        signed.insert(*name, value.to_string());
+    }
+    let canonical_headers = signed
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect::<String>();
+    let signed_headers = signed.keys().cloned().collect::<Vec<&str>>().join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        path,
+        query.join("&"),
+        canonical_headers,
+        signed_headers,
+        hex(&Sha256::digest(b""))
+    );
+    let scope = format!("{}/{}/sts/aws4_request", date, region);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        AWS_SIGNING_ALGORITHM,
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = format!("AWS4{}", credentials.secret_access_key);
+    let key = hmac_sha256(key.as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, b"sts");
+    let key = hmac_sha256(&key, b"aws4_request");
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+    let mut headers = BTreeMap::new();
+    headers.insert(
+        String::from("Authorization"),
+        format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            AWS_SIGNING_ALGORITHM, credentials.access_key_id, scope, signed_headers, signature
+        ),
+    );
+    for (name, value) in signed {
+        headers.insert(name.to_string(), value);
+    }
+    Ok(headers)
+}
+
+/// HMAC-SHA256 of `message` with `key`, as in RFC 2104
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 64;
+    let mut key = if key.len() > BLOCK_SIZE {
+        Sha256::digest(key).to_vec()
+    } else {
+        key.to_vec()
+    };
+    key.resize(BLOCK_SIZE, 0);
+    let mut inner = Sha256::new();
+    inner.input(&key.iter().map(|byte| byte ^ 0x36).collect::<Vec<u8>>());
+    inner.input(message);
+    let mut outer = Sha256::new();
+    outer.input(&key.iter().map(|byte| byte ^ 0x5c).collect::<Vec<u8>>());
+    outer.input(&inner.result());
+    outer.result().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent-encodes all but unreserved characters and slashes, like Python's `quote`,
+/// which is how the Google client libraries encode signed AWS requests
+fn quote(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,322 @@
+// Field paths such as `address.city`, `items[2].price` or `` `first.name` ``. As in
+// Firestore, a key which is not a plain identifier is quoted in backticks when written
+// out, with `\` escaping backticks and backslashes, although only keys holding `.`, `[`
+// or backticks have to be quoted when read. Indices address elements of arrays, which
+// Firestore masks cannot, so writing an element rewrites its whole array.
+
+use crate::api::{FirestoreFields, FirestoreType, Map};
+use crate::errors::{Error, Result};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// Key of a map
+    Key(String),
+    /// Element of an array, from 0
+    Index(usize),
+}
+
+/// A path to a field of a document, through maps and arrays
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldPath {
+    segments: Vec<Segment>,
+}
+
+fn invalid(path: &str, reason: &str) -> Error {
+    Error::InvalidArgument {
+        reason: format!("{} is not a valid field path, {}", path, reason),
+    }
+}
+
+/// Whether `key` can be written without backticks, as Firestore requires
+fn is_simple(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Quotes `key` in backticks unless it is a plain identifier
+pub fn escape_key(key: &str) -> String {
+    if is_simple(key) {
+        return key.to_string();
+    }
+    format!("`{}`", key.replace('\\', "\\\\").replace('`', "\\`"))
+}
+
+impl FieldPath {
+    /// The path through nested maps with said keys
+    pub fn from_keys<I, S>(keys: I) -> FieldPath
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        FieldPath {
+            segments: keys
+                .into_iter()
+                .map(|key| Segment::Key(key.into()))
+                .collect(),
+        }
+    }
+
+    pub fn parse(path: &str) -> Result<FieldPath> {
+        let mut segments = Vec::new();
+        let mut chars = path.chars().peekable();
+        loop {
+            let key = match chars.peek() {
+                Some('`') => {
+                    chars.next();
+                    let mut key = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('`') => break,
+                            Some('\\') => match chars.next() {
+                                Some(escaped) => key.push(escaped),
+                                None => return Err(invalid(path, "it ends in an escape")),
+                            },
+                            Some(c) => key.push(c),
+                            None => return Err(invalid(path, "a backtick is left open")),
+                        }
+                    }
+                    key
+                }
+                _ => {
+                    let mut key = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == '.' || c == '[' || c == '`' {
+                            break;
+                        }
+                        key.push(c);
+                        chars.next();
+                    }
+                    key
+                }
+            };
+            if key.is_empty() {
+                return Err(invalid(path, "it has an empty key"));
+            }
+            segments.push(Segment::Key(key));
+            while chars.peek() == Some(&'[') {
+                chars.next();
+                let mut digits = String::new();
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    if c == ']' {
+                        closed = true;
+                        break;
+                    }
+                    digits.push(c);
+                }
+                let index =
+                    digits.parse().ok().filter(|_| closed).ok_or_else(|| {
+                        invalid(path, "indices are written as [0], [1] and so on")
+                    })?;
+                segments.push(Segment::Index(index));
+            }
+            match chars.next() {
+                None => return Ok(FieldPath { segments }),
+                Some('.') => {}
+                Some(_) => return Err(invalid(path, "keys are separated by dots")),
+            }
+        }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Whether the path goes through an element of an array
+    pub fn has_index(&self) -> bool {
+        self.segments.iter().any(|segment| match segment {
+            Segment::Index(_) => true,
+            Segment::Key(_) => false,
+        })
+    }
+
+    /// The path up to its first index, to the array an element is in, which is the
+    /// path itself when it has no index
+    pub fn array_path(&self) -> FieldPath {
+        FieldPath {
+            segments: self
+                .segments
+                .iter()
+                .take_while(|segment| match segment {
+                    Segment::Key(_) => true,
+                    Segment::Index(_) => false,
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// The value at this path in `fields`
+    pub fn get<'f>(&self, fields: &'f FirestoreFields) -> Option<&'f FirestoreType> {
+        let (first, rest) = self.segments.split_first()?;
+        let first = match first {
+            Segment::Key(key) => fields.0.get(key)?,
+            Segment::Index(_) => return None,
+        };
+        rest.iter()
+            .try_fold(first, |value, segment| match (value, segment) {
+                (FirestoreType::Map(map), Segment::Key(key)) => map.fields.0.get(key),
+                (FirestoreType::Array(array), Segment::Index(index)) => array.values.get(*index),
+                _ => None,
+            })
+    }
+
+    /// The value at this path in a document rendered as JSON
+    pub fn get_json<'v>(&self, value: &'v Value) -> Option<&'v Value> {
+        self.segments
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.as_object()?.get(key),
+                Segment::Index(index) => value.as_array()?.get(*index),
+            })
+    }
+
+    /// Writes `value` at this path in `fields`, creating maps for keys along the way and
+    /// replacing values which are not maps. Elements are only written in arrays which
+    /// already hold them.
+    pub fn set(&self, fields: &mut FirestoreFields, value: FirestoreType) -> Result<()> {
+        match self.segments.first() {
+            Some(Segment::Key(key)) => self.set_key(fields, key, 1, value),
+            _ => Err(invalid(&self.to_string(), "it starts with an index")),
+        }
+    }
+
+    /// Writes `value` at `key` of `fields`, the map at the first `depth` segments
+    fn set_key(
+        &self,
+        fields: &mut FirestoreFields,
+        key: &str,
+        depth: usize,
+        value: FirestoreType,
+    ) -> Result<()> {
+        if depth == self.segments.len() {
+            fields.0.insert(key.to_string(), value);
+            return Ok(());
+        }
+        let entry = fields
+            .0
+            .entry(key.to_string())
+            .or_insert_with(|| FirestoreType::Map(Map::default()));
+        self.set_below(entry, depth, value)
+    }
+
+    /// Writes `value` below `target`, the value at the first `depth` segments
+    fn set_below(
+        &self,
+        target: &mut FirestoreType,
+        depth: usize,
+        value: FirestoreType,
+    ) -> Result<()> {
+        match &self.segments[depth] {
+            Segment::Key(key) => {
+                if let FirestoreType::Map(map) = target {
+                    return self.set_key(&mut map.fields, key, depth + 1, value);
+                }
+                let mut map = Map::default();
+                self.set_key(&mut map.fields, key, depth + 1, value)?;
+                *target = FirestoreType::Map(map);
+                Ok(())
+            }
+            Segment::Index(index) => {
+                let parent = FieldPath {
+                    segments: self.segments[..depth].to_vec(),
+                };
+                match target {
+                    FirestoreType::Array(array) if *index < array.values.len() => {
+                        let element = &mut array.values[*index];
+                        if depth + 1 == self.segments.len() {
+                            *element = value;
+                            return Ok(());
+                        }
+                        self.set_below(element, depth + 1, value)
+                    }
+                    FirestoreType::Array(_) => Err(Error::InvalidArgument {
+                        reason: format!("{} has no element {}", parent, index),
+                    }),
+                    _ => Err(Error::InvalidArgument {
+                        reason: format!("{} is not an array", parent),
+                    }),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for FieldPath {
+    type Err = Error;
+
+    fn from_str(path: &str) -> Result<FieldPath> {
+        FieldPath::parse(path)
+    }
+}
+
+impl fmt::Display for FieldPath {
+    /// The path as Firestore takes it, keys quoted as needed
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Key(key) if i == 0 => write!(f, "{}", escape_key(key))?,
+                Segment::Key(key) => write!(f, ".{}", escape_key(key))?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str) -> Segment {
+        Segment::Key(key.to_string())
+    }
+
+    #[test]
+    fn parses_quoted_keys_and_indices() {
+        let path = FieldPath::parse("`a.b`.c[2]").unwrap();
+        assert_eq!(path.segments(), &[key("a.b"), key("c"), Segment::Index(2)]);
+        assert_eq!(path.to_string(), "`a.b`.c[2]");
+    }
+
+    #[test]
+    fn round_trips_backticks_and_backslashes() {
+        let path = FieldPath::from_keys(vec!["tick`", "back\\slash", "plain"]);
+        let written = path.to_string();
+        assert_eq!(written, r"`tick\``.`back\\slash`.plain");
+        assert_eq!(FieldPath::parse(&*written).unwrap(), path);
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        for path in &["a.", "a[x]", "a[1]b", "`a", "a[1"] {
+            assert!(FieldPath::parse(path).is_err(), "{} was accepted", path);
+        }
+    }
+
+    #[test]
+    fn sets_elements_of_existing_arrays_only() {
+        let mut fields =
+            FirestoreFields::from_json(json!({ "items": [{ "price": 1 }, { "price": 2 }] }))
+                .unwrap();
+        let path = FieldPath::parse("items[1].price").unwrap();
+        path.set(&mut fields, FirestoreType::Integer(3)).unwrap();
+        assert_eq!(path.get(&fields), Some(&FirestoreType::Integer(3)));
+        assert_eq!(
+            FieldPath::parse("items[0].price").unwrap().get(&fields),
+            Some(&FirestoreType::Integer(1))
+        );
+        let out_of_range = FieldPath::parse("items[2].price").unwrap();
+        assert!(out_of_range
+            .set(&mut fields, FirestoreType::Integer(4))
+            .is_err());
+    }
+}
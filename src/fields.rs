@@ -0,0 +1,244 @@
+// Parsing of `path[:type]=value` field assignments given on the command line
+// e.g. name="New Name" age:int=30 address.city=Austin born:timestamp=1990-01-01
+// loc:geopoint=30.2,-97.7 manager:reference=users/bob data:bytes=base64:aGVsbG8=
+// and of `path[:type]+=value` increments, e.g. views+=1 score:double+=0.5
+// and of the paths of fields to delete, e.g. address.zip
+// Paths may hold backticked keys and array indices, e.g. `first.name`=Al items[2].price:int=5
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use libfiresale::api::{FirestoreFields, FirestoreType, GeoPoint};
+use libfiresale::errors::{Error, Result};
+use libfiresale::fieldpath::FieldPath;
+
+/// A single value to write at a (possibly nested) field path
+pub struct FieldAssignment {
+    pub path: FieldPath,
+    pub value: FirestoreType,
+}
+
+impl FieldAssignment {
+    /// The dotted form of `path`, as used in document masks
+    pub fn field_path(&self) -> String {
+        self.path.to_string()
+    }
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidArgument { reason }
+}
+
+/// Reads an RFC 3339 time, or a date which is taken at midnight UTC
+fn parse_timestamp(value: &str) -> Result<FirestoreType> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(FirestoreType::Timestamp(time.with_timezone(&Utc)));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| FirestoreType::Timestamp(Utc.from_utc_datetime(&time)))
+        .ok_or_else(|| {
+            invalid(format!(
+                "{} is not a timestamp, expected a date or a time such as 1990-01-01T12:00:00Z",
+                value
+            ))
+        })
+}
+
+/// Reads a point written as `latitude,longitude`
+fn parse_geopoint(value: &str) -> Result<FirestoreType> {
+    let mut coordinates = value.splitn(2, ',').map(|part| part.trim().parse::<f64>());
+    match (coordinates.next(), coordinates.next()) {
+        (Some(Ok(latitude)), Some(Ok(longitude)))
+            if latitude.abs() <= 90.0 && longitude.abs() <= 180.0 =>
+        {
+            Ok(FirestoreType::GeoLocation(GeoPoint {
+                latitude,
+                longitude,
+            }))
+        }
+        _ => Err(invalid(format!(
+            "{} is not a geo point, expected latitude,longitude such as 30.2,-97.7",
+            value
+        ))),
+    }
+}
+
+/// Reads the path of a document, which is resolved within the database written to,
+/// or the full resource name of a document
+fn parse_reference(value: &str) -> Result<FirestoreType> {
+    let path = value.trim_start_matches('/');
+    let segments = path.split('/').collect::<Vec<&str>>();
+    if path.starts_with("projects/") || (segments.len() % 2 == 0 && !segments.contains(&"")) {
+        return Ok(FirestoreType::Reference(path.to_string()));
+    }
+    Err(invalid(format!(
+        "{} is not the path of a document, such as users/bob",
+        value
+    )))
+}
+
+/// Reads base64 encoded bytes, optionally prefixed with `base64:`
+fn parse_bytes(value: &str) -> Result<FirestoreType> {
+    let encoded = value.trim_start_matches("base64:");
+    match base64::decode(encoded) {
+        Ok(_) => Ok(FirestoreType::Bytes(encoded.to_string())),
+        Err(_) => Err(invalid(format!("{} is not valid base64", encoded))),
+    }
+}
+
+/// Converts the textual `value` of an assignment into the Firestore type named by `kind`
+pub fn parse_value(kind: &str, value: &str) -> Result<FirestoreType> {
+    match kind {
+        "str" | "string" => Ok(FirestoreType::String(value.to_string())),
+        "int" | "integer" => value
+            .parse()
+            .map(FirestoreType::Integer)
+            .map_err(|_| invalid(format!("{} is not an integer", value))),
+        "double" | "float" => value
+            .parse()
+            .map(FirestoreType::Double)
+            .map_err(|_| invalid(format!("{} is not a double", value))),
+        "bool" | "boolean" => value
+            .parse()
+            .map(FirestoreType::Boolean)
+            .map_err(|_| invalid(format!("{} is not a boolean", value))),
+        "null" => Ok(FirestoreType::Null(())),
+        "timestamp" | "time" => parse_timestamp(value),
+        "geopoint" | "geo" => parse_geopoint(value),
+        "reference" | "ref" => parse_reference(value),
+        "bytes" => parse_bytes(value),
+        "json" => Ok(FirestoreType::from(serde_json::from_str::<
+            serde_json::Value,
+        >(value)?)),
+        _ => Err(invalid(format!("unknown field type {}", kind))),
+    }
+}
+
+/// Splits the `path[:type]` left of an assignment into its path and its type
+fn parse_target(target: &str) -> Result<(FieldPath, Option<&str>)> {
+    // a colon within a backticked key is part of the key
+    let colon = target
+        .rfind(':')
+        .filter(|&colon| !target[colon..].contains('`'));
+    let (path, kind) = match colon {
+        Some(colon) => (&target[..colon], Some(&target[colon + 1..])),
+        None => (target, None),
+    };
+    Ok((FieldPath::parse(path)?, kind))
+}
+
+/// Fails for paths to elements of arrays, which only assignments write
+fn whole_field(path: FieldPath) -> Result<FieldPath> {
+    if path.has_index() {
+        return Err(invalid(format!(
+            "{} is an element of an array, which can only be assigned",
+            path
+        )));
+    }
+    Ok(path)
+}
+
+/// Parses the path of a field written as a whole, which cannot be an element of an array
+pub fn parse_field_path(input: &str) -> Result<FieldPath> {
+    whole_field(FieldPath::parse(input)?)
+}
+
+/// Parses an assignment of the form `path[:type]=value`.
+/// Values without a type are written as strings.
+pub fn parse_assignment(input: &str) -> Result<FieldAssignment> {
+    let eq = input
+        .find('=')
+        .ok_or_else(|| invalid(format!("{} is missing a value, expected path=value", input)))?;
+    let (target, value) = (&input[..eq], &input[eq + 1..]);
+    let (path, kind) = parse_target(target)?;
+    Ok(FieldAssignment {
+        path,
+        value: parse_value(kind.unwrap_or("string"), value)?,
+    })
+}
+
+/// Parses an increment of the form `path[:type]+=value`, `None` if `input` is
+/// a plain assignment. Values without a type are integers unless they have a fraction.
+pub fn parse_increment(input: &str) -> Result<Option<FieldAssignment>> {
+    let eq = match input.find('=') {
+        Some(eq) if input[..eq].ends_with('+') => eq,
+        _ => return Ok(None),
+    };
+    let (target, value) = (&input[..eq - 1], &input[eq + 1..]);
+    let (path, kind) = parse_target(target)?;
+    let path = whole_field(path)?;
+    let kind = kind.unwrap_or_else(|| {
+        if value.parse::<i64>().is_ok() {
+            "int"
+        } else {
+            "double"
+        }
+    });
+    Ok(Some(FieldAssignment {
+        path,
+        value: parse_value(kind, value)?,
+    }))
+}
+
+/// Parses the dotted path of a field to delete
+pub fn parse_deletion(input: &str) -> Result<String> {
+    Ok(parse_field_path(input)?.to_string())
+}
+
+/// Groups the values of `path[:type]=value` assignments by their path,
+/// in the order the paths first appear
+pub fn group_elements(inputs: &[String]) -> Result<Vec<(String, Vec<FirestoreType>)>> {
+    let mut groups: Vec<(String, Vec<FirestoreType>)> = Vec::new();
+    for input in inputs {
+        let assignment = parse_assignment(input)?;
+        let field_path = whole_field(assignment.path)?.to_string();
+        match groups.iter_mut().find(|(path, _)| *path == field_path) {
+            Some((_, values)) => values.push(assignment.value),
+            None => groups.push((field_path, vec![assignment.value])),
+        }
+    }
+    Ok(groups)
+}
+
+/// Folds assignments into a document body along with the mask of paths they touch,
+/// creating intermediate maps along the way. Assignments to elements of arrays are
+/// written with `assign_elements` instead.
+pub fn into_fields(assignments: Vec<FieldAssignment>) -> (FirestoreFields, Vec<String>) {
+    let mut fields = FirestoreFields::default();
+    let mut field_paths = Vec::new();
+    for assignment in assignments {
+        field_paths.push(assignment.field_path());
+        assignment
+            .path
+            .set(&mut fields, assignment.value)
+            .expect("paths without indices are always set");
+    }
+    (fields, field_paths)
+}
+
+/// Applies assignments to elements of arrays to the `current` fields of a document,
+/// adding the arrays they are in to `fields` and `field_paths` to be written whole
+pub fn assign_elements(
+    current: &FirestoreFields,
+    elements: &[FieldAssignment],
+    fields: &mut FirestoreFields,
+    field_paths: &mut Vec<String>,
+) -> Result<()> {
+    let mut current = current.clone();
+    for element in elements {
+        element.path.set(&mut current, element.value.clone())?;
+    }
+    for element in elements {
+        let array_path = element.path.array_path();
+        let field_path = array_path.to_string();
+        if field_paths.contains(&field_path) {
+            continue;
+        }
+        let array = array_path
+            .get(&current)
+            .expect("elements are only set in existing arrays");
+        array_path.set(fields, array.clone())?;
+        field_paths.push(field_path);
+    }
+    Ok(())
+}
@@ -1,72 +1,481 @@
 // This file contains 1:1 representations of the REST APIs firestore provides
 
 use super::errors::{Error, Result};
-use reqwest::header::HeaderMap;
+use log::Level;
+use reqwest::header::{HeaderMap, AUTHORIZATION};
+use reqwest::{RequestBuilder, Response};
+use std::time::Instant;
 
-const FIRESTORE_BASE_1BETA2: &'static str = "https://firestore.googleapis.com/v1beta2";
+pub const FIRESTORE_BASE_1: &'static str = "https://firestore.googleapis.com/v1";
 
-/// Contains 1:1 representations of gRPC firestore types
-mod types {
-    use serde::Deserialize;
-    use std::collections::HashMap;
+/// The `error` object of a failed response
+#[derive(Deserialize)]
+struct ErrorStatus {
+    message: String,
+    status: String,
+}
 
-    #[derive(Deserialize)]
-    pub struct Metadata {
-        #[serde(flatten)]
-        data: HashMap<String, String>,
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorStatus,
+}
+
+/// Turns unsuccessful responses into the error their status describes
+pub trait CheckStatus: Sized {
+    fn check_status(self) -> Result<Self>;
+}
+
+/// Builds the error described by the body of an unsuccessful response
+fn error_from_body(code: reqwest::StatusCode, body: String) -> Error {
+    // streaming methods such as runQuery wrap their error in an array
+    let error = serde_json::from_str::<ErrorBody>(&*body).or_else(|_| {
+        serde_json::from_str::<Vec<ErrorBody>>(&*body)
+            .map_err(|_| ())
+            .and_then(|mut errors| errors.pop().ok_or(()))
+    });
+    match error {
+        Ok(ErrorBody { error }) => Error::from_status(&*error.status, error.message),
+        Err(_) => Error::from_http_status(code.as_u16(), body),
     }
+}
 
-    #[derive(Deserialize)]
-    pub struct Detail {
-        #[serde(rename = "@type")]
-        kind: String,
-        #[serde(flatten)]
-        data: HashMap<String, String>, // TODO(hazebooth): transform
+impl CheckStatus for Response {
+    fn check_status(mut self) -> Result<Response> {
+        let code = self.status();
+        if code.is_success() {
+            return Ok(self);
+        }
+        Err(error_from_body(code, self.text().unwrap_or_default()))
     }
+}
+
+/// Keys of JSON payloads holding secrets, whose values are left out of logs
+const SECRET_KEYS: &[&str] = &[
+    "SecretAccessKey",
+    "Token",
+    "accessToken",
+    "access_token",
+    "assertion",
+    "client_secret",
+    "id_token",
+    "private_key",
+    "refresh_token",
+    "subject_token",
+];
+
+/// Replaces the values of `SECRET_KEYS` anywhere in `value`
+fn redact_value(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SECRET_KEYS.contains(&&**key) {
+                    *value = serde_json::Value::from("<redacted>");
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+/// A payload fit for logging, with secrets redacted when it is JSON
+fn redact(payload: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(payload) {
+        Ok(mut value) => {
+            redact_value(&mut value);
+            value.to_string()
+        }
+        Err(_) => payload.to_string(),
+    }
+}
+
+/// Sending of requests with a debug line per request giving its method, path, status
+/// and latency, and at trace level the payloads going both ways
+pub trait Logged: Sized {
+    /// Sets `body` as the JSON body of the request, logging it at trace level
+    fn json_logged<T: serde::Serialize + ?Sized>(self, body: &T) -> Self;
+    fn send_logged(self) -> Result<Response>;
+}
+
+impl Logged for RequestBuilder {
+    fn json_logged<T: serde::Serialize + ?Sized>(self, body: &T) -> RequestBuilder {
+        if log_enabled!(Level::Trace) {
+            if let Ok(payload) = serde_json::to_string(body) {
+                trace!("request body: {}", redact(&*payload));
+            }
+        }
+        self.json(body)
+    }
+
+    fn send_logged(self) -> Result<Response> {
+        let request = self.try_clone().and_then(|request| request.build().ok());
+        let summary = match &request {
+            Some(request) => {
+                let url = request.url();
+                match url.query() {
+                    Some(query) => format!("{} {}?{}", request.method(), url.path(), query),
+                    None => format!("{} {}", request.method(), url.path()),
+                }
+            }
+            None => String::from("request"),
+        };
+        if let Some(request) = &request {
+            for (name, value) in request.headers() {
+                if name == AUTHORIZATION {
+                    trace!("request header {}: <redacted>", name);
+                } else {
+                    trace!("request header {}: {:?}", name, value);
+                }
+            }
+        }
+        let started = Instant::now();
+        let response = self.send();
+        let elapsed = started.elapsed();
+        let millis = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+        let mut response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("{} failed after {}ms: {}", summary, millis, e);
+                return Err(Error::from(e));
+            }
+        };
+        debug!("{} {} in {}ms", summary, response.status(), millis);
+        if !log_enabled!(Level::Trace) {
+            return Ok(response);
+        }
+        // the body can only be read once, so the response is rebuilt around it
+        let (status, headers) = (response.status(), response.headers().clone());
+        let body = response.text()?;
+        trace!("response body: {}", redact(&*body));
+        let mut logged = http::Response::new(body);
+        *logged.status_mut() = status;
+        *logged.headers_mut() = headers;
+        Ok(Response::from(logged))
+    }
+}
+
+/// Contains 1:1 representations of gRPC firestore types
+pub mod types {
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
 
     /// https://firebase.google.com/docs/firestore/reference/rest/Shared.Types/Operation#Status
-    #[derive(Deserialize)]
+    #[derive(Debug, Serialize, Deserialize)]
     pub struct Status {
-        code: i32,
-        message: String,
-        details: Vec<Detail>,
+        pub code: i32,
+        #[serde(default)]
+        pub message: String,
+        #[serde(default)]
+        pub details: Vec<Value>,
     }
 
     /// https://firebase.google.com/docs/firestore/reference/rest/Shared.Types/Operation
-    /// N.B. T is the response type, see `response` field for error
-    #[derive(Deserialize)]
-    pub struct Operation<T> {
-        name: String,
-        metadata: Metadata,
-        done: bool,
-        error: Option<Status>,
-        response: Option<T>,
+    /// N.B. T is the response type, see `error` field for a failure
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Operation<T = Value> {
+        pub name: String,
+        /// Progress of the operation, whose fields depend on what it does
+        #[serde(default)]
+        pub metadata: Value,
+        #[serde(default)]
+        pub done: bool,
+        pub error: Option<Status>,
+        pub response: Option<T>,
     }
 
     /// Represents `google.protobuf.Empty`
-    #[derive(Deserialize)]
-    pub struct EmptyResponse;
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct EmptyResponse {}
+}
+
+/// Contains 1:1 representations of the gRPC `StructuredQuery` type
+/// https://firebase.google.com/docs/firestore/reference/rest/v1/StructuredQuery
+pub mod query {
+    use crate::api::FirestoreType;
+
+    #[derive(Clone, Serialize)]
+    pub struct CollectionSelector {
+        #[serde(rename = "collectionId")]
+        pub collection_id: String,
+        #[serde(rename = "allDescendants")]
+        pub all_descendants: bool,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct FieldReference {
+        #[serde(rename = "fieldPath")]
+        pub field_path: String,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum FieldOperator {
+        LessThan,
+        LessThanOrEqual,
+        GreaterThan,
+        GreaterThanOrEqual,
+        Equal,
+        NotEqual,
+        ArrayContains,
+        In,
+        ArrayContainsAny,
+        NotIn,
+    }
+
+    #[derive(Clone, Serialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum UnaryOperator {
+        IsNan,
+        IsNull,
+        IsNotNan,
+        IsNotNull,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub enum CompositeOperator {
+        #[serde(rename = "AND")]
+        And,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct FieldFilter {
+        pub field: FieldReference,
+        pub op: FieldOperator,
+        pub value: FirestoreType,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct UnaryFilter {
+        pub op: UnaryOperator,
+        pub field: FieldReference,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct CompositeFilter {
+        pub op: CompositeOperator,
+        pub filters: Vec<Filter>,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub enum Filter {
+        #[serde(rename = "compositeFilter")]
+        Composite(CompositeFilter),
+        #[serde(rename = "fieldFilter")]
+        Field(FieldFilter),
+        #[serde(rename = "unaryFilter")]
+        Unary(UnaryFilter),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum Direction {
+        Ascending,
+        Descending,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct Order {
+        pub field: FieldReference,
+        pub direction: Direction,
+    }
+
+    /// A position in the results of a query, given by the values of its orderings
+    #[derive(Clone, Serialize)]
+    pub struct Cursor {
+        pub values: Vec<FirestoreType>,
+        /// Whether the position is just before the values rather than just after them
+        pub before: bool,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct Projection {
+        pub fields: Vec<FieldReference>,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct StructuredQuery {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub select: Option<Projection>,
+        pub from: Vec<CollectionSelector>,
+        #[serde(rename = "where")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub filter: Option<Filter>,
+        #[serde(rename = "orderBy")]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub order_by: Vec<Order>,
+        #[serde(rename = "startAt")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub start_at: Option<Cursor>,
+        #[serde(rename = "endAt")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub end_at: Option<Cursor>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub offset: Option<i32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub limit: Option<i32>,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct Count {
+        /// Stops counting once this many documents matched
+        #[serde(rename = "upTo")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub up_to: Option<i64>,
+    }
+
+    /// Operand of the `sum` and `avg` aggregations
+    #[derive(Clone, Serialize)]
+    pub struct FieldAggregation {
+        pub field: FieldReference,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub enum AggregationOperator {
+        #[serde(rename = "count")]
+        Count(Count),
+        #[serde(rename = "sum")]
+        Sum(FieldAggregation),
+        #[serde(rename = "avg")]
+        Average(FieldAggregation),
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct Aggregation {
+        /// Name of the result in the `aggregateFields` of the response
+        pub alias: String,
+        #[serde(flatten)]
+        pub operator: AggregationOperator,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct StructuredAggregationQuery {
+        #[serde(rename = "structuredQuery")]
+        pub structured_query: StructuredQuery,
+        pub aggregations: Vec<Aggregation>,
+    }
+}
+
+/// Contains 1:1 representations of the gRPC `Write` type
+/// https://firebase.google.com/docs/firestore/reference/rest/v1/Write
+pub mod write {
+    use crate::api::{Array, DocumentMask, FirestoreFields, FirestoreType};
+    use chrono::{DateTime, Utc};
+
+    /// The document body of an `update` write
+    #[derive(Clone, Serialize)]
+    pub struct Document {
+        /// Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/documents/{document_path}.
+        pub name: String,
+        pub fields: FirestoreFields,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub enum Operation {
+        #[serde(rename = "update")]
+        Update(Document),
+        #[serde(rename = "delete")]
+        Delete(String),
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/Precondition
+    #[derive(Clone, Serialize, Default)]
+    pub struct Precondition {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub exists: Option<bool>,
+        #[serde(rename = "updateTime")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub update_time: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub enum ServerValue {
+        #[serde(rename = "REQUEST_TIME")]
+        RequestTime,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/Write#FieldTransform
+    #[derive(Clone, Serialize)]
+    pub enum TransformType {
+        #[serde(rename = "setToServerValue")]
+        SetToServerValue(ServerValue),
+        #[serde(rename = "increment")]
+        Increment(FirestoreType),
+        #[serde(rename = "maximum")]
+        Maximum(FirestoreType),
+        #[serde(rename = "minimum")]
+        Minimum(FirestoreType),
+        #[serde(rename = "appendMissingElements")]
+        AppendMissingElements(Array),
+        #[serde(rename = "removeAllFromArray")]
+        RemoveAllFromArray(Array),
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct FieldTransform {
+        #[serde(rename = "fieldPath")]
+        pub field_path: String,
+        #[serde(flatten)]
+        pub transform: TransformType,
+    }
+
+    #[derive(Clone, Serialize)]
+    pub struct Write {
+        #[serde(flatten)]
+        pub operation: Operation,
+        #[serde(rename = "updateMask")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub update_mask: Option<DocumentMask>,
+        /// Applied after `operation`, only allowed along with an update
+        #[serde(rename = "updateTransforms")]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub update_transforms: Vec<FieldTransform>,
+        #[serde(rename = "currentDocument")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub current_document: Option<Precondition>,
+    }
+
+    impl Write {
+        /// Resource name of the document written to
+        pub fn document_name(&self) -> &str {
+            match &self.operation {
+                Operation::Update(document) => &document.name,
+                Operation::Delete(name) => name,
+            }
+        }
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/WriteResult
+    #[derive(Debug, Deserialize)]
+    pub struct WriteResult {
+        #[serde(rename = "updateTime")]
+        pub update_time: Option<DateTime<Utc>>,
+    }
 }
 
 pub mod databases {
-    use super::types::{EmptyResponse, Operation};
-    use super::{Error, HeaderMap, Result};
+    use super::types::Operation;
+    use super::{CheckStatus, Error, HeaderMap, Logged, Result};
     use reqwest::Client;
-    use snafu::ResultExt;
 
     /// Represents the input parameters for `export_documents`
     pub struct ExportDocumentQuery {
         /// Database to export. Should be of the form:
         /// projects/{project_id}/databases/{database_id}.
-        database_name: String,
-        collection_ids: Option<Vec<String>>,
-        output_uri_prefix: String,
+        pub database_name: String,
+        /// Every collection when empty
+        pub collection_ids: Vec<String>,
+        /// Cloud Storage location, such as gs://bucket/path
+        pub output_uri_prefix: String,
     }
 
     #[derive(Serialize)]
     struct ExportDocumentBody {
         #[serde(rename = "collectionIds")]
-        collection_ids: Option<Vec<String>>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        collection_ids: Vec<String>,
         #[serde(rename = "outputUriPrefix")]
         output_uri_prefix: String,
     }
@@ -82,34 +491,33 @@ pub mod databases {
         }
     }
 
-    /// https://firebase.google.com/docs/firestore/reference/rest/v1beta2/projects.databases/exportDocuments
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases/exportDocuments
     pub fn export_documents(
         client: Client,
+        base_url: &str,
         headers: HeaderMap,
         params: ExportDocumentQuery,
-    ) -> Result<Operation<EmptyResponse>> {
-        fn make_url(name: &str) -> String {
-            format!(
-                "{}/{{name={}}}:exportDocuments",
-                super::FIRESTORE_BASE_1BETA2,
-                name
-            )
-        }
-        // setup parameters
-        let database_name = &*params.database_name;
-        let url = &*make_url(database_name);
+    ) -> Result<Operation> {
+        let url = format!("{}/{}:exportDocuments", base_url, params.database_name);
         let request_body = params.into_body();
-        // send request
-        let mut response = client.post(url).headers(headers).send()?;
-        response
-            .json::<Operation<EmptyResponse>>()
-            .map_err(Error::from)
+        let mut response = client
+            .post(&*url)
+            .headers(headers)
+            .json_logged(&request_body)
+            .send_logged()?
+            .check_status()?;
+        response.json::<Operation>().map_err(Error::from)
     }
 
+    /// Represents the input parameters for `import_documents`
     pub struct ImportDocumentQuery {
-        database_name: String,
-        collection_ids: Vec<String>,
-        input_uri_prefix: String,
+        /// Database to import into. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}.
+        pub database_name: String,
+        /// Every collection of the export when empty
+        pub collection_ids: Vec<String>,
+        /// Location of a completed export, its `outputUriPrefix`
+        pub input_uri_prefix: String,
     }
 
     impl ImportDocumentQuery {
@@ -128,6 +536,7 @@ pub mod databases {
     struct ImportDocumentBody {
         /// Which collection ids to import. Unspecified means all collections included in the import.
         #[serde(rename = "collectionIds")]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
         collection_ids: Vec<String>,
 
         /// Location of the exported files. This must match the
@@ -136,28 +545,1171 @@ pub mod databases {
         input_uri_prefix: String,
     }
 
-    /// https://firebase.google.com/docs/firestore/reference/rest/v1beta2/projects.databases/importDocuments
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases/importDocuments
     pub fn import_documents(
         client: Client,
+        base_url: &str,
         headers: HeaderMap,
         params: ImportDocumentQuery,
-    ) -> Result<Operation<EmptyResponse>> {
-        fn make_url(name: &str) -> String {
-            format!(
-                "{}/{{name={}}}:importDocuments",
-                super::FIRESTORE_BASE_1BETA2,
-                name
-            )
-        }
-        // setup parameters
-        let database_name = &*params.database_name;
-        let url = &*make_url(database_name);
+    ) -> Result<Operation> {
+        let url = format!("{}/{}:importDocuments", base_url, params.database_name);
         let request_body = params.into_body();
-        // send request
-        let mut response = client.post(url).headers(headers).send()?;
+        let mut response = client
+            .post(&*url)
+            .headers(headers)
+            .json_logged(&request_body)
+            .send_logged()?
+            .check_status()?;
+        response.json::<Operation>().map_err(Error::from)
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases#Database
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct Database {
+        /// Should be of the form:
+        /// projects/{project_id}/databases/{database_id}.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub name: Option<String>,
+        /// Such as `nam5` or `europe-west1`
+        #[serde(rename = "locationId")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub location_id: Option<String>,
+        /// `FIRESTORE_NATIVE` or `DATASTORE_MODE`
+        #[serde(rename = "type")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub kind: Option<String>,
+        /// `DELETE_PROTECTION_ENABLED` or `DELETE_PROTECTION_DISABLED`
+        #[serde(rename = "deleteProtectionState")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub delete_protection_state: Option<String>,
+        /// `POINT_IN_TIME_RECOVERY_ENABLED` or `POINT_IN_TIME_RECOVERY_DISABLED`
+        #[serde(rename = "pointInTimeRecoveryEnablement")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub point_in_time_recovery_enablement: Option<String>,
+        /// How far back documents can be read, such as `3600s`, only set by the server
+        #[serde(rename = "versionRetentionPeriod")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub version_retention_period: Option<String>,
+        #[serde(rename = "createTime")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub create_time: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ListDatabasesResponse {
+        #[serde(default)]
+        pub databases: Vec<Database>,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases/list
+    pub fn list_databases(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        project_id: &str,
+    ) -> Result<ListDatabasesResponse> {
+        let url = format!("{}/projects/{}/databases", base_url, project_id);
+        let mut response = client
+            .get(&*url)
+            .headers(headers)
+            .send_logged()?
+            .check_status()?;
+        response
+            .json::<ListDatabasesResponse>()
+            .map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `create_database`
+    pub struct CreateDatabaseQuery {
+        pub project_id: String,
+        /// Lowercase letters, digits and hyphens, such as `staging`
+        pub database_id: String,
+        pub database: Database,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases/create
+    pub fn create_database(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: CreateDatabaseQuery,
+    ) -> Result<Operation> {
+        let url = format!("{}/projects/{}/databases", base_url, params.project_id);
+        let mut response = client
+            .post(&*url)
+            .headers(headers)
+            .query(&[("databaseId", params.database_id)])
+            .json_logged(&params.database)
+            .send_logged()?
+            .check_status()?;
+        response.json::<Operation>().map_err(Error::from)
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases/delete
+    pub fn delete_database(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        name: &str,
+    ) -> Result<Operation> {
+        let url = format!("{}/{}", base_url, name);
+        let mut response = client
+            .delete(&*url)
+            .headers(headers)
+            .send_logged()?
+            .check_status()?;
+        response.json::<Operation>().map_err(Error::from)
+    }
+}
+
+pub mod operations {
+    use super::types::Operation;
+    use super::{CheckStatus, Error, HeaderMap, Logged, Result};
+    use reqwest::Client;
+
+    /// Represents the input parameters for `list`
+    pub struct ListOperationsQuery {
+        /// Database whose operations are listed. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}.
+        pub database_name: String,
+        pub page_token: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ListOperationsResponse {
+        #[serde(default)]
+        pub operations: Vec<Operation>,
+        #[serde(rename = "nextPageToken")]
+        pub next_page_token: Option<String>,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.operations/list
+    pub fn list(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: ListOperationsQuery,
+    ) -> Result<ListOperationsResponse> {
+        let url = format!("{}/{}/operations", base_url, params.database_name);
+        let query = params
+            .page_token
+            .map(|page_token| vec![("pageToken", page_token)])
+            .unwrap_or_default();
+        let mut response = client
+            .get(&*url)
+            .headers(headers)
+            .query(&query)
+            .send_logged()?
+            .check_status()?;
         response
-            .json::<Operation<EmptyResponse>>()
+            .json::<ListOperationsResponse>()
             .map_err(Error::from)
     }
 
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.operations/get
+    pub fn get(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        name: &str,
+    ) -> Result<Operation> {
+        let url = format!("{}/{}", base_url, name);
+        let mut response = client
+            .get(&*url)
+            .headers(headers)
+            .send_logged()?
+            .check_status()?;
+        response.json::<Operation>().map_err(Error::from)
+    }
+}
+
+pub mod indexes {
+    use super::types::Operation;
+    use super::{CheckStatus, Error, HeaderMap, Logged, Result};
+    use reqwest::Client;
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.collectionGroups.indexes#IndexField
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct IndexField {
+        #[serde(rename = "fieldPath")]
+        pub field_path: String,
+        /// `ASCENDING` or `DESCENDING`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub order: Option<String>,
+        /// `CONTAINS`
+        #[serde(rename = "arrayConfig")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub array_config: Option<String>,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.collectionGroups.indexes#Index
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Index {
+        /// Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/collectionGroups/{collection_id}/indexes/{index_id}.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub name: Option<String>,
+        #[serde(rename = "queryScope")]
+        pub query_scope: String,
+        #[serde(default)]
+        pub fields: Vec<IndexField>,
+        /// Such as `CREATING` or `READY`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub state: Option<String>,
+    }
+
+    /// Represents the input parameters for `list`
+    pub struct ListIndexesQuery {
+        /// Collection group whose indexes are listed, `-` for every group. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/collectionGroups/{collection_id}.
+        pub parent: String,
+        pub page_token: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ListIndexesResponse {
+        #[serde(default)]
+        pub indexes: Vec<Index>,
+        #[serde(rename = "nextPageToken")]
+        pub next_page_token: Option<String>,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.collectionGroups.indexes/list
+    pub fn list(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: ListIndexesQuery,
+    ) -> Result<ListIndexesResponse> {
+        let url = format!("{}/{}/indexes", base_url, params.parent);
+        let query = params
+            .page_token
+            .map(|page_token| vec![("pageToken", page_token)])
+            .unwrap_or_default();
+        let mut response = client
+            .get(&*url)
+            .headers(headers)
+            .query(&query)
+            .send_logged()?
+            .check_status()?;
+        response.json::<ListIndexesResponse>().map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `create`
+    pub struct CreateIndexQuery {
+        /// Collection group the index is created for. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/collectionGroups/{collection_id}.
+        pub parent: String,
+        pub index: Index,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.collectionGroups.indexes/create
+    pub fn create(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: CreateIndexQuery,
+    ) -> Result<Operation> {
+        let url = format!("{}/{}/indexes", base_url, params.parent);
+        let mut response = client
+            .post(&*url)
+            .headers(headers)
+            .json_logged(&params.index)
+            .send_logged()?
+            .check_status()?;
+        response.json::<Operation>().map_err(Error::from)
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.collectionGroups.indexes/delete
+    pub fn delete(client: Client, base_url: &str, headers: HeaderMap, name: &str) -> Result<()> {
+        let url = format!("{}/{}", base_url, name);
+        client
+            .delete(&*url)
+            .headers(headers)
+            .send_logged()?
+            .check_status()?;
+        Ok(())
+    }
+}
+
+pub mod fields {
+    use super::types::Operation;
+    use super::{CheckStatus, Error, HeaderMap, Logged, Result};
+    use reqwest::Client;
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.collectionGroups.fields#TtlConfig
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct TtlConfig {
+        /// Such as `CREATING` or `ACTIVE`, only set by the server
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub state: Option<String>,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.collectionGroups.fields#Field
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Field {
+        /// Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/collectionGroups/{collection_id}/fields/{field_path}.
+        pub name: String,
+        /// `None` when documents do not expire by this field
+        #[serde(rename = "ttlConfig")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub ttl_config: Option<TtlConfig>,
+    }
+
+    /// Represents the input parameters for `list`
+    pub struct ListFieldsQuery {
+        /// Collection group whose fields are listed, `-` for every group. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/collectionGroups/{collection_id}.
+        pub parent: String,
+        /// Such as `ttlConfig:*`, the API only lists fields with a configuration of their own
+        pub filter: String,
+        pub page_token: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ListFieldsResponse {
+        #[serde(default)]
+        pub fields: Vec<Field>,
+        #[serde(rename = "nextPageToken")]
+        pub next_page_token: Option<String>,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.collectionGroups.fields/list
+    pub fn list(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: ListFieldsQuery,
+    ) -> Result<ListFieldsResponse> {
+        let url = format!("{}/{}/fields", base_url, params.parent);
+        let mut query = vec![("filter", params.filter)];
+        if let Some(page_token) = params.page_token {
+            query.push(("pageToken", page_token));
+        }
+        let mut response = client
+            .get(&*url)
+            .headers(headers)
+            .query(&query)
+            .send_logged()?
+            .check_status()?;
+        response.json::<ListFieldsResponse>().map_err(Error::from)
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.collectionGroups.fields/patch
+    /// Only the parts of the configuration named by `update_mask`, such as `ttlConfig`, are changed
+    pub fn patch(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        field: Field,
+        update_mask: &str,
+    ) -> Result<Operation> {
+        let url = format!("{}/{}", base_url, field.name);
+        let mut response = client
+            .patch(&*url)
+            .headers(headers)
+            .query(&[("updateMask", update_mask)])
+            .json_logged(&field)
+            .send_logged()?
+            .check_status()?;
+        response.json::<Operation>().map_err(Error::from)
+    }
+}
+
+pub mod documents {
+    use super::query::{StructuredAggregationQuery, StructuredQuery};
+    use super::write::{Precondition, Write, WriteResult};
+    use super::{CheckStatus, Error, HeaderMap, Logged, Result};
+    use crate::api::{list_documents, Document, FirestoreFields};
+    use chrono::{DateTime, Utc};
+    use reqwest::{Client, Response};
+    use serde_aux::field_attributes::deserialize_number_from_string;
+
+    /// Represents the input parameters for `get`
+    pub struct GetDocumentQuery {
+        /// Document to read. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/documents/{document_path}.
+        pub name: String,
+        /// Reads the document as part of this transaction
+        pub transaction: Option<String>,
+        /// Reads the document as it was at this time, instead of a transaction
+        pub read_time: Option<DateTime<Utc>>,
+        /// Only returns these fields of the document
+        pub mask: Option<Vec<String>>,
+    }
+
+    impl GetDocumentQuery {
+        fn into_params(self) -> Vec<(&'static str, String)> {
+            let mut params = Vec::new();
+            if let Some(transaction) = self.transaction {
+                params.push(("transaction", transaction));
+            }
+            if let Some(read_time) = self.read_time {
+                params.push(("readTime", read_time.to_rfc3339()));
+            }
+            for field_path in self.mask.unwrap_or_default() {
+                params.push(("mask.fieldPaths", field_path));
+            }
+            params
+        }
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/get
+    pub fn get(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: GetDocumentQuery,
+    ) -> Result<Document> {
+        let url = format!("{}/{}", base_url, params.name);
+        let query = params.into_params();
+        let mut response = client
+            .get(&*url)
+            .headers(headers)
+            .query(&query)
+            .send_logged()?
+            .check_status()?;
+        response.json::<Document>().map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `delete`
+    pub struct DeleteDocumentQuery {
+        /// Document to delete. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/documents/{document_path}.
+        pub name: String,
+        /// The delete fails unless the document meets this precondition
+        pub current_document: Precondition,
+    }
+
+    /// Query parameters of a precondition on the document written
+    fn precondition_params(precondition: &Precondition) -> Vec<(&'static str, String)> {
+        let mut params = Vec::new();
+        if let Some(exists) = precondition.exists {
+            params.push(("currentDocument.exists", exists.to_string()));
+        }
+        if let Some(update_time) = precondition.update_time {
+            params.push(("currentDocument.updateTime", update_time.to_rfc3339()));
+        }
+        params
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/delete
+    pub fn delete(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: DeleteDocumentQuery,
+    ) -> Result<()> {
+        let url = format!("{}/{}", base_url, params.name);
+        client
+            .delete(&*url)
+            .headers(headers)
+            .query(&precondition_params(&params.current_document))
+            .send_logged()?
+            .check_status()?;
+        Ok(())
+    }
+
+    /// Represents the input parameters for `list`
+    pub struct ListDocumentsQuery {
+        /// Parent of the collection. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/documents.
+        pub parent: String,
+        pub collection_id: String,
+        pub page_size: Option<i32>,
+        pub page_token: Option<String>,
+        /// Comma separated fields to sort by, each optionally followed by ` desc`
+        pub order_by: Option<String>,
+        /// Only returns these fields of each document
+        pub mask: Option<Vec<String>>,
+        /// Lists the documents as they were at this time
+        pub read_time: Option<DateTime<Utc>>,
+    }
+
+    impl ListDocumentsQuery {
+        fn into_params(self) -> Vec<(&'static str, String)> {
+            let mut params = Vec::new();
+            if let Some(page_size) = self.page_size {
+                params.push(("pageSize", page_size.to_string()));
+            }
+            if let Some(page_token) = self.page_token {
+                params.push(("pageToken", page_token));
+            }
+            if let Some(order_by) = self.order_by {
+                params.push(("orderBy", order_by));
+            }
+            if let Some(read_time) = self.read_time {
+                params.push(("readTime", read_time.to_rfc3339()));
+            }
+            for field_path in self.mask.unwrap_or_default() {
+                params.push(("mask.fieldPaths", field_path));
+            }
+            params
+        }
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/list
+    pub fn list(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: ListDocumentsQuery,
+    ) -> Result<list_documents::Response> {
+        let url = format!("{}/{}/{}", base_url, params.parent, params.collection_id);
+        let query = params.into_params();
+        let mut response = client
+            .get(&*url)
+            .headers(headers)
+            .query(&query)
+            .send_logged()?
+            .check_status()?;
+        response
+            .json::<list_documents::Response>()
+            .map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `patch`
+    pub struct PatchDocumentQuery {
+        /// Document to write. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/documents/{document_path}.
+        pub name: String,
+        pub fields: FirestoreFields,
+        /// Fields to update. When `None` the whole document is replaced,
+        /// otherwise only these paths are written and the rest are preserved.
+        pub update_mask: Option<Vec<String>>,
+        /// The write fails unless the document meets this precondition
+        pub current_document: Precondition,
+    }
+
+    /// Input body for `patch` and `create`
+    #[derive(Serialize)]
+    struct PatchDocumentBody {
+        fields: FirestoreFields,
+    }
+
+    impl PatchDocumentQuery {
+        fn into_body(self) -> (Vec<(&'static str, String)>, PatchDocumentBody) {
+            let mut params: Vec<(&'static str, String)> = self
+                .update_mask
+                .unwrap_or_default()
+                .into_iter()
+                .map(|path| ("updateMask.fieldPaths", path))
+                .collect();
+            params.extend(precondition_params(&self.current_document));
+            let fields = self.fields;
+            (params, PatchDocumentBody { fields })
+        }
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/patch
+    pub fn patch(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: PatchDocumentQuery,
+    ) -> Result<Document> {
+        let url = format!("{}/{}", base_url, params.name);
+        let (query, request_body) = params.into_body();
+        let mut response = client
+            .patch(&*url)
+            .headers(headers)
+            .query(&query)
+            .json_logged(&request_body)
+            .send_logged()?
+            .check_status()?;
+        response.json::<Document>().map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `create`
+    pub struct CreateDocumentQuery {
+        /// Parent of the collection. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/documents or a document below it.
+        pub parent: String,
+        pub collection_id: String,
+        /// ID of the new document, chosen by Firestore when `None`
+        pub document_id: Option<String>,
+        pub fields: FirestoreFields,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/createDocument
+    pub fn create(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: CreateDocumentQuery,
+    ) -> Result<Document> {
+        let url = format!("{}/{}/{}", base_url, params.parent, params.collection_id);
+        let query = params
+            .document_id
+            .map(|id| vec![("documentId", id)])
+            .unwrap_or_default();
+        let mut response = client
+            .post(&*url)
+            .headers(headers)
+            .query(&query)
+            .json_logged(&PatchDocumentBody {
+                fields: params.fields,
+            })
+            .send_logged()?
+            .check_status()?;
+        response.json::<Document>().map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `run_query`
+    pub struct RunQueryQuery {
+        /// Parent of the queried collections. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/documents.
+        pub parent: String,
+        pub structured_query: StructuredQuery,
+        /// Runs the query as part of this transaction
+        pub transaction: Option<String>,
+        /// Runs the query against the documents as they were at this time
+        pub read_time: Option<DateTime<Utc>>,
+        /// Asks for the plan of the query instead of or along with its results
+        pub explain_options: Option<ExplainOptions>,
+    }
+
+    /// Input body for `run_query`
+    #[derive(Serialize)]
+    struct RunQueryBody {
+        #[serde(rename = "structuredQuery")]
+        structured_query: StructuredQuery,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        transaction: Option<String>,
+        #[serde(rename = "readTime")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        read_time: Option<DateTime<Utc>>,
+        #[serde(rename = "explainOptions")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        explain_options: Option<ExplainOptions>,
+    }
+
+    impl RunQueryQuery {
+        fn into_body(self) -> RunQueryBody {
+            let structured_query = self.structured_query;
+            let transaction = self.transaction;
+            let read_time = self.read_time;
+            let explain_options = self.explain_options;
+            RunQueryBody {
+                structured_query,
+                transaction,
+                read_time,
+                explain_options,
+            }
+        }
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/ExplainOptions
+    #[derive(Clone, Serialize)]
+    pub struct ExplainOptions {
+        /// Runs the query as well, returning its results and statistics along with the plan
+        pub analyze: bool,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/ExplainMetrics
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ExplainMetrics {
+        #[serde(rename = "planSummary")]
+        #[serde(default)]
+        pub plan_summary: PlanSummary,
+        /// Only set when the query was analyzed
+        #[serde(rename = "executionStats")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub execution_stats: Option<ExecutionStats>,
+    }
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    pub struct PlanSummary {
+        /// Properties of each index, such as `query_scope` and `properties`
+        #[serde(rename = "indexesUsed")]
+        #[serde(default)]
+        pub indexes_used: Vec<serde_json::Map<String, serde_json::Value>>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ExecutionStats {
+        #[serde(rename = "resultsReturned")]
+        #[serde(default, deserialize_with = "deserialize_number_from_string")]
+        pub results_returned: i64,
+        /// Such as `0.015s`
+        #[serde(rename = "executionDuration")]
+        #[serde(default)]
+        pub execution_duration: String,
+        #[serde(rename = "readOperations")]
+        #[serde(default, deserialize_with = "deserialize_number_from_string")]
+        pub read_operations: i64,
+        /// Details such as the documents and index entries scanned
+        #[serde(rename = "debugStats")]
+        #[serde(default)]
+        pub debug_stats: serde_json::Map<String, serde_json::Value>,
+    }
+
+    /// A single entry of the `run_query` response stream
+    #[derive(Debug, Deserialize)]
+    pub struct RunQueryResponse {
+        pub document: Option<Document>,
+        #[serde(rename = "readTime")]
+        pub read_time: Option<DateTime<Utc>>,
+        #[serde(rename = "skippedResults")]
+        #[serde(default)]
+        pub skipped_results: i32,
+        /// Set on the last entry of a stream that failed after it started
+        pub error: Option<super::types::Status>,
+        /// Set on the last entry when explain options were given
+        #[serde(rename = "explainMetrics")]
+        pub explain_metrics: Option<ExplainMetrics>,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/runQuery
+    pub fn run_query(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: RunQueryQuery,
+    ) -> Result<Vec<RunQueryResponse>> {
+        let url = format!("{}/{}:runQuery", base_url, params.parent);
+        let request_body = params.into_body();
+        let mut response = client
+            .post(&*url)
+            .headers(headers)
+            .json_logged(&request_body)
+            .send_logged()?
+            .check_status()?;
+        response
+            .json::<Vec<RunQueryResponse>>()
+            .map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `run_aggregation_query`
+    pub struct RunAggregationQueryQuery {
+        /// Parent of the queried collections. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/documents.
+        pub parent: String,
+        pub structured_aggregation_query: StructuredAggregationQuery,
+        /// Runs the query as part of this transaction
+        pub transaction: Option<String>,
+        /// Runs the query against the documents as they were at this time
+        pub read_time: Option<DateTime<Utc>>,
+    }
+
+    /// Input body for `run_aggregation_query`
+    #[derive(Serialize)]
+    struct RunAggregationQueryBody {
+        #[serde(rename = "structuredAggregationQuery")]
+        structured_aggregation_query: StructuredAggregationQuery,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        transaction: Option<String>,
+        #[serde(rename = "readTime")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        read_time: Option<DateTime<Utc>>,
+    }
+
+    impl RunAggregationQueryQuery {
+        fn into_body(self) -> RunAggregationQueryBody {
+            RunAggregationQueryBody {
+                structured_aggregation_query: self.structured_aggregation_query,
+                transaction: self.transaction,
+                read_time: self.read_time,
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct AggregationResult {
+        /// The result of every aggregation, keyed by its alias
+        #[serde(rename = "aggregateFields")]
+        #[serde(default)]
+        pub aggregate_fields: FirestoreFields,
+    }
+
+    /// A single entry of the `run_aggregation_query` response stream
+    #[derive(Debug, Deserialize)]
+    pub struct RunAggregationQueryResponse {
+        pub result: Option<AggregationResult>,
+        #[serde(rename = "readTime")]
+        pub read_time: Option<DateTime<Utc>>,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/runAggregationQuery
+    pub fn run_aggregation_query(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: RunAggregationQueryQuery,
+    ) -> Result<Vec<RunAggregationQueryResponse>> {
+        let url = format!("{}/{}:runAggregationQuery", base_url, params.parent);
+        let request_body = params.into_body();
+        let mut response = client
+            .post(&*url)
+            .headers(headers)
+            .json_logged(&request_body)
+            .send_logged()?
+            .check_status()?;
+        response
+            .json::<Vec<RunAggregationQueryResponse>>()
+            .map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `commit`
+    pub struct CommitQuery {
+        /// Database to write to. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}.
+        pub database: String,
+        pub writes: Vec<Write>,
+        /// Commits this transaction along with the writes
+        pub transaction: Option<String>,
+    }
+
+    /// Input body for `commit`
+    #[derive(Serialize)]
+    struct CommitBody {
+        writes: Vec<Write>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        transaction: Option<String>,
+    }
+
+    impl CommitQuery {
+        fn into_body(self) -> CommitBody {
+            let writes = self.writes;
+            let transaction = self.transaction;
+            CommitBody {
+                writes,
+                transaction,
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct CommitResponse {
+        #[serde(rename = "writeResults")]
+        #[serde(default)]
+        pub write_results: Vec<WriteResult>,
+        #[serde(rename = "commitTime")]
+        pub commit_time: DateTime<Utc>,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/commit
+    pub fn commit(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: CommitQuery,
+    ) -> Result<CommitResponse> {
+        let url = format!("{}/{}/documents:commit", base_url, params.database);
+        let request_body = params.into_body();
+        let mut response = client
+            .post(&*url)
+            .headers(headers)
+            .json_logged(&request_body)
+            .send_logged()?
+            .check_status()?;
+        response.json::<CommitResponse>().map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `list_collection_ids`
+    pub struct ListCollectionIdsQuery {
+        /// Document whose subcollections are listed. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}/documents/{document_path}.
+        pub parent: String,
+        pub page_size: Option<i32>,
+        pub page_token: Option<String>,
+        /// Lists the collections as they were at this time
+        pub read_time: Option<DateTime<Utc>>,
+    }
+
+    /// Input body for `list_collection_ids`
+    #[derive(Serialize)]
+    struct ListCollectionIdsBody {
+        #[serde(rename = "pageSize")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        page_size: Option<i32>,
+        #[serde(rename = "pageToken")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        page_token: Option<String>,
+        #[serde(rename = "readTime")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        read_time: Option<DateTime<Utc>>,
+    }
+
+    impl ListCollectionIdsQuery {
+        fn into_body(self) -> ListCollectionIdsBody {
+            let page_size = self.page_size;
+            let page_token = self.page_token;
+            let read_time = self.read_time;
+            ListCollectionIdsBody {
+                page_size,
+                page_token,
+                read_time,
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ListCollectionIdsResponse {
+        #[serde(rename = "collectionIds")]
+        #[serde(default)]
+        pub collection_ids: Vec<String>,
+        #[serde(rename = "nextPageToken")]
+        pub next_page_token: Option<String>,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/listCollectionIds
+    pub fn list_collection_ids(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: ListCollectionIdsQuery,
+    ) -> Result<ListCollectionIdsResponse> {
+        let url = format!("{}/{}:listCollectionIds", base_url, params.parent);
+        let request_body = params.into_body();
+        let mut response = client
+            .post(&*url)
+            .headers(headers)
+            .json_logged(&request_body)
+            .send_logged()?
+            .check_status()?;
+        response
+            .json::<ListCollectionIdsResponse>()
+            .map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `begin_transaction`
+    pub struct BeginTransactionQuery {
+        /// Database to transact on. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}.
+        pub database: String,
+        /// Transaction being retried after it was aborted
+        pub retry_transaction: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct ReadWrite {
+        #[serde(rename = "retryTransaction")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        retry_transaction: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct TransactionOptions {
+        #[serde(rename = "readWrite")]
+        read_write: ReadWrite,
+    }
+
+    /// Input body for `begin_transaction`
+    #[derive(Serialize)]
+    struct BeginTransactionBody {
+        options: TransactionOptions,
+    }
+
+    impl BeginTransactionQuery {
+        fn into_body(self) -> BeginTransactionBody {
+            let retry_transaction = self.retry_transaction;
+            BeginTransactionBody {
+                options: TransactionOptions {
+                    read_write: ReadWrite { retry_transaction },
+                },
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct BeginTransactionResponse {
+        pub transaction: String,
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/beginTransaction
+    pub fn begin_transaction(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: BeginTransactionQuery,
+    ) -> Result<BeginTransactionResponse> {
+        let url = format!(
+            "{}/{}/documents:beginTransaction",
+            base_url, params.database
+        );
+        let request_body = params.into_body();
+        let mut response = client
+            .post(&*url)
+            .headers(headers)
+            .json_logged(&request_body)
+            .send_logged()?
+            .check_status()?;
+        response
+            .json::<BeginTransactionResponse>()
+            .map_err(Error::from)
+    }
+
+    /// Represents the input parameters for `rollback`
+    pub struct RollbackQuery {
+        /// Database of the transaction. Should be of the form:
+        /// projects/{project_id}/databases/{database_id}.
+        pub database: String,
+        pub transaction: String,
+    }
+
+    /// Input body for `rollback`
+    #[derive(Serialize)]
+    struct RollbackBody {
+        transaction: String,
+    }
+
+    impl RollbackQuery {
+        fn into_body(self) -> RollbackBody {
+            let transaction = self.transaction;
+            RollbackBody { transaction }
+        }
+    }
+
+    /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/rollback
+    pub fn rollback(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: RollbackQuery,
+    ) -> Result<()> {
+        let url = format!("{}/{}/documents:rollback", base_url, params.database);
+        let request_body = params.into_body();
+        client
+            .post(&*url)
+            .headers(headers)
+            .json_logged(&request_body)
+            .send_logged()?
+            .check_status()?;
+        Ok(())
+    }
+
+    /// Same request as `run_query`, but the response is returned as it is for its entries
+    /// to be read one at a time while Firestore is still sending the rest
+    pub fn run_query_stream(
+        client: Client,
+        base_url: &str,
+        headers: HeaderMap,
+        params: RunQueryQuery,
+    ) -> Result<Response> {
+        let url = format!("{}/{}:runQuery", base_url, params.parent);
+        let request_body = params.into_body();
+        // `send_logged` reads bodies in full to trace them, which would wait for every result
+        let response = client
+            .post(&*url)
+            .headers(headers)
+            .json_logged(&request_body)
+            .send()?
+            .check_status()?;
+        debug!("POST {} {}", url, response.status());
+        Ok(response)
+    }
+
+    /// Futures based counterparts of the requests above
+    #[cfg(feature = "async")]
+    pub mod nonblocking {
+        use super::super::error_from_body;
+        use super::{
+            CommitQuery, CommitResponse, Document, Error, GetDocumentQuery, HeaderMap,
+            ListDocumentsQuery, RunQueryQuery, RunQueryResponse,
+        };
+        use crate::api::list_documents;
+        use futures::future::{self, Either};
+        use futures::Future;
+        use reqwest::r#async::{Client, Response};
+
+        /// Turns unsuccessful responses into the error their status describes
+        fn check_status(mut response: Response) -> impl Future<Item = Response, Error = Error> {
+            let code = response.status();
+            if code.is_success() {
+                Either::A(future::ok(response))
+            } else {
+                Either::B(
+                    response
+                        .text()
+                        .then(move |body| Err(error_from_body(code, body.unwrap_or_default()))),
+                )
+            }
+        }
+
+        /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/get
+        pub fn get(
+            client: Client,
+            base_url: &str,
+            headers: HeaderMap,
+            params: GetDocumentQuery,
+        ) -> impl Future<Item = Document, Error = Error> {
+            let url = format!("{}/{}", base_url, params.name);
+            let query = params.into_params();
+            client
+                .get(&*url)
+                .headers(headers)
+                .query(&query)
+                .send()
+                .map_err(Error::from)
+                .and_then(check_status)
+                .and_then(|mut response| response.json::<Document>().map_err(Error::from))
+        }
+
+        /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/list
+        pub fn list(
+            client: Client,
+            base_url: &str,
+            headers: HeaderMap,
+            params: ListDocumentsQuery,
+        ) -> impl Future<Item = list_documents::Response, Error = Error> {
+            let url = format!("{}/{}/{}", base_url, params.parent, params.collection_id);
+            let query = params.into_params();
+            client
+                .get(&*url)
+                .headers(headers)
+                .query(&query)
+                .send()
+                .map_err(Error::from)
+                .and_then(check_status)
+                .and_then(|mut response| {
+                    response
+                        .json::<list_documents::Response>()
+                        .map_err(Error::from)
+                })
+        }
+
+        /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/runQuery
+        pub fn run_query(
+            client: Client,
+            base_url: &str,
+            headers: HeaderMap,
+            params: RunQueryQuery,
+        ) -> impl Future<Item = Vec<RunQueryResponse>, Error = Error> {
+            let url = format!("{}/{}:runQuery", base_url, params.parent);
+            let request_body = params.into_body();
+            client
+                .post(&*url)
+                .headers(headers)
+                .json(&request_body)
+                .send()
+                .map_err(Error::from)
+                .and_then(check_status)
+                .and_then(|mut response| {
+                    response
+                        .json::<Vec<RunQueryResponse>>()
+                        .map_err(Error::from)
+                })
+        }
+
+        /// https://firebase.google.com/docs/firestore/reference/rest/v1/projects.databases.documents/commit
+        pub fn commit(
+            client: Client,
+            base_url: &str,
+            headers: HeaderMap,
+            params: CommitQuery,
+        ) -> impl Future<Item = CommitResponse, Error = Error> {
+            let url = format!("{}/{}/documents:commit", base_url, params.database);
+            let request_body = params.into_body();
+            client
+                .post(&*url)
+                .headers(headers)
+                .json(&request_body)
+                .send()
+                .map_err(Error::from)
+                .and_then(check_status)
+                .and_then(|mut response| response.json::<CommitResponse>().map_err(Error::from))
+        }
+    }
 }
@@ -0,0 +1,65 @@
+// Commands run for each change seen by `watch --exec`, such as './notify.sh {path}'.
+// `{path}`, `{id}` and `{change}` are replaced by the document path, its ID and the kind
+// of change, quoted for the shell. The document is written to the command's stdin as JSON,
+// `null` once it is removed, and the same values are set as FIRESALE_* variables.
+
+use libfiresale::errors::Result;
+use std::io::Write;
+use std::process::{Command, ExitStatus, Stdio};
+
+/// A command given with `--exec`
+#[derive(Debug, Clone)]
+pub struct Hook {
+    command: String,
+}
+
+/// Quotes `value` as a single word for the shell
+fn quote(value: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+impl Hook {
+    pub fn new(command: String) -> Hook {
+        Hook { command }
+    }
+
+    /// Runs the command for a change to the document at `path`, waiting for it to exit
+    pub fn run(
+        &self,
+        change: &str,
+        path: &str,
+        document: &serde_json::Value,
+    ) -> Result<ExitStatus> {
+        let id = path.rsplit('/').next().unwrap_or(path);
+        let line = self
+            .command
+            .replace("{path}", &quote(path))
+            .replace("{id}", &quote(id))
+            .replace("{change}", &quote(change));
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+        let mut child = command
+            .arg(line)
+            .env("FIRESALE_PATH", path)
+            .env("FIRESALE_ID", id)
+            .env("FIRESALE_CHANGE", change)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // a command that exits without reading its input is not a failure
+            let _ = writeln!(stdin, "{}", document);
+        }
+        Ok(child.wait()?)
+    }
+}
@@ -0,0 +1,114 @@
+// How the IDs of documents written in bulk, such as by `import` and `seed`, are chosen:
+// auto IDs like those Firestore generates, UUIDs, ULIDs, which sort by the time they were
+// made, or a hash of a field such as a natural key, so that writing the same record again
+// writes the same document instead of a new one.
+
+use crate::api::{auto_id, FirestoreFields, FirestoreType};
+use crate::errors::{Error, Result};
+use crate::fieldpath::FieldPath;
+use chrono::Utc;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+/// Digits of ULIDs, Crockford's base 32 without I, L, O and U
+const CROCKFORD: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Characters of a ULID, 48 bits of time followed by 80 random bits
+const ULID_LENGTH: usize = 26;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdStrategy {
+    /// 20 random letters and digits, see `api::auto_id`
+    Auto,
+    /// A random version 4 UUID
+    Uuid,
+    /// A ULID, which sorts by the millisecond it was made in
+    Ulid,
+    /// The hex SHA-256 digest of the value at this field path
+    Hash(FieldPath),
+}
+
+/// A random version 4 UUID such as `0b5c5cb4-8a52-4d3c-9d2f-1c2a3b4c5d6e`
+pub fn uuid<R: Rng>(rng: &mut R) -> String {
+    let mut bytes: [u8; 16] = rng.gen();
+    // version 4, variant 1
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// A ULID for the current time such as `01ARZ3NDEKTSV4RRFFQ69G5FAV`
+pub fn ulid<R: Rng>(rng: &mut R) -> String {
+    let millis = Utc::now().timestamp_millis() as u128 & ((1 << 48) - 1);
+    let random = rng.gen::<u128>() & ((1 << 80) - 1);
+    let mut value = (millis << 80) | random;
+    let mut digits = [0u8; ULID_LENGTH];
+    for digit in digits.iter_mut().rev() {
+        *digit = CROCKFORD[(value & 0x1f) as usize];
+        value >>= 5;
+    }
+    String::from_utf8(digits.to_vec()).unwrap()
+}
+
+/// The hex SHA-256 digest of `value`, of its text for strings and its JSON otherwise
+fn hash(value: &FirestoreType) -> String {
+    let digest = match value {
+        FirestoreType::String(text) => Sha256::digest(text.as_bytes()),
+        value => Sha256::digest(value.to_json().to_string().as_bytes()),
+    };
+    digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>()
+}
+
+impl IdStrategy {
+    /// The ID of a new document with said fields, which only a hash depends on
+    pub fn id_for(&self, fields: &FirestoreFields) -> Result<String> {
+        match self {
+            IdStrategy::Auto => Ok(auto_id()),
+            IdStrategy::Uuid => Ok(uuid(&mut rand::thread_rng())),
+            IdStrategy::Ulid => Ok(ulid(&mut rand::thread_rng())),
+            IdStrategy::Hash(path) => match path.get(fields) {
+                Some(value) => Ok(hash(value)),
+                None => Err(Error::InvalidDocument {
+                    reason: format!("a document has no {} field to hash into its ID", path),
+                }),
+            },
+        }
+    }
+}
+
+impl FromStr for IdStrategy {
+    type Err = Error;
+
+    /// Reads `auto`, `uuid`, `ulid` or `hash:<field path>`
+    fn from_str(strategy: &str) -> Result<IdStrategy> {
+        match strategy {
+            "auto" => Ok(IdStrategy::Auto),
+            "uuid" => Ok(IdStrategy::Uuid),
+            "ulid" => Ok(IdStrategy::Ulid),
+            _ if strategy.starts_with("hash:") => Ok(IdStrategy::Hash(FieldPath::parse(
+                &strategy["hash:".len()..],
+            )?)),
+            _ => Err(Error::InvalidArgument {
+                reason: format!(
+                    "{} is not an ID strategy, expected auto, uuid, ulid or hash:<field>",
+                    strategy
+                ),
+            }),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+// Composite indexes, the fields they cover and how to create them. A query that needs
+// an index which does not exist fails with FAILED_PRECONDITION and a link to create it,
+// whose `create_composite` parameter holds the index as a base64 encoded protobuf.
+
+use crate::errors::Error;
+use crate::firestore::indexes as rest;
+use serde_json::Value;
+use std::fmt;
+
+/// Whether an index serves the queries of a single collection or of a collection group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryScope {
+    Collection,
+    CollectionGroup,
+}
+
+impl QueryScope {
+    /// Name of the scope in the Admin API
+    pub fn name(self) -> &'static str {
+        match self {
+            QueryScope::Collection => "COLLECTION",
+            QueryScope::CollectionGroup => "COLLECTION_GROUP",
+        }
+    }
+}
+
+/// How a field is indexed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldMode {
+    Ascending,
+    Descending,
+    /// Array membership, for `array-contains` filters
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexField {
+    pub field_path: String,
+    pub mode: FieldMode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    /// ID of the collections the index covers
+    pub collection_group: String,
+    pub query_scope: QueryScope,
+    pub fields: Vec<IndexField>,
+    /// Resource name, `None` until the index is created
+    pub name: Option<String>,
+    /// Such as `CREATING` or `READY`, `None` until the index is created
+    pub state: Option<String>,
+}
+
+/// The collection group in the resource name of an index or of its collection group
+fn collection_group_of(name: &str) -> Option<String> {
+    // projects/{project}/databases/{database}/collectionGroups/{id}/indexes/{index}
+    let mut segments = name.split('/');
+    segments.find(|segment| *segment == "collectionGroups")?;
+    segments.next().map(String::from)
+}
+
+/// A field of an encoded protobuf message
+enum ProtoValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// The fields of an encoded protobuf message by number, in the order they were encoded.
+/// Fixed width fields are skipped, as indexes have none.
+fn proto_fields(mut bytes: &[u8]) -> Option<Vec<(u64, ProtoValue<'_>)>> {
+    let mut fields = Vec::new();
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        let value = match key & 0x7 {
+            0 => ProtoValue::Varint(read_varint(&mut bytes)?),
+            1 | 5 => {
+                let width = if key & 0x7 == 1 { 8 } else { 4 };
+                bytes = bytes.get(width..)?;
+                continue;
+            }
+            2 => {
+                let length = read_varint(&mut bytes)? as usize;
+                let value = bytes.get(..length)?;
+                bytes = &bytes[length..];
+                ProtoValue::Bytes(value)
+            }
+            _ => return None,
+        };
+        fields.push((key >> 3, value));
+    }
+    Some(fields)
+}
+
+/// Decodes the `%XX` escapes of a URL parameter
+fn percent_decode(value: &str) -> String {
+    let mut decoded = Vec::new();
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = bytes
+                .next()
+                .into_iter()
+                .chain(bytes.next())
+                .collect::<Vec<u8>>();
+            if let Some(byte) = std::str::from_utf8(&hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                decoded.push(byte);
+                continue;
+            }
+        }
+        decoded.push(byte);
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+impl Index {
+    /// An index to create over `fields` of the collections with said ID
+    pub fn new<S: Into<String>>(
+        collection_group: S,
+        query_scope: QueryScope,
+        fields: Vec<IndexField>,
+    ) -> Index {
+        Index {
+            collection_group: collection_group.into(),
+            query_scope,
+            fields,
+            name: None,
+            state: None,
+        }
+    }
+
+    /// The ID Firestore gave the index, the last segment of its name
+    pub fn id(&self) -> Option<&str> {
+        self.name.as_ref().and_then(|name| name.rsplit('/').next())
+    }
+
+    /// Reads an index listed by the Admin API, `None` for kinds of indexes this does not
+    /// know of, such as vector indexes
+    pub(crate) fn from_rest(index: rest::Index) -> Option<Index> {
+        let query_scope = match &*index.query_scope {
+            "COLLECTION" => QueryScope::Collection,
+            "COLLECTION_GROUP" => QueryScope::CollectionGroup,
+            _ => return None,
+        };
+        let mut fields = Vec::new();
+        for field in index.fields {
+            let mode = match (field.order.as_ref().map(|o| &**o), field.array_config) {
+                (Some("ASCENDING"), _) => FieldMode::Ascending,
+                (Some("DESCENDING"), _) => FieldMode::Descending,
+                (_, Some(_)) => FieldMode::Contains,
+                _ => return None,
+            };
+            fields.push(IndexField {
+                field_path: field.field_path,
+                mode,
+            });
+        }
+        Some(Index {
+            collection_group: collection_group_of(index.name.as_ref()?)?,
+            query_scope,
+            fields,
+            name: index.name,
+            state: index.state,
+        })
+    }
+
+    /// The index as the Admin API takes it
+    pub(crate) fn to_rest(&self) -> rest::Index {
+        rest::Index {
+            name: None,
+            query_scope: self.query_scope.name().to_string(),
+            fields: self
+                .fields
+                .iter()
+                .map(|field| rest::IndexField {
+                    field_path: field.field_path.clone(),
+                    order: match field.mode {
+                        FieldMode::Ascending => Some(String::from("ASCENDING")),
+                        FieldMode::Descending => Some(String::from("DESCENDING")),
+                        FieldMode::Contains => None,
+                    },
+                    array_config: match field.mode {
+                        FieldMode::Contains => Some(String::from("CONTAINS")),
+                        _ => None,
+                    },
+                })
+                .collect(),
+            state: None,
+        }
+    }
+
+    /// The index a query needs, when `error` is the failure of a query missing one
+    pub fn from_error(error: &Error) -> Option<Index> {
+        match error {
+            Error::FailedPrecondition { message } => Index::from_message(message),
+            _ => None,
+        }
+    }
+
+    /// Reads the index from the `create_composite` parameter of the link in `message`
+    pub fn from_message(message: &str) -> Option<Index> {
+        let start = message.find("create_composite=")? + "create_composite=".len();
+        let encoded = message[start..]
+            .split(|c: char| c.is_whitespace() || c == '&' || c == '"')
+            .next()?;
+        let encoded = percent_decode(encoded);
+        let bytes = if encoded.contains('-') || encoded.contains('_') {
+            base64::decode_config(&*encoded, base64::URL_SAFE)
+        } else {
+            base64::decode(&*encoded)
+        };
+        Index::decode(&bytes.ok()?)
+    }
+
+    /// Decodes a `google.firestore.admin.v1.Index` message
+    fn decode(bytes: &[u8]) -> Option<Index> {
+        let mut index = Index::new(String::new(), QueryScope::Collection, Vec::new());
+        for (number, value) in proto_fields(bytes)? {
+            match (number, value) {
+                (1, ProtoValue::Bytes(name)) => {
+                    index.collection_group = collection_group_of(std::str::from_utf8(name).ok()?)?
+                }
+                (2, ProtoValue::Varint(2)) => index.query_scope = QueryScope::CollectionGroup,
+                (3, ProtoValue::Bytes(field)) => index.fields.push(Index::decode_field(field)?),
+                _ => {}
+            }
+        }
+        if index.collection_group.is_empty() || index.fields.is_empty() {
+            return None;
+        }
+        Some(index)
+    }
+
+    /// Decodes a `google.firestore.admin.v1.Index.IndexField` message
+    fn decode_field(bytes: &[u8]) -> Option<IndexField> {
+        let mut field_path = None;
+        let mut mode = None;
+        for (number, value) in proto_fields(bytes)? {
+            match (number, value) {
+                (1, ProtoValue::Bytes(path)) => {
+                    field_path = Some(std::str::from_utf8(path).ok()?.to_string())
+                }
+                (2, ProtoValue::Varint(1)) => mode = Some(FieldMode::Ascending),
+                (2, ProtoValue::Varint(2)) => mode = Some(FieldMode::Descending),
+                (3, ProtoValue::Varint(1)) => mode = Some(FieldMode::Contains),
+                _ => {}
+            }
+        }
+        Some(IndexField {
+            field_path: field_path?,
+            mode: mode?,
+        })
+    }
+
+    /// The index as an entry of the `indexes` of a `firestore.indexes.json` file
+    pub fn to_json(&self) -> Value {
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| match field.mode {
+                FieldMode::Ascending => {
+                    json!({ "fieldPath": field.field_path, "order": "ASCENDING" })
+                }
+                FieldMode::Descending => {
+                    json!({ "fieldPath": field.field_path, "order": "DESCENDING" })
+                }
+                FieldMode::Contains => {
+                    json!({ "fieldPath": field.field_path, "arrayConfig": "CONTAINS" })
+                }
+            })
+            .collect::<Vec<Value>>();
+        json!({
+            "collectionGroup": self.collection_group,
+            "queryScope": self.query_scope.name(),
+            "fields": fields,
+        })
+    }
+
+    /// A `gcloud` command creating the index in said database
+    pub fn gcloud_command(&self, project_id: &str, database_id: &str) -> String {
+        let mut command = format!(
+            "gcloud firestore indexes composite create --project={} --database='{}' --collection-group={} --query-scope={}",
+            project_id,
+            database_id,
+            self.collection_group,
+            self.query_scope.name()
+        );
+        for field in &self.fields {
+            let config = match field.mode {
+                FieldMode::Ascending => "order=ascending",
+                FieldMode::Descending => "order=descending",
+                FieldMode::Contains => "array-config=contains",
+            };
+            command.push_str(&format!(
+                " --field-config=field-path='{}',{}",
+                field.field_path, config
+            ));
+        }
+        command
+    }
+}
+
+impl fmt::Display for Index {
+    /// Such as `users (collection): age ascending, tags contains`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scope = match self.query_scope {
+            QueryScope::Collection => "collection",
+            QueryScope::CollectionGroup => "collection group",
+        };
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| {
+                let mode = match field.mode {
+                    FieldMode::Ascending => "ascending",
+                    FieldMode::Descending => "descending",
+                    FieldMode::Contains => "contains",
+                };
+                format!("{} {}", field.field_path, mode)
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "{} ({}): {}", self.collection_group, scope, fields)
+    }
+}
@@ -0,0 +1,429 @@
+// JSON Schema validation for `validate`, covering the draft 7 keywords for types, enums,
+// numbers, strings, arrays, objects, combinations, conditionals and local `$ref`s.
+// `format` and other annotations are ignored, as are keywords this module does not know.
+// Documents are validated as plain JSON, so timestamps and references are strings.
+
+use libfiresale::errors::{Error, Result};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A reason a document does not match the schema
+#[derive(Debug, Clone)]
+pub struct Violation {
+    /// Dotted path of the offending value, empty for the whole document
+    pub field: String,
+    pub message: String,
+}
+
+/// A schema loaded from a file, with its patterns compiled
+pub struct Validator {
+    root: Value,
+    patterns: HashMap<String, Regex>,
+}
+
+/// Compiles every `pattern` and `patternProperties` key below `schema`
+fn collect_patterns(schema: &Value, patterns: &mut HashMap<String, Regex>) -> Result<()> {
+    let mut compile = |pattern: &str| -> Result<()> {
+        if !patterns.contains_key(pattern) {
+            let regex = Regex::new(pattern).map_err(|e| Error::InvalidArgument {
+                reason: format!("{} is not a valid pattern: {}", pattern, e),
+            })?;
+            patterns.insert(pattern.to_string(), regex);
+        }
+        Ok(())
+    };
+    match schema {
+        Value::Object(object) => {
+            if let Some(Value::String(pattern)) = object.get("pattern") {
+                compile(pattern)?;
+            }
+            if let Some(Value::Object(properties)) = object.get("patternProperties") {
+                for pattern in properties.keys() {
+                    compile(pattern)?;
+                }
+            }
+            for value in object.values() {
+                collect_patterns(value, patterns)?;
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_patterns(value, patterns)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The name of the JSON type of `value`
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Whether `value` is of the JSON Schema type `name`
+fn has_type(value: &Value, name: &str) -> bool {
+    match (name, value) {
+        ("integer", Value::Number(n)) => {
+            n.is_i64() || n.is_u64() || n.as_f64().map_or(false, |f| f.fract() == 0.0)
+        }
+        (name, value) => type_of(value) == name,
+    }
+}
+
+/// A violation at `field`
+fn violation(field: &str, message: String) -> Violation {
+    Violation {
+        field: field.to_string(),
+        message,
+    }
+}
+
+/// Joins a field path and a key or index
+fn child(field: &str, key: &str) -> String {
+    if field.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", field, key)
+    }
+}
+
+impl Validator {
+    /// Loads a schema, failing if it is neither an object nor a boolean
+    /// or if any of its patterns is not a valid regular expression
+    pub fn new(schema: Value) -> Result<Validator> {
+        match schema {
+            Value::Object(_) | Value::Bool(_) => {}
+            _ => {
+                return Err(Error::InvalidArgument {
+                    reason: String::from("a JSON Schema must be an object or a boolean"),
+                })
+            }
+        }
+        let mut patterns = HashMap::new();
+        collect_patterns(&schema, &mut patterns)?;
+        Ok(Validator {
+            root: schema,
+            patterns,
+        })
+    }
+
+    /// Every way in which `value` does not match the schema
+    pub fn validate(&self, value: &Value) -> Result<Vec<Violation>> {
+        let mut violations = Vec::new();
+        self.check(&self.root, value, "", &mut violations)?;
+        Ok(violations)
+    }
+
+    /// The schema at a `$ref` of the form `#/definitions/name`
+    fn resolve(&self, reference: &str) -> Result<&Value> {
+        let unsupported = || Error::InvalidArgument {
+            reason: format!("{} is not a reference within the schema", reference),
+        };
+        if !reference.starts_with('#') {
+            return Err(unsupported());
+        }
+        let pointer = reference[1..]
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect::<Vec<String>>()
+            .join("/");
+        self.root.pointer(&pointer).ok_or_else(unsupported)
+    }
+
+    fn matches(&self, schema: &Value, value: &Value, field: &str) -> Result<bool> {
+        let mut violations = Vec::new();
+        self.check(schema, value, field, &mut violations)?;
+        Ok(violations.is_empty())
+    }
+
+    fn check(
+        &self,
+        schema: &Value,
+        value: &Value,
+        field: &str,
+        violations: &mut Vec<Violation>,
+    ) -> Result<()> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok(()),
+            Value::Bool(false) => {
+                violations.push(violation(field, String::from("no value is allowed here")));
+                return Ok(());
+            }
+            Value::Object(schema) => schema,
+            _ => return Ok(()),
+        };
+        if let Some(Value::String(reference)) = schema.get("$ref") {
+            // in draft 7 a reference replaces every other keyword next to it
+            return self.check(self.resolve(reference)?, value, field, violations);
+        }
+
+        match schema.get("type") {
+            Some(Value::String(name)) if !has_type(value, name) => violations.push(violation(
+                field,
+                format!("expected {}, found {}", name, type_of(value)),
+            )),
+            Some(Value::Array(names))
+                if !names
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|name| has_type(value, name)) =>
+            {
+                let names = names
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<&str>>()
+                    .join(" or ");
+                violations.push(violation(
+                    field,
+                    format!("expected {}, found {}", names, type_of(value)),
+                ))
+            }
+            _ => {}
+        }
+        if let Some(Value::Array(allowed)) = schema.get("enum") {
+            if !allowed.contains(value) {
+                violations.push(violation(
+                    field,
+                    format!("{} is not one of {}", value, Value::Array(allowed.clone())),
+                ));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if expected != value {
+                violations.push(violation(
+                    field,
+                    format!("expected {}, found {}", expected, value),
+                ));
+            }
+        }
+
+        if let Some(number) = value.as_f64() {
+            let bound = |name| schema.get(name).and_then(Value::as_f64);
+            if let Some(minimum) = bound("minimum") {
+                if number < minimum {
+                    violations.push(violation(
+                        field,
+                        format!("{} is less than the minimum of {}", number, minimum),
+                    ));
+                }
+            }
+            if let Some(maximum) = bound("maximum") {
+                if number > maximum {
+                    violations.push(violation(
+                        field,
+                        format!("{} is more than the maximum of {}", number, maximum),
+                    ));
+                }
+            }
+            if let Some(minimum) = bound("exclusiveMinimum") {
+                if number <= minimum {
+                    violations.push(violation(
+                        field,
+                        format!("{} is not more than {}", number, minimum),
+                    ));
+                }
+            }
+            if let Some(maximum) = bound("exclusiveMaximum") {
+                if number >= maximum {
+                    violations.push(violation(
+                        field,
+                        format!("{} is not less than {}", number, maximum),
+                    ));
+                }
+            }
+            if let Some(divisor) = bound("multipleOf") {
+                if divisor > 0.0 && (number / divisor).fract() != 0.0 {
+                    violations.push(violation(
+                        field,
+                        format!("{} is not a multiple of {}", number, divisor),
+                    ));
+                }
+            }
+        }
+
+        if let Value::String(text) = value {
+            let length = text.chars().count() as u64;
+            if let Some(minimum) = schema.get("minLength").and_then(Value::as_u64) {
+                if length < minimum {
+                    violations.push(violation(
+                        field,
+                        format!("shorter than {} characters", minimum),
+                    ));
+                }
+            }
+            if let Some(maximum) = schema.get("maxLength").and_then(Value::as_u64) {
+                if length > maximum {
+                    violations.push(violation(
+                        field,
+                        format!("longer than {} characters", maximum),
+                    ));
+                }
+            }
+            if let Some(Value::String(pattern)) = schema.get("pattern") {
+                if !self.patterns[pattern].is_match(text) {
+                    violations.push(violation(
+                        field,
+                        format!("{:?} does not match {}", text, pattern),
+                    ));
+                }
+            }
+        }
+
+        if let Value::Array(items) = value {
+            let count = items.len() as u64;
+            if let Some(minimum) = schema.get("minItems").and_then(Value::as_u64) {
+                if count < minimum {
+                    violations.push(violation(field, format!("fewer than {} items", minimum)));
+                }
+            }
+            if let Some(maximum) = schema.get("maxItems").and_then(Value::as_u64) {
+                if count > maximum {
+                    violations.push(violation(field, format!("more than {} items", maximum)));
+                }
+            }
+            if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+                let duplicate = items
+                    .iter()
+                    .enumerate()
+                    .any(|(i, item)| items[..i].contains(item));
+                if duplicate {
+                    violations.push(violation(field, String::from("items are not unique")));
+                }
+            }
+            if let Some(contains) = schema.get("contains") {
+                let mut found = false;
+                for (i, item) in items.iter().enumerate() {
+                    if self.matches(contains, item, &child(field, &i.to_string()))? {
+                        found = true;
+                        break;
+                    }
+                }
+                if !found {
+                    violations.push(violation(field, String::from("no item matches contains")));
+                }
+            }
+            match schema.get("items") {
+                Some(Value::Array(tuple)) => {
+                    for (i, (item, item_schema)) in items.iter().zip(tuple).enumerate() {
+                        let field = child(field, &i.to_string());
+                        self.check(item_schema, item, &field, violations)?;
+                    }
+                    if let Some(additional) = schema.get("additionalItems") {
+                        for (i, item) in items.iter().enumerate().skip(tuple.len()) {
+                            let field = child(field, &i.to_string());
+                            self.check(additional, item, &field, violations)?;
+                        }
+                    }
+                }
+                Some(item_schema) => {
+                    for (i, item) in items.iter().enumerate() {
+                        let field = child(field, &i.to_string());
+                        self.check(item_schema, item, &field, violations)?;
+                    }
+                }
+                None => {}
+            }
+        }
+
+        if let Value::Object(object) = value {
+            let count = object.len() as u64;
+            if let Some(minimum) = schema.get("minProperties").and_then(Value::as_u64) {
+                if count < minimum {
+                    violations.push(violation(field, format!("fewer than {} fields", minimum)));
+                }
+            }
+            if let Some(maximum) = schema.get("maxProperties").and_then(Value::as_u64) {
+                if count > maximum {
+                    violations.push(violation(field, format!("more than {} fields", maximum)));
+                }
+            }
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(name) {
+                        let message = String::from("required field is missing");
+                        violations.push(violation(&child(field, name), message));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            let pattern_properties = schema.get("patternProperties").and_then(Value::as_object);
+            for (key, property) in object {
+                let property_field = child(field, key);
+                let mut described = false;
+                if let Some(property_schema) = properties.and_then(|p| p.get(key)) {
+                    described = true;
+                    self.check(property_schema, property, &property_field, violations)?;
+                }
+                for (pattern, property_schema) in pattern_properties.into_iter().flatten() {
+                    if self.patterns[pattern].is_match(key) {
+                        described = true;
+                        self.check(property_schema, property, &property_field, violations)?;
+                    }
+                }
+                if let (false, Some(additional)) = (described, schema.get("additionalProperties")) {
+                    if additional == &Value::Bool(false) {
+                        let message = String::from("field is not allowed");
+                        violations.push(violation(&property_field, message));
+                    } else {
+                        self.check(additional, property, &property_field, violations)?;
+                    }
+                }
+            }
+        }
+
+        if let Some(Value::Array(schemas)) = schema.get("allOf") {
+            for sub_schema in schemas {
+                self.check(sub_schema, value, field, violations)?;
+            }
+        }
+        if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+            let mut any = false;
+            for sub_schema in schemas {
+                if self.matches(sub_schema, value, field)? {
+                    any = true;
+                    break;
+                }
+            }
+            if !any {
+                violations.push(violation(field, String::from("matches none of anyOf")));
+            }
+        }
+        if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+            let mut matching = 0;
+            for sub_schema in schemas {
+                if self.matches(sub_schema, value, field)? {
+                    matching += 1;
+                }
+            }
+            if matching != 1 {
+                let message = format!("matches {} of oneOf rather than exactly one", matching);
+                violations.push(violation(field, message));
+            }
+        }
+        if let Some(not) = schema.get("not") {
+            if self.matches(not, value, field)? {
+                violations.push(violation(field, String::from("matches the schema of not")));
+            }
+        }
+        if let Some(condition) = schema.get("if") {
+            let branch = if self.matches(condition, value, field)? {
+                schema.get("then")
+            } else {
+                schema.get("else")
+            };
+            if let Some(branch) = branch {
+                self.check(branch, value, field, violations)?;
+            }
+        }
+        Ok(())
+    }
+}
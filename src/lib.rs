@@ -1,8 +1,32 @@
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+#[macro_use]
 extern crate serde_derive;
 #[macro_use]
+extern crate serde_json;
+#[macro_use]
 extern crate snafu_derive;
 
 pub mod api;
+pub mod auth;
+pub mod bulk;
+pub mod credential_store;
+pub mod diff;
 pub mod errors;
+pub mod external_account;
+pub mod fieldpath;
 pub mod firestore;
+pub mod id_strategy;
+pub mod index;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+pub mod operation;
+pub mod pool;
+pub mod proxy;
+pub mod ratelimit;
+pub mod serializer;
+pub mod size;
+pub mod token;
+pub mod type_style;
@@ -0,0 +1,231 @@
+// `auth login`, the OAuth flow for installed apps. The consent page is opened in the
+// browser with a redirect to a port of the loopback interface, where the authorization
+// code is picked up and traded for a refresh token. The refresh token is stored as
+// `authorized_user` credentials in the keychain, or in a file of the config directory
+// when firesale is built without it, which commands fall back to when no other
+// credentials are given, the same way they use the gcloud ADC file.
+// The OAuth client is a "Desktop app" client created in the Google Cloud console.
+// `auth store-key` puts other credentials files in the keychain.
+
+use crate::config;
+use libfiresale::auth::KEYCHAIN_PREFIX;
+use libfiresale::credential_store;
+use libfiresale::errors::{Error, Result};
+use libfiresale::firestore::{CheckStatus, Logged};
+use libfiresale::proxy;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use reqwest::Url;
+use serde_derive::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+
+const AUTH_URI: &'static str = "https://accounts.google.com/o/oauth2/v2/auth";
+const TOKEN_URI: &'static str = "https://oauth2.googleapis.com/token";
+/// Covers Firestore as well as impersonating service accounts
+const SCOPE: &'static str = "https://www.googleapis.com/auth/cloud-platform";
+
+const AUTHORIZED_PAGE: &'static str =
+    "<html><body>firesale was authorized, this window can be closed.</body></html>";
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    refresh_token: Option<String>,
+}
+
+fn login_error(reason: String) -> Error {
+    Error::Authentication { reason }
+}
+
+/// A random string of `len` letters and digits
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .collect()
+}
+
+/// Opens `url` in the default browser, returning whether that worked
+fn open_browser(url: &str) -> bool {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        // unlike `start`, this leaves the ampersands of the URL alone
+        let mut command = Command::new("rundll32");
+        command.arg("url.dll,FileProtocolHandler");
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+    command
+        .arg(url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+fn respond(mut stream: &TcpStream, status: &str, body: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+/// Waits for the browser to be redirected back with the authorization code,
+/// ignoring any other request such as the one for a favicon
+fn receive_code(listener: &TcpListener, redirect_uri: &str, state: &str) -> Result<String> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+        // GET /?state=...&code=... HTTP/1.1
+        let target = request_line.split_whitespace().nth(1).unwrap_or("");
+        let url = match Url::parse(redirect_uri).and_then(|base| base.join(target)) {
+            Ok(url) => url,
+            Err(_) => continue,
+        };
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        if let Some(error) = param("error") {
+            respond(&stream, "400 Bad Request", &*error)?;
+            return Err(login_error(format!("sign in failed: {}", error)));
+        }
+        let code = match param("code") {
+            Some(code) => code,
+            None => {
+                respond(&stream, "404 Not Found", "")?;
+                continue;
+            }
+        };
+        if param("state").as_ref().map(|s| &**s) != Some(state) {
+            respond(&stream, "400 Bad Request", "state mismatch")?;
+            return Err(login_error(String::from(
+                "sign in failed: the redirect does not belong to this login",
+            )));
+        }
+        respond(&stream, "200 OK", AUTHORIZED_PAGE)?;
+        return Ok(code);
+    }
+    Err(login_error(String::from("the redirect listener closed")))
+}
+
+/// Signs in as a user with the OAuth client `client_id`, storing the refresh token
+/// for later commands
+pub fn login(query: crate::LoginQuery) -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let redirect_uri = format!("http://127.0.0.1:{}/", listener.local_addr()?.port());
+    let state = random_string(32);
+    // PKCE, so that an intercepted code is useless without the verifier
+    let verifier = random_string(64);
+    let challenge = base64::encode_config(
+        &Sha256::digest(verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    );
+    let consent_url = Url::parse_with_params(
+        AUTH_URI,
+        &[
+            ("client_id", &*query.client_id),
+            ("redirect_uri", &*redirect_uri),
+            ("response_type", "code"),
+            ("scope", SCOPE),
+            ("access_type", "offline"),
+            // a refresh token is only handed out on consent
+            ("prompt", "consent"),
+            ("state", &*state),
+            ("code_challenge", &*challenge),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|e| login_error(e.to_string()))?;
+    if !query.open_browser || !open_browser(consent_url.as_str()) {
+        eprintln!("Visit this URL to sign in:\n\n{}\n", consent_url);
+    } else {
+        eprintln!("Continue signing in in the browser");
+    }
+    let code = receive_code(&listener, &*redirect_uri, &*state)?;
+    let mut response = reqwest::Client::builder()
+        .proxy(proxy::reqwest_proxy())
+        .build()?
+        .post(TOKEN_URI)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", &*code),
+            ("client_id", &*query.client_id),
+            ("client_secret", &*query.client_secret),
+            ("redirect_uri", &*redirect_uri),
+            ("code_verifier", &*verifier),
+        ])
+        .send_logged()?
+        .check_status()?;
+    let refresh_token = response
+        .json::<TokenResponse>()?
+        .refresh_token
+        .ok_or_else(|| login_error(String::from("no refresh token was handed out")))?;
+    let credentials = json!({
+        "type": "authorized_user",
+        "client_id": query.client_id,
+        "client_secret": query.client_secret,
+        "refresh_token": refresh_token,
+    });
+    if credential_store::is_available() {
+        credential_store::set(config::LOGIN_ACCOUNT, &*credentials.to_string())?;
+        status!("Signed in, credentials saved to the keychain");
+        return Ok(());
+    }
+    let path = config::credentials_path()
+        .ok_or_else(|| login_error(String::from("there is no config directory")))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    serde_json::to_writer_pretty(options.open(&path)?, &credentials)?;
+    status!("Signed in, credentials saved to {}", path.display());
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct CredentialsKind {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// Stores the credentials file of `query` in the keychain under its name
+pub fn store_key(query: crate::StoreKeyQuery) -> Result<()> {
+    let contents = fs::read_to_string(&query.file)?;
+    let kind: CredentialsKind = serde_json::from_str(&*contents)?;
+    match &*kind.kind {
+        "service_account" | "authorized_user" => {}
+        other => {
+            return Err(Error::InvalidArgument {
+                reason: format!("{} has unsupported credentials type {}", query.file, other),
+            })
+        }
+    }
+    credential_store::set(&*query.name, &*contents)?;
+    status!(
+        "Stored {} in the keychain, use it with {}{}",
+        query.file,
+        KEYCHAIN_PREFIX,
+        query.name
+    );
+    Ok(())
+}
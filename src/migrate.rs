@@ -0,0 +1,146 @@
+// Schema migrations, applied one document at a time. Each document is read and written
+// back within a transaction of its own, so that a write made to it in the meantime is
+// never overwritten, and documents with nothing to migrate are left alone.
+
+use crate::fields::{self, FieldAssignment};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use libfiresale::api::{split_document_path, DatabaseContext, FirestoreFields, FirestoreType};
+use libfiresale::errors::Result;
+use libfiresale::fieldpath::FieldPath;
+
+/// Names of the types `cast` converts to
+pub const CAST_TYPES: &[&str] = &["int", "double", "string", "timestamp"];
+
+/// What a migration does to a document
+pub enum Migration {
+    /// Writes the fields to the paths of the mask, deleting the paths they have no value at
+    Update(FirestoreFields, Vec<String>),
+    /// The document has nothing to migrate
+    Unchanged,
+    /// The document cannot be migrated, for said reason
+    Skipped(String),
+}
+
+/// Reads the document at `path` and writes back the update `migrate` makes of its
+/// fields, within a single transaction
+pub fn migrate_document<F>(ctx: &DatabaseContext, path: &str, migrate: F) -> Result<Migration>
+where
+    F: Fn(&FirestoreFields) -> Migration,
+{
+    let (collection_name, document_id) = split_document_path(path);
+    ctx.run_transaction(|transaction| {
+        let document = transaction.get_document(collection_name, document_id)?;
+        let migration = migrate(document.fields());
+        if let Migration::Update(fields, field_paths) = &migration {
+            transaction.update(
+                collection_name,
+                document_id,
+                fields.clone(),
+                field_paths.clone(),
+            );
+        }
+        Ok(migration)
+    })
+}
+
+/// Writes `value` at the field path `to`, deleting `also_deleted` along the way
+fn write_field(to: &str, value: FirestoreType, also_deleted: Option<&str>) -> Migration {
+    let (fields, mut field_paths) = fields::into_fields(vec![FieldAssignment {
+        path: FieldPath::parse(to).expect("migrated field paths are validated"),
+        value,
+    }]);
+    field_paths.extend(also_deleted.map(String::from));
+    Migration::Update(fields, field_paths)
+}
+
+/// Moves the value at the field path `from` to `to`
+pub fn rename_field(fields: &FirestoreFields, from: &str, to: &str) -> Migration {
+    let value = match fields.get(from) {
+        Some(value) => value.clone(),
+        None => return Migration::Unchanged,
+    };
+    if fields.get(to).is_some() {
+        return Migration::Skipped(format!("{} is already set", to));
+    }
+    write_field(to, value, Some(from))
+}
+
+/// Reads a timestamp written as RFC 3339 or as a date, which is taken at midnight UTC
+fn parse_timestamp(text: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+    Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
+}
+
+/// Converts `value` to the type named `to`, one of `CAST_TYPES`. Numbers become
+/// timestamps as seconds since the epoch, and doubles become integers only when whole.
+fn cast_value(value: &FirestoreType, to: &str) -> Option<FirestoreType> {
+    let whole = |number: f64| {
+        if number.fract() == 0.0 && number.abs() < i64::max_value() as f64 {
+            Some(number as i64)
+        } else {
+            None
+        }
+    };
+    let cast = match (value, to) {
+        (FirestoreType::String(text), "int") => match text.trim().parse() {
+            Ok(integer) => FirestoreType::Integer(integer),
+            Err(_) => FirestoreType::Integer(whole(text.trim().parse().ok()?)?),
+        },
+        (FirestoreType::Double(number), "int") => FirestoreType::Integer(whole(*number)?),
+        (FirestoreType::Boolean(boolean), "int") => FirestoreType::Integer(*boolean as i64),
+        (FirestoreType::String(text), "double") => FirestoreType::Double(text.trim().parse().ok()?),
+        (FirestoreType::Integer(integer), "double") => FirestoreType::Double(*integer as f64),
+        (FirestoreType::Integer(integer), "string") => FirestoreType::String(integer.to_string()),
+        (FirestoreType::Double(number), "string") => FirestoreType::String(number.to_string()),
+        (FirestoreType::Boolean(boolean), "string") => FirestoreType::String(boolean.to_string()),
+        (FirestoreType::Timestamp(time), "string") => FirestoreType::String(time.to_rfc3339()),
+        (FirestoreType::String(text), "timestamp") => {
+            FirestoreType::Timestamp(parse_timestamp(text.trim())?)
+        }
+        (FirestoreType::Integer(seconds), "timestamp") => {
+            FirestoreType::Timestamp(Utc.timestamp_opt(*seconds, 0).single()?)
+        }
+        (FirestoreType::Double(seconds), "timestamp") => {
+            let nanoseconds = (seconds.fract() * 1e9) as u32;
+            FirestoreType::Timestamp(
+                Utc.timestamp_opt(seconds.trunc() as i64, nanoseconds)
+                    .single()?,
+            )
+        }
+        _ => return None,
+    };
+    Some(cast)
+}
+
+/// Whether `value` already has the type named `to`
+fn has_type(value: &FirestoreType, to: &str) -> bool {
+    match (value, to) {
+        (FirestoreType::Integer(_), "int")
+        | (FirestoreType::Double(_), "double")
+        | (FirestoreType::String(_), "string")
+        | (FirestoreType::Timestamp(_), "timestamp") => true,
+        _ => false,
+    }
+}
+
+/// Converts the value at `field_path` to the type named `to`, leaving missing and null
+/// values alone
+pub fn cast_field(fields: &FirestoreFields, field_path: &str, to: &str) -> Migration {
+    let value = match fields.get(field_path) {
+        None | Some(FirestoreType::Null(_)) => return Migration::Unchanged,
+        Some(value) if has_type(value, to) => return Migration::Unchanged,
+        Some(value) => value,
+    };
+    match cast_value(value, to) {
+        Some(cast) => write_field(field_path, cast, None),
+        None => Migration::Skipped(format!(
+            "{} of {} cannot be converted to {}",
+            value.to_json(),
+            field_path,
+            to
+        )),
+    }
+}
@@ -0,0 +1,187 @@
+// Futures based counterpart of `DatabaseContext`, enabled with the `async` feature.
+// Authentication stays blocking: the token is resolved for every request as the blocking
+// context does, and a stale one is refreshed with the blocking client on the thread that
+// polls the future, holding up that thread of the executor until it is minted.
+
+use super::api::{query, DatabaseContext, Document};
+use super::errors::{Error, Result};
+use super::firestore::documents::{self, nonblocking, CommitResponse};
+use super::firestore::write::Write;
+use super::proxy;
+use futures::future::{self, Future};
+use futures::stream::{self, Stream};
+use reqwest::header::HeaderMap;
+
+/// Issues requests without blocking, so many of them can be in flight at once
+/// on a tokio runtime
+pub struct AsyncDatabaseContext {
+    ctx: DatabaseContext,
+    client: reqwest::r#async::Client,
+}
+
+impl DatabaseContext {
+    /// Converts this context into one whose requests return futures, with the same timeout
+    pub fn into_async(self) -> AsyncDatabaseContext {
+        let builder = reqwest::r#async::Client::builder().proxy(proxy::reqwest_proxy());
+        let builder = match self.timeout() {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        };
+        AsyncDatabaseContext {
+            ctx: self,
+            client: builder
+                .build()
+                .expect("the TLS backend initializes like it does for Client::new"),
+        }
+    }
+}
+
+impl AsyncDatabaseContext {
+    /// The blocking context this was created from
+    pub fn blocking(&self) -> &DatabaseContext {
+        &self.ctx
+    }
+
+    fn headers(&self) -> impl Future<Item = HeaderMap, Error = Error> {
+        future::result(self.ctx.auth_header_map())
+    }
+
+    /// GETs a document from said collection
+    pub fn get_document<S>(
+        &self,
+        collection_name: S,
+        document_id: S,
+    ) -> impl Future<Item = Document, Error = Error>
+    where
+        S: Into<String>,
+    {
+        let client = self.client.clone();
+        let base_url = self.ctx.base_url.clone();
+        let name = self
+            .ctx
+            .document_path(&*collection_name.into(), &*document_id.into());
+        let read_time = self.ctx.read_time();
+        self.headers().and_then(move |headers| {
+            nonblocking::get(
+                client,
+                &*base_url,
+                headers,
+                documents::GetDocumentQuery {
+                    name,
+                    transaction: None,
+                    read_time,
+                    mask: None,
+                },
+            )
+        })
+    }
+
+    /// Runs a structured query, resolving to every matching document
+    pub fn run_query(
+        &self,
+        query: query::Query,
+    ) -> impl Future<Item = Vec<Document>, Error = Error> {
+        let client = self.client.clone();
+        let base_url = self.ctx.base_url.clone();
+        let parent = self.ctx.parent_path(query.parent());
+        let read_time = self.ctx.read_time();
+        self.headers()
+            .and_then(move |headers| {
+                nonblocking::run_query(
+                    client,
+                    &*base_url,
+                    headers,
+                    documents::RunQueryQuery {
+                        parent,
+                        structured_query: query.into_structured_query(),
+                        transaction: None,
+                        read_time,
+                        explain_options: None,
+                    },
+                )
+            })
+            .map(|responses| {
+                responses
+                    .into_iter()
+                    .filter_map(|response| response.document)
+                    .collect()
+            })
+    }
+
+    /// Applies `writes` atomically, as `DatabaseContext::commit` does
+    pub fn commit(&self, writes: Vec<Write>) -> impl Future<Item = CommitResponse, Error = Error> {
+        let client = self.client.clone();
+        let base_url = self.ctx.base_url.clone();
+        let database = self.ctx.database_path();
+        self.headers().and_then(move |headers| {
+            nonblocking::commit(
+                client,
+                &*base_url,
+                headers,
+                documents::CommitQuery {
+                    database,
+                    writes,
+                    transaction: None,
+                },
+            )
+        })
+    }
+
+    /// Streams every document of said collection, requesting the next page
+    /// only once the previous one has been consumed. Every page is requested with
+    /// the token current at the time, so a long stream outlives its first token.
+    pub fn documents<S>(
+        &self,
+        collection_name: S,
+        page_size: Option<i32>,
+    ) -> impl Stream<Item = Document, Error = Error>
+    where
+        S: Into<String>,
+    {
+        let client = self.client.clone();
+        let base_url = self.ctx.base_url.clone();
+        let parent = self.ctx.documents_path();
+        let collection_id = collection_name.into();
+        let read_time = self.ctx.read_time();
+        let ctx = self.ctx.clone();
+        // `None` once the last page has been requested
+        let first_page: Option<Option<String>> = Some(None);
+        stream::unfold(first_page, move |page_token| {
+            let page_token = page_token?;
+            let query = documents::ListDocumentsQuery {
+                parent: parent.clone(),
+                collection_id: collection_id.clone(),
+                page_size,
+                page_token,
+                order_by: None,
+                mask: None,
+                read_time,
+            };
+            let client = client.clone();
+            let base_url = base_url.clone();
+            let page = future::result(ctx.auth_header_map())
+                .and_then(move |headers| nonblocking::list(client, &*base_url, headers, query));
+            Some(page.map(|response| {
+                let next_page_token = response
+                    .next_page_token
+                    .filter(|token| !token.is_empty())
+                    .map(Some);
+                (
+                    stream::iter_ok::<_, Error>(response.documents),
+                    next_page_token,
+                )
+            }))
+        })
+        .flatten()
+    }
+}
+
+/// Runs `future` to completion on a new tokio runtime, for callers that are not async themselves
+pub fn block_on<F>(future: F) -> Result<F::Item>
+where
+    F: Future<Error = Error> + Send + 'static,
+    F::Item: Send + 'static,
+{
+    let mut runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(future)
+}
@@ -0,0 +1,107 @@
+// Long-running operations of the Admin API, such as managed exports and imports, index
+// builds and database creation. An `OperationPoller` checks on an operation until it is
+// done, doubling the time between checks from a second up to ten seconds by default.
+
+use crate::api::DatabaseContext;
+use crate::errors::{Error, Result};
+use crate::firestore::types::Operation;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time before the first check on an operation
+const DEFAULT_INITIAL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest time between checks on an operation
+const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How far along an operation is in the documents it goes through
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub completed: u64,
+    /// Only an estimate, which the completed documents can exceed
+    pub estimated: Option<u64>,
+}
+
+/// The progress an operation reports in its metadata, for exports and imports
+pub fn document_progress(operation: &Operation) -> Option<Progress> {
+    let progress = &operation.metadata["progressDocuments"];
+    // int64 values are strings in JSON
+    let work = |key: &str| progress[key].as_str().and_then(|work| work.parse().ok());
+    Some(Progress {
+        completed: work("completedWork")?,
+        estimated: work("estimatedWork"),
+    })
+}
+
+/// What an operation does, such as `ExportDocuments`, from the type of its metadata
+pub fn operation_kind(operation: &Operation) -> Option<&str> {
+    let kind = operation.metadata["@type"].as_str()?;
+    let kind = kind.rsplit('.').next()?;
+    Some(kind.trim_end_matches("Metadata"))
+}
+
+/// Waits for operations to be done, see `DatabaseContext::operation_poller`
+pub struct OperationPoller<'a> {
+    ctx: &'a DatabaseContext,
+    initial_interval: Duration,
+    max_interval: Duration,
+    timeout: Option<Duration>,
+}
+
+impl<'a> OperationPoller<'a> {
+    pub(crate) fn new(ctx: &'a DatabaseContext) -> OperationPoller<'a> {
+        OperationPoller {
+            ctx,
+            initial_interval: DEFAULT_INITIAL_INTERVAL,
+            max_interval: DEFAULT_MAX_INTERVAL,
+            timeout: None,
+        }
+    }
+
+    /// Waits `initial` before checking on the operation again, twice as long after each
+    /// check that finds it still running, up to `max`
+    pub fn with_interval(mut self, initial: Duration, max: Duration) -> OperationPoller<'a> {
+        self.initial_interval = initial;
+        self.max_interval = max.max(initial);
+        self
+    }
+
+    /// Gives up with a DEADLINE_EXCEEDED error when the operation is still running after
+    /// `timeout`. The operation itself keeps running.
+    pub fn with_timeout(mut self, timeout: Duration) -> OperationPoller<'a> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Checks on the operation with said name or ID until it is done, passing it to
+    /// `on_progress` after each check. Fails with the error of the operation if it failed,
+    /// otherwise returns the operation as it ended.
+    pub fn wait<F>(&self, name: &str, mut on_progress: F) -> Result<Operation>
+    where
+        F: FnMut(&Operation),
+    {
+        let started = Instant::now();
+        let mut interval = self.initial_interval;
+        loop {
+            let operation = self.ctx.get_operation(name)?;
+            on_progress(&operation);
+            if let Some(status) = operation.error {
+                return Err(Error::from_code(status.code, status.message));
+            }
+            if operation.done {
+                return Ok(operation);
+            }
+            if let Some(timeout) = self.timeout {
+                let elapsed = started.elapsed();
+                if elapsed >= timeout {
+                    return Err(Error::DeadlineExceeded {
+                        message: format!("{} is still running", operation.name),
+                    });
+                }
+                interval = interval.min(timeout - elapsed);
+            }
+            thread::sleep(interval);
+            interval = (interval * 2).min(self.max_interval);
+        }
+    }
+}
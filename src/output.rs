@@ -0,0 +1,1056 @@
+// Rendering of documents in the format chosen with `--format`, after the projection
+// chosen with `--select`, or as lines filled in from `--template`
+
+use crate::dedupe::DuplicateGroup;
+use crate::delimited;
+use crate::jsonschema::Violation;
+use crate::schema::Schema;
+use crate::select::Select;
+use crate::stats::Stats;
+use crate::template::Template;
+use crate::terminal;
+use chrono::{DateTime, Utc};
+use libfiresale::api::{Document, TtlPolicy};
+use libfiresale::diff::{Change, DocumentDiff, FieldDiff};
+use libfiresale::errors::{Error, Result};
+use libfiresale::fieldpath::FieldPath;
+use libfiresale::firestore::databases::Database;
+use libfiresale::firestore::documents::ExplainMetrics;
+use libfiresale::firestore::types::Operation;
+use libfiresale::index::Index;
+use libfiresale::operation::{self, Progress};
+use libfiresale::size;
+use libfiresale::type_style::TypeStyle;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Longest value shown in a cell of the table format, longer values are cut short
+const MAX_CELL_WIDTH: usize = 40;
+
+// ANSI colors of highlighted JSON
+const KEY_COLOR: u8 = 34;
+const STRING_COLOR: u8 = 32;
+const NUMBER_COLOR: u8 = 36;
+const BOOL_COLOR: u8 = 33;
+const NULL_COLOR: u8 = 90;
+
+/// Names accepted by `--format`, in the order they are listed in help
+pub const FORMATS: &[&str] = &["json", "pretty-json", "yaml", "table", "ndjson", "csv"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    PrettyJson,
+    Yaml,
+    Table,
+    Ndjson,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Format> {
+        match name {
+            "json" => Ok(Format::Json),
+            "pretty-json" => Ok(Format::PrettyJson),
+            "yaml" => Ok(Format::Yaml),
+            "table" => Ok(Format::Table),
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            _ => Err(Error::InvalidArgument {
+                reason: format!("{} is not a known output format", name),
+            }),
+        }
+    }
+}
+
+/// How documents are written to stdout
+#[derive(Debug, Clone)]
+pub struct Printer {
+    pub format: Format,
+    /// Projection applied to every document, which may turn it into any number of values
+    pub select: Option<Select>,
+    /// Line every value is written as, in place of the format
+    pub template: Option<Template>,
+    /// How references, geo points, bytes and timestamps are rendered
+    pub types: TypeStyle,
+    /// Whether the table and CSV formats have columns for the metadata of documents,
+    /// which the other formats always write
+    pub meta: bool,
+}
+
+/// Metadata of a document written by `Document::to_json`, besides its ID
+pub const META_COLUMNS: &[&str] = &["name", "createTime", "updateTime"];
+
+impl Printer {
+    /// The values written for `document`, the document itself unless it is projected
+    fn render(&self, document: &Document) -> Result<Vec<Value>> {
+        self.render_json(document.to_json_with(&self.types))
+    }
+
+    /// The values written for a document already rendered as JSON
+    fn render_json(&self, value: Value) -> Result<Vec<Value>> {
+        match &self.select {
+            Some(select) => select.apply(&value),
+            None => Ok(vec![value]),
+        }
+    }
+
+    /// Whether values are written one per line rather than in the format: with a
+    /// template, or projected in the table and CSV formats which expect documents
+    fn writes_lines(&self) -> bool {
+        self.template.is_some()
+            || (self.select.is_some()
+                && (self.format == Format::Table || self.format == Format::Csv))
+    }
+
+    fn line(&self, value: &Value) -> String {
+        match &self.template {
+            Some(template) => template.render(value),
+            None => plain(value),
+        }
+    }
+}
+
+/// Writes `values` one per line
+fn print_lines(printer: &Printer, values: &[Value]) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for value in values {
+        writeln!(out, "{}", printer.line(value))?;
+    }
+    Ok(())
+}
+
+fn yaml_error(source: serde_yaml::Error) -> Error {
+    Error::Output {
+        reason: source.to_string(),
+    }
+}
+
+/// Renders a cell of the table format, strings are shown without quotes
+fn cell(value: Option<&Value>) -> String {
+    let text = match value {
+        None => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+    };
+    if text.chars().count() <= MAX_CELL_WIDTH {
+        return text;
+    }
+    let mut text = text.chars().take(MAX_CELL_WIDTH - 3).collect::<String>();
+    text.push_str("...");
+    text
+}
+
+/// Renders a value as plain text: strings as they are, other values as JSON
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Finds the value at a dotted field path such as `address.city`
+fn lookup<'a>(fields: &'a Value, field_path: &str) -> Option<&'a Value> {
+    field_path
+        .split('.')
+        .try_fold(fields, |value, segment| value.get(segment))
+}
+
+/// Writes documents as a table with an ID column followed by a column for each
+/// of `fields`, or for every top level field when none are given
+fn write_table<W: Write>(
+    out: &mut W,
+    documents: &[Value],
+    fields: Option<&[String]>,
+    meta: bool,
+) -> Result<()> {
+    let meta_columns = if meta { META_COLUMNS } else { &[] };
+    let columns = match fields {
+        Some(fields) => fields.to_vec(),
+        None => documents
+            .iter()
+            .filter_map(|document| document["fields"].as_object())
+            .flat_map(|fields| fields.keys().cloned())
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect(),
+    };
+    let mut rows = vec![std::iter::once(String::from("id"))
+        .chain(meta_columns.iter().map(|column| column.to_string()))
+        .chain(columns.iter().cloned())
+        .collect::<Vec<String>>()];
+    for document in documents {
+        let mut row = vec![cell(document.get("id"))];
+        // names are written whole, unlike fields, so they can be copied from the table
+        row.extend(meta_columns.iter().map(|column| plain(&document[*column])));
+        row.extend(
+            columns
+                .iter()
+                .map(|column| cell(lookup(&document["fields"], column))),
+        );
+        rows.push(row);
+    }
+    write_rows(out, rows)
+}
+
+/// Writes `rows` with every column padded to its widest cell
+fn write_rows<W: Write>(out: &mut W, rows: Vec<Vec<String>>) -> Result<()> {
+    let widths = (0..rows[0].len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<usize>>();
+    for row in rows {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect::<Vec<String>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Writes `documents` to stdout, as a list for the formats that have one.
+/// The table format only has columns for `fields` when they are given.
+/// Projected documents are written one value per line in the table and CSV formats.
+pub fn print_documents(
+    printer: &Printer,
+    documents: &[Document],
+    fields: Option<&[String]>,
+) -> Result<()> {
+    let mut values = Vec::with_capacity(documents.len());
+    for document in documents {
+        values.extend(printer.render(document)?);
+    }
+    if printer.writes_lines() {
+        return print_lines(printer, &values);
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match printer.format {
+        Format::Json => writeln!(out, "{}", json_text(&Value::Array(values), false)?)?,
+        Format::PrettyJson => writeln!(out, "{}", json_text(&Value::Array(values), true)?)?,
+        Format::Yaml => writeln!(
+            out,
+            "{}",
+            serde_yaml::to_string(&values).map_err(yaml_error)?
+        )?,
+        Format::Table => write_table(&mut out, &values, fields, printer.meta)?,
+        Format::Csv => delimited::write_documents(&mut out, &values, fields, printer.meta)?,
+        Format::Ndjson => {
+            for value in values {
+                writeln!(out, "{}", json_text(&value, false)?)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Indents every line of `text` by two spaces, as serde_json indents array elements
+fn indent(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("  {}", line))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Writes `documents` to stdout as they are yielded rather than once all of them are read,
+/// producing the same output as `print_documents`. The table and CSV formats need every
+/// row to choose their columns, so they are still written at the end.
+pub fn print_document_stream<I>(
+    printer: &Printer,
+    documents: I,
+    fields: Option<&[String]>,
+) -> Result<()>
+where
+    I: IntoIterator<Item = Result<Document>>,
+{
+    if printer.writes_lines() {
+        for document in documents {
+            print_lines(printer, &printer.render(&document?)?)?;
+        }
+        return Ok(());
+    }
+    let format = printer.format;
+    if format == Format::Table || format == Format::Csv {
+        let documents = documents.into_iter().collect::<Result<Vec<Document>>>()?;
+        return print_documents(printer, &documents, fields);
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut count = 0;
+    for document in documents {
+        for value in printer.render(&document?)? {
+            match format {
+                Format::Json => write!(
+                    out,
+                    "{}{}",
+                    if count == 0 { "[" } else { "," },
+                    json_text(&value, false)?
+                )?,
+                Format::PrettyJson => write!(
+                    out,
+                    "{}\n{}",
+                    if count == 0 { "[" } else { "," },
+                    indent(&json_text(&value, true)?)
+                )?,
+                Format::Yaml => {
+                    let item = serde_yaml::to_string(&[value]).map_err(yaml_error)?;
+                    if count == 0 {
+                        writeln!(out, "---")?;
+                    }
+                    writeln!(out, "{}", item.trim_start_matches("---\n").trim_end())?;
+                }
+                Format::Table | Format::Csv | Format::Ndjson => {
+                    writeln!(out, "{}", json_text(&value, false)?)?
+                }
+            }
+            out.flush()?;
+            count += 1;
+        }
+    }
+    match format {
+        Format::Json if count == 0 => writeln!(out, "[]")?,
+        Format::Json => writeln!(out, "]")?,
+        Format::PrettyJson if count == 0 => writeln!(out, "[]")?,
+        Format::PrettyJson => writeln!(out, "\n]")?,
+        Format::Yaml if count == 0 => writeln!(out, "--- []")?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Writes a list of names, such as collection IDs, to stdout.
+/// The table, CSV and NDJSON formats write one name per line.
+pub fn print_names(format: Format, names: &[String]) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Json => writeln!(out, "{}", json_text(&Value::from(names.to_vec()), false)?)?,
+        Format::PrettyJson => writeln!(out, "{}", json_text(&Value::from(names.to_vec()), true)?)?,
+        Format::Yaml => writeln!(out, "{}", serde_yaml::to_string(names).map_err(yaml_error)?)?,
+        Format::Table | Format::Csv | Format::Ndjson => {
+            for name in names {
+                writeln!(out, "{}", name)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the results of aggregations as a table of labels and values
+pub fn print_aggregations(results: &[(String, Value)]) -> Result<()> {
+    let mut rows = vec![vec![String::from("aggregation"), String::from("value")]];
+    rows.extend(
+        results
+            .iter()
+            .map(|(label, value)| vec![label.clone(), cell(Some(value))]),
+    );
+    let stdout = io::stdout();
+    write_rows(&mut stdout.lock(), rows)
+}
+
+/// Writes the plan of a query and the statistics of running it, if it was run,
+/// as a table of labels and values in the table and CSV formats
+pub fn print_explain(format: Format, metrics: &ExplainMetrics) -> Result<()> {
+    if format != Format::Table && format != Format::Csv {
+        return print_values(format, &[serde_json::to_value(metrics)?]);
+    }
+    let mut rows = vec![vec![String::from("metric"), String::from("value")]];
+    for index in &metrics.plan_summary.indexes_used {
+        let description = match (index.get("properties"), index.get("query_scope")) {
+            (Some(Value::String(properties)), Some(Value::String(scope))) => {
+                format!("{} {}", properties, scope)
+            }
+            _ => Value::Object(index.clone()).to_string(),
+        };
+        rows.push(vec![String::from("index used"), description]);
+    }
+    if let Some(stats) = &metrics.execution_stats {
+        rows.push(vec![
+            String::from("results returned"),
+            stats.results_returned.to_string(),
+        ]);
+        rows.push(vec![
+            String::from("read operations"),
+            stats.read_operations.to_string(),
+        ]);
+        rows.push(vec![
+            String::from("execution duration"),
+            stats.execution_duration.clone(),
+        ]);
+        for (name, value) in &stats.debug_stats {
+            rows.push(vec![name.replace('_', " "), cell(Some(value))]);
+        }
+    }
+    let stdout = io::stdout();
+    write_rows(&mut stdout.lock(), rows)
+}
+
+/// Writes composite indexes, a row each in the table and CSV formats
+/// and `firestore.indexes.json` entries along with their ID and state otherwise
+pub fn print_indexes(format: Format, indexes: &[Index]) -> Result<()> {
+    if format != Format::Table && format != Format::Csv {
+        let values = indexes
+            .iter()
+            .map(|index| {
+                let mut value = index.to_json();
+                value["id"] = serde_json::json!(index.id());
+                value["state"] = serde_json::json!(index.state);
+                value
+            })
+            .collect::<Vec<Value>>();
+        return print_values(format, &values);
+    }
+    let mut rows = vec![vec![
+        String::from("id"),
+        String::from("collection"),
+        String::from("scope"),
+        String::from("fields"),
+        String::from("state"),
+    ]];
+    for index in indexes {
+        let description = index.to_string();
+        // the fields follow the collection and scope in the description
+        let fields = description.splitn(2, ": ").nth(1).unwrap_or_default();
+        rows.push(vec![
+            index.id().unwrap_or_default().to_string(),
+            index.collection_group.clone(),
+            index.query_scope.name().to_lowercase(),
+            fields.to_string(),
+            index.state.clone().unwrap_or_default(),
+        ]);
+    }
+    let stdout = io::stdout();
+    write_rows(&mut stdout.lock(), rows)
+}
+
+/// Writes databases, a row each in the table and CSV formats
+/// and their resource as returned by the Admin API otherwise
+pub fn print_databases(format: Format, databases: &[Database]) -> Result<()> {
+    if format != Format::Table && format != Format::Csv {
+        let values = databases
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<std::result::Result<Vec<Value>, _>>()?;
+        return print_values(format, &values);
+    }
+    let mut rows = vec![vec![
+        String::from("id"),
+        String::from("location"),
+        String::from("mode"),
+        String::from("delete protection"),
+        String::from("pitr"),
+        String::from("created"),
+    ]];
+    // settings are shown without the prefix repeating their name, such as ENABLED
+    let setting = |value: &Option<String>, prefix: &str| {
+        value
+            .as_ref()
+            .map(|value| value.trim_start_matches(prefix).to_lowercase())
+            .unwrap_or_default()
+    };
+    for database in databases {
+        let name = database.name.clone().unwrap_or_default();
+        rows.push(vec![
+            name.rsplit('/').next().unwrap_or_default().to_string(),
+            database.location_id.clone().unwrap_or_default(),
+            match database.kind.as_ref().map(|kind| &**kind) {
+                Some("FIRESTORE_NATIVE") => String::from("native"),
+                Some("DATASTORE_MODE") => String::from("datastore"),
+                Some(kind) => kind.to_lowercase(),
+                None => String::new(),
+            },
+            setting(&database.delete_protection_state, "DELETE_PROTECTION_"),
+            setting(
+                &database.point_in_time_recovery_enablement,
+                "POINT_IN_TIME_RECOVERY_",
+            ),
+            database.create_time.clone().unwrap_or_default(),
+        ]);
+    }
+    let stdout = io::stdout();
+    write_rows(&mut stdout.lock(), rows)
+}
+
+/// Where an operation stands, as its metadata tells or from whether it is done
+fn operation_state(operation: &Operation) -> String {
+    match (
+        &operation.error,
+        operation.metadata["operationState"].as_str(),
+    ) {
+        (Some(_), _) => String::from("failed"),
+        (None, Some(state)) => state.to_lowercase(),
+        (None, None) if operation.done => String::from("done"),
+        (None, None) => String::from("running"),
+    }
+}
+
+/// Such as `400/1000 documents`
+fn operation_progress(operation: &Operation) -> String {
+    match operation::document_progress(operation) {
+        Some(Progress {
+            completed,
+            estimated: Some(estimated),
+        }) => format!("{}/{} documents", completed, estimated),
+        Some(Progress { completed, .. }) => format!("{} documents", completed),
+        None => String::new(),
+    }
+}
+
+/// Writes long-running operations, a row each in the table and CSV formats
+/// and as returned by the Admin API otherwise
+pub fn print_operations(format: Format, operations: &[Operation]) -> Result<()> {
+    if format != Format::Table && format != Format::Csv {
+        let values = operations
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<std::result::Result<Vec<Value>, _>>()?;
+        return print_values(format, &values);
+    }
+    let mut rows = vec![vec![
+        String::from("id"),
+        String::from("kind"),
+        String::from("state"),
+        String::from("started"),
+        String::from("progress"),
+    ]];
+    for operation in operations {
+        rows.push(vec![
+            operation
+                .name
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            operation::operation_kind(operation)
+                .unwrap_or_default()
+                .to_string(),
+            operation_state(operation),
+            cell(operation.metadata.get("startTime")),
+            operation_progress(operation),
+        ]);
+    }
+    let stdout = io::stdout();
+    write_rows(&mut stdout.lock(), rows)
+}
+
+/// Writes a long-running operation, a row for each of its details in the table and CSV
+/// formats and as returned by the Admin API otherwise
+pub fn print_operation(format: Format, operation: &Operation) -> Result<()> {
+    if format != Format::Table && format != Format::Csv {
+        return print_values(format, &[serde_json::to_value(operation)?]);
+    }
+    let mut rows = vec![
+        vec![String::from("detail"), String::from("value")],
+        vec![String::from("name"), operation.name.clone()],
+        vec![
+            String::from("kind"),
+            operation::operation_kind(operation)
+                .unwrap_or_default()
+                .to_string(),
+        ],
+        vec![String::from("state"), operation_state(operation)],
+    ];
+    for (detail, key) in &[("started", "startTime"), ("ended", "endTime")] {
+        if let Some(time) = operation.metadata.get(*key) {
+            rows.push(vec![detail.to_string(), cell(Some(time))]);
+        }
+    }
+    let progress = operation_progress(operation);
+    if !progress.is_empty() {
+        rows.push(vec![String::from("progress"), progress]);
+    }
+    if let Some(status) = &operation.error {
+        rows.push(vec![String::from("error"), status.message.clone()]);
+    }
+    let stdout = io::stdout();
+    write_rows(&mut stdout.lock(), rows)
+}
+
+/// Writes TTL policies, a row each in the table and CSV formats and an object each otherwise
+pub fn print_ttl_policies(format: Format, policies: &[TtlPolicy]) -> Result<()> {
+    if format != Format::Table && format != Format::Csv {
+        let values = policies
+            .iter()
+            .map(|policy| {
+                serde_json::json!({
+                    "collectionGroup": policy.collection_group,
+                    "fieldPath": policy.field_path,
+                    "state": policy.state,
+                })
+            })
+            .collect::<Vec<Value>>();
+        return print_values(format, &values);
+    }
+    let mut rows = vec![vec![
+        String::from("collection"),
+        String::from("field"),
+        String::from("state"),
+    ]];
+    for policy in policies {
+        rows.push(vec![
+            policy.collection_group.clone(),
+            policy.field_path.clone(),
+            policy.state.clone().unwrap_or_default(),
+        ]);
+    }
+    let stdout = io::stdout();
+    write_rows(&mut stdout.lock(), rows)
+}
+
+/// Writes the report of `schema`, a table of fields in the table and CSV formats
+pub fn print_schema(format: Format, schema: &Schema) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Table | Format::Csv => {
+            let mut rows = schema.rows();
+            for row in rows.iter_mut().skip(1) {
+                let example = row.pop().unwrap_or_default();
+                row.push(cell(Some(&Value::String(example))));
+            }
+            writeln!(out, "{} documents", schema.documents())?;
+            write_rows(&mut out, rows)?;
+        }
+        format => print_values(format, &[schema.to_json()])?,
+    }
+    Ok(())
+}
+
+/// Writes groups of duplicates, a row per document in the table and CSV formats
+/// and an object per group otherwise
+pub fn print_duplicates(format: Format, groups: &[DuplicateGroup]) -> Result<()> {
+    if format != Format::Table && format != Format::Csv {
+        let values = groups
+            .iter()
+            .map(|group| {
+                serde_json::json!({
+                    "key": group.key,
+                    "kept": group.kept,
+                    "duplicates": group.duplicates,
+                })
+            })
+            .collect::<Vec<Value>>();
+        return print_values(format, &values);
+    }
+    let mut rows = vec![vec![
+        String::from("key"),
+        String::from("document"),
+        String::from("status"),
+    ]];
+    for group in groups {
+        let key = cell(Some(&group.key));
+        rows.push(vec![key.clone(), group.kept.clone(), String::from("kept")]);
+        for duplicate in &group.duplicates {
+            rows.push(vec![
+                key.clone(),
+                duplicate.clone(),
+                String::from("duplicate"),
+            ]);
+        }
+    }
+    let stdout = io::stdout();
+    write_rows(&mut stdout.lock(), rows)
+}
+
+/// Share of the document size limit taken by `size`, such as `12.5%`
+fn limit_share(size: usize) -> String {
+    format!(
+        "{:.1}%",
+        size as f64 * 100.0 / size::MAX_DOCUMENT_SIZE as f64
+    )
+}
+
+/// Writes the size of the document at `path` along with what it is made of: its name,
+/// its top level fields from the largest and the overhead of every document
+pub fn print_document_size(format: Format, path: &str, document: &Document) -> Result<()> {
+    let mut fields = document
+        .fields()
+        .0
+        .iter()
+        .map(|(name, value)| (name.clone(), size::field_size(name, value)))
+        .collect::<Vec<(String, usize)>>();
+    fields.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let total = size::document_size(document);
+    if format != Format::Table && format != Format::Csv {
+        let value = serde_json::json!({
+            "path": path,
+            "size": total,
+            "name": size::name_size(path),
+            "fields": fields
+                .iter()
+                .map(|(name, size)| (name.clone(), Value::from(*size)))
+                .collect::<serde_json::Map<String, Value>>(),
+            "limit": size::MAX_DOCUMENT_SIZE,
+        });
+        return print_values(format, &[value]);
+    }
+    let mut rows = vec![vec![String::from("part"), String::from("size")]];
+    rows.push(vec![
+        String::from("(name)"),
+        size::name_size(path).to_string(),
+    ]);
+    for (name, size) in fields {
+        rows.push(vec![name, size.to_string()]);
+    }
+    rows.push(vec![
+        String::from("(document)"),
+        size::DOCUMENT_OVERHEAD.to_string(),
+    ]);
+    rows.push(vec![
+        String::from("total"),
+        format!("{} ({} of the limit)", total, limit_share(total)),
+    ]);
+    let stdout = io::stdout();
+    write_rows(&mut stdout.lock(), rows)
+}
+
+/// Writes the sizes of documents, a row each in the table and CSV formats
+/// and an object each otherwise
+pub fn print_sizes(format: Format, sizes: &[(String, usize)]) -> Result<()> {
+    if format != Format::Table && format != Format::Csv {
+        let values = sizes
+            .iter()
+            .map(|(path, size)| serde_json::json!({ "path": path, "size": size }))
+            .collect::<Vec<Value>>();
+        return print_values(format, &values);
+    }
+    let mut rows = vec![vec![
+        String::from("document"),
+        String::from("size"),
+        String::from("limit"),
+    ]];
+    for (path, size) in sizes {
+        rows.push(vec![path.clone(), size.to_string(), limit_share(*size)]);
+    }
+    let stdout = io::stdout();
+    write_rows(&mut stdout.lock(), rows)
+}
+
+/// Writes the statistics of a collection, its figures then a table of its fields in the
+/// table and CSV formats
+pub fn print_stats(format: Format, stats: &Stats) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Table | Format::Csv => {
+            write_rows(&mut out, stats.summary_rows())?;
+            writeln!(out)?;
+            write_rows(&mut out, stats.field_rows())?;
+        }
+        format => print_values(format, &[stats.to_json()])?,
+    }
+    Ok(())
+}
+
+/// Writes the violations of the document at `path` as they are found,
+/// a line of text each in the table and CSV formats and a JSON object each otherwise
+pub fn print_violations(format: Format, path: &str, violations: &[Violation]) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for violation in violations {
+        match format {
+            Format::Table | Format::Csv => {
+                let field = if violation.field.is_empty() {
+                    "(document)"
+                } else {
+                    &*violation.field
+                };
+                writeln!(out, "{}  {}  {}", path, field, violation.message)?
+            }
+            _ => writeln!(
+                out,
+                "{}",
+                serde_json::json!({
+                    "path": path,
+                    "field": violation.field,
+                    "error": violation.message,
+                })
+            )?,
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Writes a change seen by `watch` as soon as it happens, a line of text in the table
+/// and CSV formats and a JSON object with the document otherwise
+pub fn print_change(format: Format, change: &str, path: &str, document: &Value) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Table | Format::Csv => writeln!(out, "{}  {}", change, path)?,
+        _ => writeln!(
+            out,
+            "{}",
+            json_text(
+                &serde_json::json!({
+                    "change": change,
+                    "path": path,
+                    "document": document,
+                }),
+                false
+            )?
+        )?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Wraps `text` in the ANSI escape code `color`
+fn paint(text: String, color: u8, enabled: bool) -> String {
+    if enabled {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text
+    }
+}
+
+/// Starts a line indented `depth` levels deep when `pretty`
+fn newline(out: &mut String, pretty: bool, depth: usize) {
+    if pretty {
+        out.push('\n');
+        for _ in 0..depth {
+            out.push_str("  ");
+        }
+    }
+}
+
+/// Writes `value` as serde_json does, compact or pretty, with keys and values colored
+fn highlight(value: &Value, pretty: bool, depth: usize, out: &mut String) {
+    match value {
+        Value::Null => out.push_str(&paint(value.to_string(), NULL_COLOR, true)),
+        Value::Bool(_) => out.push_str(&paint(value.to_string(), BOOL_COLOR, true)),
+        Value::Number(_) => out.push_str(&paint(value.to_string(), NUMBER_COLOR, true)),
+        Value::String(_) => out.push_str(&paint(value.to_string(), STRING_COLOR, true)),
+        Value::Array(values) if values.is_empty() => out.push_str("[]"),
+        Value::Object(map) if map.is_empty() => out.push_str("{}"),
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, pretty, depth + 1);
+                highlight(value, pretty, depth + 1, out);
+            }
+            newline(out, pretty, depth);
+            out.push(']');
+        }
+        Value::Object(map) => {
+            out.push('{');
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, pretty, depth + 1);
+                let key = Value::String(key.clone()).to_string();
+                out.push_str(&paint(key, KEY_COLOR, true));
+                out.push_str(if pretty { ": " } else { ":" });
+                highlight(value, pretty, depth + 1, out);
+            }
+            newline(out, pretty, depth);
+            out.push('}');
+        }
+    }
+}
+
+/// `value` as compact or pretty JSON, highlighted when stdout takes colors
+fn json_text(value: &Value, pretty: bool) -> Result<String> {
+    if terminal::color_stdout() {
+        let mut text = String::new();
+        highlight(value, pretty, 0, &mut text);
+        Ok(text)
+    } else if pretty {
+        Ok(serde_json::to_string_pretty(value)?)
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+/// Renders a differing field as `-` for removed, `+` for added or `~` for changed
+fn diff_line(diff: &FieldDiff, color: bool) -> String {
+    let path = diff.field_path();
+    match &diff.change {
+        Change::Removed(old) => paint(format!("- {}: {}", path, old.to_json()), 31, color),
+        Change::Added(new) => paint(format!("+ {}: {}", path, new.to_json()), 32, color),
+        Change::Changed(old, new) => paint(
+            format!("~ {}: {} -> {}", path, old.to_json(), new.to_json()),
+            33,
+            color,
+        ),
+    }
+}
+
+/// Writes a revision of a document seen by `history`: the time and kind of the change
+/// followed by the indented field differences in the table and CSV formats, and a JSON
+/// object with the differing fields otherwise
+pub fn print_revision(
+    format: Format,
+    time: DateTime<Utc>,
+    change: &str,
+    diffs: &[FieldDiff],
+) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Table | Format::Csv => {
+            let color = terminal::color_stdout();
+            writeln!(out, "{}  {}", time.to_rfc3339(), change)?;
+            for diff in diffs {
+                writeln!(out, "    {}", diff_line(diff, color))?;
+            }
+        }
+        _ => {
+            let fields = diffs
+                .iter()
+                .map(|diff| {
+                    let (old, new) = match &diff.change {
+                        Change::Removed(old) => (old.to_json(), Value::Null),
+                        Change::Added(new) => (Value::Null, new.to_json()),
+                        Change::Changed(old, new) => (old.to_json(), new.to_json()),
+                    };
+                    serde_json::json!({ "field": diff.field_path(), "old": old, "new": new })
+                })
+                .collect::<Vec<Value>>();
+            writeln!(
+                out,
+                "{}",
+                json_text(
+                    &serde_json::json!({
+                        "time": time.to_rfc3339(),
+                        "change": change,
+                        "fields": fields,
+                    }),
+                    false
+                )?
+            )?
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Writes one line per differing field, `-` for removed, `+` for added and `~` for
+/// changed fields, colored when stdout is a terminal
+pub fn print_diff(diffs: &[FieldDiff]) -> Result<()> {
+    let color = terminal::color_stdout();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for diff in diffs {
+        writeln!(out, "{}", diff_line(diff, color))?;
+    }
+    Ok(())
+}
+
+/// Writes documents as their differences are found, `-` for those missing from the second
+/// collection, `+` for extra ones and `~` followed by the indented field differences for
+/// those that changed. A summary of the counts is written to stderr at the end.
+pub fn print_collection_diff<I>(diffs: I) -> Result<()>
+where
+    I: IntoIterator<Item = Result<DocumentDiff>>,
+{
+    let color = terminal::color_stdout();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let (mut missing, mut extra, mut changed) = (0, 0, 0);
+    for diff in diffs {
+        match diff? {
+            DocumentDiff::Missing(id) => {
+                missing += 1;
+                writeln!(out, "{}", paint(format!("- {}", id), 31, color))?;
+            }
+            DocumentDiff::Extra(id) => {
+                extra += 1;
+                writeln!(out, "{}", paint(format!("+ {}", id), 32, color))?;
+            }
+            DocumentDiff::Changed(id, fields) => {
+                changed += 1;
+                writeln!(out, "{}", paint(format!("~ {}", id), 33, color))?;
+                for field in &fields {
+                    writeln!(out, "    {}", diff_line(field, color))?;
+                }
+            }
+        }
+        out.flush()?;
+    }
+    status!("{} missing, {} extra, {} changed", missing, extra, changed);
+    Ok(())
+}
+
+/// Writes the value of `field_path` in `document` to stdout. With `raw`, or in the
+/// table and csv formats, strings are written as they are and other values as JSON.
+pub fn print_field(
+    printer: &Printer,
+    document: &Document,
+    field_path: &str,
+    raw: bool,
+) -> Result<()> {
+    let value = FieldPath::parse(field_path)?
+        .get(document.fields())
+        .map(|value| value.to_json_with(&printer.types))
+        .ok_or_else(|| Error::NotFound {
+            message: format!("{} has no field {}", document.name(), field_path),
+        })?;
+    if raw {
+        writeln!(io::stdout(), "{}", plain(&value))?;
+        return Ok(());
+    }
+    print_values(printer.format, &[value])
+}
+
+/// Writes each of `values` on its own, the table and CSV formats as plain text
+fn print_values(format: Format, values: &[Value]) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for value in values {
+        match format {
+            Format::Json | Format::Ndjson => writeln!(out, "{}", json_text(value, false)?)?,
+            Format::PrettyJson => writeln!(out, "{}", json_text(value, true)?)?,
+            Format::Yaml => writeln!(out, "{}", serde_yaml::to_string(value).map_err(yaml_error)?)?,
+            Format::Table | Format::Csv => writeln!(out, "{}", plain(value))?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes a single document to stdout
+pub fn print_document(printer: &Printer, document: &Document) -> Result<()> {
+    print_document_json(printer, document.to_json_with(&printer.types))
+}
+
+/// Writes a single document already rendered as JSON to stdout, such as one with its
+/// references embedded
+pub fn print_document_json(printer: &Printer, value: Value) -> Result<()> {
+    if printer.writes_lines() {
+        return print_lines(printer, &printer.render_json(value)?);
+    }
+    if printer.select.is_some() {
+        return print_values(printer.format, &printer.render_json(value)?);
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match printer.format {
+        Format::Json | Format::Ndjson => writeln!(out, "{}", json_text(&value, false)?)?,
+        Format::PrettyJson => writeln!(out, "{}", json_text(&value, true)?)?,
+        Format::Yaml => writeln!(
+            out,
+            "{}",
+            serde_yaml::to_string(&value).map_err(yaml_error)?
+        )?,
+        Format::Table => write_table(&mut out, &[value], None, printer.meta)?,
+        Format::Csv => delimited::write_documents(&mut out, &[value], None, printer.meta)?,
+    }
+    Ok(())
+}
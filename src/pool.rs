@@ -0,0 +1,151 @@
+// Commits of bulk writes spread over worker threads. Every worker commits through its
+// own clone of the `DatabaseContext`, at most `DatabaseContext::concurrency` commits are
+// in flight at once and their completions are reported in the order the batches were
+// submitted, so the running total given to `progress` only ever grows. Commits are
+// paced by the rate limiter of the context.
+
+use crate::api::{DatabaseContext, WriteBatch};
+use crate::errors::Result;
+use crate::firestore::write::Write;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A batch to commit, numbered in the order it was submitted
+type Job = (usize, Vec<Write>);
+
+/// The number of a committed batch, how many writes it held and how its commit went
+type Outcome = (usize, usize, Result<()>);
+
+/// Commits batches as they are submitted while the caller carries on reading.
+/// With a concurrency of one every batch is committed on the calling thread.
+pub struct CommitPool<'a> {
+    ctx: &'a DatabaseContext,
+    /// `None` when batches are committed on the calling thread
+    jobs: Option<Sender<Job>>,
+    outcomes: Receiver<Outcome>,
+    workers: Vec<JoinHandle<()>>,
+    submitted: usize,
+    /// Batches handed to a worker whose outcome has not been received yet
+    in_flight: usize,
+    /// Outcomes received ahead of those of earlier batches, by batch number
+    early: BTreeMap<usize, (usize, Result<()>)>,
+    reported: usize,
+    written: usize,
+    progress: Box<dyn FnMut(usize) + 'a>,
+}
+
+fn work(ctx: DatabaseContext, jobs: Arc<Mutex<Receiver<Job>>>, outcomes: Sender<Outcome>) {
+    loop {
+        // the lock is released before committing, letting the other workers take jobs
+        let job = jobs.lock().map(|jobs| jobs.recv());
+        let (number, writes) = match job {
+            Ok(Ok(job)) => job,
+            _ => return,
+        };
+        let count = writes.len();
+        let result = ctx.commit_throttled(writes).map(|_| ());
+        if outcomes.send((number, count, result)).is_err() {
+            return;
+        }
+    }
+}
+
+impl<'a> CommitPool<'a> {
+    /// Creates a pool committing to the database of `ctx`, calling `progress` with
+    /// the running total of written documents after each commit
+    pub fn new<F>(ctx: &'a DatabaseContext, progress: F) -> CommitPool<'a>
+    where
+        F: FnMut(usize) + 'a,
+    {
+        let (outcome_sender, outcomes) = mpsc::channel();
+        let mut pool = CommitPool {
+            ctx,
+            jobs: None,
+            outcomes,
+            workers: Vec::new(),
+            submitted: 0,
+            in_flight: 0,
+            early: BTreeMap::new(),
+            reported: 0,
+            written: 0,
+            progress: Box::new(progress),
+        };
+        if ctx.concurrency() > 1 {
+            let (jobs, job_receiver) = mpsc::channel();
+            let job_receiver = Arc::new(Mutex::new(job_receiver));
+            for _ in 0..ctx.concurrency() {
+                let (ctx, job_receiver, outcome_sender) =
+                    (ctx.clone(), job_receiver.clone(), outcome_sender.clone());
+                pool.workers.push(thread::spawn(move || {
+                    work(ctx, job_receiver, outcome_sender)
+                }));
+            }
+            pool.jobs = Some(jobs);
+        }
+        pool
+    }
+
+    /// Hands `batch`, which must write to the database of the pool, to the next
+    /// free worker, waiting for one while every worker is busy. Fails with the error
+    /// of the first failed commit once every commit before it has been reported.
+    pub fn submit(&mut self, batch: WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let jobs = match &self.jobs {
+            Some(jobs) => jobs.clone(),
+            None => {
+                self.written += batch.len();
+                self.ctx.commit_throttled(batch.into_writes())?;
+                (self.progress)(self.written);
+                return Ok(());
+            }
+        };
+        while self.in_flight >= self.ctx.concurrency() {
+            self.receive()?;
+        }
+        jobs.send((self.submitted, batch.into_writes()))
+            .expect("workers run until the pool is dropped");
+        self.submitted += 1;
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Waits for one outcome and reports every outcome that is next in line
+    fn receive(&mut self) -> Result<()> {
+        let (number, count, result) = self
+            .outcomes
+            .recv()
+            .expect("workers run until the pool is dropped");
+        self.in_flight -= 1;
+        self.early.insert(number, (count, result));
+        while let Some((count, result)) = self.early.remove(&self.reported) {
+            result?;
+            self.reported += 1;
+            self.written += count;
+            (self.progress)(self.written);
+        }
+        Ok(())
+    }
+
+    /// Waits for every submitted batch to be committed, returning the number of
+    /// documents written
+    pub fn finish(mut self) -> Result<usize> {
+        while self.reported < self.submitted {
+            self.receive()?;
+        }
+        Ok(self.written)
+    }
+}
+
+impl<'a> Drop for CommitPool<'a> {
+    fn drop(&mut self) {
+        // closing the queue lets the workers finish the jobs they hold and return
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// Progress of bulk commands on stderr. Commands that know how many documents they will
+// go through draw a bar with the time left, the others a spinner, and both show how
+// many documents were processed at what rate. Nothing is drawn with `--quiet` or when
+// stderr is not a terminal, the summary line printed once a command is done stays.
+
+use crate::terminal;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::time::Instant;
+
+const BAR_TEMPLATE: &'static str = "{bar:40} {pos}/{len} documents {msg}, {eta} left";
+const SPINNER_TEMPLATE: &'static str = "{spinner} {pos} documents {msg}, {elapsed} elapsed";
+
+fn style(has_total: bool) -> ProgressStyle {
+    if has_total {
+        ProgressStyle::default_bar().template(BAR_TEMPLATE)
+    } else {
+        ProgressStyle::default_spinner().template(SPINNER_TEMPLATE)
+    }
+}
+
+pub struct Bar {
+    bar: ProgressBar,
+    started: Instant,
+}
+
+impl Bar {
+    /// A spinner counting documents, hidden when `quiet` or when stderr is not a terminal
+    pub fn new(quiet: bool) -> Bar {
+        Bar::start(None, quiet)
+    }
+
+    /// A bar counting up to `total` documents
+    pub fn with_total(total: usize, quiet: bool) -> Bar {
+        Bar::start(Some(total), quiet)
+    }
+
+    fn start(total: Option<usize>, quiet: bool) -> Bar {
+        let bar = if quiet || !terminal::stderr_is_terminal() {
+            ProgressBar::hidden()
+        } else {
+            let bar = match total {
+                Some(total) => ProgressBar::new(total as u64),
+                None => ProgressBar::new_spinner(),
+            };
+            bar.set_style(style(total.is_some()));
+            bar.set_message("0/s");
+            bar
+        };
+        Bar {
+            bar,
+            started: Instant::now(),
+        }
+    }
+
+    /// Whether anything is drawn, so that totals are only worked out when they are shown
+    pub fn is_hidden(&self) -> bool {
+        self.bar.is_hidden()
+    }
+
+    /// Turns a spinner into a bar once the number of documents is known
+    pub fn set_total(&self, total: usize) {
+        if self.bar.is_hidden() {
+            return;
+        }
+        self.bar.set_length(total as u64);
+        self.bar.set_style(style(true));
+    }
+
+    /// Moves to `done` documents processed
+    pub fn set(&self, done: usize) {
+        if self.bar.is_hidden() {
+            return;
+        }
+        let elapsed = self.started.elapsed();
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        let rate = if seconds > 0.0 {
+            done as f64 / seconds
+        } else {
+            0.0
+        };
+        self.bar.set_message(&format!("{:.0}/s", rate));
+        self.bar.set_position(done as u64);
+    }
+
+    /// Takes the bar off the terminal, leaving the line to the summary
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
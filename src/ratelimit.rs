@@ -0,0 +1,87 @@
+// Throttling of bulk writes. Firestore asks for new traffic to start at 500 writes per
+// second and grow by at most 50% every 5 minutes, and answers RESOURCE_EXHAUSTED when a
+// client goes faster than a collection can take. Bulk writes take tokens from a bucket
+// refilled at the configured rate, and every RESOURCE_EXHAUSTED halves the rate.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Rate a limiter without a configured rate falls back to once Firestore pushes back,
+/// the starting point of the 500/50/5 ramp-up guidance
+pub const RAMP_UP_WRITES_PER_SECOND: f64 = 500.0;
+
+/// The slowest rate slowing down goes to
+const MIN_WRITES_PER_SECOND: f64 = 1.0;
+
+#[derive(Debug)]
+struct Bucket {
+    /// Writes per second, `None` while unlimited
+    rate: Option<f64>,
+    /// Writes that may be made without waiting, negative while writers are waiting
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// A token bucket shared by every thread writing through clones of a `DatabaseContext`
+#[derive(Debug)]
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    /// A limiter allowing `rate` writes per second, or any number when `None`
+    pub fn new(rate: Option<f64>) -> RateLimiter {
+        RateLimiter {
+            bucket: Mutex::new(Bucket {
+                rate,
+                tokens: rate.unwrap_or(0.0),
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Current rate in writes per second, `None` while unlimited
+    pub fn rate(&self) -> Option<f64> {
+        self.bucket.lock().unwrap().rate
+    }
+
+    /// Blocks until `writes` writes may be made. A bucket holds at most a second of
+    /// writes, larger requests are let through by going into debt that later requests
+    /// wait out, so callers are served in the order they asked.
+    pub fn acquire(&self, writes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let rate = match bucket.rate {
+                Some(rate) => rate,
+                None => return,
+            };
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled_at);
+            let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate) - writes as f64;
+            bucket.refilled_at = now;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_millis((-bucket.tokens / rate * 1000.0) as u64)
+        };
+        thread::sleep(wait);
+    }
+
+    /// Halves the rate after Firestore answered RESOURCE_EXHAUSTED, starting from
+    /// `RAMP_UP_WRITES_PER_SECOND` when there was no limit
+    pub fn slow_down(&self) {
+        let mut bucket = self.bucket.lock().unwrap();
+        let rate = match bucket.rate {
+            Some(rate) => (rate / 2.0).max(MIN_WRITES_PER_SECOND),
+            None => RAMP_UP_WRITES_PER_SECOND,
+        };
+        debug!(
+            "Firestore pushed back, slowing down to {} writes per second",
+            rate
+        );
+        bucket.rate = Some(rate);
+        bucket.tokens = bucket.tokens.min(rate);
+    }
+}
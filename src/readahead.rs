@@ -0,0 +1,97 @@
+// Listing of several collections at once for export. Every collection is listed on a
+// worker thread, up to `concurrency` of them at a time, and its pages are handed over in
+// the order the collections were given. A worker stays at most `PAGES_AHEAD` pages ahead
+// of the consumer, so no more than a few pages per worker are ever held in memory.
+
+use libfiresale::api::{DatabaseContext, Document};
+use libfiresale::errors::Result;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Pages a worker may list before the consumer takes the first of them
+const PAGES_AHEAD: usize = 2;
+
+pub struct Page {
+    pub documents: Vec<Document>,
+    /// Token to continue listing the collection after this page, `None` after the last page
+    pub resume_token: Option<String>,
+}
+
+/// A collection to list, the page to start from and where its pages go
+type Job = (String, Option<String>, SyncSender<Result<Page>>);
+
+/// Lists a collection page by page into `pages`, stopping at the first error
+/// or once the consumer hangs up
+fn list(
+    ctx: &DatabaseContext,
+    collection_name: &str,
+    page_token: Option<String>,
+    page_size: Option<i32>,
+    pages: &SyncSender<Result<Page>>,
+) {
+    let mut documents = ctx.iter_documents(collection_name, page_size);
+    if let Some(page_token) = page_token {
+        documents = documents.resume_from(page_token);
+    }
+    let mut page = Vec::new();
+    while let Some(document) = documents.next() {
+        match document {
+            Ok(document) => page.push(document),
+            Err(e) => {
+                let _ = pages.send(Err(e));
+                return;
+            }
+        }
+        if let Some(resume_token) = documents.resume_token() {
+            let full = Page {
+                documents: mem::replace(&mut page, Vec::new()),
+                resume_token: Some(resume_token.to_string()),
+            };
+            if pages.send(Ok(full)).is_err() {
+                return;
+            }
+        }
+    }
+    let _ = pages.send(Ok(Page {
+        documents: page,
+        resume_token: None,
+    }));
+}
+
+/// Starts listing every `(collection, page token)` of `collections` with up to
+/// `ctx.concurrency()` collections listed at once, returning the pages of each
+/// collection in the order they were given. Collections are started in order,
+/// so the first one not yet consumed is always being listed.
+pub fn read_collections(
+    ctx: &DatabaseContext,
+    collections: Vec<(String, Option<String>)>,
+    page_size: Option<i32>,
+) -> Vec<Receiver<Result<Page>>> {
+    let (jobs, job_receiver) = mpsc::channel::<Job>();
+    let mut receivers = Vec::new();
+    let workers = ctx.concurrency().min(collections.len());
+    for (collection_name, page_token) in collections {
+        let (pages, receiver) = mpsc::sync_channel(PAGES_AHEAD);
+        jobs.send((collection_name, page_token, pages))
+            .expect("the queue is open until every job is sent");
+        receivers.push(receiver);
+    }
+    // the workers return once the queue runs dry
+    drop(jobs);
+    let job_receiver = Arc::new(Mutex::new(job_receiver));
+    for _ in 0..workers {
+        let (ctx, job_receiver) = (ctx.clone(), job_receiver.clone());
+        thread::spawn(move || loop {
+            let job = job_receiver.lock().map(|jobs| jobs.recv());
+            match job {
+                Ok(Ok((collection_name, page_token, pages))) => {
+                    list(&ctx, &*collection_name, page_token, page_size, &pages)
+                }
+                _ => return,
+            }
+        });
+    }
+    receivers
+}
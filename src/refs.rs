@@ -0,0 +1,104 @@
+// Following of references for `get --resolve-refs`: the documents a document references
+// are read and embedded in place of their references, and so on up to a given depth.
+// References to a document that already embeds them, to missing documents or to other
+// databases are rendered as they would be otherwise.
+
+use libfiresale::api::{relative_path, split_document_path, DatabaseContext, Document};
+use libfiresale::api::{FirestoreFields, FirestoreType};
+use libfiresale::errors::{Error, Result};
+use libfiresale::type_style::TypeStyle;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Embeds referenced documents, rendering them as documents are printed
+pub struct Resolver<'a> {
+    ctx: &'a DatabaseContext,
+    style: TypeStyle,
+}
+
+/// The part of a resource name before the document path, naming its database
+fn database_of(name: &str) -> &str {
+    &name[..name.len() - relative_path(name).len()]
+}
+
+fn collect_references<'f>(value: &'f FirestoreType, names: &mut Vec<&'f str>) {
+    match value {
+        FirestoreType::Reference(name) => names.push(name),
+        FirestoreType::Array(array) => {
+            for value in &array.values {
+                collect_references(value, names);
+            }
+        }
+        FirestoreType::Map(map) => {
+            for value in map.fields.0.values() {
+                collect_references(value, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The resource names of all documents referenced in `fields`, once each
+fn references(fields: &FirestoreFields) -> Vec<&str> {
+    let mut names = Vec::new();
+    for value in fields.0.values() {
+        collect_references(value, &mut names);
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(ctx: &'a DatabaseContext, style: TypeStyle) -> Resolver<'a> {
+        Resolver { ctx, style }
+    }
+
+    /// The document as JSON, with referenced documents embedded `depth` levels deep
+    pub fn resolve(&self, document: &Document, depth: usize) -> Result<Value> {
+        self.embed(document, depth, &mut vec![document.name().to_string()])
+    }
+
+    /// `ancestors` holds the names of the documents `document` is embedded in, itself
+    /// included, which are not embedded again
+    fn embed(
+        &self,
+        document: &Document,
+        depth: usize,
+        ancestors: &mut Vec<String>,
+    ) -> Result<Value> {
+        let mut embedded = HashMap::new();
+        if depth > 0 {
+            for name in references(document.fields()) {
+                if ancestors.iter().any(|ancestor| ancestor == name)
+                    || database_of(name) != database_of(document.name())
+                {
+                    continue;
+                }
+                let referenced = match self.read(name)? {
+                    Some(referenced) => referenced,
+                    None => continue,
+                };
+                ancestors.push(name.to_string());
+                let value = self.embed(&referenced, depth - 1, ancestors)?;
+                ancestors.pop();
+                embedded.insert(name, value);
+            }
+        }
+        let mut value = document.to_json_with(&self.style);
+        value["fields"] = document
+            .fields()
+            .to_json_embedding(&self.style, &|name| embedded.get(name).cloned());
+        Ok(value)
+    }
+
+    /// The document with said resource name, `None` if it does not exist
+    fn read(&self, name: &str) -> Result<Option<Document>> {
+        let (collection, id) = split_document_path(relative_path(name));
+        match self.ctx.get_document(collection, id) {
+            Ok(document) => Ok(Some(document)),
+            Err(Error::NotFound { .. }) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+// Schema inference for `schema`: the fields seen across the documents of a collection,
+// with the types each of them had, how often it was null or missing and an example value.
+// Maps are described both as a field of their own and by their nested fields in dotted form.
+
+use libfiresale::api::{FirestoreFields, FirestoreType};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// The name of the type of a Firestore value, as shown in the report
+fn type_name(value: &FirestoreType) -> &'static str {
+    match value {
+        FirestoreType::Integer(_) => "integer",
+        FirestoreType::Double(_) => "double",
+        FirestoreType::Boolean(_) => "boolean",
+        FirestoreType::String(_) => "string",
+        FirestoreType::GeoLocation(_) => "geopoint",
+        FirestoreType::Array(_) => "array",
+        FirestoreType::Map(_) => "map",
+        FirestoreType::Timestamp(_) => "timestamp",
+        FirestoreType::Null(_) => "null",
+        FirestoreType::Reference(_) => "reference",
+        FirestoreType::Bytes(_) => "bytes",
+    }
+}
+
+/// What was seen of a single field path
+#[derive(Debug, Default)]
+struct FieldStats {
+    /// Documents having the field, by the type of its value
+    types: BTreeMap<&'static str, usize>,
+    /// First value seen other than null
+    example: Option<Value>,
+}
+
+impl FieldStats {
+    fn present(&self) -> usize {
+        self.types.values().sum()
+    }
+
+    fn nulls(&self) -> usize {
+        self.types.get("null").cloned().unwrap_or(0)
+    }
+}
+
+/// The union of the fields of the documents observed so far
+#[derive(Debug, Default)]
+pub struct Schema {
+    documents: usize,
+    fields: BTreeMap<String, FieldStats>,
+}
+
+/// Share of `count` in `total` as a percentage with one decimal
+fn percent(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    (count as f64 * 1000.0 / total as f64).round() / 10.0
+}
+
+impl Schema {
+    /// Adds the fields of a document to the schema
+    pub fn observe(&mut self, fields: &FirestoreFields) {
+        self.documents += 1;
+        self.observe_fields("", fields);
+    }
+
+    fn observe_fields(&mut self, prefix: &str, fields: &FirestoreFields) {
+        for (key, value) in &fields.0 {
+            let path = format!("{}{}", prefix, key);
+            let stats = self.fields.entry(path.clone()).or_default();
+            *stats.types.entry(type_name(value)).or_insert(0) += 1;
+            match value {
+                FirestoreType::Null(_) => {}
+                FirestoreType::Map(map) => {
+                    if stats.example.is_none() && map.fields.0.is_empty() {
+                        stats.example = Some(value.to_json());
+                    }
+                    self.observe_fields(&format!("{}.", path), &map.fields);
+                }
+                value => {
+                    if stats.example.is_none() {
+                        stats.example = Some(value.to_json());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Number of documents observed
+    pub fn documents(&self) -> usize {
+        self.documents
+    }
+
+    /// The report as JSON, an object of field paths
+    pub fn to_json(&self) -> Value {
+        let fields = self
+            .fields
+            .iter()
+            .map(|(path, stats)| {
+                let missing = self.documents - stats.present();
+                let field = json!({
+                    "types": stats.types,
+                    "present": stats.present(),
+                    "null": stats.nulls(),
+                    "missing": missing,
+                    "nullRate": percent(stats.nulls(), self.documents),
+                    "missingRate": percent(missing, self.documents),
+                    "example": stats.example,
+                });
+                (path.clone(), field)
+            })
+            .collect::<serde_json::Map<String, Value>>();
+        json!({
+            "documents": self.documents,
+            "fields": fields,
+        })
+    }
+
+    /// The report as rows of a table, after a header row
+    pub fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![vec![
+            String::from("field"),
+            String::from("types"),
+            String::from("null"),
+            String::from("missing"),
+            String::from("example"),
+        ]];
+        for (path, stats) in &self.fields {
+            let types = stats
+                .types
+                .iter()
+                .map(|(name, count)| format!("{} {}%", name, percent(*count, self.documents)))
+                .collect::<Vec<String>>()
+                .join(", ");
+            let missing = self.documents - stats.present();
+            rows.push(vec![
+                path.clone(),
+                types,
+                format!("{}%", percent(stats.nulls(), self.documents)),
+                format!("{}%", percent(missing, self.documents)),
+                stats
+                    .example
+                    .as_ref()
+                    .map(Value::to_string)
+                    .unwrap_or_default(),
+            ]);
+        }
+        rows
+    }
+}
@@ -0,0 +1,292 @@
+// Document templates for `seed`: JSON whose strings may hold placeholders such as
+// {{name}}, {{int 1 100}}, {{uuid}} or {{timestamp -30d}}, filled in with new random
+// values for every document. A string that is a single placeholder takes the type of its
+// value, so "{{int 1 100}}" is an integer and "{{timestamp -30d}}" a timestamp, while
+// placeholders within longer strings are written as text.
+
+use chrono::{DateTime, Utc};
+use libfiresale::api::{Array, FirestoreFields, FirestoreType, Map};
+use libfiresale::errors::{Error, Result};
+use libfiresale::id_strategy;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde_json::Value;
+
+const OPEN: &'static str = "{{";
+const CLOSE: &'static str = "}}";
+
+const FIRST_NAMES: &[&str] = &[
+    "Alice", "Bob", "Carol", "Dave", "Erin", "Frank", "Grace", "Heidi", "Ivan", "Judy", "Mallory",
+    "Niaj", "Olivia", "Peggy", "Rupert", "Sybil", "Trent", "Victor", "Walter", "Yara",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Smith", "Johnson", "Williams", "Brown", "Jones", "Garcia", "Miller", "Davis", "Martin",
+    "Lopez", "Wilson", "Anderson", "Thomas", "Taylor", "Moore", "Jackson", "Lee", "Walker",
+    "Young", "King",
+];
+
+const WORDS: &[&str] = &[
+    "apple", "river", "stone", "cloud", "ember", "forest", "harbor", "meadow", "orbit", "pepper",
+    "quartz", "saddle", "timber", "velvet", "willow", "anchor", "beacon", "canyon", "delta",
+    "falcon",
+];
+
+/// A value generated anew for every document
+#[derive(Debug, Clone)]
+enum Placeholder {
+    FirstName,
+    LastName,
+    /// A first and a last name
+    Name,
+    Email,
+    Word,
+    Uuid,
+    Bool,
+    /// Between both bounds, included
+    Int(i64, i64),
+    Float(f64, f64),
+    /// One of the given words
+    Pick(Vec<String>),
+    /// Between now and this many seconds from now, in the past when negative
+    Timestamp(i64),
+    /// Number of the document, from 1
+    Index,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(Value),
+    Text(Vec<Part>),
+    Array(Vec<Node>),
+    Object(Vec<(String, Node)>),
+}
+
+/// A template given with `seed --template`
+#[derive(Debug, Clone)]
+pub struct SeedTemplate {
+    fields: Vec<(String, Node)>,
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidArgument { reason }
+}
+
+/// Reads a span such as `-30d` or `+2h` as signed seconds
+fn parse_offset(value: &str) -> Result<i64> {
+    let (sign, span) = match value.chars().next() {
+        Some('-') => (-1, &value[1..]),
+        Some('+') => (1, &value[1..]),
+        _ => (1, value),
+    };
+    let duration = crate::parse_duration(span)
+        .map_err(invalid)?
+        .unwrap_or_default();
+    Ok(sign * duration.as_secs() as i64)
+}
+
+impl Placeholder {
+    fn parse(text: &str) -> Result<Placeholder> {
+        let words = text.split_whitespace().collect::<Vec<&str>>();
+        let number = |i: usize| -> Result<f64> {
+            words
+                .get(i)
+                .and_then(|word| word.parse().ok())
+                .ok_or_else(|| {
+                    invalid(format!("{{{{{}}}}} takes a lower and an upper bound", text))
+                })
+        };
+        let placeholder = match words.first().cloned().unwrap_or_default() {
+            "first_name" => Placeholder::FirstName,
+            "last_name" => Placeholder::LastName,
+            "name" => Placeholder::Name,
+            "email" => Placeholder::Email,
+            "word" => Placeholder::Word,
+            "uuid" => Placeholder::Uuid,
+            "bool" => Placeholder::Bool,
+            "index" => Placeholder::Index,
+            "int" => {
+                let (low, high) = (number(1)? as i64, number(2)? as i64);
+                Placeholder::Int(low.min(high), low.max(high))
+            }
+            "float" => {
+                let (low, high) = (number(1)?, number(2)?);
+                Placeholder::Float(low.min(high), low.max(high))
+            }
+            "pick" if words.len() > 1 => {
+                Placeholder::Pick(words[1..].iter().map(|word| word.to_string()).collect())
+            }
+            "timestamp" => match words.get(1) {
+                Some(offset) => Placeholder::Timestamp(parse_offset(offset)?),
+                None => Placeholder::Timestamp(0),
+            },
+            _ => {
+                return Err(invalid(format!(
+                    "{{{{{}}}}} is not a known placeholder",
+                    text
+                )))
+            }
+        };
+        Ok(placeholder)
+    }
+
+    fn generate<R: Rng>(&self, index: usize, now: DateTime<Utc>, rng: &mut R) -> FirestoreType {
+        let pick = |words: &[&str], rng: &mut R| words.choose(rng).unwrap().to_string();
+        match self {
+            Placeholder::FirstName => FirestoreType::String(pick(FIRST_NAMES, rng)),
+            Placeholder::LastName => FirestoreType::String(pick(LAST_NAMES, rng)),
+            Placeholder::Name => FirestoreType::String(format!(
+                "{} {}",
+                pick(FIRST_NAMES, rng),
+                pick(LAST_NAMES, rng)
+            )),
+            Placeholder::Email => FirestoreType::String(format!(
+                "{}.{}{}@example.com",
+                pick(FIRST_NAMES, rng).to_lowercase(),
+                pick(LAST_NAMES, rng).to_lowercase(),
+                rng.gen_range(1, 1000)
+            )),
+            Placeholder::Word => FirestoreType::String(pick(WORDS, rng)),
+            Placeholder::Uuid => FirestoreType::String(id_strategy::uuid(rng)),
+            Placeholder::Bool => FirestoreType::Boolean(rng.gen()),
+            Placeholder::Int(low, high) => FirestoreType::Integer(rng.gen_range(*low, *high + 1)),
+            Placeholder::Float(low, high) if low == high => FirestoreType::Double(*low),
+            Placeholder::Float(low, high) => FirestoreType::Double(rng.gen_range(*low, *high)),
+            Placeholder::Pick(words) => FirestoreType::String(words.choose(rng).unwrap().clone()),
+            Placeholder::Timestamp(0) => FirestoreType::Timestamp(now),
+            Placeholder::Timestamp(offset) => {
+                let seconds = rng.gen_range(0, offset.abs() + 1) * offset.signum();
+                FirestoreType::Timestamp(now + chrono::Duration::seconds(seconds))
+            }
+            Placeholder::Index => FirestoreType::Integer(index as i64),
+        }
+    }
+}
+
+/// Splits a string of the template into text and placeholders
+fn parse_text(text: &str) -> Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(open) = rest.find(OPEN) {
+        if open > 0 {
+            parts.push(Part::Text(rest[..open].to_string()));
+        }
+        let after = &rest[open + OPEN.len()..];
+        let close = after
+            .find(CLOSE)
+            .ok_or_else(|| invalid(format!("{} has a {} without a {}", text, OPEN, CLOSE)))?;
+        parts.push(Part::Placeholder(Placeholder::parse(
+            after[..close].trim(),
+        )?));
+        rest = &after[close + CLOSE.len()..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+fn parse_node(value: Value) -> Result<Node> {
+    Ok(match value {
+        Value::String(text) => {
+            if text.contains(OPEN) {
+                Node::Text(parse_text(&text)?)
+            } else {
+                Node::Literal(Value::String(text))
+            }
+        }
+        Value::Array(values) => Node::Array(
+            values
+                .into_iter()
+                .map(parse_node)
+                .collect::<Result<Vec<Node>>>()?,
+        ),
+        Value::Object(object) => Node::Object(parse_object(object)?),
+        value => Node::Literal(value),
+    })
+}
+
+fn parse_object(object: serde_json::Map<String, Value>) -> Result<Vec<(String, Node)>> {
+    object
+        .into_iter()
+        .map(|(key, value)| Ok((key, parse_node(value)?)))
+        .collect()
+}
+
+fn generate_fields<R: Rng>(
+    fields: &[(String, Node)],
+    index: usize,
+    now: DateTime<Utc>,
+    rng: &mut R,
+) -> FirestoreFields {
+    FirestoreFields(
+        fields
+            .iter()
+            .map(|(key, node)| (key.clone(), generate_node(node, index, now, rng)))
+            .collect(),
+    )
+}
+
+fn generate_node<R: Rng>(
+    node: &Node,
+    index: usize,
+    now: DateTime<Utc>,
+    rng: &mut R,
+) -> FirestoreType {
+    match node {
+        Node::Literal(value) => FirestoreType::from(value.clone()),
+        Node::Text(parts) => match parts.as_slice() {
+            [Part::Placeholder(placeholder)] => placeholder.generate(index, now, rng),
+            parts => {
+                let mut text = String::new();
+                for part in parts {
+                    match part {
+                        Part::Text(literal) => text.push_str(literal),
+                        Part::Placeholder(placeholder) => {
+                            match placeholder.generate(index, now, rng) {
+                                FirestoreType::String(value) => text.push_str(&value),
+                                FirestoreType::Timestamp(time) => text.push_str(&time.to_rfc3339()),
+                                value => text.push_str(&value.to_json().to_string()),
+                            }
+                        }
+                    }
+                }
+                FirestoreType::String(text)
+            }
+        },
+        Node::Array(nodes) => FirestoreType::Array(Array {
+            values: nodes
+                .iter()
+                .map(|node| generate_node(node, index, now, rng))
+                .collect(),
+        }),
+        Node::Object(fields) => FirestoreType::Map(Map {
+            fields: generate_fields(fields, index, now, rng),
+        }),
+    }
+}
+
+impl SeedTemplate {
+    /// Reads a template, which must be a JSON object
+    pub fn parse(value: Value) -> Result<SeedTemplate> {
+        match value {
+            Value::Object(object) => Ok(SeedTemplate {
+                fields: parse_object(object)?,
+            }),
+            _ => Err(invalid(String::from(
+                "the template must be a JSON object of fields",
+            ))),
+        }
+    }
+
+    /// The fields of the document numbered `index`, from 1
+    pub fn generate<R: Rng>(&self, index: usize, rng: &mut R) -> FirestoreFields {
+        generate_fields(&self.fields, index, Utc::now(), rng)
+    }
+}
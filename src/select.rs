@@ -0,0 +1,431 @@
+// Projection of documents with `--select`, a subset of the jq language:
+//   .            the document itself, with its id, name, times and fields
+//   .a.b ."a b"  a field of an object, null when missing
+//   .[0] .[]     an element of an array, every element of an array or object
+//   a | b        b applied to every output of a
+//   a, b         the outputs of a followed by those of b
+//   {id, n: .x}  an object, `id` being short for `id: .id`
+//   [a]          the outputs of a collected into an array
+// along with parentheses and string, number, boolean and null literals.
+// e.g. --select '.fields.items[] | {id: .sku, price}'
+
+use libfiresale::errors::{Error, Result};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    LeftBracket,
+    RightBracket,
+    LeftBrace,
+    RightBrace,
+    LeftParen,
+    RightParen,
+    Pipe,
+    Comma,
+    Colon,
+    Identifier(String),
+    Str(String),
+    Number(f64),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Dot => write!(f, "."),
+            Token::LeftBracket => write!(f, "["),
+            Token::RightBracket => write!(f, "]"),
+            Token::LeftBrace => write!(f, "{{"),
+            Token::RightBrace => write!(f, "}}"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::Pipe => write!(f, "|"),
+            Token::Comma => write!(f, ","),
+            Token::Colon => write!(f, ":"),
+            Token::Identifier(name) => write!(f, "{}", name),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Number(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Identity,
+    Field(Box<Expr>, String),
+    Index(Box<Expr>, i64),
+    Iterate(Box<Expr>),
+    Pipe(Box<Expr>, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+    Object(Vec<(String, Expr)>),
+    Array(Option<Box<Expr>>),
+    Literal(Value),
+}
+
+/// A compiled `--select` expression
+#[derive(Debug, Clone)]
+pub struct Select {
+    expr: Expr,
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidArgument { reason }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars = input.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '.' => Token::Dot,
+            '[' => Token::LeftBracket,
+            ']' => Token::RightBracket,
+            '{' => Token::LeftBrace,
+            '}' => Token::RightBrace,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '|' => Token::Pipe,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '"' => {
+                // the literal is handed to serde_json as is, escapes included
+                let start = i;
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                if i >= chars.len() {
+                    return Err(invalid(format!("unterminated string in {}", input)));
+                }
+                let literal = chars[start..=i].iter().collect::<String>();
+                i += 1;
+                tokens.push(Token::Str(serde_json::from_str(&*literal).map_err(
+                    |_| invalid(format!("{} is not a valid string", literal)),
+                )?));
+                continue;
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let literal = chars[start..i].iter().collect::<String>();
+                tokens.push(Token::Number(literal.parse().map_err(|_| {
+                    invalid(format!("{} is not a valid number", literal))
+                })?));
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push(Token::Identifier(chars[start..i].iter().collect()));
+                continue;
+            }
+            c => return Err(invalid(format!("unexpected {} in {}", c, input))),
+        };
+        tokens.push(token);
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens of an expression
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next() {
+            Some(ref token) if *token == expected => Ok(()),
+            Some(token) => Err(invalid(format!("expected {}, found {}", expected, token))),
+            None => Err(invalid(format!("expected {} at the end", expected))),
+        }
+    }
+
+    /// a | b | ...
+    fn pipeline(&mut self) -> Result<Expr> {
+        let mut expr = self.comma()?;
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            expr = Expr::Pipe(Box::new(expr), Box::new(self.comma()?));
+        }
+        Ok(expr)
+    }
+
+    /// a, b, ...
+    fn comma(&mut self) -> Result<Expr> {
+        let mut expr = self.term()?;
+        while self.peek() == Some(&Token::Comma) {
+            self.next();
+            expr = Expr::Comma(Box::new(expr), Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    /// The value of an object entry, which may be piped but not separated by commas
+    fn object_value(&mut self) -> Result<Expr> {
+        let mut expr = self.term()?;
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            expr = Expr::Pipe(Box::new(expr), Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    /// A primary expression followed by any number of `.field` and `[...]` suffixes
+    fn term(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Dot) => {
+                    self.next();
+                    expr = Expr::Field(Box::new(expr), self.field_name()?);
+                }
+                Some(Token::LeftBracket) => {
+                    self.next();
+                    expr = self.bracket(expr)?;
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    /// The name after a `.`
+    fn field_name(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Identifier(name)) | Some(Token::Str(name)) => Ok(name),
+            Some(token) => Err(invalid(format!("expected a field name, found {}", token))),
+            None => Err(invalid(String::from("expected a field name at the end"))),
+        }
+    }
+
+    /// `[]`, `[n]` or `["name"]` applied to `expr`, after the opening bracket
+    fn bracket(&mut self, expr: Expr) -> Result<Expr> {
+        let expr = match self.next() {
+            Some(Token::RightBracket) => return Ok(Expr::Iterate(Box::new(expr))),
+            Some(Token::Number(n)) if n.fract() == 0.0 => Expr::Index(Box::new(expr), n as i64),
+            Some(Token::Str(name)) => Expr::Field(Box::new(expr), name),
+            Some(token) => {
+                return Err(invalid(format!(
+                    "expected an index or a name, found {}",
+                    token
+                )))
+            }
+            None => return Err(invalid(String::from("unterminated ["))),
+        };
+        self.expect(Token::RightBracket)?;
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Dot) => match self.peek() {
+                Some(Token::Identifier(_)) | Some(Token::Str(_)) => {
+                    Ok(Expr::Field(Box::new(Expr::Identity), self.field_name()?))
+                }
+                _ => Ok(Expr::Identity),
+            },
+            Some(Token::LeftParen) => {
+                let expr = self.pipeline()?;
+                self.expect(Token::RightParen)?;
+                Ok(expr)
+            }
+            Some(Token::LeftBracket) => {
+                if self.peek() == Some(&Token::RightBracket) {
+                    self.next();
+                    return Ok(Expr::Array(None));
+                }
+                let expr = self.pipeline()?;
+                self.expect(Token::RightBracket)?;
+                Ok(Expr::Array(Some(Box::new(expr))))
+            }
+            Some(Token::LeftBrace) => self.object(),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Number(n)) => Ok(Expr::Literal(if n.fract() == 0.0 && n.abs() < 1e15 {
+                Value::from(n as i64)
+            } else {
+                Value::from(n)
+            })),
+            Some(Token::Identifier(name)) => match &*name {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ => Err(invalid(format!(
+                    "{} is not supported, only paths, objects, arrays and literals are",
+                    name
+                ))),
+            },
+            Some(token) => Err(invalid(format!("unexpected {}", token))),
+            None => Err(invalid(String::from("unexpected end of the expression"))),
+        }
+    }
+
+    /// `{key, key: value, "key": value}`, after the opening brace
+    fn object(&mut self) -> Result<Expr> {
+        let mut entries = Vec::new();
+        if self.peek() == Some(&Token::RightBrace) {
+            self.next();
+            return Ok(Expr::Object(entries));
+        }
+        loop {
+            let key = self.field_name()?;
+            let value = if self.peek() == Some(&Token::Colon) {
+                self.next();
+                self.object_value()?
+            } else {
+                Expr::Field(Box::new(Expr::Identity), key.clone())
+            };
+            entries.push((key, value));
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::RightBrace) => return Ok(Expr::Object(entries)),
+                Some(token) => return Err(invalid(format!("expected , or }}, found {}", token))),
+                None => return Err(invalid(String::from("unterminated {"))),
+            }
+        }
+    }
+}
+
+/// The type of `value` in errors
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+fn eval(expr: &Expr, input: &Value) -> Result<Vec<Value>> {
+    match expr {
+        Expr::Identity => Ok(vec![input.clone()]),
+        Expr::Literal(value) => Ok(vec![value.clone()]),
+        Expr::Field(target, name) => eval(target, input)?
+            .into_iter()
+            .map(|value| match value {
+                Value::Object(mut object) => Ok(object.remove(name).unwrap_or(Value::Null)),
+                Value::Null => Ok(Value::Null),
+                other => Err(invalid(format!(
+                    "cannot take field {} of {}",
+                    name,
+                    type_name(&other)
+                ))),
+            })
+            .collect(),
+        Expr::Index(target, index) => eval(target, input)?
+            .into_iter()
+            .map(|value| match value {
+                Value::Array(mut array) => {
+                    // negative indices count from the end
+                    let index = if *index < 0 {
+                        array.len() as i64 + index
+                    } else {
+                        *index
+                    };
+                    if index < 0 || index as usize >= array.len() {
+                        Ok(Value::Null)
+                    } else {
+                        Ok(array.swap_remove(index as usize))
+                    }
+                }
+                Value::Null => Ok(Value::Null),
+                other => Err(invalid(format!("cannot index {}", type_name(&other)))),
+            })
+            .collect(),
+        Expr::Iterate(target) => {
+            let mut outputs = Vec::new();
+            for value in eval(target, input)? {
+                match value {
+                    Value::Array(array) => outputs.extend(array),
+                    Value::Object(object) => outputs.extend(object.into_iter().map(|(_, v)| v)),
+                    other => {
+                        return Err(invalid(format!(
+                            "cannot iterate over {}",
+                            type_name(&other)
+                        )))
+                    }
+                }
+            }
+            Ok(outputs)
+        }
+        Expr::Pipe(left, right) => {
+            let mut outputs = Vec::new();
+            for value in eval(left, input)? {
+                outputs.extend(eval(right, &value)?);
+            }
+            Ok(outputs)
+        }
+        Expr::Comma(left, right) => {
+            let mut outputs = eval(left, input)?;
+            outputs.extend(eval(right, input)?);
+            Ok(outputs)
+        }
+        Expr::Array(None) => Ok(vec![Value::Array(Vec::new())]),
+        Expr::Array(Some(inner)) => Ok(vec![Value::Array(eval(inner, input)?)]),
+        Expr::Object(entries) => {
+            // an entry with several values yields an object for each of them
+            let mut objects = vec![Map::new()];
+            for (key, expr) in entries {
+                let values = eval(expr, input)?;
+                let mut extended = Vec::with_capacity(objects.len() * values.len());
+                for object in &objects {
+                    for value in &values {
+                        let mut object = object.clone();
+                        object.insert(key.clone(), value.clone());
+                        extended.push(object);
+                    }
+                }
+                objects = extended;
+            }
+            Ok(objects.into_iter().map(Value::Object).collect())
+        }
+    }
+}
+
+impl Select {
+    /// The outputs of this expression for `input`, any number of them
+    pub fn apply(&self, input: &Value) -> Result<Vec<Value>> {
+        eval(&self.expr, input)
+    }
+}
+
+impl FromStr for Select {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Select> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            position: 0,
+        };
+        let expr = parser.pipeline()?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(format!("unexpected {} in {}", token, input)));
+        }
+        Ok(Select { expr })
+    }
+}
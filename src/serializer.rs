@@ -0,0 +1,400 @@
+// Conversion of serde serializable values into Firestore fields
+
+use super::api::{Array, FirestoreFields, FirestoreType, Map};
+use super::errors::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::ser::{self, Serialize};
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidDocument { reason }
+}
+
+/// Converts `value` into Firestore fields, failing unless it serializes as a map or struct.
+/// Strings holding RFC 3339 timestamps, such as serialized `chrono::DateTime`s, are written
+/// as timestamps and byte buffers as bytes. Enums are written the way serde_json writes them.
+pub fn to_fields<T>(value: &T) -> Result<FirestoreFields>
+where
+    T: Serialize + ?Sized,
+{
+    match to_value(value)? {
+        FirestoreType::Map(map) => Ok(map.fields),
+        _ => Err(invalid(String::from(
+            "only maps and structs can be written as documents",
+        ))),
+    }
+}
+
+/// Converts `value` into a single Firestore value
+pub fn to_value<T>(value: &T) -> Result<FirestoreType>
+where
+    T: Serialize + ?Sized,
+{
+    value.serialize(Serializer)
+}
+
+/// Wraps `value` in a single entry map, as serde_json does for enum variants with data
+fn variant(variant: &'static str, value: FirestoreType) -> FirestoreType {
+    let mut fields = FirestoreFields::default();
+    fields.0.insert(variant.to_string(), value);
+    FirestoreType::Map(Map { fields })
+}
+
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = FirestoreType;
+    type Error = Error;
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeArray;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<FirestoreType> {
+        Ok(FirestoreType::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<FirestoreType> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<FirestoreType> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<FirestoreType> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<FirestoreType> {
+        Ok(FirestoreType::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<FirestoreType> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<FirestoreType> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<FirestoreType> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<FirestoreType> {
+        if v > i64::max_value() as u64 {
+            return Err(invalid(format!("{} does not fit in a 64 bit integer", v)));
+        }
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<FirestoreType> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<FirestoreType> {
+        Ok(FirestoreType::Double(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<FirestoreType> {
+        Ok(FirestoreType::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<FirestoreType> {
+        match DateTime::parse_from_rfc3339(v) {
+            Ok(time) => Ok(FirestoreType::Timestamp(time.with_timezone(&Utc))),
+            Err(_) => Ok(FirestoreType::String(v.to_string())),
+        }
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<FirestoreType> {
+        Ok(FirestoreType::Bytes(base64::encode(v)))
+    }
+
+    fn serialize_none(self) -> Result<FirestoreType> {
+        Ok(FirestoreType::Null(()))
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<FirestoreType>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<FirestoreType> {
+        Ok(FirestoreType::Null(()))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<FirestoreType> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<FirestoreType> {
+        Ok(FirestoreType::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<FirestoreType>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant_name: &'static str,
+        value: &T,
+    ) -> Result<FirestoreType>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(variant(variant_name, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray> {
+        Ok(SerializeArray {
+            variant: None,
+            values: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray> {
+        Ok(SerializeArray {
+            variant: Some(variant),
+            values: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap> {
+        Ok(SerializeMap {
+            variant: None,
+            fields: FirestoreFields::default(),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeMap> {
+        Ok(SerializeMap {
+            variant: Some(variant),
+            fields: FirestoreFields::default(),
+            next_key: None,
+        })
+    }
+}
+
+pub struct SerializeArray {
+    variant: Option<&'static str>,
+    values: Vec<FirestoreType>,
+}
+
+impl SerializeArray {
+    fn push<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.values.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<FirestoreType> {
+        let array = FirestoreType::Array(Array {
+            values: self.values,
+        });
+        Ok(match self.variant {
+            Some(name) => variant(name, array),
+            None => array,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = FirestoreType;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<FirestoreType> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = FirestoreType;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<FirestoreType> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = FirestoreType;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<FirestoreType> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeArray {
+    type Ok = FirestoreType;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<FirestoreType> {
+        self.finish()
+    }
+}
+
+pub struct SerializeMap {
+    variant: Option<&'static str>,
+    fields: FirestoreFields,
+    next_key: Option<String>,
+}
+
+impl SerializeMap {
+    fn insert<T>(&mut self, key: String, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.fields.0.insert(key, to_value(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<FirestoreType> {
+        let map = FirestoreType::Map(Map {
+            fields: self.fields,
+        });
+        Ok(match self.variant {
+            Some(name) => variant(name, map),
+            None => map,
+        })
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = FirestoreType;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        // keys are kept as written, even when they look like timestamps
+        let key = match serde_json::to_value(key)? {
+            serde_json::Value::String(key) => key,
+            serde_json::Value::Number(key) => key.to_string(),
+            serde_json::Value::Bool(key) => key.to_string(),
+            _ => return Err(invalid(String::from("map keys must be strings"))),
+        };
+        self.next_key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| invalid(String::from("map value without a key")))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<FirestoreType> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = FirestoreType;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<FirestoreType> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = FirestoreType;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<FirestoreType> {
+        self.finish()
+    }
+}
@@ -0,0 +1,58 @@
+// Storage sizes of documents, as Firestore bills them and applies its 1 MiB limit to: the
+// size of the document name, plus the names and values of its fields, plus 32 bytes.
+// Strings count their UTF-8 bytes and one more, numbers and timestamps 8 bytes each.
+
+use crate::api::{relative_path, Document, FirestoreFields, FirestoreType};
+
+/// Largest size of a document
+pub const MAX_DOCUMENT_SIZE: usize = 1024 * 1024;
+
+/// Added to the size of every document
+pub const DOCUMENT_OVERHEAD: usize = 32;
+
+/// Added to the size of every document name
+const NAME_OVERHEAD: usize = 16;
+
+fn string_size(value: &str) -> usize {
+    value.len() + 1
+}
+
+/// Size of the name of the document at said slash-delimited path, its segments
+/// counted as strings
+pub fn name_size(path: &str) -> usize {
+    path.split('/').map(string_size).sum::<usize>() + NAME_OVERHEAD
+}
+
+pub fn value_size(value: &FirestoreType) -> usize {
+    match value {
+        FirestoreType::Integer(_) | FirestoreType::Double(_) | FirestoreType::Timestamp(_) => 8,
+        FirestoreType::Boolean(_) | FirestoreType::Null(_) => 1,
+        FirestoreType::String(value) => string_size(value),
+        FirestoreType::GeoLocation(_) => 16,
+        FirestoreType::Array(array) => array.values.iter().map(value_size).sum(),
+        FirestoreType::Map(map) => fields_size(&map.fields),
+        FirestoreType::Reference(name) => name_size(relative_path(name)),
+        FirestoreType::Bytes(encoded) => match base64::decode(encoded) {
+            Ok(bytes) => bytes.len(),
+            Err(_) => encoded.len() * 3 / 4,
+        },
+    }
+}
+
+/// Size of a field, its name and its value
+pub fn field_size(name: &str, value: &FirestoreType) -> usize {
+    string_size(name) + value_size(value)
+}
+
+/// Size of the names and values of fields
+pub fn fields_size(fields: &FirestoreFields) -> usize {
+    fields
+        .0
+        .iter()
+        .map(|(name, value)| field_size(name, value))
+        .sum()
+}
+
+pub fn document_size(document: &Document) -> usize {
+    name_size(document.path()) + fields_size(document.fields()) + DOCUMENT_OVERHEAD
+}
@@ -0,0 +1,162 @@
+// Collection statistics for `stats`: the sizes of a sample of documents, how many distinct
+// values each field has among them, and how deep subcollections nest below some of them.
+// Only the document count covers the whole collection, the rest are estimates.
+
+use libfiresale::api::{split_document_path, DatabaseContext, Document};
+use libfiresale::errors::Result;
+use libfiresale::size;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+
+/// What was seen of a field path in the sample
+#[derive(Debug, Default)]
+struct FieldValues {
+    present: usize,
+    /// JSON of the distinct values
+    distinct: HashSet<String>,
+}
+
+/// Statistics of a collection, from the documents observed so far
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Documents of the whole collection
+    pub documents: i64,
+    /// Sizes of the observed documents, with the path of the largest
+    sizes: Vec<usize>,
+    largest: Option<(usize, String)>,
+    fields: BTreeMap<String, FieldValues>,
+    /// Subcollection levels below the observed documents, with the deepest collection
+    pub nesting: Option<(usize, String)>,
+}
+
+impl Stats {
+    /// Adds a document of the sample
+    pub fn observe(&mut self, document: &Document) {
+        let document_size = size::document_size(document);
+        self.sizes.push(document_size);
+        if self
+            .largest
+            .as_ref()
+            .map_or(true, |(max, _)| document_size > *max)
+        {
+            self.largest = Some((document_size, document.path().to_string()));
+        }
+        let fields = document.fields();
+        for path in fields.field_paths() {
+            if let Some(value) = fields.get(&path) {
+                let values = self.fields.entry(path).or_default();
+                values.present += 1;
+                values.distinct.insert(value.to_json().to_string());
+            }
+        }
+    }
+
+    /// Number of documents observed
+    pub fn sampled(&self) -> usize {
+        self.sizes.len()
+    }
+
+    fn average_size(&self) -> usize {
+        if self.sizes.is_empty() {
+            return 0;
+        }
+        self.sizes.iter().sum::<usize>() / self.sizes.len()
+    }
+
+    fn median_size(&self) -> usize {
+        let mut sizes = self.sizes.clone();
+        sizes.sort();
+        sizes.get(sizes.len() / 2).cloned().unwrap_or(0)
+    }
+
+    /// The report as JSON
+    pub fn to_json(&self) -> Value {
+        let fields = self
+            .fields
+            .iter()
+            .map(|(path, values)| {
+                let field = json!({
+                    "present": values.present,
+                    "distinct": values.distinct.len(),
+                });
+                (path.clone(), field)
+            })
+            .collect::<serde_json::Map<String, Value>>();
+        json!({
+            "documents": self.documents,
+            "sampled": self.sampled(),
+            "size": {
+                "average": self.average_size(),
+                "median": self.median_size(),
+                "max": self.largest.as_ref().map(|(size, _)| size),
+                "largest": self.largest.as_ref().map(|(_, path)| path),
+            },
+            "nesting": self.nesting.as_ref().map(|(depth, _)| depth),
+            "deepest": self.nesting.as_ref().map(|(_, path)| path),
+            "fields": fields,
+        })
+    }
+
+    /// The figures of the collection as rows of two columns
+    pub fn summary_rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![
+            vec![String::from("documents"), self.documents.to_string()],
+            vec![String::from("sampled"), self.sampled().to_string()],
+            vec![
+                String::from("average size"),
+                self.average_size().to_string(),
+            ],
+            vec![String::from("median size"), self.median_size().to_string()],
+        ];
+        if let Some((size, path)) = &self.largest {
+            rows.push(vec![
+                String::from("max size"),
+                format!("{} ({})", size, path),
+            ]);
+        }
+        if let Some((depth, path)) = &self.nesting {
+            let nesting = match depth {
+                0 => String::from("0"),
+                depth => format!("{} ({})", depth, path),
+            };
+            rows.push(vec![String::from("nesting"), nesting]);
+        }
+        rows
+    }
+
+    /// The fields as rows of a table, after a header row
+    pub fn field_rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![vec![
+            String::from("field"),
+            String::from("present"),
+            String::from("distinct"),
+        ]];
+        for (path, values) in &self.fields {
+            rows.push(vec![
+                path.clone(),
+                values.present.to_string(),
+                values.distinct.len().to_string(),
+            ]);
+        }
+        rows
+    }
+}
+
+/// Subcollection levels below the document at `path`, along with the deepest collection.
+/// Only the first document of each subcollection is descended into.
+pub fn nesting_depth(ctx: &DatabaseContext, path: &str) -> Result<(usize, String)> {
+    let (collection_name, document_id) = split_document_path(path);
+    let mut deepest = (0, String::new());
+    for collection_id in ctx.list_collection_ids(collection_name, document_id)? {
+        let collection = format!("{}/{}", path, collection_id);
+        let (depth, below) = match ctx.iter_documents(&*collection, Some(1)).next() {
+            Some(document) => nesting_depth(ctx, document?.path())?,
+            None => (0, String::new()),
+        };
+        if deepest.1.is_empty() || depth + 1 > deepest.0 {
+            let below = if depth == 0 { collection } else { below };
+            deepest = (depth + 1, below);
+        }
+    }
+    Ok(deepest)
+}
@@ -0,0 +1,86 @@
+// Line templates given with `--template`, such as '{{id}}: {{fields.name}} ({{fields.email}})'.
+// Every `{{path}}` is replaced by the value at that dotted path of the document, or of
+// the value selected with `--select`: strings as they are, other values as JSON and
+// missing values as nothing. Numeric segments index arrays, as in `fields.tags.0`.
+
+use libfiresale::errors::{Error, Result};
+use serde_json::Value;
+use std::str::FromStr;
+
+const OPEN: &'static str = "{{";
+const CLOSE: &'static str = "}}";
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Text(String),
+    Path(Vec<String>),
+}
+
+/// A compiled `--template`
+#[derive(Debug, Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+/// The value at `path` within `value`
+fn lookup<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, segment| match value {
+        Value::Array(array) => array.get(segment.parse::<usize>().ok()?),
+        value => value.get(&**segment),
+    })
+}
+
+impl Template {
+    /// Fills the template in with the fields of `value`
+    pub fn render(&self, value: &Value) -> String {
+        let mut line = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => line.push_str(text),
+                Segment::Path(path) => match lookup(value, path) {
+                    None | Some(Value::Null) => {}
+                    Some(Value::String(s)) => line.push_str(s),
+                    Some(other) => line.push_str(&other.to_string()),
+                },
+            }
+        }
+        line
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Template> {
+        let mut segments = Vec::new();
+        let mut rest = input;
+        while let Some(open) = rest.find(OPEN) {
+            if open > 0 {
+                segments.push(Segment::Text(rest[..open].to_string()));
+            }
+            let after = &rest[open + OPEN.len()..];
+            let close = after.find(CLOSE).ok_or_else(|| Error::InvalidArgument {
+                reason: format!("{} is missing a closing }}}}", input),
+            })?;
+            let path = after[..close].trim();
+            // `{{.}}` and `{{}}` stand for the whole value
+            let path = path.trim_start_matches('.');
+            let path = if path.is_empty() {
+                Vec::new()
+            } else {
+                path.split('.').map(String::from).collect::<Vec<String>>()
+            };
+            if path.iter().any(String::is_empty) {
+                return Err(Error::InvalidArgument {
+                    reason: format!("{} is not a valid field path", &after[..close]),
+                });
+            }
+            segments.push(Segment::Path(path));
+            rest = &after[close + CLOSE.len()..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        Ok(Template { segments })
+    }
+}
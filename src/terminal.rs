@@ -0,0 +1,126 @@
+// What the terminal firesale runs in can do, asked by every path that writes output.
+// Data goes to stdout and everything meant for people, status lines, progress and
+// questions, to stderr. `--quiet` leaves out all of the latter but errors, colors are
+// only written to a terminal unless `--color` says otherwise and questions are only asked
+// when someone can answer them, so that scripts get the same output whether or not they
+// pass any flags. Commands that read may page their output like git does, through $PAGER
+// when stdout is a terminal, which less only holds on to when it does not fit on a screen.
+
+use std::env;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Choices of `--color`, in the order they are listed in help
+pub const COLOR_CHOICES: &[&str] = &["auto", "always", "never"];
+
+/// Pager used when PAGER is not set
+const DEFAULT_PAGER: &'static str = "less";
+
+/// Options of less when LESS is not set, as git sets them: quit when the output fits on
+/// one screen, pass colors through and leave the output on the screen
+const DEFAULT_LESS: &'static str = "FRX";
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static COLOR: AtomicBool = AtomicBool::new(false);
+/// Process ID of the pager stdout goes to, 0 when there is none
+static PAGER: AtomicUsize = AtomicUsize::new(0);
+
+/// Prints a status line to stderr like `eprintln!`, unless `--quiet` was given
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::terminal::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Set once the arguments are read
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Whether `--quiet` was given
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+pub fn stdout_is_terminal() -> bool {
+    atty::is(atty::Stream::Stdout)
+}
+
+pub fn stderr_is_terminal() -> bool {
+    atty::is(atty::Stream::Stderr)
+}
+
+/// Set once the arguments are read from `--color`, where `auto` colors a terminal
+/// unless the NO_COLOR environment variable is set
+pub fn set_color(choice: &str) {
+    let color = match choice {
+        "always" => true,
+        "never" => false,
+        _ => stdout_is_terminal() && env::var_os("NO_COLOR").is_none(),
+    };
+    COLOR.store(color, Ordering::Relaxed);
+}
+
+/// Whether stdout takes ANSI colors
+pub fn color_stdout() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Whether a question on stderr can be answered on stdin
+pub fn can_prompt() -> bool {
+    atty::is(atty::Stream::Stdin) && stderr_is_terminal()
+}
+
+/// Sends stdout through $PAGER from here on when it is a terminal. Firesale waits for
+/// the pager when it exits, stdout is left as it is when the pager cannot be started.
+#[cfg(unix)]
+pub fn start_pager() {
+    use std::os::unix::io::AsRawFd;
+    use std::process::{Command, Stdio};
+    if !stdout_is_terminal() {
+        return;
+    }
+    let pager = env::var("PAGER").unwrap_or_else(|_| String::from(DEFAULT_PAGER));
+    if pager.trim().is_empty() || pager == "cat" {
+        return;
+    }
+    // through the shell, so that PAGER may hold arguments such as `less -S`
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(&pager).stdin(Stdio::piped());
+    if env::var_os("LESS").is_none() {
+        command.env("LESS", DEFAULT_LESS);
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            log::debug!("cannot start the pager {}: {}", pager, e);
+            return;
+        }
+    };
+    let input = child.stdin.take().unwrap();
+    io::stdout().flush().ok();
+    unsafe {
+        libc::dup2(input.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::atexit(wait_for_pager);
+    }
+    PAGER.store(child.id() as usize, Ordering::Relaxed);
+}
+
+#[cfg(not(unix))]
+pub fn start_pager() {}
+
+/// Run at exit, closes stdout so the pager sees the end of the output and waits for it
+#[cfg(unix)]
+extern "C" fn wait_for_pager() {
+    let pid = PAGER.load(Ordering::Relaxed);
+    if pid == 0 {
+        return;
+    }
+    io::stdout().flush().ok();
+    unsafe {
+        libc::close(libc::STDOUT_FILENO);
+        libc::waitpid(pid as libc::pid_t, std::ptr::null_mut(), 0);
+    }
+}
@@ -0,0 +1,160 @@
+// Access tokens of a `DatabaseContext`, shared by every clone of it. A token is used
+// until it comes within `REFRESH_MARGIN_SECONDS` of expiring and is then replaced
+// before the next request, so long exports and sessions swap tokens between requests
+// instead of failing with UNAUTHENTICATED halfway through. Tokens may also be kept on
+// disk, readable by their owner only, or in the keychain, so that consecutive runs
+// share a token.
+
+use super::auth::CredentialSource;
+use super::credential_store;
+use super::errors::Result;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// How long before it expires a token is replaced
+const REFRESH_MARGIN_SECONDS: i64 = 5 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedToken {
+    access_token: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl CachedToken {
+    fn is_fresh(&self) -> bool {
+        self.expires_at - Duration::seconds(REFRESH_MARGIN_SECONDS) > Utc::now()
+    }
+}
+
+/// Where tokens are kept between runs
+#[derive(Debug)]
+enum Storage {
+    Memory,
+    File(PathBuf),
+    /// Account of the keychain entry
+    Keychain(String),
+}
+
+/// Mints access tokens from a `CredentialSource` and hands out the current one
+/// while it is fresh
+#[derive(Debug)]
+pub struct TokenCache {
+    source: CredentialSource,
+    storage: Storage,
+    token: Mutex<Option<CachedToken>>,
+}
+
+/// Writes `contents` to `path` with permissions for the owner only, replacing
+/// the previous file once the new one is complete
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&partial)?.write_all(contents)?;
+    fs::rename(partial, path)
+}
+
+impl TokenCache {
+    pub fn new(source: CredentialSource) -> TokenCache {
+        TokenCache {
+            source,
+            storage: Storage::Memory,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Name of the tokens of this credential source
+    fn name(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        format!("{:?}", self.source).hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Keeps tokens in `dir` between runs, in a file per credential source
+    pub fn with_dir<P: AsRef<Path>>(mut self, dir: P) -> TokenCache {
+        let path = dir.as_ref().join(format!("{}.json", self.name()));
+        self.storage = Storage::File(path);
+        self
+    }
+
+    /// Keeps tokens in the keychain between runs, in an entry per credential source
+    pub fn with_keychain(mut self) -> TokenCache {
+        self.storage = Storage::Keychain(format!("token-{}", self.name()));
+        self
+    }
+
+    /// The current access token, minting a new one with `client` when there is
+    /// none or it is about to expire
+    pub fn access_token(&self, client: &reqwest::Client) -> Result<String> {
+        // held while minting, so that threads needing a token wait for a single new one
+        let mut token = self.token.lock().unwrap();
+        if token.is_none() {
+            *token = self.load();
+        }
+        match &*token {
+            Some(current) if current.is_fresh() => return Ok(current.access_token.clone()),
+            _ => {}
+        }
+        let minted = self.mint(client)?;
+        if let Err(e) = self.store(&minted) {
+            debug!("failed to keep the access token: {}", e);
+        }
+        let access_token = minted.access_token.clone();
+        *token = Some(minted);
+        Ok(access_token)
+    }
+
+    fn mint(&self, client: &reqwest::Client) -> Result<CachedToken> {
+        let token = self.source.token(client)?;
+        // goauth keeps the lifetime of a token to itself, apart from serializing it
+        let expires_in = serde_json::to_value(&token)?["expires_in"]
+            .as_i64()
+            .unwrap_or(0);
+        debug!("minted an access token expiring in {}s", expires_in);
+        Ok(CachedToken {
+            access_token: token.access_token().to_string(),
+            expires_at: Utc::now() + Duration::seconds(expires_in),
+        })
+    }
+
+    /// The token kept between runs, if any. An unreadable one is as good as none.
+    fn load(&self) -> Option<CachedToken> {
+        match &self.storage {
+            Storage::Memory => None,
+            Storage::File(path) => {
+                let file = File::open(path).ok()?;
+                serde_json::from_reader(BufReader::new(file)).ok()
+            }
+            Storage::Keychain(account) => {
+                let secret = credential_store::get(account).ok()??;
+                serde_json::from_str(&*secret).ok()
+            }
+        }
+    }
+
+    fn store(&self, token: &CachedToken) -> Result<()> {
+        match &self.storage {
+            Storage::Memory => Ok(()),
+            Storage::File(path) => {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                write_private(path, &serde_json::to_vec(token)?)?;
+                Ok(())
+            }
+            Storage::Keychain(account) => {
+                credential_store::set(account, &*serde_json::to_string(token)?)
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// How values without a JSON counterpart are rendered as JSON, chosen per type with
+// entries such as `reference=path,geopoint=array,timestamp=epoch`. References default to
+// resource names, geo points to objects, bytes to base64 and timestamps to RFC 3339.
+
+use crate::api::{relative_path, GeoPoint};
+use crate::errors::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Types whose style can be chosen, in the order they are listed in help
+pub const STYLED_TYPES: &[&str] = &["reference", "geopoint", "bytes", "timestamp"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReferenceStyle {
+    /// Full resource name of the document
+    Name,
+    /// Slash-delimited path of the document within its database
+    Path,
+    /// `{"type": "reference", "path": ...}`
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoPointStyle {
+    /// `{"latitude": ..., "longitude": ...}`
+    Object,
+    /// `[latitude, longitude]`
+    Array,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BytesStyle {
+    Base64,
+    /// `{"type": "bytes", "base64": ...}`
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampStyle {
+    Rfc3339,
+    /// Whole seconds since the Unix epoch
+    Epoch,
+    /// Whole milliseconds since the Unix epoch
+    EpochMillis,
+}
+
+/// Styles of all the types, see `FirestoreType::to_json_with`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TypeStyle {
+    pub reference: ReferenceStyle,
+    pub geo_point: GeoPointStyle,
+    pub bytes: BytesStyle,
+    pub timestamp: TimestampStyle,
+}
+
+impl Default for TypeStyle {
+    fn default() -> TypeStyle {
+        TypeStyle {
+            reference: ReferenceStyle::Name,
+            geo_point: GeoPointStyle::Object,
+            bytes: BytesStyle::Base64,
+            timestamp: TimestampStyle::Rfc3339,
+        }
+    }
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidArgument { reason }
+}
+
+impl TypeStyle {
+    /// Applies an entry such as `reference=path`
+    pub fn set(&mut self, entry: &str) -> Result<()> {
+        let mut parts = entry.splitn(2, '=');
+        let (kind, style) = match (parts.next(), parts.next()) {
+            (Some(kind), Some(style)) => (kind.trim(), style.trim()),
+            _ => {
+                return Err(invalid(format!(
+                    "{} is not a type style, expected type=style such as reference=path",
+                    entry
+                )))
+            }
+        };
+        let unknown = || {
+            invalid(format!(
+                "{} is not a known style for {} values",
+                style, kind
+            ))
+        };
+        match kind {
+            "reference" | "ref" => {
+                self.reference = match style {
+                    "name" => ReferenceStyle::Name,
+                    "path" => ReferenceStyle::Path,
+                    "object" => ReferenceStyle::Object,
+                    _ => return Err(unknown()),
+                }
+            }
+            "geopoint" | "geo" => {
+                self.geo_point = match style {
+                    "object" => GeoPointStyle::Object,
+                    "array" => GeoPointStyle::Array,
+                    _ => return Err(unknown()),
+                }
+            }
+            "bytes" => {
+                self.bytes = match style {
+                    "base64" => BytesStyle::Base64,
+                    "object" => BytesStyle::Object,
+                    _ => return Err(unknown()),
+                }
+            }
+            "timestamp" | "time" => {
+                self.timestamp = match style {
+                    "rfc3339" => TimestampStyle::Rfc3339,
+                    "epoch" => TimestampStyle::Epoch,
+                    "epoch-millis" => TimestampStyle::EpochMillis,
+                    _ => return Err(unknown()),
+                }
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "{} is not a type with styles, expected one of {}",
+                    kind,
+                    STYLED_TYPES.join(", ")
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn reference(&self, name: &str) -> Value {
+        match self.reference {
+            ReferenceStyle::Name => Value::String(name.to_string()),
+            ReferenceStyle::Path => Value::String(relative_path(name).to_string()),
+            ReferenceStyle::Object => json!({ "type": "reference", "path": relative_path(name) }),
+        }
+    }
+
+    pub(crate) fn geo_point(&self, point: &GeoPoint) -> Value {
+        match self.geo_point {
+            GeoPointStyle::Object => json!({
+                "latitude": point.latitude,
+                "longitude": point.longitude,
+            }),
+            GeoPointStyle::Array => json!([point.latitude, point.longitude]),
+        }
+    }
+
+    pub(crate) fn bytes(&self, bytes: &str) -> Value {
+        match self.bytes {
+            BytesStyle::Base64 => Value::String(bytes.to_string()),
+            BytesStyle::Object => json!({ "type": "bytes", "base64": bytes }),
+        }
+    }
+
+    pub(crate) fn timestamp(&self, time: &DateTime<Utc>) -> Value {
+        match self.timestamp {
+            TimestampStyle::Rfc3339 => Value::String(time.to_rfc3339()),
+            TimestampStyle::Epoch => Value::from(time.timestamp()),
+            TimestampStyle::EpochMillis => Value::from(time.timestamp_millis()),
+        }
+    }
+}
+
+impl FromStr for TypeStyle {
+    type Err = Error;
+
+    /// Reads comma-separated entries, types left out keep their default style
+    fn from_str(entries: &str) -> Result<TypeStyle> {
+        let mut style = TypeStyle::default();
+        for entry in entries.split(',').filter(|entry| !entry.trim().is_empty()) {
+            style.set(entry)?;
+        }
+        Ok(style)
+    }
+}